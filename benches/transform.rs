@@ -0,0 +1,124 @@
+//! Benchmarks for the request and response transform hot paths
+//!
+//! Run natively with `cargo bench --bench transform`.
+
+use ccr::models::{AnthropicRequest, OpenAICompletion};
+use ccr::transform::{anthropic_to_openai, completion_to_anthropic, openai_to_anthropic};
+use ccr::utils::Routing;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde_json::json;
+
+/// A Claude Code style conversation: content blocks, tool results and a tool list
+fn conversation(message_count: usize) -> AnthropicRequest {
+    let messages: Vec<_> = (0..message_count)
+        .map(|i| {
+            if i % 2 == 0 {
+                json!({
+                    "role": "user",
+                    "content": [{
+                        "type": "text",
+                        "text": format!("Step {i}: {}", "please refactor this function. ".repeat(40)),
+                        "cache_control": {"type": "ephemeral"}
+                    }]
+                })
+            } else {
+                json!({
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": "fn main() {}\n".repeat(60)},
+                        {"type": "text", "text": "Done."}
+                    ]
+                })
+            }
+        })
+        .collect();
+    let tools: Vec<_> = (0..20)
+        .map(|i| {
+            json!({
+                "name": format!("tool_{i}"),
+                "description": "Reads a file from the workspace",
+                "input_schema": {
+                    "type": "object",
+                    "properties": {"path": {"type": "string"}},
+                    "required": ["path"]
+                },
+                "cache_control": {"type": "ephemeral"}
+            })
+        })
+        .collect();
+
+    serde_json::from_value(json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 8192,
+        "system": "You are Claude Code, a coding assistant.",
+        "messages": messages,
+        "tools": tools,
+        "stream": true
+    }))
+    .expect("valid request")
+}
+
+fn bench_anthropic_to_openai(c: &mut Criterion) {
+    let config = Routing::default();
+    let request = conversation(100);
+    c.bench_function("anthropic_to_openai/100_messages", |b| {
+        b.iter(|| anthropic_to_openai(black_box(&request), &config).unwrap())
+    });
+}
+
+/// A large non-streaming response body: a long answer and a few tool calls
+fn response_body() -> String {
+    let tool_calls: Vec<_> = (0..5)
+        .map(|i| {
+            json!({
+                "id": format!("call_{i}"),
+                "type": "function",
+                "function": {
+                    "name": "Write",
+                    "arguments": json!({"path": format!("src/file_{i}.rs"), "content": "fn main() {}\n".repeat(500)}).to_string()
+                }
+            })
+        })
+        .collect();
+    json!({
+        "id": "gen-bench",
+        "choices": [{
+            "message": {
+                "role": "assistant",
+                "content": "Here is the refactored module, with \"quotes\" and\nnewlines. ".repeat(2000),
+                "tool_calls": tool_calls
+            },
+            "finish_reason": "tool_calls"
+        }],
+        "usage": {"prompt_tokens": 50000, "completion_tokens": 12000}
+    })
+    .to_string()
+}
+
+/// Body to Anthropic JSON, through a `Value` and straight into the typed completion
+fn bench_openai_to_anthropic(c: &mut Criterion) {
+    let body = response_body();
+    let mut group = c.benchmark_group("openai_to_anthropic/large_response");
+    group.bench_function("value", |b| {
+        b.iter(|| {
+            let response: serde_json::Value = serde_json::from_str(black_box(&body)).unwrap();
+            let message = openai_to_anthropic(&response, "claude-sonnet-4").unwrap();
+            serde_json::to_string(&message).unwrap()
+        })
+    });
+    group.bench_function("typed", |b| {
+        b.iter(|| {
+            let completion: OpenAICompletion = serde_json::from_str(black_box(&body)).unwrap();
+            let message = completion_to_anthropic(completion, "claude-sonnet-4").unwrap();
+            serde_json::to_string(&message).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_anthropic_to_openai,
+    bench_openai_to_anthropic
+);
+criterion_main!(benches);
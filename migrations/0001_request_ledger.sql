@@ -0,0 +1,21 @@
+-- One row per proxied request, written by the Worker when LEDGER_DB is bound
+CREATE TABLE IF NOT EXISTS requests (
+    request_id TEXT NOT NULL,
+    -- Unix timestamp (ms) the request finished
+    ts INTEGER NOT NULL,
+    -- SHA-256 hex of the caller's key
+    key_hash TEXT NOT NULL,
+    -- Upstream model that answered, and the model the client asked for
+    model TEXT NOT NULL,
+    requested_model TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    latency_ms REAL NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    -- Estimated from OpenRouter's prices; NULL when the model couldn't be priced
+    cost_usd REAL
+);
+
+CREATE INDEX IF NOT EXISTS requests_ts ON requests (ts);
+CREATE INDEX IF NOT EXISTS requests_key_hash_ts ON requests (key_hash, ts);
+CREATE INDEX IF NOT EXISTS requests_model_ts ON requests (model, ts);
@@ -0,0 +1,133 @@
+//! Isolate-wide admission control under load
+//!
+//! An isolate serves many requests at once, and each one waiting on the
+//! upstream holds memory and a connection. Requests are either interactive,
+//! with someone waiting on the answer, or background work that can try again
+//! later. The tier router tells them apart: Claude Code sends its titles,
+//! summaries and quick subagent calls to the `haiku` alias, and `auto` sends
+//! simple requests to its cheap tier. Once the isolate has
+//! `ADMISSION_BACKGROUND_LIMIT` requests in flight, background requests are shed
+//! with a retryable 429 `rate_limit_error` so the interactive ones keep their
+//! headroom; interactive requests are always admitted.
+
+use crate::model_route::Route;
+use std::cell::Cell;
+
+/// Request header overriding how a request is classified
+pub const PRIORITY_HEADER: &str = "x-ccr-priority";
+
+/// Route rules sending a request to the cheap tier, which marks it background
+const BACKGROUND_RULES: &[&str] = &["alias.haiku", "auto.simple_request"];
+
+thread_local! {
+    /// Requests of this isolate waiting on the upstream
+    static IN_FLIGHT: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Whether a request is shed before others under load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Background,
+}
+
+impl Priority {
+    /// Classifies a request: `x-ccr-priority: interactive|background` when sent,
+    /// otherwise requests routed to the cheap tier are background and the rest
+    /// interactive
+    pub fn classify(header: Option<&str>, route: &Route) -> Result<Self, String> {
+        match header
+            .map(|value| value.trim().to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("interactive") => Ok(Priority::Interactive),
+            Some("background") => Ok(Priority::Background),
+            Some(_) => Err(format!(
+                "{PRIORITY_HEADER} must be interactive or background"
+            )),
+            None if BACKGROUND_RULES.iter().any(|rule| route.applied(rule)) => {
+                Ok(Priority::Background)
+            }
+            None => Ok(Priority::Interactive),
+        }
+    }
+}
+
+/// A request counted as in flight until the ticket is dropped
+#[derive(Debug)]
+pub struct Ticket(());
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|count| count.set(count.get().saturating_sub(1)));
+    }
+}
+
+/// Admits a request, or returns `None` when a background request is shed
+///
+/// `background_limit` is the number of requests in flight at which background
+/// requests are turned away; 0 admits everything.
+pub fn admit(priority: Priority, background_limit: u32) -> Option<Ticket> {
+    IN_FLIGHT.with(|count| {
+        let shed = priority == Priority::Background
+            && background_limit > 0
+            && count.get() >= background_limit;
+        if shed {
+            return None;
+        }
+        count.set(count.get() + 1);
+        Some(Ticket(()))
+    })
+}
+
+/// Requests of this isolate currently in flight
+pub fn in_flight() -> u32 {
+    IN_FLIGHT.with(Cell::get)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let mut haiku = Route::new("claude-3-5-haiku");
+        haiku.push("alias.haiku", "anthropic/claude-3.5-haiku");
+        let mut simple = Route::new("auto");
+        simple.push("auto.simple_request", "google/gemini-2.5-flash");
+        let mut sonnet = Route::new("claude-sonnet-4");
+        sonnet.push("alias.sonnet", "anthropic/claude-sonnet-4");
+        let direct = Route::new("deepseek/deepseek-chat");
+
+        assert_eq!(Priority::classify(None, &haiku), Ok(Priority::Background));
+        assert_eq!(Priority::classify(None, &simple), Ok(Priority::Background));
+        assert_eq!(Priority::classify(None, &sonnet), Ok(Priority::Interactive));
+        assert_eq!(Priority::classify(None, &direct), Ok(Priority::Interactive));
+        assert_eq!(
+            Priority::classify(Some("Background"), &sonnet),
+            Ok(Priority::Background)
+        );
+        assert_eq!(
+            Priority::classify(Some("interactive"), &haiku),
+            Ok(Priority::Interactive)
+        );
+        assert!(Priority::classify(Some("urgent"), &haiku).is_err());
+    }
+
+    #[test]
+    fn test_background_requests_are_shed_under_load() {
+        let first = admit(Priority::Background, 2).unwrap();
+        let second = admit(Priority::Interactive, 2).unwrap();
+        assert_eq!(in_flight(), 2);
+
+        // At the limit only interactive requests get in
+        assert!(admit(Priority::Background, 2).is_none());
+        let third = admit(Priority::Interactive, 2).unwrap();
+        assert_eq!(in_flight(), 3);
+        assert!(admit(Priority::Background, 0).is_some());
+
+        drop((first, second, third));
+        assert_eq!(in_flight(), 0);
+        assert!(admit(Priority::Background, 2).is_some());
+    }
+}
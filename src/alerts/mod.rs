@@ -0,0 +1,437 @@
+use crate::catalog::{ModelCheck, ModelProblem};
+use crate::config::Config;
+use crate::http;
+use crate::utils::format_date;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::{
+    durable_object, Date, Env, Method, ObjectNamespace, Request, RequestInit, Response, Result,
+    State,
+};
+
+/// Durable Object namespace binding that evaluates alert thresholds
+pub const ALERT_MONITOR_BINDING: &str = "ALERT_MONITOR";
+
+/// Error rate and p95 aren't meaningful over a handful of requests
+const MIN_WINDOW_REQUESTS: u32 = 10;
+
+/// Latencies kept per window for the p95; older samples are dropped first
+const MAX_LATENCY_SAMPLES: usize = 1000;
+
+const WINDOW_KEY: &str = "window";
+const SPEND_KEY: &str = "spend";
+
+/// Every request goes to one instance, so thresholds apply to the whole deployment
+const INSTANCE_NAME: &str = "global";
+
+/// Thresholds and destination for operator alerts, enabled by `ALERT_WEBHOOK_URL`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    /// Slack or Discord incoming webhook, or any URL accepting a JSON POST
+    pub webhook_url: String,
+    /// Length of the window error rate and latency are evaluated over
+    pub window_secs: u64,
+    /// Share of upstream 429s and 5xx in a window that triggers an alert (0 = off)
+    pub error_rate_percent: u8,
+    /// p95 latency in a window that triggers an alert (0 = off)
+    pub latency_p95_ms: u64,
+    /// Estimated spend in a UTC day that triggers an alert (0 = off)
+    pub daily_spend_usd: f64,
+}
+
+impl AlertConfig {
+    pub fn new(webhook_url: String) -> Self {
+        AlertConfig {
+            webhook_url,
+            window_secs: 300,
+            error_rate_percent: 20,
+            latency_p95_ms: 30000,
+            daily_spend_usd: 0.0,
+        }
+    }
+}
+
+/// Outcome of one proxied request, as reported to the monitor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Observation {
+    pub status: u16,
+    pub latency_ms: f64,
+    pub cost_usd: f64,
+}
+
+/// Requests seen since the window's alarm was set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub requests: u32,
+    pub errors: u32,
+    pub latencies: Vec<f64>,
+}
+
+impl Window {
+    pub fn add(&mut self, observation: &Observation) {
+        self.requests += 1;
+        if is_upstream_error(observation.status) {
+            self.errors += 1;
+        }
+        self.latencies.push(observation.latency_ms);
+        if self.latencies.len() > MAX_LATENCY_SAMPLES {
+            self.latencies.remove(0);
+        }
+    }
+}
+
+/// Spend so far in one UTC day, and whether it has already been alerted on
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailySpend {
+    pub day: String,
+    pub usd: f64,
+    pub alerted: bool,
+}
+
+/// Rate limits and server errors point at OpenRouter or the model provider;
+/// other client errors are the caller's problem
+fn is_upstream_error(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// Nearest-rank percentile of `values` (0 when empty)
+pub fn percentile(values: &[f64], percent: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// A threshold that was passed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    ErrorRate {
+        percent: f64,
+        errors: u32,
+        requests: u32,
+    },
+    LatencyP95 {
+        p95_ms: f64,
+        requests: u32,
+    },
+    DailySpend {
+        day: String,
+        usd: f64,
+        threshold: f64,
+    },
+    /// A configured model found missing or deprecated by the cron trigger
+    ConfiguredModel(ModelCheck),
+}
+
+impl Alert {
+    pub fn message(&self) -> String {
+        match self {
+            Alert::ErrorRate {
+                percent,
+                errors,
+                requests,
+            } => format!(
+                "Upstream error rate is {percent:.0}% ({errors} of {requests} requests failed)"
+            ),
+            Alert::LatencyP95 { p95_ms, requests } => {
+                format!("p95 latency is {p95_ms:.0}ms over {requests} requests")
+            }
+            Alert::DailySpend {
+                day,
+                usd,
+                threshold,
+            } => format!("Spend on {day} reached ${usd:.2} (threshold ${threshold:.2})"),
+            Alert::ConfiguredModel(check) => match &check.problem {
+                ModelProblem::Unlisted => format!(
+                    "{} is set to {}, which the OpenRouter catalog no longer lists",
+                    check.setting, check.model
+                ),
+                ModelProblem::Deprecated { expires } => format!(
+                    "{} is set to {}, which OpenRouter retires on {expires}",
+                    check.setting, check.model
+                ),
+            },
+        }
+    }
+}
+
+/// Checks a finished window against the error rate and latency thresholds
+pub fn evaluate_window(window: &Window, config: &AlertConfig) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    if window.requests < MIN_WINDOW_REQUESTS {
+        return alerts;
+    }
+
+    let percent = f64::from(window.errors) * 100.0 / f64::from(window.requests);
+    if config.error_rate_percent > 0 && percent >= f64::from(config.error_rate_percent) {
+        alerts.push(Alert::ErrorRate {
+            percent,
+            errors: window.errors,
+            requests: window.requests,
+        });
+    }
+
+    let p95_ms = percentile(&window.latencies, 95.0);
+    if config.latency_p95_ms > 0 && p95_ms >= config.latency_p95_ms as f64 {
+        alerts.push(Alert::LatencyP95 {
+            p95_ms,
+            requests: window.requests,
+        });
+    }
+    alerts
+}
+
+/// Adds spend for `day`, returning an alert the first time the threshold is passed
+pub fn add_spend(spend: &mut DailySpend, day: &str, usd: f64, threshold: f64) -> Option<Alert> {
+    if spend.day != day {
+        *spend = DailySpend {
+            day: day.to_string(),
+            ..DailySpend::default()
+        };
+    }
+    spend.usd += usd;
+
+    if threshold <= 0.0 || spend.alerted || spend.usd < threshold {
+        return None;
+    }
+    spend.alerted = true;
+    Some(Alert::DailySpend {
+        day: spend.day.clone(),
+        usd: spend.usd,
+        threshold,
+    })
+}
+
+/// Builds the webhook body for a list of alerts
+pub fn webhook_payload(webhook_url: &str, alerts: &[Alert]) -> Value {
+    text_payload(webhook_url, &alert_text(alerts))
+}
+
+fn alert_text(alerts: &[Alert]) -> String {
+    alerts
+        .iter()
+        .map(|alert| format!("⚠️ CCR: {}", alert.message()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Builds a webhook body: Discord reads `content`, Slack and others read `text`
+pub fn text_payload(webhook_url: &str, text: &str) -> Value {
+    if webhook_url.contains("discord.com/api/webhooks") {
+        serde_json::json!({ "content": text })
+    } else {
+        serde_json::json!({ "text": text })
+    }
+}
+
+/// Posts a message to a Slack, Discord or generic JSON webhook
+pub async fn post_text(webhook_url: &str, text: &str) -> Result<()> {
+    let response = http::Client::new()
+        .post(webhook_url)
+        .json(&text_payload(webhook_url, text))
+        .send()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Webhook failed: {e}")))?;
+
+    if !response.is_success() {
+        return Err(worker::Error::RustError(format!(
+            "Webhook failed with HTTP {}",
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// Posts alerts to the configured webhook
+pub async fn send(config: &AlertConfig, alerts: &[Alert]) -> Result<()> {
+    post_text(&config.webhook_url, &alert_text(alerts)).await
+}
+
+/// Collects observations into windows and alerts when a threshold is passed
+///
+/// The first observation in a window sets an alarm for its end; the alarm
+/// evaluates the window and starts a new one with the next observation. Spend is
+/// checked as it arrives, and alerted at most once per day.
+#[durable_object]
+pub struct AlertMonitor {
+    state: State,
+    env: Env,
+}
+
+impl DurableObject for AlertMonitor {
+    fn new(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let config = Config::cached(&self.env)?;
+        let Some(config) = &config.alerts else {
+            return Response::empty();
+        };
+        let observation: Observation = req.json().await?;
+        let storage = self.state.storage();
+
+        let mut window: Window = storage.get(WINDOW_KEY).await.unwrap_or_default();
+        window.add(&observation);
+        storage.put(WINDOW_KEY, &window).await?;
+        if storage.get_alarm().await?.is_none() {
+            storage
+                .set_alarm(std::time::Duration::from_secs(config.window_secs))
+                .await?;
+        }
+
+        if observation.cost_usd > 0.0 {
+            let mut spend: DailySpend = storage.get(SPEND_KEY).await.unwrap_or_default();
+            let day = format_date(Date::now().as_millis());
+            let alert = add_spend(
+                &mut spend,
+                &day,
+                observation.cost_usd,
+                config.daily_spend_usd,
+            );
+            storage.put(SPEND_KEY, &spend).await?;
+            if let Some(alert) = alert {
+                send(config, &[alert]).await?;
+            }
+        }
+
+        Response::empty()
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        let storage = self.state.storage();
+        let window: Window = storage.get(WINDOW_KEY).await.unwrap_or_default();
+        storage.delete(WINDOW_KEY).await?;
+
+        let config = Config::cached(&self.env)?;
+        if let Some(config) = &config.alerts {
+            let alerts = evaluate_window(&window, config);
+            if !alerts.is_empty() {
+                send(config, &alerts).await?;
+            }
+        }
+        Response::empty()
+    }
+}
+
+/// Reports a request's outcome to the alert monitor
+pub async fn observe(namespace: &ObjectNamespace, observation: &Observation) -> Result<()> {
+    let stub = namespace.id_from_name(INSTANCE_NAME)?.get_stub()?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(serde_json::to_string(observation)?.into()));
+    let req = Request::new_with_init("https://alert-monitor/observe", &init)?;
+
+    stub.fetch_with_request(req).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(statuses: &[u16], latency_ms: f64) -> Window {
+        let mut window = Window::default();
+        for &status in statuses {
+            window.add(&Observation {
+                status,
+                latency_ms,
+                cost_usd: 0.0,
+            });
+        }
+        window
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 95.0), 95.0);
+        assert_eq!(percentile(&values, 50.0), 50.0);
+        assert_eq!(percentile(&[7.0], 95.0), 7.0);
+        assert_eq!(percentile(&[], 95.0), 0.0);
+    }
+
+    #[test]
+    fn test_evaluate_window_error_rate() {
+        let config = AlertConfig::new("https://hooks.slack.com/services/x".to_string());
+
+        let mut statuses = vec![200; 7];
+        statuses.extend([502, 429, 400]);
+        let alerts = evaluate_window(&window(&statuses, 100.0), &config);
+        assert_eq!(
+            alerts,
+            [Alert::ErrorRate {
+                percent: 20.0,
+                errors: 2,
+                requests: 10
+            }]
+        );
+
+        // Too few requests to judge
+        assert!(evaluate_window(&window(&[500, 500], 100.0), &config).is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_window_latency() {
+        let mut config = AlertConfig::new("https://example.com/hook".to_string());
+        config.latency_p95_ms = 10000;
+        let alerts = evaluate_window(&window(&[200; 20], 12000.0), &config);
+        assert_eq!(
+            alerts,
+            [Alert::LatencyP95 {
+                p95_ms: 12000.0,
+                requests: 20
+            }]
+        );
+
+        config.latency_p95_ms = 0;
+        assert!(evaluate_window(&window(&[200; 20], 12000.0), &config).is_empty());
+    }
+
+    #[test]
+    fn test_add_spend_alerts_once_per_day() {
+        let mut spend = DailySpend::default();
+        assert_eq!(add_spend(&mut spend, "2025-06-01", 4.0, 5.0), None);
+        assert!(add_spend(&mut spend, "2025-06-01", 2.0, 5.0).is_some());
+        assert_eq!(add_spend(&mut spend, "2025-06-01", 2.0, 5.0), None);
+        assert_eq!(spend.usd, 8.0);
+
+        // A new day starts from zero
+        assert_eq!(add_spend(&mut spend, "2025-06-02", 1.0, 5.0), None);
+        assert_eq!(spend.usd, 1.0);
+        assert_eq!(add_spend(&mut spend, "2025-06-02", 100.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_webhook_payload() {
+        let alerts = [Alert::LatencyP95 {
+            p95_ms: 12000.0,
+            requests: 20,
+        }];
+        let slack = webhook_payload("https://hooks.slack.com/services/x", &alerts);
+        assert_eq!(
+            slack["text"],
+            "⚠️ CCR: p95 latency is 12000ms over 20 requests"
+        );
+
+        let discord = webhook_payload("https://discord.com/api/webhooks/1/abc", &alerts);
+        assert!(discord["content"].as_str().unwrap().contains("p95 latency"));
+        assert!(discord.get("text").is_none());
+
+        let deprecated = Alert::ConfiguredModel(ModelCheck {
+            setting: "AUTO_CHEAP_MODEL".to_string(),
+            model: "google/gemini-2.0-flash-001".to_string(),
+            problem: ModelProblem::Deprecated {
+                expires: "2026-02-06".to_string(),
+            },
+        });
+        assert_eq!(
+            deprecated.message(),
+            "AUTO_CHEAP_MODEL is set to google/gemini-2.0-flash-001, which OpenRouter retires on 2026-02-06"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! `anthropic-version` negotiation
+//!
+//! Clients pin the Anthropic API behaviour they were written against with the
+//! `anthropic-version` header. CCR accepts the versions the Anthropic API does,
+//! rejects anything else the way it would, and adapts responses for the older
+//! version so clients see the shapes they expect.
+
+use crate::models::AnthropicResponse;
+use std::str::FromStr;
+
+/// Request header naming the API version
+pub const VERSION_HEADER: &str = "anthropic-version";
+
+/// An `anthropic-version` CCR can serve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApiVersion {
+    /// The first version, whose responses end turns with `stop_sequence`
+    V2023_01_01,
+    /// The current version; also assumed when the header is missing
+    #[default]
+    V2023_06_01,
+}
+
+impl ApiVersion {
+    pub const SUPPORTED: [ApiVersion; 2] = [ApiVersion::V2023_01_01, ApiVersion::V2023_06_01];
+
+    /// Reads the header value; a missing header means the current version
+    pub fn from_header(value: Option<&str>) -> Result<Self, String> {
+        match value.map(str::trim).filter(|value| !value.is_empty()) {
+            Some(value) => value.parse(),
+            None => Ok(ApiVersion::default()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiVersion::V2023_01_01 => "2023-01-01",
+            ApiVersion::V2023_06_01 => "2023-06-01",
+        }
+    }
+
+    /// Whether responses are served as the translation produces them
+    pub fn is_current(self) -> bool {
+        self == ApiVersion::default()
+    }
+
+    /// The `stop_reason` this version reports for a current one
+    pub fn stop_reason(self, stop_reason: &str) -> &str {
+        match (self, stop_reason) {
+            (ApiVersion::V2023_01_01, "end_turn") => "stop_sequence",
+            _ => stop_reason,
+        }
+    }
+
+    /// Rewrites a response for this version
+    pub fn adapt_response(self, response: &mut AnthropicResponse) {
+        if let Some(stop_reason) = &response.stop_reason {
+            response.stop_reason = Some(self.stop_reason(stop_reason).to_string());
+        }
+    }
+
+    /// Rewrites serialized stream events for this version
+    pub fn adapt_events(self, events: String) -> String {
+        if self.is_current() {
+            return events;
+        }
+        events
+            .lines()
+            .map(|line| match line.strip_prefix("data: ") {
+                Some(data) if data.contains("\"message_delta\"") => {
+                    match serde_json::from_str::<serde_json::Value>(data) {
+                        Ok(mut event) => {
+                            if let Some(stop_reason) = event["delta"]["stop_reason"].as_str() {
+                                let adapted = self.stop_reason(stop_reason).to_string();
+                                event["delta"]["stop_reason"] = adapted.into();
+                            }
+                            format!("data: {event}")
+                        }
+                        Err(_) => line.to_string(),
+                    }
+                }
+                _ => line.to_string(),
+            })
+            .map(|line| line + "\n")
+            .collect()
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ApiVersion::SUPPORTED
+            .into_iter()
+            .find(|version| version.as_str() == s.trim())
+            .ok_or_else(|| {
+                let supported: Vec<_> = ApiVersion::SUPPORTED.iter().map(|v| v.as_str()).collect();
+                format!(
+                    "{VERSION_HEADER}: unsupported version '{}'. Supported versions are: {}",
+                    s.trim(),
+                    supported.join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_header() {
+        assert_eq!(ApiVersion::from_header(None), Ok(ApiVersion::V2023_06_01));
+        assert_eq!(
+            ApiVersion::from_header(Some(" 2023-01-01 ")),
+            Ok(ApiVersion::V2023_01_01)
+        );
+        let err = ApiVersion::from_header(Some("2024-01-01")).unwrap_err();
+        assert!(err.contains("'2024-01-01'"), "{err}");
+        assert!(err.contains("2023-01-01, 2023-06-01"), "{err}");
+    }
+
+    #[test]
+    fn test_legacy_stop_reasons() {
+        let mut response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1", "type": "message", "role": "assistant", "content": [],
+            "stop_reason": "end_turn", "stop_sequence": null, "model": "claude-sonnet-4"
+        }))
+        .unwrap();
+        ApiVersion::V2023_06_01.adapt_response(&mut response);
+        assert_eq!(response.stop_reason.as_deref(), Some("end_turn"));
+        ApiVersion::V2023_01_01.adapt_response(&mut response);
+        assert_eq!(response.stop_reason.as_deref(), Some("stop_sequence"));
+        assert_eq!(ApiVersion::V2023_01_01.stop_reason("tool_use"), "tool_use");
+    }
+
+    #[test]
+    fn test_legacy_stream_events() {
+        let events = "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null}}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        let adapted = ApiVersion::V2023_01_01.adapt_events(events.to_string());
+        assert!(
+            adapted.contains("\"stop_reason\":\"stop_sequence\""),
+            "{adapted}"
+        );
+        assert!(adapted.ends_with("data: {\"type\":\"message_stop\"}\n\n"));
+        assert_eq!(
+            ApiVersion::V2023_06_01.adapt_events(events.to_string()),
+            events
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::auth::hash_key;
+use crate::models::{AnthropicRequest, OpenAIRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// Queue that asynchronous requests are sent to; async mode is off while unbound
+pub const ASYNC_QUEUE_BINDING: &str = "ASYNC_QUEUE";
+
+/// KV namespace holding the status and result of each job
+pub const ASYNC_RESULTS_BINDING: &str = "ASYNC_RESULTS";
+
+/// Request header that asks for asynchronous execution (`x-ccr-async: true`)
+pub const ASYNC_HEADER: &str = "x-ccr-async";
+
+/// Path jobs are polled at, followed by the job ID
+pub const POLL_PATH: &str = "/v1/async/";
+
+/// A request waiting in the queue, with everything the consumer needs to run it
+///
+/// The request was already authenticated and admitted, and is translated; the
+/// consumer only forwards it and stores the result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// Ray ID of the request that enqueued the job, for correlating logs
+    pub request_id: String,
+    pub url: String,
+    /// Where the consumer gets the upstream key; keys are never put on the queue
+    pub key_source: KeySource,
+    pub anthropic_request: AnthropicRequest,
+    pub openai_request: OpenAIRequest,
+    /// Budget ledger subject and month the spend is charged to, when the key has a budget
+    pub ledger: Option<(String, String)>,
+}
+
+/// The upstream key a job is sent with, resolved again by the consumer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// The deployment's key, or the `MODEL_KEYS` credential of the model's family
+    Server,
+    /// The upstream key of the virtual key whose hash this is
+    VirtualKey(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Completed,
+    Failed,
+}
+
+/// What `GET /v1/async/{id}` returns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: JobStatus,
+    /// Hash of the caller's rate limit subject; only that caller can poll the job
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub owner: String,
+    /// Unix timestamp (ms) the job was enqueued
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+    /// The Anthropic-format message of a completed job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// The Anthropic-format error body of a failed job, with its HTTP status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_status: Option<u16>,
+}
+
+impl JobRecord {
+    /// A freshly enqueued job owned by `subject`
+    pub fn queued(id: &str, subject: &str, now: u64) -> Self {
+        JobRecord {
+            id: id.to_string(),
+            kind: "async_job".to_string(),
+            status: JobStatus::Queued,
+            owner: hash_key(subject),
+            created_at: now,
+            completed_at: None,
+            response: None,
+            error: None,
+            error_status: None,
+        }
+    }
+
+    /// Records the job's response
+    pub fn complete(self, response: Value, now: u64) -> Self {
+        JobRecord {
+            status: JobStatus::Completed,
+            completed_at: Some(now),
+            response: Some(response),
+            ..self
+        }
+    }
+
+    /// Records the job's failure as an Anthropic error body
+    pub fn fail(self, status: u16, error: Value, now: u64) -> Self {
+        JobRecord {
+            status: JobStatus::Failed,
+            completed_at: Some(now),
+            error: Some(error),
+            error_status: Some(status),
+            ..self
+        }
+    }
+
+    /// Whether `subject` enqueued the job
+    pub fn is_owned_by(&self, subject: &str) -> bool {
+        self.owner == hash_key(subject)
+    }
+
+    /// The record as shown to its owner
+    pub fn public(self) -> Self {
+        JobRecord {
+            owner: String::new(),
+            ..self
+        }
+    }
+}
+
+/// A job ID that can't be guessed from another caller's IDs
+pub fn job_id(subject: &str, request_id: &str, now: u64) -> String {
+    format!(
+        "job_{}",
+        &hash_key(&format!("{subject}\n{request_id}\n{now}"))[..32]
+    )
+}
+
+/// Whether a job ID has the shape `job_id` produces
+pub fn is_valid_id(id: &str) -> bool {
+    id.strip_prefix("job_")
+        .is_some_and(|hex| hex.len() == 32 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn storage_key(id: &str) -> String {
+    format!("async:{id}")
+}
+
+/// Looks up a job's record
+pub async fn lookup(kv: &KvStore, id: &str) -> Result<Option<JobRecord>> {
+    kv.get(&storage_key(id))
+        .json::<JobRecord>()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to read async job: {e}")))
+}
+
+/// Stores a job's record for `ttl_secs`
+pub async fn store(kv: &KvStore, record: &JobRecord, ttl_secs: u64) -> Result<()> {
+    kv.put(&storage_key(&record.id), serde_json::to_string(record)?)?
+        .expiration_ttl(ttl_secs)
+        .execute()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to store async job: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_job_ids_are_unique_and_valid() {
+        let id = job_id("key:abc", "8f1c2d", 1_700_000_000_000);
+        assert!(is_valid_id(&id), "{id}");
+        assert_ne!(id, job_id("key:abc", "8f1c2e", 1_700_000_000_000));
+        assert!(!is_valid_id("job_123"));
+        assert!(!is_valid_id("../../admin"));
+    }
+
+    #[test]
+    fn test_record_lifecycle() {
+        let queued = JobRecord::queued("job_1", "key:abc", 1000);
+        assert_eq!(queued.status, JobStatus::Queued);
+        assert!(queued.is_owned_by("key:abc"));
+        assert!(!queued.is_owned_by("key:def"));
+
+        let completed = queued.clone().complete(json!({"type": "message"}), 5000);
+        assert_eq!(completed.status, JobStatus::Completed);
+        assert_eq!(completed.completed_at, Some(5000));
+
+        let failed = queued.fail(402, json!({"type": "error"}), 5000);
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error_status, Some(402));
+    }
+
+    #[test]
+    fn test_public_record_hides_owner() {
+        let record = JobRecord::queued("job_1", "key:abc", 1000).public();
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json,
+            json!({"id": "job_1", "type": "async_job", "status": "queued", "created_at": 1000})
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Large attachments handed to the upstream by URL rather than inline
+//!
+//! Base64 images and documents grow a request by a third and count against the
+//! upstream's size limits each time a conversation is resent. With an R2 bucket
+//! bound as `ATTACHMENTS` and `ATTACHMENT_SIGNING_KEY` set, the OpenAI-format
+//! requests of `/v1/chat/completions` and the Gemini endpoint have their large
+//! `data:` URLs uploaded once, under the hash of their bytes, and replaced with
+//! a short-lived signed link to `GET /v1/attachments/{id}` on this Worker.
+//!
+//! `/v1/messages` has nothing to offload: its translation sends the upstream the
+//! text of each message, so image and document blocks never go out inline (see
+//! `IMAGE_FALLBACK` for what a text-only model is told of them instead).
+
+use crate::auth::constant_time_eq;
+use crate::log::Logger;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac, NewMac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use worker::{Bucket, Date, Env, HttpMetadata, Response, Result};
+
+/// R2 binding that stores offloaded attachments
+pub const ATTACHMENTS_BINDING: &str = "ATTACHMENTS";
+
+/// Path attachments are served under
+pub const ATTACHMENT_PATH: &str = "/v1/attachments/";
+
+/// Offloading settings, present when `ATTACHMENT_SIGNING_KEY` is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachmentConfig {
+    /// Secret the links are signed with
+    pub signing_key: String,
+    /// Smallest decoded attachment offloaded (`ATTACHMENT_OFFLOAD_BYTES`)
+    pub min_bytes: usize,
+    /// Seconds a link stays valid (`ATTACHMENT_URL_TTL_SECS`)
+    pub url_ttl_secs: u64,
+}
+
+impl AttachmentConfig {
+    pub const DEFAULT_MIN_BYTES: usize = 1024 * 1024;
+    pub const DEFAULT_URL_TTL_SECS: u64 = 600;
+}
+
+/// An inline attachment decoded from a `data:` URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataUrl {
+    pub media_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Decodes a base64 `data:` URL; other URLs and encodings give `None`
+pub fn parse_data_url(url: &str) -> Option<DataUrl> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let media_type = header.strip_suffix(";base64")?;
+    Some(DataUrl {
+        media_type: if media_type.is_empty() {
+            "application/octet-stream".to_string()
+        } else {
+            media_type.to_string()
+        },
+        bytes: STANDARD.decode(data.trim()).ok()?,
+    })
+}
+
+/// ID of an attachment: the hex SHA-256 of its bytes, so resent attachments are stored once
+pub fn attachment_id(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn storage_key(id: &str) -> String {
+    format!("attachments/{id}")
+}
+
+/// Hex HMAC-SHA256 over the attachment ID and expiry
+pub fn signature(key: &str, id: &str, expires: u64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{id}:{expires}").as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The signed link to attachment `id`, valid until `expires` (Unix seconds)
+pub fn signed_url(origin: &str, key: &str, id: &str, expires: u64) -> String {
+    format!(
+        "{origin}{ATTACHMENT_PATH}{id}?expires={expires}&signature={}",
+        signature(key, id, expires)
+    )
+}
+
+/// Whether a link's signature is valid and it hasn't expired at `now` (Unix seconds)
+pub fn verify(key: &str, id: &str, expires: u64, signature: &str, now: u64) -> bool {
+    now <= expires && constant_time_eq(&self::signature(key, id, expires), signature)
+}
+
+/// The `data:` URLs in an OpenAI-format request that can be sent by URL: image
+/// parts and the data of file parts
+fn attachment_urls(request: &mut Value) -> Vec<&mut Value> {
+    let mut urls = Vec::new();
+    let Some(messages) = request["messages"].as_array_mut() else {
+        return urls;
+    };
+    for message in messages {
+        let Some(parts) = message["content"].as_array_mut() else {
+            continue;
+        };
+        for part in parts {
+            let url = match part["type"].as_str() {
+                Some("image_url") => part["image_url"].get_mut("url"),
+                Some("file") => part["file"].get_mut("file_data"),
+                _ => None,
+            };
+            if let Some(url) =
+                url.filter(|url| url.as_str().is_some_and(|u| u.starts_with("data:")))
+            {
+                urls.push(url);
+            }
+        }
+    }
+    urls
+}
+
+/// Uploads the request's large attachments and replaces them with signed links
+///
+/// `origin` is the Worker's own origin, which the upstream fetches the links from.
+/// Attachments that fail to upload stay inline. Returns how many were offloaded.
+pub async fn offload(
+    bucket: &Bucket,
+    config: &AttachmentConfig,
+    origin: &str,
+    now: u64,
+    request: &mut Value,
+    log: &Logger,
+) -> usize {
+    let mut offloaded = 0;
+    for url in attachment_urls(request) {
+        let Some(attachment) = url.as_str().and_then(parse_data_url) else {
+            continue;
+        };
+        if attachment.bytes.len() < config.min_bytes {
+            continue;
+        }
+        let id = attachment_id(&attachment.bytes);
+        let size = attachment.bytes.len();
+        let upload = bucket
+            .put(storage_key(&id), attachment.bytes)
+            .http_metadata(HttpMetadata {
+                content_type: Some(attachment.media_type),
+                ..HttpMetadata::default()
+            })
+            .execute()
+            .await;
+        match upload {
+            Ok(_) => {
+                let expires = now + config.url_ttl_secs;
+                *url = signed_url(origin, &config.signing_key, &id, expires).into();
+                offloaded += 1;
+            }
+            Err(e) => log.warn(
+                "attachment upload failed",
+                &[("bytes", size.into()), ("error", e.to_string().into())],
+            ),
+        }
+    }
+    offloaded
+}
+
+/// Offloads the request's large attachments when the deployment is set up for it
+pub async fn offload_request(
+    env: &Env,
+    config: Option<&AttachmentConfig>,
+    origin: &str,
+    request: &mut Value,
+    log: &Logger,
+) -> usize {
+    let (Some(config), Ok(bucket)) = (config, env.bucket(ATTACHMENTS_BINDING)) else {
+        return 0;
+    };
+    let now = Date::now().as_millis() / 1000;
+    let offloaded = offload(&bucket, config, origin, now, request, log).await;
+    if offloaded > 0 {
+        log.debug("attachments offloaded", &[("count", offloaded.into())]);
+    }
+    offloaded
+}
+
+/// Serves `GET /v1/attachments/{id}?expires=..&signature=..`
+///
+/// The signature is the only credential, since the upstream fetching the link
+/// has no key for this deployment. Invalid and expired links get a 404.
+pub async fn serve(
+    bucket: &Bucket,
+    config: &AttachmentConfig,
+    id: &str,
+    query: &[(String, String)],
+    now: u64,
+) -> Result<Response> {
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let expires = param("expires").and_then(|expires| expires.parse().ok());
+    let valid = match (expires, param("signature")) {
+        (Some(expires), Some(signature)) => {
+            verify(&config.signing_key, id, expires, signature, now)
+        }
+        _ => false,
+    };
+    if !valid {
+        return Response::error("Not Found", 404);
+    }
+
+    let Some(object) = bucket.get(storage_key(id)).execute().await? else {
+        return Response::error("Not Found", 404);
+    };
+    let content_type = object
+        .http_metadata()
+        .content_type
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let Some(body) = object.body() else {
+        return Response::error("Not Found", 404);
+    };
+    let mut response = Response::from_bytes(body.bytes().await?)?;
+    response.headers_mut().set("Content-Type", &content_type)?;
+    response
+        .headers_mut()
+        .set("Cache-Control", "private, max-age=60")?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_data_url() {
+        let attachment = parse_data_url("data:image/png;base64,aGVsbG8=").unwrap();
+        assert_eq!(attachment.media_type, "image/png");
+        assert_eq!(attachment.bytes, b"hello");
+        assert_eq!(parse_data_url("https://example.com/a.png"), None);
+        assert_eq!(parse_data_url("data:text/plain,hello"), None);
+        assert_eq!(parse_data_url("data:image/png;base64,@@@"), None);
+    }
+
+    #[test]
+    fn test_signed_urls() {
+        let id = attachment_id(b"hello");
+        let url = signed_url("https://ccr.example.com", "secret", &id, 1_000);
+        assert!(url.starts_with(&format!(
+            "https://ccr.example.com/v1/attachments/{id}?expires=1000&signature="
+        )));
+        let signature = url.rsplit_once('=').unwrap().1;
+        assert!(verify("secret", &id, 1_000, signature, 1_000));
+        assert!(!verify("secret", &id, 1_000, signature, 1_001));
+        assert!(!verify("other", &id, 1_000, signature, 999));
+        assert!(!verify("secret", &id, 2_000, signature, 999));
+        assert!(!verify("secret", "0000", 1_000, signature, 999));
+    }
+
+    #[test]
+    fn test_attachment_urls() {
+        let mut request = json!({
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Compare these"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+                    {"type": "file", "file": {"filename": "spec.pdf", "file_data": "data:application/pdf;base64,AAAA"}}
+                ]},
+                {"role": "assistant", "content": "Sure"}
+            ]
+        });
+        let urls = attachment_urls(&mut request);
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[1], "data:application/pdf;base64,AAAA");
+    }
+}
@@ -0,0 +1,331 @@
+use crate::http;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Mutex;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use worker::{Date, Result};
+
+/// Header Cloudflare Access puts the signed identity token in
+pub const CF_ACCESS_HEADER: &str = "Cf-Access-Jwt-Assertion";
+
+/// Clock skew tolerated when checking `exp` and `nbf`
+const LEEWAY_SECS: u64 = 60;
+
+/// How long fetched signing keys are reused before the JWKS URL is fetched again
+const JWKS_TTL_MS: u64 = 60 * 60 * 1000;
+
+/// Signing keys from the last JWKS fetch, shared by requests in this isolate
+static JWKS_CACHE: Mutex<Option<CachedJwks>> = Mutex::new(None);
+
+struct CachedJwks {
+    url: String,
+    keys: Vec<Value>,
+    fetched_at_ms: u64,
+}
+
+/// Where to find and how to check identity tokens
+///
+/// For Cloudflare Access, `jwks_url` is `https://<team>.cloudflareaccess.com/cdn-cgi/access/certs`
+/// and `audience` is the application's AUD tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtConfig {
+    pub jwks_url: String,
+    /// Request header carrying the token
+    pub header: String,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+/// Claims CCR relies on; everything else in the token is ignored
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Claims {
+    /// Stable user identifier, used to key per-user limits
+    pub sub: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub iss: Option<String>,
+    /// Either a single audience or a list of them
+    #[serde(default)]
+    pub aud: Option<Value>,
+    pub exp: u64,
+    #[serde(default)]
+    pub nbf: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// A token split into its decoded parts
+#[derive(Debug)]
+struct DecodedToken {
+    header: Header,
+    claims: Claims,
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+fn decode(token: &str) -> std::result::Result<DecodedToken, String> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err("token must have three parts".to_string());
+    };
+
+    let decode_part = |part: &str| {
+        URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|e| format!("invalid base64url: {e}"))
+    };
+
+    Ok(DecodedToken {
+        header: serde_json::from_slice(&decode_part(header)?)
+            .map_err(|e| format!("invalid header: {e}"))?,
+        claims: serde_json::from_slice(&decode_part(payload)?)
+            .map_err(|e| format!("invalid claims: {e}"))?,
+        signing_input: format!("{header}.{payload}"),
+        signature: decode_part(signature)?,
+    })
+}
+
+/// Checks expiry, not-before, issuer and audience against the configuration
+pub fn validate_claims(
+    claims: &Claims,
+    config: &JwtConfig,
+    now_secs: u64,
+) -> std::result::Result<(), String> {
+    if claims.exp + LEEWAY_SECS < now_secs {
+        return Err("token has expired".to_string());
+    }
+    if claims.nbf.is_some_and(|nbf| nbf > now_secs + LEEWAY_SECS) {
+        return Err("token is not valid yet".to_string());
+    }
+    if let Some(issuer) = &config.issuer {
+        if claims.iss.as_ref() != Some(issuer) {
+            return Err("unexpected issuer".to_string());
+        }
+    }
+    if let Some(audience) = &config.audience {
+        let matches = match &claims.aud {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !matches {
+            return Err("unexpected audience".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Verifies an RS256 token's signature and claims, returning the claims
+pub async fn verify(token: &str, config: &JwtConfig, client: &http::Client) -> Result<Claims> {
+    let rejected = |reason: String| worker::Error::RustError(format!("Invalid JWT: {reason}"));
+
+    let token = decode(token).map_err(rejected)?;
+    if token.header.alg != "RS256" {
+        return Err(rejected(format!(
+            "unsupported algorithm {}",
+            token.header.alg
+        )));
+    }
+
+    let kid = token.header.kid.as_deref();
+    let jwk = match find_key(&signing_keys(client, &config.jwks_url, false).await?, kid) {
+        Some(jwk) => jwk,
+        // An unknown key ID usually means the keys were rotated since the last fetch
+        None => find_key(&signing_keys(client, &config.jwks_url, true).await?, kid)
+            .ok_or_else(|| rejected("unknown signing key".to_string()))?,
+    };
+
+    if !verify_rs256(&jwk, token.signing_input.as_bytes(), &token.signature).await? {
+        return Err(rejected("bad signature".to_string()));
+    }
+
+    validate_claims(&token.claims, config, Date::now().as_millis() / 1000).map_err(rejected)?;
+    Ok(token.claims)
+}
+
+fn find_key(keys: &[Value], kid: Option<&str>) -> Option<Value> {
+    keys.iter()
+        .find(|key| kid.is_none() || key["kid"].as_str() == kid)
+        .cloned()
+}
+
+/// Returns the JWKS signing keys, reusing the isolate's copy while it is fresh
+async fn signing_keys(
+    client: &http::Client,
+    jwks_url: &str,
+    force_refresh: bool,
+) -> Result<Vec<Value>> {
+    let now = Date::now().as_millis();
+    if !force_refresh {
+        if let Some(cached) = JWKS_CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            if cached.url == jwks_url && now.saturating_sub(cached.fetched_at_ms) < JWKS_TTL_MS {
+                return Ok(cached.keys.clone());
+            }
+        }
+    }
+
+    let jwks: Value = client
+        .get(jwks_url)
+        .send()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to fetch JWKS: {e}")))?
+        .json()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to parse JWKS: {e}")))?;
+    let keys = jwks["keys"].as_array().cloned().unwrap_or_default();
+
+    *JWKS_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = Some(CachedJwks {
+        url: jwks_url.to_string(),
+        keys: keys.clone(),
+        fetched_at_ms: now,
+    });
+    Ok(keys)
+}
+
+/// Verifies an RSASSA-PKCS1-v1_5 SHA-256 signature with the runtime's WebCrypto
+async fn verify_rs256(jwk: &Value, data: &[u8], signature: &[u8]) -> Result<bool> {
+    let js_error = |e: wasm_bindgen::JsValue| worker::Error::RustError(format!("WebCrypto: {e:?}"));
+
+    let subtle = js_sys::global()
+        .unchecked_into::<web_sys::WorkerGlobalScope>()
+        .crypto()
+        .map_err(js_error)?
+        .subtle();
+
+    let algorithm = js_sys::Object::new();
+    js_sys::Reflect::set(&algorithm, &"name".into(), &"RSASSA-PKCS1-v1_5".into())
+        .map_err(js_error)?;
+    js_sys::Reflect::set(&algorithm, &"hash".into(), &"SHA-256".into()).map_err(js_error)?;
+
+    let key_data = js_sys::JSON::parse(&jwk.to_string()).map_err(js_error)?;
+    let usages = js_sys::Array::of1(&"verify".into());
+    let import = subtle
+        .import_key_with_object("jwk", key_data.unchecked_ref(), &algorithm, false, &usages)
+        .map_err(js_error)?;
+    let key = JsFuture::from(import).await.map_err(js_error)?;
+
+    let verification = subtle
+        .verify_with_object_and_u8_array_and_u8_array(
+            &algorithm,
+            key.unchecked_ref(),
+            signature,
+            data,
+        )
+        .map_err(js_error)?;
+    let valid = JsFuture::from(verification).await.map_err(js_error)?;
+    Ok(valid.as_bool().unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            jwks_url: "https://team.cloudflareaccess.com/cdn-cgi/access/certs".to_string(),
+            header: CF_ACCESS_HEADER.to_string(),
+            issuer: Some("https://team.cloudflareaccess.com".to_string()),
+            audience: Some("app-aud-tag".to_string()),
+        }
+    }
+
+    fn claims(aud: Value) -> Claims {
+        Claims {
+            sub: "user-123".to_string(),
+            email: Some("alice@example.com".to_string()),
+            iss: Some("https://team.cloudflareaccess.com".to_string()),
+            aud: Some(aud),
+            exp: NOW + 600,
+            nbf: Some(NOW - 10),
+        }
+    }
+
+    fn encode(value: &Value) -> String {
+        URL_SAFE_NO_PAD.encode(value.to_string())
+    }
+
+    #[test]
+    fn test_decode() {
+        let header = serde_json::json!({"alg": "RS256", "kid": "key-1"});
+        let payload = serde_json::json!({"sub": "user-123", "exp": NOW});
+        let token = format!("{}.{}.c2ln", encode(&header), encode(&payload));
+
+        let decoded = decode(&token).unwrap();
+        assert_eq!(decoded.header.alg, "RS256");
+        assert_eq!(decoded.header.kid.as_deref(), Some("key-1"));
+        assert_eq!(decoded.claims.sub, "user-123");
+        assert_eq!(decoded.signature, b"sig");
+        assert!(token.starts_with(&decoded.signing_input));
+
+        assert!(decode("only.two").is_err());
+        assert!(decode("a.b.c.d").is_err());
+        assert!(decode("!!.??.sig").is_err());
+    }
+
+    #[test]
+    fn test_validate_claims_accepts_valid_token() {
+        let config = config();
+        assert!(validate_claims(&claims("app-aud-tag".into()), &config, NOW).is_ok());
+        assert!(validate_claims(
+            &claims(serde_json::json!(["other", "app-aud-tag"])),
+            &config,
+            NOW
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_validate_claims_rejects() {
+        let config = config();
+
+        let expired = Claims {
+            exp: NOW - LEEWAY_SECS - 1,
+            ..claims("app-aud-tag".into())
+        };
+        assert_eq!(
+            validate_claims(&expired, &config, NOW).unwrap_err(),
+            "token has expired"
+        );
+
+        let early = Claims {
+            nbf: Some(NOW + LEEWAY_SECS + 1),
+            ..claims("app-aud-tag".into())
+        };
+        assert!(validate_claims(&early, &config, NOW).is_err());
+
+        let wrong_issuer = Claims {
+            iss: Some("https://evil.example.com".to_string()),
+            ..claims("app-aud-tag".into())
+        };
+        assert!(validate_claims(&wrong_issuer, &config, NOW).is_err());
+
+        assert!(validate_claims(&claims("other-app".into()), &config, NOW).is_err());
+    }
+
+    #[test]
+    fn test_find_key() {
+        let keys = vec![
+            serde_json::json!({"kid": "a", "kty": "RSA"}),
+            serde_json::json!({"kid": "b", "kty": "RSA"}),
+        ];
+        assert_eq!(find_key(&keys, Some("b")).unwrap()["kid"], "b");
+        assert!(find_key(&keys, Some("c")).is_none());
+        assert_eq!(find_key(&keys, None).unwrap()["kid"], "a");
+    }
+}
@@ -0,0 +1,205 @@
+pub mod jwt;
+pub mod virtual_keys;
+
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use worker::Result;
+
+/// Header carrying the deployment's shared `CCR_ACCESS_TOKEN`
+///
+/// Claude Code can send it with `ANTHROPIC_CUSTOM_HEADERS="x-ccr-access-token: ..."`.
+pub const ACCESS_TOKEN_HEADER: &str = "x-ccr-access-token";
+
+/// Extracts the API key sent by the client
+///
+/// Claude Code sends the key either as `x-api-key` (ANTHROPIC_API_KEY) or as a
+/// bearer token (ANTHROPIC_AUTH_TOKEN). Blank values are treated as absent.
+pub fn client_key(
+    x_api_key: Option<String>,
+    authorization: Option<String>,
+) -> Result<Option<String>> {
+    if let Some(key) = x_api_key.filter(|key| !key.trim().is_empty()) {
+        return Ok(Some(key));
+    }
+
+    match authorization.filter(|value| !value.trim().is_empty()) {
+        Some(value) => value
+            .strip_prefix("Bearer ")
+            .map(|token| Some(token.to_string()))
+            .ok_or_else(|| {
+                worker::Error::RustError("Invalid Authorization header format".to_string())
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Chooses the key used to call the upstream API
+///
+/// The client's key is used when present, falling back to the operator's
+/// `OPENROUTER_API_KEY` secret. With `force_server_key`, the server key always wins
+/// so team members never need the upstream key on their laptops.
+pub fn resolve_upstream_key(
+    client_key: Option<String>,
+    server_key: Option<&str>,
+    force_server_key: bool,
+) -> Option<String> {
+    match (client_key, server_key) {
+        (_, Some(server_key)) if force_server_key => Some(server_key.to_string()),
+        (Some(client_key), _) => Some(client_key),
+        (None, server_key) => server_key.map(str::to_string),
+    }
+}
+
+/// Decides whether a caller may use this deployment
+///
+/// With neither `CCR_ACCESS_TOKEN` nor `ALLOWED_KEY_HASHES` configured the proxy is
+/// open. Otherwise the caller must present the access token (in its header or as
+/// the API key itself) or an API key whose SHA-256 hash is allowlisted.
+pub fn is_authorized(
+    access_token: Option<&str>,
+    allowed_key_hashes: &HashSet<String>,
+    presented_token: Option<&str>,
+    client_key: Option<&str>,
+) -> bool {
+    if access_token.is_none() && allowed_key_hashes.is_empty() {
+        return true;
+    }
+
+    let token_matches = |candidate: Option<&str>| match (access_token, candidate) {
+        (Some(expected), Some(candidate)) => constant_time_eq(expected, candidate),
+        _ => false,
+    };
+
+    token_matches(presented_token)
+        || token_matches(client_key)
+        || client_key.is_some_and(|key| allowed_key_hashes.contains(&hash_key(key)))
+}
+
+/// Compares secrets without short-circuiting on the first differing byte
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Hashes an API key into a lowercase hex SHA-256 digest
+///
+/// Keys are only ever stored or used as identifiers in hashed form.
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_key() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_is_authorized_open_by_default() {
+        assert!(is_authorized(None, &HashSet::new(), None, None));
+        assert!(is_authorized(
+            None,
+            &HashSet::new(),
+            None,
+            Some("sk-or-any")
+        ));
+    }
+
+    #[test]
+    fn test_is_authorized_access_token() {
+        let none = HashSet::new();
+        assert!(is_authorized(Some("secret"), &none, Some("secret"), None));
+        assert!(is_authorized(Some("secret"), &none, None, Some("secret")));
+        assert!(!is_authorized(Some("secret"), &none, Some("guess"), None));
+        assert!(!is_authorized(
+            Some("secret"),
+            &none,
+            None,
+            Some("sk-or-any")
+        ));
+        assert!(!is_authorized(Some("secret"), &none, None, None));
+    }
+
+    #[test]
+    fn test_is_authorized_key_hashes() {
+        let allowed = HashSet::from([hash_key("sk-or-team")]);
+        assert!(is_authorized(None, &allowed, None, Some("sk-or-team")));
+        assert!(!is_authorized(None, &allowed, None, Some("sk-or-other")));
+        assert!(!is_authorized(None, &allowed, None, None));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[test]
+    fn test_client_key_prefers_x_api_key() {
+        let key = client_key(
+            Some("sk-or-x".to_string()),
+            Some("Bearer sk-or-bearer".to_string()),
+        )
+        .unwrap();
+        assert_eq!(key.as_deref(), Some("sk-or-x"));
+    }
+
+    #[test]
+    fn test_client_key_from_bearer() {
+        let key = client_key(None, Some("Bearer sk-or-bearer".to_string())).unwrap();
+        assert_eq!(key.as_deref(), Some("sk-or-bearer"));
+
+        let key = client_key(
+            Some("".to_string()),
+            Some("Bearer sk-or-bearer".to_string()),
+        );
+        assert_eq!(key.unwrap().as_deref(), Some("sk-or-bearer"));
+    }
+
+    #[test]
+    fn test_client_key_missing_or_invalid() {
+        assert_eq!(client_key(None, None).unwrap(), None);
+        assert!(client_key(None, Some("Basic abc".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_resolve_upstream_key() {
+        let client = || Some("client-key".to_string());
+
+        assert_eq!(
+            resolve_upstream_key(client(), None, false).as_deref(),
+            Some("client-key")
+        );
+        assert_eq!(
+            resolve_upstream_key(client(), Some("server-key"), false).as_deref(),
+            Some("client-key")
+        );
+        assert_eq!(
+            resolve_upstream_key(None, Some("server-key"), false).as_deref(),
+            Some("server-key")
+        );
+        assert_eq!(
+            resolve_upstream_key(client(), Some("server-key"), true).as_deref(),
+            Some("server-key")
+        );
+        assert_eq!(
+            resolve_upstream_key(client(), None, true).as_deref(),
+            Some("client-key")
+        );
+        assert_eq!(resolve_upstream_key(None, None, false), None);
+    }
+}
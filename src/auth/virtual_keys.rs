@@ -0,0 +1,164 @@
+use super::hash_key;
+use serde::{Deserialize, Serialize};
+use worker::kv::KvStore;
+use worker::Result;
+
+/// KV namespace binding holding virtual key records
+pub const VIRTUAL_KEYS_BINDING: &str = "VIRTUAL_KEYS";
+
+/// Prefix that marks a client key as CCR-issued rather than an upstream key
+pub const VIRTUAL_KEY_PREFIX: &str = "ccr-";
+
+/// A CCR-issued key and the settings that apply to requests made with it
+///
+/// Records are stored as JSON under `vk:<sha256 hex of the key>`, so the plaintext
+/// key never lives in KV:
+///
+/// ```json
+/// {"name": "alice", "upstream_key": "sk-or-...", "allowed_models": ["anthropic/*"],
+///  "max_tokens_cap": 8192, "monthly_budget_usd": 50.0}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VirtualKey {
+    /// Human-readable owner of the key, used in logs
+    pub name: String,
+    /// OpenRouter key used upstream; falls back to `OPENROUTER_API_KEY` when absent
+    #[serde(default)]
+    pub upstream_key: Option<String>,
+    /// Upstream model IDs this key may use; a trailing `*` matches a prefix.
+    /// An empty list allows every model.
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Upper bound applied to `max_tokens` on every request
+    #[serde(default)]
+    pub max_tokens_cap: Option<u32>,
+    /// Monthly spend limit in USD
+    #[serde(default)]
+    pub monthly_budget_usd: Option<f64>,
+    /// Tenant profile whose policy applies to this key (see [`crate::profiles`])
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Requests per minute, overriding `RATE_LIMIT_KEY_RPM`
+    #[serde(default)]
+    pub rpm: Option<u32>,
+    /// Estimated prompt tokens per minute, overriding `RATE_LIMIT_KEY_TPM`
+    #[serde(default)]
+    pub tpm: Option<u32>,
+    /// Requests in flight at once, overriding `RATE_LIMIT_KEY_CONCURRENCY`
+    #[serde(default)]
+    pub concurrency: Option<u32>,
+    /// Revoked keys are kept for auditing but rejected
+    #[serde(default)]
+    pub disabled: bool,
+    /// Saves every conversation's transcript for export, as `x-ccr-transcript: true` does
+    #[serde(default)]
+    pub save_transcripts: bool,
+}
+
+impl VirtualKey {
+    /// Returns true if this key may call the given upstream model
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty()
+            || self
+                .allowed_models
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => pattern == model,
+                })
+    }
+
+    /// Clamps the requested `max_tokens` to the key's cap
+    ///
+    /// A request without `max_tokens` gets the cap itself so the limit always applies.
+    pub fn cap_max_tokens(&self, requested: Option<u32>) -> Option<u32> {
+        match (requested, self.max_tokens_cap) {
+            (Some(requested), Some(cap)) => Some(requested.min(cap)),
+            (None, cap) => cap,
+            (requested, None) => requested,
+        }
+    }
+}
+
+/// Returns true if the client key was issued by CCR
+pub fn is_virtual_key(key: &str) -> bool {
+    key.starts_with(VIRTUAL_KEY_PREFIX)
+}
+
+/// KV key under which the record for a virtual key is stored
+pub fn storage_key(key: &str) -> String {
+    format!("vk:{}", hash_key(key))
+}
+
+/// Loads the record for a virtual key, if one exists
+pub async fn lookup(kv: &KvStore, key: &str) -> Result<Option<VirtualKey>> {
+    lookup_hash(kv, &hash_key(key)).await
+}
+
+/// Loads the record for the virtual key with SHA-256 `key_hash`, if one exists
+pub async fn lookup_hash(kv: &KvStore, key_hash: &str) -> Result<Option<VirtualKey>> {
+    kv.get(&format!("vk:{key_hash}"))
+        .json::<VirtualKey>()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to read virtual key: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_key() {
+        assert_eq!(
+            storage_key("abc"),
+            "vk:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_is_virtual_key() {
+        assert!(is_virtual_key("ccr-team-alice"));
+        assert!(!is_virtual_key("sk-or-v1-abc"));
+    }
+
+    #[test]
+    fn test_deserialize_minimal_record() {
+        let key: VirtualKey = serde_json::from_str(r#"{"name": "alice"}"#).unwrap();
+        assert_eq!(key.name, "alice");
+        assert_eq!(key.upstream_key, None);
+        assert!(key.allowed_models.is_empty());
+        assert!(!key.disabled);
+    }
+
+    #[test]
+    fn test_allows_model() {
+        let key = VirtualKey {
+            allowed_models: vec![
+                "anthropic/*".to_string(),
+                "google/gemini-2.5-flash".to_string(),
+            ],
+            ..VirtualKey::default()
+        };
+        assert!(key.allows_model("anthropic/claude-sonnet-4"));
+        assert!(key.allows_model("google/gemini-2.5-flash"));
+        assert!(!key.allows_model("google/gemini-2.5-pro"));
+        assert!(!key.allows_model("openai/gpt-4o"));
+
+        assert!(VirtualKey::default().allows_model("openai/gpt-4o"));
+    }
+
+    #[test]
+    fn test_cap_max_tokens() {
+        let capped = VirtualKey {
+            max_tokens_cap: Some(1000),
+            ..VirtualKey::default()
+        };
+        assert_eq!(capped.cap_max_tokens(Some(4096)), Some(1000));
+        assert_eq!(capped.cap_max_tokens(Some(500)), Some(500));
+        assert_eq!(capped.cap_max_tokens(None), Some(1000));
+
+        let uncapped = VirtualKey::default();
+        assert_eq!(uncapped.cap_max_tokens(Some(4096)), Some(4096));
+        assert_eq!(uncapped.cap_max_tokens(None), None);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::models::AnthropicRequest;
+use serde_json::Value;
+
+/// Model name that opts a request into heuristic model selection
+pub const AUTO_MODEL: &str = "auto";
+
+/// Thresholds and targets for `model: "auto"` selection
+#[derive(Debug, Clone)]
+pub struct AutoModelConfig {
+    /// Model used for short, simple requests
+    pub cheap_model: String,
+    /// Model used once any heuristic flags the request as demanding
+    pub strong_model: String,
+    /// Prompts longer than this many characters go to the strong model
+    pub max_cheap_prompt_chars: usize,
+    /// Requests with more tools than this go to the strong model
+    pub max_cheap_tools: usize,
+    /// Whether fenced code blocks in the prompt force the strong model
+    pub code_requires_strong: bool,
+}
+
+impl Default for AutoModelConfig {
+    fn default() -> Self {
+        AutoModelConfig {
+            cheap_model: "google/gemini-2.5-flash".to_string(),
+            strong_model: "anthropic/claude-sonnet-4".to_string(),
+            max_cheap_prompt_chars: 4000,
+            max_cheap_tools: 0,
+            code_requires_strong: true,
+        }
+    }
+}
+
+/// Which side of the cheap/strong split a request landed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Cheap,
+    Strong,
+}
+
+/// The outcome of auto selection, including why the tier was chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoSelection {
+    pub tier: Tier,
+    pub model: String,
+    pub reason: &'static str,
+}
+
+/// Returns true if the requested model asks CCR to pick one
+pub fn is_auto(model: &str) -> bool {
+    model.eq_ignore_ascii_case(AUTO_MODEL)
+}
+
+/// Picks between the cheap and strong model based on request heuristics
+///
+/// The request goes to the strong model if the prompt is long, contains fenced code
+/// blocks, or carries more tools than the configured threshold.
+pub fn select(req: &AnthropicRequest, config: &AutoModelConfig) -> AutoSelection {
+    let tool_count = req.tools.as_ref().map_or(0, Vec::len);
+    let mut prompt_chars = 0;
+    let mut has_code = false;
+
+    let texts = req.system.iter().chain(req.messages.iter());
+    for value in texts {
+        visit_text(value, &mut |text| {
+            prompt_chars += text.chars().count();
+            has_code |= text.contains("```");
+        });
+    }
+
+    let reason = if prompt_chars > config.max_cheap_prompt_chars {
+        Some("long_prompt")
+    } else if config.code_requires_strong && has_code {
+        Some("code_blocks")
+    } else if tool_count > config.max_cheap_tools {
+        Some("tools")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => AutoSelection {
+            tier: Tier::Strong,
+            model: config.strong_model.clone(),
+            reason,
+        },
+        None => AutoSelection {
+            tier: Tier::Cheap,
+            model: config.cheap_model.clone(),
+            reason: "simple_request",
+        },
+    }
+}
+
+/// Calls `f` for every piece of prompt text in an Anthropic message or system value
+fn visit_text(value: &Value, f: &mut impl FnMut(&str)) {
+    match value {
+        Value::String(text) => f(text),
+        Value::Array(items) => items.iter().for_each(|item| visit_text(item, f)),
+        Value::Object(map) => {
+            if let Some(text) = map.get("text").and_then(Value::as_str) {
+                f(text);
+            }
+            if let Some(content) = map.get("content") {
+                visit_text(content, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(messages: Vec<Value>, tools: Option<Vec<Value>>) -> AnthropicRequest {
+        AnthropicRequest {
+            model: "auto".to_string(),
+            messages,
+            system: None,
+            temperature: None,
+            tools,
+            stream: None,
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        }
+    }
+
+    #[test]
+    fn test_is_auto() {
+        assert!(is_auto("auto"));
+        assert!(is_auto("AUTO"));
+        assert!(!is_auto("auto/model"));
+        assert!(!is_auto("sonnet"));
+    }
+
+    #[test]
+    fn test_select_simple_request_is_cheap() {
+        let req = request(vec![json!({"role": "user", "content": "Hello"})], None);
+        let selection = select(&req, &AutoModelConfig::default());
+
+        assert_eq!(selection.tier, Tier::Cheap);
+        assert_eq!(selection.model, "google/gemini-2.5-flash");
+        assert_eq!(selection.reason, "simple_request");
+    }
+
+    #[test]
+    fn test_select_long_prompt_is_strong() {
+        let config = AutoModelConfig {
+            max_cheap_prompt_chars: 10,
+            ..AutoModelConfig::default()
+        };
+        let req = request(
+            vec![json!({
+                "role": "user",
+                "content": [{"type": "text", "text": "This prompt is clearly too long"}]
+            })],
+            None,
+        );
+        let selection = select(&req, &config);
+
+        assert_eq!(selection.tier, Tier::Strong);
+        assert_eq!(selection.model, "anthropic/claude-sonnet-4");
+        assert_eq!(selection.reason, "long_prompt");
+    }
+
+    #[test]
+    fn test_select_code_blocks() {
+        let req = request(
+            vec![json!({"role": "user", "content": "Fix this:\n```rust\nfn main() {}\n```"})],
+            None,
+        );
+        assert_eq!(
+            select(&req, &AutoModelConfig::default()).reason,
+            "code_blocks"
+        );
+
+        let config = AutoModelConfig {
+            code_requires_strong: false,
+            ..AutoModelConfig::default()
+        };
+        assert_eq!(select(&req, &config).tier, Tier::Cheap);
+    }
+
+    #[test]
+    fn test_select_tool_results_count_towards_prompt() {
+        let config = AutoModelConfig {
+            max_cheap_prompt_chars: 20,
+            ..AutoModelConfig::default()
+        };
+        let req = request(
+            vec![json!({
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "tool_use_id": "toolu_1",
+                    "content": "a long tool output that exceeds the limit"
+                }]
+            })],
+            None,
+        );
+
+        assert_eq!(select(&req, &config).reason, "long_prompt");
+    }
+
+    #[test]
+    fn test_select_tools_threshold() {
+        let tools = vec![json!({"name": "read_file"}), json!({"name": "write_file"})];
+        let req = request(vec![json!({"role": "user", "content": "Hi"})], Some(tools));
+        assert_eq!(select(&req, &AutoModelConfig::default()).reason, "tools");
+
+        let config = AutoModelConfig {
+            max_cheap_tools: 2,
+            ..AutoModelConfig::default()
+        };
+        assert_eq!(select(&req, &config).tier, Tier::Cheap);
+    }
+}
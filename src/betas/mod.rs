@@ -0,0 +1,54 @@
+//! `anthropic-beta` features carried over to the upstream
+//!
+//! Claude Code opts into beta API features with the `anthropic-beta` header.
+//! Most have no OpenAI-format equivalent and are dropped with the header, but
+//! `token-efficient-tools` only changes how Claude encodes tool calls, so it is
+//! passed to OpenRouter as `x-anthropic-beta`. OpenRouter applies it when the
+//! request is served by Anthropic; other providers never see it.
+
+/// Request header listing the client's beta features, comma-separated
+pub const BETA_HEADER: &str = "anthropic-beta";
+
+/// Header OpenRouter reads Anthropic beta features from
+pub const UPSTREAM_BETA_HEADER: &str = "x-anthropic-beta";
+
+/// Prefixes of the betas passed through, which are versioned by date
+const PASSTHROUGH: &[&str] = &["token-efficient-tools-"];
+
+/// The betas in `header` that are passed through, in the client's order
+pub fn passthrough(header: Option<&str>) -> Vec<&str> {
+    header
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .map(str::trim)
+        .filter(|beta| PASSTHROUGH.iter().any(|prefix| beta.starts_with(prefix)))
+        .collect()
+}
+
+/// Upstream headers for the client's `anthropic-beta` header, if any beta applies
+pub fn upstream_headers(header: Option<&str>) -> Vec<(String, String)> {
+    let betas = passthrough(header);
+    if betas.is_empty() {
+        return Vec::new();
+    }
+    vec![(UPSTREAM_BETA_HEADER.to_string(), betas.join(","))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_through_token_efficient_tools_only() {
+        let header = "interleaved-thinking-2025-05-14, token-efficient-tools-2025-02-19";
+        assert_eq!(
+            upstream_headers(Some(header)),
+            [(
+                "x-anthropic-beta".to_string(),
+                "token-efficient-tools-2025-02-19".to_string()
+            )]
+        );
+        assert!(upstream_headers(Some("prompt-caching-2024-07-31")).is_empty());
+        assert!(upstream_headers(None).is_empty());
+    }
+}
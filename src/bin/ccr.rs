@@ -0,0 +1,63 @@
+//! `ccr serve [--port PORT]`: runs the proxy as a local HTTP server
+//!
+//! Build with `cargo run --features server -- serve --port 8080`.
+
+use ccr::server::{self, ServerConfig};
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: ccr serve [--port PORT]";
+const DEFAULT_PORT: u16 = 8080;
+
+/// Parses `serve [--port PORT]` into the port to listen on
+fn parse_args(args: &[String]) -> Result<u16, String> {
+    let Some((command, options)) = args.split_first() else {
+        return Err(USAGE.to_string());
+    };
+    if command != "serve" {
+        return Err(format!("Unknown command '{command}'\n{USAGE}"));
+    }
+    match options {
+        [] => Ok(DEFAULT_PORT),
+        [flag, port] if flag == "--port" => port
+            .parse()
+            .map_err(|_| format!("Invalid port '{port}'\n{USAGE}")),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let port = match parse_args(&args) {
+        Ok(port) => port,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::from(2);
+        }
+    };
+    match server::serve(port, ServerConfig::from_env()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ccr: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&args(&["serve"])), Ok(8080));
+        assert_eq!(parse_args(&args(&["serve", "--port", "3000"])), Ok(3000));
+        assert!(parse_args(&args(&["serve", "--port", "http"])).is_err());
+        assert!(parse_args(&args(&["deploy"])).is_err());
+        assert!(parse_args(&args(&[])).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Per-deployment content of the static pages
+//!
+//! `/`, `/terms` and `/privacy` name the deployment, link to its operator and
+//! show `ANTHROPIC_*` exports to copy. Forks set their own through
+//! `BRAND_NAME`, `PUBLIC_URL`, `CONTACT_URL` and `EXAMPLE_ENV`, or by storing
+//! the same settings as JSON under `branding` in the `BRANDING` KV namespace,
+//! which wins over the variables and can be edited without a redeploy.
+
+use crate::log::Logger;
+use serde::Deserialize;
+use worker::Env;
+
+/// Optional KV namespace holding branding overrides
+pub const BRANDING_BINDING: &str = "BRANDING";
+
+/// KV key of the overrides
+const BRANDING_KEY: &str = "branding";
+
+/// Seconds Cloudflare may serve cached overrides before re-reading KV
+const BRANDING_CACHE_TTL_SECS: u64 = 300;
+
+/// What the static pages say about the deployment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branding {
+    /// Shown as the home page heading and in page titles
+    pub name: String,
+    /// URL clients point `ANTHROPIC_BASE_URL` at; the request's origin when unset
+    pub public_url: Option<String>,
+    /// The operator's site or `mailto:` address, linked from every page
+    pub contact_url: String,
+    /// Exports added to the custom model example after the base URL and key, in
+    /// name order
+    pub example_env: Vec<(String, String)>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            name: "CCR - Claude Code Router".to_string(),
+            public_url: None,
+            contact_url: "https://duyet.net".to_string(),
+            example_env: vec![(
+                "ANTHROPIC_MODEL".to_string(),
+                "moonshotai/kimi-k2:free".to_string(),
+            )],
+        }
+    }
+}
+
+/// Branding settings that replace the configured ones, as stored in KV
+///
+/// ```json
+/// {"name": "Acme Router", "public_url": "https://llm.acme.dev",
+///  "contact_url": "mailto:platform@acme.dev",
+///  "example_env": {"ANTHROPIC_MODEL": "deepseek/deepseek-chat"}}
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BrandingOverrides {
+    pub name: Option<String>,
+    pub public_url: Option<String>,
+    pub contact_url: Option<String>,
+    pub example_env: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Branding {
+    /// The link text for `contact_url`: its address or host
+    pub fn contact_label(&self) -> &str {
+        let url = &self.contact_url;
+        match url.strip_prefix("mailto:") {
+            Some(address) => address,
+            None => {
+                let rest = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+                let host = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+                host.strip_prefix("www.").unwrap_or(host)
+            }
+        }
+    }
+
+    /// Applies `overrides`, validating them as the variables are
+    pub fn apply(&self, overrides: BrandingOverrides) -> std::result::Result<Branding, String> {
+        let mut branding = self.clone();
+        if let Some(name) = overrides.name.filter(|name| !name.trim().is_empty()) {
+            branding.name = name.trim().to_string();
+        }
+        if let Some(url) = overrides.public_url {
+            branding.public_url = Some(parse_public_url(&url)?);
+        }
+        if let Some(url) = overrides.contact_url {
+            branding.contact_url = parse_contact_url(&url)?;
+        }
+        if let Some(env) = overrides.example_env {
+            branding.example_env = parse_example_env(env)?;
+        }
+        Ok(branding)
+    }
+}
+
+/// Validates a `PUBLIC_URL`, dropping any trailing slash
+pub fn parse_public_url(raw: &str) -> std::result::Result<String, String> {
+    match worker::Url::parse(raw.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            Ok(raw.trim().trim_end_matches('/').to_string())
+        }
+        _ => Err("expected an http(s) URL".to_string()),
+    }
+}
+
+/// Validates a `CONTACT_URL`: an http(s) URL or a `mailto:` address
+pub fn parse_contact_url(raw: &str) -> std::result::Result<String, String> {
+    let raw = raw.trim();
+    match worker::Url::parse(raw) {
+        Ok(url) if matches!(url.scheme(), "http" | "https" | "mailto") => Ok(raw.to_string()),
+        _ => Err("expected an http(s) or mailto: URL".to_string()),
+    }
+}
+
+/// Reads `EXAMPLE_ENV`'s JSON object of variable names to string values
+pub fn parse_example_env(
+    env: serde_json::Map<String, serde_json::Value>,
+) -> std::result::Result<Vec<(String, String)>, String> {
+    env.into_iter()
+        .map(|(name, value)| {
+            let valid_name = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            match value {
+                serde_json::Value::String(value) if valid_name => Ok((name, value)),
+                serde_json::Value::String(_) => {
+                    Err(format!("'{name}' is not an environment variable name"))
+                }
+                _ => Err(format!("the value of '{name}' must be a string")),
+            }
+        })
+        .collect()
+}
+
+/// The configured branding with the KV overrides applied, when the namespace is bound
+///
+/// Unreadable or invalid overrides are logged and ignored rather than taking the
+/// pages down.
+pub async fn load(env: &Env, configured: &Branding, log: &Logger) -> Branding {
+    let Ok(kv) = env.kv(BRANDING_BINDING) else {
+        return configured.clone();
+    };
+    let applied = match kv
+        .get(BRANDING_KEY)
+        .cache_ttl(BRANDING_CACHE_TTL_SECS)
+        .json::<BrandingOverrides>()
+        .await
+    {
+        Ok(Some(overrides)) => configured.apply(overrides),
+        Ok(None) => return configured.clone(),
+        Err(e) => Err(e.to_string()),
+    };
+    applied.unwrap_or_else(|e| {
+        log.warn("branding overrides ignored", &[("error", e.into())]);
+        configured.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact_label() {
+        let mut branding = Branding::default();
+        assert_eq!(branding.contact_label(), "duyet.net");
+        branding.contact_url = "mailto:platform@acme.dev".to_string();
+        assert_eq!(branding.contact_label(), "platform@acme.dev");
+        branding.contact_url = "https://www.acme.dev/support?ref=ccr".to_string();
+        assert_eq!(branding.contact_label(), "acme.dev");
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let overrides: BrandingOverrides = serde_json::from_str(
+            r#"{"name": "Acme Router", "public_url": "https://llm.acme.dev/",
+                "example_env": {"ANTHROPIC_MODEL": "deepseek/deepseek-chat"}}"#,
+        )
+        .unwrap();
+        let branding = Branding::default().apply(overrides).unwrap();
+        assert_eq!(branding.name, "Acme Router");
+        assert_eq!(branding.public_url.as_deref(), Some("https://llm.acme.dev"));
+        assert_eq!(branding.contact_url, Branding::default().contact_url);
+        assert_eq!(
+            branding.example_env,
+            [(
+                "ANTHROPIC_MODEL".to_string(),
+                "deepseek/deepseek-chat".to_string()
+            )]
+        );
+
+        let invalid: BrandingOverrides =
+            serde_json::from_str(r#"{"example_env": {"ANTHROPIC MODEL": "x"}}"#).unwrap();
+        assert!(Branding::default().apply(invalid).is_err());
+        let invalid: BrandingOverrides =
+            serde_json::from_str(r#"{"contact_url": "javascript:alert(1)"}"#).unwrap();
+        assert!(Branding::default().apply(invalid).is_err());
+    }
+}
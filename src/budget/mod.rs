@@ -0,0 +1,118 @@
+use crate::utils::format_date;
+use serde::{Deserialize, Serialize};
+use worker::{
+    durable_object, Env, Error, Method, ObjectNamespace, Request, RequestInit, Response, Result,
+    State,
+};
+
+/// Durable Object namespace binding holding per-key spend
+pub const BUDGET_LEDGER_BINDING: &str = "BUDGET_LEDGER";
+
+/// Converts a USD budget to micro-USD
+pub fn usd_to_micros(usd: f64) -> u64 {
+    (usd * 1_000_000.0).round().max(0.0) as u64
+}
+
+/// Billing month (`YYYY-MM`, UTC) of a Unix timestamp in milliseconds
+pub fn billing_month(millis: u64) -> String {
+    format_date(millis)[..7].to_string()
+}
+
+/// What `Storage::get` fails with for a key that was never stored
+const MISSING_KEY: &str = "No such value in storage.";
+
+/// A month's spend as read from storage: a month without spend has no key and
+/// reads as 0, while any other failure is passed on rather than resetting it
+fn stored_spend(read: Result<u64>) -> Result<u64> {
+    match read {
+        Err(Error::JsError(message)) if message == MISSING_KEY => Ok(0),
+        read => read,
+    }
+}
+
+/// Spend to add to a key's ledger for one month
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LedgerEntry {
+    month: String,
+    micros: u64,
+}
+
+/// Running monthly spend of one key, in micro-USD
+///
+/// Each key gets its own instance, so concurrent requests from the same key are
+/// serialized and no spend is lost to read-modify-write races.
+#[durable_object]
+pub struct BudgetLedger {
+    state: State,
+}
+
+impl DurableObject for BudgetLedger {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let entry: LedgerEntry = req.json().await?;
+        let storage = self.state.storage();
+        let key = format!("spend:{}", entry.month);
+
+        let spent = stored_spend(storage.get::<u64>(&key).await)? + entry.micros;
+        if entry.micros > 0 {
+            storage.put(&key, spent).await?;
+        }
+
+        Response::from_json(&spent)
+    }
+}
+
+/// Adds `micros` to the subject's spend for `month` and returns the new total
+///
+/// Passing zero reads the current spend without changing it.
+pub async fn add_spend(
+    namespace: &ObjectNamespace,
+    subject: &str,
+    month: &str,
+    micros: u64,
+) -> Result<u64> {
+    let stub = namespace.id_from_name(subject)?.get_stub()?;
+    let body = serde_json::to_string(&LedgerEntry {
+        month: month.to_string(),
+        micros,
+    })?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post).with_body(Some(body.into()));
+    let req = Request::new_with_init("https://budget-ledger/spend", &init)?;
+
+    stub.fetch_with_request(req).await?.json().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usd_to_micros() {
+        assert_eq!(usd_to_micros(50.0), 50_000_000);
+        assert_eq!(usd_to_micros(0.25), 250_000);
+        assert_eq!(usd_to_micros(-1.0), 0);
+    }
+
+    #[test]
+    fn test_stored_spend() {
+        assert_eq!(stored_spend(Ok(1_500)).unwrap(), 1_500);
+        let missing = Err(Error::JsError(MISSING_KEY.to_string()));
+        assert_eq!(stored_spend(missing).unwrap(), 0);
+        let failed = Err(Error::JsError(
+            "Durable Object storage unavailable".to_string(),
+        ));
+        assert!(stored_spend(failed).is_err());
+        assert!(stored_spend(Err(Error::RustError("bad value".to_string()))).is_err());
+    }
+
+    #[test]
+    fn test_billing_month() {
+        assert_eq!(billing_month(0), "1970-01");
+        assert_eq!(billing_month(1_735_689_600_000), "2025-01");
+    }
+}
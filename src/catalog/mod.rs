@@ -0,0 +1,422 @@
+use crate::http;
+use crate::utils::format_timestamp;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// Optional KV namespace holding the catalog, kept fresh by the cron trigger
+pub const CATALOG_BINDING: &str = "CATALOG";
+
+/// KV key of the catalog
+pub const CATALOG_KEY: &str = "models";
+
+/// Seconds the catalog is kept in KV when no cron trigger refreshes it sooner
+pub const CATALOG_TTL_SECS: u64 = 86_400;
+
+/// Page size when `limit` isn't given, as in the Anthropic API
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+
+/// Largest page the Anthropic API allows
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Fetches the OpenRouter model catalog from `{base_url}/models`
+pub async fn fetch(client: &http::Client, base_url: &str) -> Result<Value> {
+    client
+        .get(format!("{base_url}/models"))
+        .send()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to fetch model catalog: {e}")))?
+        .json()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to parse model catalog: {e}")))
+}
+
+/// Returns the catalog stored in KV, fetching and storing it when missing
+///
+/// Without a KV namespace every call fetches the catalog.
+pub async fn cached(client: &http::Client, base_url: &str, kv: Option<&KvStore>) -> Result<Value> {
+    if let Some(kv) = kv {
+        if let Ok(Some(catalog)) = kv.get(CATALOG_KEY).json::<Value>().await {
+            return Ok(catalog);
+        }
+    }
+    refresh(client, base_url, kv).await
+}
+
+/// Fetches the catalog, storing it in KV when bound
+pub async fn refresh(client: &http::Client, base_url: &str, kv: Option<&KvStore>) -> Result<Value> {
+    let catalog = fetch(client, base_url).await?;
+    if let Some(kv) = kv {
+        kv.put(CATALOG_KEY, catalog.to_string())?
+            .expiration_ttl(CATALOG_TTL_SECS)
+            .execute()
+            .await
+            .map_err(|e| worker::Error::RustError(format!("Failed to store catalog: {e}")))?;
+    }
+    Ok(catalog)
+}
+
+/// What is wrong with a model the configuration names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelProblem {
+    /// The catalog doesn't list the model
+    Unlisted,
+    /// The catalog lists the model with an `expiration_date`
+    Deprecated { expires: String },
+}
+
+/// A configured model that needs attention, and the variable naming it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelCheck {
+    pub setting: String,
+    pub model: String,
+    pub problem: ModelProblem,
+}
+
+/// Checks configured models against the catalog
+///
+/// Variant suffixes the catalog doesn't list separately (`:nitro`, `:online`,
+/// ...) are checked by their base model.
+pub fn check_models<'a>(
+    catalog: &Value,
+    configured: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<ModelCheck> {
+    let entries: HashMap<&str, &Value> = catalog["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|model| Some((model["id"].as_str()?, model)))
+        .collect();
+
+    configured
+        .into_iter()
+        .filter_map(|(setting, model)| {
+            let base = model.split(':').next().unwrap_or(model);
+            let problem = match entries.get(model).or_else(|| entries.get(base)) {
+                None => ModelProblem::Unlisted,
+                Some(entry) => ModelProblem::Deprecated {
+                    expires: entry["expiration_date"].as_str()?.to_string(),
+                },
+            };
+            Some(ModelCheck {
+                setting: setting.to_string(),
+                model: model.to_string(),
+                problem,
+            })
+        })
+        .collect()
+}
+
+/// A model in the Anthropic models API shape
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelInfo {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+    pub display_name: String,
+    /// RFC 3339 timestamp of when the model was added to OpenRouter
+    pub created_at: String,
+}
+
+impl ModelInfo {
+    fn from_catalog_entry(entry: &Value) -> Option<Self> {
+        let id = entry["id"].as_str()?;
+        Some(ModelInfo {
+            kind: "model",
+            id: id.to_string(),
+            display_name: entry["name"].as_str().unwrap_or(id).to_string(),
+            created_at: format_timestamp(entry["created"].as_u64().unwrap_or(0) * 1000),
+        })
+    }
+}
+
+/// Converts an OpenRouter `/models` response to Anthropic model entries, in catalog order
+pub fn anthropic_models(catalog: &Value) -> Vec<ModelInfo> {
+    catalog["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(ModelInfo::from_catalog_entry)
+        .collect()
+}
+
+/// Whether each catalog model takes image input, by ID
+///
+/// Read from `architecture.input_modalities`, or the input side of the older
+/// `architecture.modality` (`text+image->text`).
+pub fn image_support(catalog: &Value) -> HashMap<String, bool> {
+    catalog["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            let id = model["id"].as_str()?;
+            let architecture = &model["architecture"];
+            let accepts = match architecture["input_modalities"].as_array() {
+                Some(modalities) => modalities.iter().any(|m| m == "image"),
+                None => architecture["modality"]
+                    .as_str()?
+                    .split("->")
+                    .next()?
+                    .contains("image"),
+            };
+            Some((id.to_string(), accepts))
+        })
+        .collect()
+}
+
+/// The `:free` variant of `model`, when `is_listed` finds it in the catalog
+///
+/// Other variant suffixes (`:nitro`, `:floor`, ...) are replaced; free models have none.
+pub fn free_variant(model: &str, is_listed: impl Fn(&str) -> bool) -> Option<String> {
+    if model.ends_with(":free") {
+        return None;
+    }
+    let base = model.split(':').next().unwrap_or(model);
+    let free = format!("{base}:free");
+    is_listed(&free).then_some(free)
+}
+
+/// Cursor and size of a models page, from the `before_id`, `after_id` and `limit` query parameters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageParams {
+    pub before_id: Option<String>,
+    pub after_id: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// One page of the models list
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelsPage {
+    pub data: Vec<ModelInfo>,
+    pub has_more: bool,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+}
+
+/// Selects a page of models the way the Anthropic API does
+///
+/// `after_id` returns the models following that ID, `before_id` the models
+/// immediately preceding it, and `has_more` says whether the list continues in
+/// the direction of travel. An unknown cursor yields an empty page.
+pub fn paginate(models: Vec<ModelInfo>, params: &PageParams) -> ModelsPage {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let position = |id: &str| models.iter().position(|model| model.id == id);
+
+    let (start, end, has_more) = match (&params.before_id, &params.after_id) {
+        (Some(before_id), _) => match position(before_id) {
+            Some(end) => {
+                let start = end.saturating_sub(limit);
+                (start, end, start > 0)
+            }
+            None => (0, 0, false),
+        },
+        (None, Some(after_id)) => match position(after_id) {
+            Some(index) => {
+                let start = index + 1;
+                let end = (start + limit).min(models.len());
+                (start, end, end < models.len())
+            }
+            None => (0, 0, false),
+        },
+        (None, None) => {
+            let end = limit.min(models.len());
+            (0, end, end < models.len())
+        }
+    };
+
+    let data: Vec<ModelInfo> = models[start..end].to_vec();
+    ModelsPage {
+        first_id: data.first().map(|model| model.id.clone()),
+        last_id: data.last().map(|model| model.id.clone()),
+        data,
+        has_more,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn models(count: usize) -> Vec<ModelInfo> {
+        (0..count)
+            .map(|i| ModelInfo {
+                kind: "model",
+                id: format!("m{i}"),
+                display_name: format!("Model {i}"),
+                created_at: format_timestamp(0),
+            })
+            .collect()
+    }
+
+    fn ids(page: &ModelsPage) -> Vec<&str> {
+        page.data.iter().map(|model| model.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_image_support() {
+        let catalog = json!({
+            "data": [
+                {"id": "openai/gpt-4o", "architecture": {"input_modalities": ["text", "image"]}},
+                {"id": "deepseek/deepseek-chat", "architecture": {"input_modalities": ["text"]}},
+                {"id": "legacy/vision", "architecture": {"modality": "text+image->text"}},
+                {"id": "legacy/image-gen", "architecture": {"modality": "text->image"}},
+                {"id": "unknown/model"}
+            ]
+        });
+        let support = image_support(&catalog);
+        assert_eq!(support.get("openai/gpt-4o"), Some(&true));
+        assert_eq!(support.get("deepseek/deepseek-chat"), Some(&false));
+        assert_eq!(support.get("legacy/vision"), Some(&true));
+        assert_eq!(support.get("legacy/image-gen"), Some(&false));
+        assert_eq!(support.get("unknown/model"), None);
+    }
+
+    #[test]
+    fn test_free_variant() {
+        let listed =
+            |id: &str| ["deepseek/deepseek-chat:free", "qwen/qwen3-coder:free"].contains(&id);
+        assert_eq!(
+            free_variant("deepseek/deepseek-chat", listed).as_deref(),
+            Some("deepseek/deepseek-chat:free")
+        );
+        assert_eq!(
+            free_variant("qwen/qwen3-coder:nitro", listed).as_deref(),
+            Some("qwen/qwen3-coder:free")
+        );
+        assert_eq!(free_variant("anthropic/claude-sonnet-4", listed), None);
+        assert_eq!(free_variant("deepseek/deepseek-chat:free", listed), None);
+    }
+
+    #[test]
+    fn test_check_models() {
+        let catalog = json!({
+            "data": [
+                {"id": "anthropic/claude-sonnet-4", "expiration_date": null},
+                {"id": "google/gemini-2.0-flash-001", "expiration_date": "2026-02-06"},
+                {"id": "moonshotai/kimi-k2:free"}
+            ]
+        });
+        let checks = check_models(
+            &catalog,
+            [
+                ("MODEL_SONNET", "anthropic/claude-sonnet-4"),
+                ("HEDGE_MODEL", "anthropic/claude-sonnet-4:nitro"),
+                ("AUTO_CHEAP_MODEL", "google/gemini-2.0-flash-001"),
+                ("SHADOW_MODEL", "moonshotai/kimi-k2:free"),
+                ("MODEL_OPUS", "anthropic/claude-opus-3"),
+            ],
+        );
+        assert_eq!(
+            checks,
+            [
+                ModelCheck {
+                    setting: "AUTO_CHEAP_MODEL".to_string(),
+                    model: "google/gemini-2.0-flash-001".to_string(),
+                    problem: ModelProblem::Deprecated {
+                        expires: "2026-02-06".to_string()
+                    },
+                },
+                ModelCheck {
+                    setting: "MODEL_OPUS".to_string(),
+                    model: "anthropic/claude-opus-3".to_string(),
+                    problem: ModelProblem::Unlisted,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anthropic_models() {
+        let catalog = json!({
+            "data": [
+                {"id": "anthropic/claude-sonnet-4", "name": "Anthropic: Claude Sonnet 4", "created": 1747930371},
+                {"id": "moonshotai/kimi-k2:free"},
+                {"name": "missing id"}
+            ]
+        });
+
+        let models = anthropic_models(&catalog);
+        assert_eq!(models.len(), 2);
+        assert_eq!(
+            serde_json::to_value(&models[0]).unwrap(),
+            json!({
+                "type": "model",
+                "id": "anthropic/claude-sonnet-4",
+                "display_name": "Anthropic: Claude Sonnet 4",
+                "created_at": "2025-05-22T16:12:51Z"
+            })
+        );
+        assert_eq!(models[1].display_name, "moonshotai/kimi-k2:free");
+        assert!(anthropic_models(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_paginate_forward() {
+        let first = paginate(
+            models(5),
+            &PageParams {
+                limit: Some(2),
+                ..PageParams::default()
+            },
+        );
+        assert_eq!(ids(&first), ["m0", "m1"]);
+        assert!(first.has_more);
+        assert_eq!(first.last_id.as_deref(), Some("m1"));
+
+        let last = paginate(
+            models(5),
+            &PageParams {
+                after_id: Some("m2".to_string()),
+                limit: Some(2),
+                ..PageParams::default()
+            },
+        );
+        assert_eq!(ids(&last), ["m3", "m4"]);
+        assert!(!last.has_more);
+    }
+
+    #[test]
+    fn test_paginate_backward() {
+        let page = paginate(
+            models(5),
+            &PageParams {
+                before_id: Some("m3".to_string()),
+                limit: Some(2),
+                ..PageParams::default()
+            },
+        );
+        assert_eq!(ids(&page), ["m1", "m2"]);
+        assert!(page.has_more);
+
+        let page = paginate(
+            models(5),
+            &PageParams {
+                before_id: Some("m1".to_string()),
+                ..PageParams::default()
+            },
+        );
+        assert_eq!(ids(&page), ["m0"]);
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn test_paginate_unknown_cursor() {
+        let page = paginate(
+            models(5),
+            &PageParams {
+                after_id: Some("gone".to_string()),
+                ..PageParams::default()
+            },
+        );
+        assert!(page.data.is_empty());
+        assert_eq!(page.first_id, None);
+        assert!(!page.has_more);
+    }
+}
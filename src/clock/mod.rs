@@ -0,0 +1,99 @@
+//! Time and message IDs for the transform layer
+//!
+//! Translated responses need a message ID, which is derived from the current
+//! time. Both come through [`Clock`] and [`IdGenerator`], so tests can pin them
+//! and the IDs an isolate hands out keep increasing even when two responses
+//! are translated within the same millisecond or the clock steps back.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A source of the current time
+pub trait Clock {
+    /// Current Unix time in milliseconds
+    fn now_ms(&self) -> u64;
+}
+
+/// The host's system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
+
+/// A clock reading from a plain function, such as the Workers runtime's
+impl Clock for fn() -> u64 {
+    fn now_ms(&self) -> u64 {
+        self()
+    }
+}
+
+/// A source of Anthropic message IDs
+pub trait IdGenerator {
+    /// A new `msg_` ID
+    fn message_id(&self) -> String;
+}
+
+/// Last timestamp used for an ID in this isolate
+static LAST_ID_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Message IDs from a clock's timestamp, strictly increasing within the isolate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClockIds<C = SystemClock> {
+    clock: C,
+}
+
+impl<C: Clock> ClockIds<C> {
+    pub fn new(clock: C) -> Self {
+        ClockIds { clock }
+    }
+}
+
+impl<C: Clock> IdGenerator for ClockIds<C> {
+    fn message_id(&self) -> String {
+        let now = self.clock.now_ms();
+        let last = LAST_ID_MS
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_default();
+        format!("msg_{}", now.max(last + 1))
+    }
+}
+
+/// The same message ID every time, for reproducible translations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedIds(pub String);
+
+impl IdGenerator for FixedIds {
+    fn message_id(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_ids_increase_within_a_millisecond() {
+        fn frozen() -> u64 {
+            1_760_000_000_000
+        }
+        let ids = ClockIds::new(frozen as fn() -> u64);
+        let id = |text: String| -> u64 { text.strip_prefix("msg_").unwrap().parse().unwrap() };
+        let first = id(ids.message_id());
+        let second = id(ids.message_id());
+        assert!(first >= frozen());
+        assert!(second > first);
+
+        assert_eq!(
+            FixedIds("msg_fixture".to_string()).message_id(),
+            "msg_fixture"
+        );
+    }
+}
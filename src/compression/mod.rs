@@ -0,0 +1,142 @@
+//! Compression of large JSON responses for clients that accept it
+//!
+//! Bodies are compressed with the runtime's `CompressionStream` as they are
+//! sent, so nothing is buffered twice. Upstream responses need no counterpart:
+//! the Workers `fetch` decompresses gzip and brotli bodies on its own.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{ReadableStream, ReadableWritablePair, ResponseInit, WritableStream};
+use worker::{Response, Result};
+
+/// A content coding `CompressionStream` can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// The runtime's `CompressionStream`, still behind an unstable flag in `web-sys`
+    #[wasm_bindgen(extends = js_sys::Object)]
+    type CompressionStream;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(format: &str) -> std::result::Result<CompressionStream, JsValue>;
+
+    #[wasm_bindgen(method, getter)]
+    fn readable(this: &CompressionStream) -> ReadableStream;
+
+    #[wasm_bindgen(method, getter)]
+    fn writable(this: &CompressionStream) -> WritableStream;
+}
+
+/// Picks the coding an `Accept-Encoding` header prefers, gzip on ties
+///
+/// Brotli isn't offered by `CompressionStream`, so clients that only accept `br`
+/// get uncompressed responses.
+pub fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for item in accept_encoding?.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let encoding = match coding.as_str() {
+            "gzip" | "x-gzip" | "*" => Encoding::Gzip,
+            "deflate" => Encoding::Deflate,
+            _ => continue,
+        };
+        let better = match best {
+            Some((current, best_quality)) => {
+                quality > best_quality
+                    || (quality == best_quality
+                        && encoding == Encoding::Gzip
+                        && current != Encoding::Gzip)
+            }
+            None => true,
+        };
+        if quality > 0.0 && better {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+fn js_error(e: JsValue) -> worker::Error {
+    worker::Error::RustError(format!("Compression: {e:?}"))
+}
+
+/// A JSON response, compressed with `encoding` when its body has at least
+/// `min_bytes` (0 never compresses)
+pub fn json_response<T: Serialize>(
+    value: &T,
+    encoding: Option<Encoding>,
+    min_bytes: usize,
+) -> Result<Response> {
+    let mut body = serde_json::to_vec(value)?;
+    let encoding = match encoding {
+        Some(encoding) if min_bytes > 0 && body.len() >= min_bytes => encoding,
+        _ => return Response::from_json(value),
+    };
+
+    let plain = web_sys::Response::new_with_opt_u8_array(Some(&mut body)).map_err(js_error)?;
+    let stream = plain
+        .body()
+        .ok_or_else(|| worker::Error::RustError("Compression: response has no body".into()))?;
+    let compressor = CompressionStream::new(encoding.as_str()).map_err(js_error)?;
+    let compressed = stream.pipe_through(&ReadableWritablePair::new(
+        &compressor.readable(),
+        &compressor.writable(),
+    ));
+
+    let headers = web_sys::Headers::new().map_err(js_error)?;
+    headers
+        .set("Content-Type", "application/json")
+        .map_err(js_error)?;
+    headers
+        .set("Content-Encoding", encoding.as_str())
+        .map_err(js_error)?;
+    headers.set("Vary", "Accept-Encoding").map_err(js_error)?;
+    let init = ResponseInit::new();
+    init.set_headers(headers.unchecked_ref());
+    // The body is already encoded; without this the runtime would encode it again
+    js_sys::Reflect::set(&init, &"encodeBody".into(), &"manual".into()).map_err(js_error)?;
+
+    let response =
+        web_sys::Response::new_with_opt_readable_stream_and_init(Some(&compressed), &init)
+            .map_err(js_error)?;
+    Ok(response.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(None), None);
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("deflate, gzip")), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(Some("gzip;q=0.5, deflate;q=0.8")),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate(Some("br")), None);
+        assert_eq!(negotiate(Some("gzip;q=0, identity")), None);
+        assert_eq!(negotiate(Some("*")), Some(Encoding::Gzip));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use worker::{
+    durable_object, Date, Env, Method, ObjectNamespace, Request, RequestInit, Response, Result,
+    State, Stub,
+};
+
+/// Durable Object namespace binding backing the concurrency limiter
+pub const CONCURRENCY_LIMITER_BINDING: &str = "CONCURRENCY_LIMITER";
+
+/// How long a slot is held at most; frees slots of requests that never released them
+pub const LEASE_MS: u64 = 15 * 60_000;
+
+/// How often a queued request asks again for a free slot
+pub const QUEUE_POLL_MS: u64 = 250;
+
+/// Storage key of the lease table inside each Durable Object
+const LEASES_KEY: &str = "leases";
+
+/// In-flight requests of one subject: lease ID → time (ms) the lease expires
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Leases {
+    active: HashMap<String, u64>,
+}
+
+impl Leases {
+    /// Takes a slot for `lease` if fewer than `limit` are held
+    pub fn acquire(&mut self, lease: &str, limit: u32, now: u64) -> bool {
+        self.active.retain(|_, expires_at| *expires_at > now);
+        if self.active.contains_key(lease) {
+            return true;
+        }
+        if self.active.len() >= limit as usize {
+            return false;
+        }
+        self.active
+            .insert(lease.to_string(), now.saturating_add(LEASE_MS));
+        true
+    }
+
+    /// Gives back the slot held by `lease`
+    pub fn release(&mut self, lease: &str) {
+        self.active.remove(lease);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.active.len()
+    }
+}
+
+/// Body sent from the proxy to a concurrency limiter Durable Object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum SlotRequest {
+    Acquire { lease: String, limit: u32 },
+    Release { lease: String },
+}
+
+/// Semaphore Durable Object, one instance per concurrency-limited subject
+///
+/// Like [`crate::rate_limit::RateLimitBucket`], it relies on the instance
+/// processing requests serially to count in-flight requests across isolates.
+#[durable_object]
+pub struct ConcurrencyLimiter {
+    state: State,
+}
+
+impl DurableObject for ConcurrencyLimiter {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let slot_request: SlotRequest = req.json().await?;
+        let storage = self.state.storage();
+        let mut leases = storage.get::<Leases>(LEASES_KEY).await.unwrap_or_default();
+
+        let acquired = match slot_request {
+            SlotRequest::Acquire { lease, limit } => {
+                leases.acquire(&lease, limit, Date::now().as_millis())
+            }
+            SlotRequest::Release { lease } => {
+                leases.release(&lease);
+                true
+            }
+        };
+        storage.put(LEASES_KEY, &leases).await?;
+
+        Response::from_json(&acquired)
+    }
+}
+
+/// A held slot, to be released once the upstream request has finished
+pub struct Slot {
+    stub: Stub,
+    lease: String,
+}
+
+impl Slot {
+    /// Gives the slot back to the subject's limiter
+    pub async fn release(self) -> Result<()> {
+        send(&self.stub, &SlotRequest::Release { lease: self.lease }).await?;
+        Ok(())
+    }
+}
+
+async fn send(stub: &Stub, slot_request: &SlotRequest) -> Result<bool> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(serde_json::to_string(slot_request)?.into()));
+    let req = Request::new_with_init("https://concurrency-limiter/slot", &init)?;
+    stub.fetch_with_request(req).await?.json().await
+}
+
+/// Takes one of `limit` slots of `subject` for the request `lease`, if one is free
+pub async fn acquire(
+    namespace: &ObjectNamespace,
+    subject: &str,
+    lease: &str,
+    limit: u32,
+) -> Result<Option<Slot>> {
+    let stub = namespace.id_from_name(subject)?.get_stub()?;
+    let slot_request = SlotRequest::Acquire {
+        lease: lease.to_string(),
+        limit,
+    };
+    Ok(send(&stub, &slot_request).await?.then(|| Slot {
+        stub,
+        lease: lease.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    #[test]
+    fn test_slots_are_capped_and_released() {
+        let mut leases = Leases::default();
+        assert!(leases.acquire("a", 2, NOW));
+        assert!(leases.acquire("b", 2, NOW));
+        assert!(!leases.acquire("c", 2, NOW));
+        // Retrying with a lease that's already held doesn't take a second slot
+        assert!(leases.acquire("a", 2, NOW));
+        assert_eq!(leases.in_flight(), 2);
+
+        leases.release("a");
+        assert!(leases.acquire("c", 2, NOW));
+    }
+
+    #[test]
+    fn test_expired_leases_free_their_slots() {
+        let mut leases = Leases::default();
+        assert!(leases.acquire("a", 1, NOW));
+        assert!(!leases.acquire("b", 1, NOW + LEASE_MS - 1));
+        assert!(leases.acquire("b", 1, NOW + LEASE_MS));
+        assert_eq!(leases.in_flight(), 1);
+    }
+}
@@ -1,38 +1,1139 @@
-use worker::{Env, Result};
+use crate::alerts::AlertConfig;
+use crate::attachments::AttachmentConfig;
+use crate::auth::jwt::{self, JwtConfig};
+use crate::auto_model::AutoModelConfig;
+use crate::branding::{self, Branding};
+use crate::context_recovery::ContextRecovery;
+use crate::cors;
+use crate::features::{FeatureFlags, FlagSet};
+use crate::geo::{self, RequestLocation};
+use crate::guardrails::RequestLimits;
+use crate::header_policy::{self, HeaderPolicy};
+use crate::injection::InjectionMode;
+use crate::key_pool;
+use crate::log::Level;
+use crate::moderation::{ModerationAction, ModerationConfig, ModerationProvider};
+use crate::pii::{Detector, Redactor};
+use crate::rate_limit::RateLimitConfig;
+use crate::reporting::ReportSink;
+use crate::retry::RetryPolicy;
+use crate::semantic_cache::SemanticCacheConfig;
+use crate::transcripts::TranscriptMode;
+use crate::transform::request::UnsupportedParams;
+use crate::usage::{self, AnalyticsSqlConfig};
+use crate::utils::{Attribution, ModelRouting, ModelTargets};
+use crate::vision::{self, ImageFallback};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::rc::Rc;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use worker::{Date, Env, EnvBinding, Result};
 
+thread_local! {
+    /// Configuration parsed per isolate, see [`Config::cached`]
+    static CONFIG: ConfigCache = ConfigCache::default();
+}
+
+/// A parsed configuration, reused until its `CONFIG_TTL_SECS` lapses
+///
+/// Workers run one request at a time per thread, so the cache is per-thread
+/// and hands out `Rc`s; a request keeps the configuration it started with even
+/// if the cache is refreshed while it runs.
+#[derive(Default)]
+pub struct ConfigCache {
+    /// The configuration and when it expires, in Unix milliseconds
+    entry: RefCell<Option<(Rc<Config>, u64)>>,
+}
+
+impl ConfigCache {
+    /// Returns the cached configuration, calling `load` when there is none or it has expired
+    ///
+    /// Failed loads are not cached, so they are retried (and reported) on every call.
+    pub fn get(&self, now_ms: u64, load: impl FnOnce() -> Result<Config>) -> Result<Rc<Config>> {
+        if let Some((config, expires_at)) = &*self.entry.borrow() {
+            if now_ms < *expires_at {
+                return Ok(Rc::clone(config));
+            }
+        }
+
+        let config = Rc::new(load()?);
+        let expires_at = match config.config_ttl_secs {
+            0 => u64::MAX,
+            ttl_secs => now_ms.saturating_add(ttl_secs.saturating_mul(1000)),
+        };
+        *self.entry.borrow_mut() = Some((Rc::clone(&config), expires_at));
+        Ok(config)
+    }
+}
+
+/// How much detail upstream error responses carry back to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorVerbosity {
+    /// Short message with status, model and upstream error text
+    Basic,
+    /// Full diagnostics: parsed upstream fields, request context and suggestions
+    Detailed,
+}
+
+impl FromStr for ErrorVerbosity {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "basic" => Ok(ErrorVerbosity::Basic),
+            "detailed" => Ok(ErrorVerbosity::Detailed),
+            _ => Err("expected 'basic' or 'detailed'".to_string()),
+        }
+    }
+}
+
+/// Where a credential such as an upstream API key is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    /// A plain var or `wrangler secret`, fixed until the next deploy
+    Static(String),
+    /// A Cloudflare Secrets Store binding, read on every use so rotations apply immediately
+    SecretsStore { binding: String },
+    /// Several keys used in turn, skipping those benched after a 429 or 402
+    Pool(Vec<String>),
+}
+
+impl Credential {
+    /// Returns the current value of the credential
+    pub async fn resolve(&self, env: &Env) -> Result<Option<String>> {
+        match self {
+            Credential::Static(value) => Ok(Some(value.clone())),
+            Credential::SecretsStore { binding } => {
+                let secret: SecretsStoreSecret = env.get_binding(binding)?;
+                let value = JsFuture::from(secret.get().map_err(secrets_store_error)?)
+                    .await
+                    .map_err(secrets_store_error)?;
+                Ok(value.as_string().filter(|value| !value.trim().is_empty()))
+            }
+            Credential::Pool(keys) => Ok(key_pool::next_key(keys, Date::now().as_millis())),
+        }
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Secrets Store secret binding, which worker doesn't wrap yet
+    #[wasm_bindgen(extends = js_sys::Object)]
+    type SecretsStoreSecret;
+
+    #[wasm_bindgen(method, catch)]
+    fn get(this: &SecretsStoreSecret) -> std::result::Result<js_sys::Promise, JsValue>;
+}
+
+impl EnvBinding for SecretsStoreSecret {
+    const TYPE_NAME: &'static str = "SecretsStoreSecret";
+
+    fn get(value: JsValue) -> Result<Self> {
+        Ok(value.unchecked_into())
+    }
+}
+
+fn secrets_store_error(e: JsValue) -> worker::Error {
+    worker::Error::RustError(format!("Secrets Store: {e:?}"))
+}
+
+/// Upstream keys of model families (`MODEL_KEYS`), by model ID prefix
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelKeys {
+    /// Longest prefix first, so the most specific family wins
+    entries: Vec<(String, Credential)>,
+}
+
+impl ModelKeys {
+    /// Parses a JSON object mapping model ID prefixes to a key or a list of keys
+    /// used in turn, e.g. `{"openai/": "sk-or-...", "moonshotai/": ["sk-or-...", "sk-or-..."]}`
+    ///
+    /// Errors never quote the keys.
+    pub fn parse(raw: &str) -> std::result::Result<Self, String> {
+        let families: HashMap<String, serde_json::Value> =
+            serde_json::from_str(raw).map_err(|e| format!("expected a JSON object: {e}"))?;
+        let mut entries = Vec::with_capacity(families.len());
+        for (prefix, keys) in families {
+            if prefix.is_empty() {
+                return Err("model prefixes can't be empty".to_string());
+            }
+            let credential = match keys {
+                serde_json::Value::String(key) if !key.trim().is_empty() => {
+                    Credential::Static(key.trim().to_string())
+                }
+                serde_json::Value::Array(keys) => {
+                    let keys: Option<Vec<String>> = keys
+                        .iter()
+                        .map(|key| key.as_str().map(|key| key.trim().to_string()))
+                        .filter(|key| key.as_deref() != Some(""))
+                        .collect();
+                    match keys {
+                        Some(keys) if !keys.is_empty() => Credential::Pool(keys),
+                        _ => return Err(format!("expected keys for '{prefix}'")),
+                    }
+                }
+                _ => return Err(format!("expected a key or a list of keys for '{prefix}'")),
+            };
+            entries.push((prefix, credential));
+        }
+        entries.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Ok(ModelKeys { entries })
+    }
+
+    /// The key of the family `model` belongs to, if it has one
+    pub fn for_model(&self, model: &str) -> Option<&Credential> {
+        self.entries
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .map(|(_, credential)| credential)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct Config {
     pub openrouter_base_url: String,
+    /// Site and app name sent as OpenRouter's `HTTP-Referer` and `X-Title`
+    pub attribution: Attribution,
     pub default_max_tokens: u32,
+    /// Secondary model raced against the primary when it is slow to respond
+    pub hedge_model: Option<String>,
+    /// Delay in milliseconds before the hedged request is fired
+    pub hedge_delay_ms: u64,
+    /// Retries of non-streaming requests that fail transiently
+    pub retry: RetryPolicy,
+    /// Longest wait for an upstream response before the fetch is aborted; 0 waits indefinitely
+    pub upstream_timeout_ms: u64,
+    /// Secondary model that sampled requests are mirrored to for offline evaluation
+    pub shadow_model: Option<String>,
+    /// Percentage (0-100) of non-streaming requests mirrored to the shadow model
+    pub shadow_sample_percent: u8,
+    /// Targets and thresholds for `model: "auto"` selection
+    pub auto_model: AutoModelConfig,
+    /// Regional upstream base URLs keyed by colo, country or continent code
+    pub regional_upstreams: HashMap<String, String>,
+    /// Request parameters stripped for the model ID prefixes whose providers reject them
+    pub unsupported_params: UnsupportedParams,
+    /// Model that requests using the code execution tool are sent to (`CODE_EXECUTION_MODEL`)
+    pub code_execution_model: Option<String>,
+    /// Targets for the Claude short model names
+    pub model_targets: ModelTargets,
+    /// Detail level of error responses transformed from upstream failures
+    pub error_verbosity: ErrorVerbosity,
+    /// Elapsed time after which a request is logged as approaching the runtime limit
+    pub slow_request_warn_ms: u64,
+    /// Upstream stream events after which deltas are merged per read (0 = never)
+    pub stream_coalesce_after_events: u32,
+    /// Most verbose log level emitted
+    pub log_level: Level,
+    /// Log every event of every request with per-step timing, as `x-ccr-trace: 1` does for one
+    pub debug_tracing: bool,
+    /// Features on for requests made for no key in particular; callers get theirs
+    /// from `feature_flags`
+    pub features: FeatureFlags,
+    /// Features switched off or rolled out by `DISABLED_FEATURES` and `FEATURE_FLAGS`
+    pub feature_flags: FlagSet,
+    /// Operator's upstream key used when clients send none: the Secrets Store binding
+    /// named by `OPENROUTER_API_KEY_STORE`, or else the `OPENROUTER_API_KEY` secret
+    pub server_api_key: Option<Credential>,
+    /// Keys used in place of `server_api_key` for the model families that have one
+    pub model_keys: ModelKeys,
+    /// Seconds a pooled server key sits out after a 429 or 402
+    pub key_cooldown_secs: u64,
+    /// Retry requests refused for lack of credit (402) on the model's `:free` variant
+    pub free_fallback: bool,
+    /// Retry requests too long for the model's context window once, shortened or rerouted
+    pub context_recovery: ContextRecovery,
+    /// Model requests are rerouted to with `CONTEXT_RECOVERY=reroute`
+    pub long_context_model: Option<String>,
+    /// Models `/v1/experiments/compare` sends a prompt to when the request names none
+    pub compare_models: Vec<String>,
+    /// Always use `server_api_key`, ignoring keys sent by clients
+    pub force_server_key: bool,
+    /// Per-key and per-IP request and token limits
+    pub rate_limits: RateLimitConfig,
+    /// Body, message, tool and image size limits checked before proxying
+    pub request_limits: RequestLimits,
+    /// Shared secret callers must present to use this deployment
+    pub access_token: Option<String>,
+    /// SHA-256 hashes (lowercase hex) of client API keys allowed to use this deployment
+    pub allowed_key_hashes: HashSet<String>,
+    /// Identity token validation (e.g. Cloudflare Access), enabled by `JWT_JWKS_URL`
+    pub jwt: Option<JwtConfig>,
+    /// Browser origins allowed to call the `/v1` API (`CORS_ALLOWED_ORIGINS`); `*` allows any
+    pub cors_allowed_origins: Vec<String>,
+    /// Extra headers passed between client and upstream (`FORWARD_HEADERS`, `EXPOSE_HEADERS`, `BLOCK_HEADERS`)
+    pub header_policy: HeaderPolicy,
+    /// Name, links and examples shown on the static pages
+    pub branding: Branding,
+    /// Seconds a response stays replayable under its `Idempotency-Key`
+    pub idempotency_ttl_secs: u64,
+    /// Seconds the status and result of an `x-ccr-async` job can be polled
+    pub async_result_ttl_secs: u64,
+    /// Seconds a `temperature: 0` response is served from `RESPONSE_CACHE`
+    pub response_cache_ttl_secs: u64,
+    /// Smallest non-streaming response body compressed for clients that accept it; 0 disables compression
+    pub compress_min_bytes: usize,
+    /// Most messages an `x-ccr-session` history keeps; older turns are dropped
+    pub session_max_messages: usize,
+    /// Seconds an idle `x-ccr-session` history, or a conversation's chosen model, is kept
+    pub session_ttl_secs: u64,
+    /// Eligible models and similarity threshold of the Vectorize-backed semantic cache
+    pub semantic_cache: SemanticCacheConfig,
+    /// Masking of personal data in outgoing requests (`PII_REDACTION`, `PII_PATTERNS`)
+    pub pii: Option<Redactor>,
+    /// How tool results are screened for prompt injection (`PROMPT_INJECTION`)
+    pub prompt_injection: InjectionMode,
+    /// What models without vision receive in place of images (`IMAGE_FALLBACK`)
+    pub image_fallback: ImageFallback,
+    /// Vision model that describes images for `IMAGE_FALLBACK=describe`
+    pub image_caption_model: String,
+    /// Send prompt cache keys and breakpoints derived from the system prompt and
+    /// tools (`PROMPT_CACHE_HINTS`)
+    pub prompt_cache_hints: bool,
+    /// Ground every `/v1/messages` request with OpenRouter's web plugin (`WEB_SEARCH`)
+    pub web_search: bool,
+    /// Pre-flight classification of prompts, enabled by `MODERATION`
+    pub moderation: Option<ModerationConfig>,
+    /// Read access to the usage metrics for `GET /usage`
+    pub analytics_sql: Option<AnalyticsSqlConfig>,
+    /// Whether request/response transcripts are stored in R2 (`LOG_TO_R2`)
+    pub log_to_r2: TranscriptMode,
+    /// Where transform and upstream errors are reported (`SENTRY_DSN` or `ERROR_WEBHOOK_URL`)
+    pub error_reporting: Option<ReportSink>,
+    /// Error rate, latency and spend alerts, enabled by `ALERT_WEBHOOK_URL`
+    pub alerts: Option<AlertConfig>,
+    /// Webhook the cron trigger posts the spend digest to (`DIGEST_WEBHOOK_URL`)
+    pub digest_webhook_url: Option<String>,
+    /// Offloading of large attachments to R2, enabled by `ATTACHMENT_SIGNING_KEY`
+    pub attachments: Option<AttachmentConfig>,
+    /// Key responses of the authenticated routes are signed with (`RESPONSE_SIGNING_KEY`)
+    pub response_signing_key: Option<String>,
+    /// Seconds a parsed configuration is reused before it is read again; 0 keeps it for the isolate's lifetime
+    pub config_ttl_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            openrouter_base_url: "https://openrouter.ai/api/v1".to_string(),
+            attribution: Attribution::default(),
+            default_max_tokens: 4096,
+            hedge_model: None,
+            hedge_delay_ms: 3000,
+            retry: RetryPolicy::default(),
+            upstream_timeout_ms: 0,
+            shadow_model: None,
+            shadow_sample_percent: 0,
+            auto_model: AutoModelConfig::default(),
+            regional_upstreams: HashMap::new(),
+            unsupported_params: UnsupportedParams::default(),
+            code_execution_model: None,
+            model_targets: ModelTargets::default(),
+            error_verbosity: ErrorVerbosity::Basic,
+            slow_request_warn_ms: 25000,
+            stream_coalesce_after_events: 1000,
+            log_level: Level::Info,
+            debug_tracing: false,
+            features: FeatureFlags::default(),
+            feature_flags: FlagSet::default(),
+            server_api_key: None,
+            model_keys: ModelKeys::default(),
+            key_cooldown_secs: 60,
+            free_fallback: false,
+            context_recovery: ContextRecovery::Off,
+            long_context_model: None,
+            compare_models: Vec::new(),
+            force_server_key: false,
+            rate_limits: RateLimitConfig::default(),
+            request_limits: RequestLimits::default(),
+            access_token: None,
+            allowed_key_hashes: HashSet::new(),
+            jwt: None,
+            cors_allowed_origins: Vec::new(),
+            header_policy: HeaderPolicy::default(),
+            branding: Branding::default(),
+            idempotency_ttl_secs: 86400,
+            async_result_ttl_secs: 86400,
+            response_cache_ttl_secs: 3600,
+            compress_min_bytes: 1024,
+            session_max_messages: 100,
+            session_ttl_secs: 604800,
+            semantic_cache: SemanticCacheConfig::default(),
+            pii: None,
+            prompt_injection: InjectionMode::Off,
+            image_fallback: ImageFallback::Off,
+            image_caption_model: vision::DEFAULT_CAPTION_MODEL.to_string(),
+            prompt_cache_hints: false,
+            web_search: false,
+            moderation: None,
+            analytics_sql: None,
+            log_to_r2: TranscriptMode::Off,
+            error_reporting: None,
+            alerts: None,
+            digest_webhook_url: None,
+            attachments: None,
+            response_signing_key: None,
+            config_ttl_secs: 300,
+        }
+    }
+}
+
+/// Typed access to configuration variables
+///
+/// Unset or blank variables fall back to defaults; set but malformed values are
+/// errors that name the offending variable, so misconfiguration fails loudly.
+struct Vars<F: Fn(&str) -> Option<String>>(F);
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn string(&self, name: &str) -> Option<String> {
+        (self.0)(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn string_or(&self, name: &str, default: String) -> String {
+        self.string(name).unwrap_or(default)
+    }
+
+    fn parse<T>(&self, name: &str, default: T) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.string(name) {
+            Some(raw) => raw.parse().map_err(|e| invalid(name, &raw, e)),
+            None => Ok(default),
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> Result<bool> {
+        match self.string(name) {
+            Some(raw) => match raw.to_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Ok(true),
+                "false" | "0" | "no" | "off" => Ok(false),
+                _ => Err(invalid(name, &raw, "expected true or false")),
+            },
+            None => Ok(default),
+        }
+    }
+}
+
+fn invalid(name: &str, raw: &str, reason: impl Display) -> worker::Error {
+    worker::Error::RustError(format!("Invalid configuration: {name}='{raw}' ({reason})"))
+}
+
+impl ModelRouting for Config {
+    fn model_targets(&self) -> &ModelTargets {
+        &self.model_targets
+    }
+
+    fn auto_model(&self) -> Option<&AutoModelConfig> {
+        self.features.auto_model.then_some(&self.auto_model)
+    }
+
+    fn unsupported_params(&self) -> &UnsupportedParams {
+        &self.unsupported_params
+    }
+
+    fn code_execution_model(&self) -> Option<&str> {
+        self.code_execution_model.as_deref()
+    }
+
+    fn features(&self) -> &FeatureFlags {
+        &self.features
+    }
 }
 
 impl Config {
     pub fn from_env(env: &Env) -> Result<Self> {
-        let openrouter_base_url = env
-            .var("OPENROUTER_BASE_URL")
-            .ok()
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "https://openrouter.ai/api/v1".to_string());
-
-        let default_max_tokens = env
-            .var("DEFAULT_MAX_TOKENS")
-            .ok()
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "4096".to_string())
-            .parse()
-            .unwrap_or(4096);
-
-        Ok(Config {
-            openrouter_base_url,
-            default_max_tokens,
+        Self::from_vars(|name| {
+            env.var(name)
+                .or_else(|_| env.secret(name))
+                .ok()
+                .map(|v| v.to_string())
         })
     }
 
+    /// Returns the configuration for this isolate, parsing it on first use
+    ///
+    /// The parsed config is reused across requests and read again once
+    /// `CONFIG_TTL_SECS` has passed, so sources that change without a deploy are
+    /// picked up without parsing on every request. Invalid configuration is not
+    /// cached and is reported on every request until fixed.
+    pub fn cached(env: &Env) -> Result<Rc<Config>> {
+        CONFIG.with(|cache| cache.get(Date::now().as_millis(), || Self::from_env(env)))
+    }
+
+    /// Builds and validates a configuration from a variable lookup function
+    pub fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let vars = Vars(lookup);
+        let defaults = Config::default();
+        let auto_defaults = defaults.auto_model;
+        let target_defaults = defaults.model_targets;
+        let limit_defaults = defaults.rate_limits;
+        let size_defaults = defaults.request_limits;
+        let retry_defaults = defaults.retry;
+        let semantic_defaults = defaults.semantic_cache.clone();
+
+        let regional_upstreams = match vars.string("REGIONAL_UPSTREAMS") {
+            Some(raw) => geo::parse_regional_upstreams(&raw)
+                .map_err(|e| invalid("REGIONAL_UPSTREAMS", &raw, e))?,
+            None => HashMap::new(),
+        };
+
+        let unsupported_params = match vars.string("UNSUPPORTED_PARAMS") {
+            Some(raw) => serde_json::from_str::<HashMap<String, Vec<String>>>(&raw)
+                .map_err(|e| e.to_string())
+                .and_then(|overrides| UnsupportedParams::default().with_overrides(overrides))
+                .map_err(|e| invalid("UNSUPPORTED_PARAMS", &raw, e))?,
+            None => UnsupportedParams::default(),
+        };
+
+        let mut feature_flags = match vars.string("DISABLED_FEATURES") {
+            Some(raw) => FlagSet::from_disabled_list(&raw)
+                .map_err(|e| invalid("DISABLED_FEATURES", &raw, e))?,
+            None => FlagSet::default(),
+        };
+        if let Some(raw) = vars.string("FEATURE_FLAGS") {
+            let flags = FlagSet::parse(&raw).map_err(|e| invalid("FEATURE_FLAGS", &raw, e))?;
+            feature_flags = feature_flags.with(&flags);
+        }
+        let mut features = FeatureFlags::default();
+        feature_flags.apply(&mut features, None);
+
+        let pii_detectors = match vars.string("PII_REDACTION") {
+            Some(raw) if raw.trim().eq_ignore_ascii_case("all") => Detector::ALL.to_vec(),
+            Some(raw) => raw
+                .split(',')
+                .filter(|name| !name.trim().is_empty())
+                .map(Detector::from_str)
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| invalid("PII_REDACTION", &raw, e))?,
+            None => Vec::new(),
+        };
+        let pii_patterns: Vec<String> = match vars.string("PII_PATTERNS") {
+            Some(raw) => serde_json::from_str(&raw).map_err(|e| {
+                invalid("PII_PATTERNS", &raw, format!("expected a JSON array: {e}"))
+            })?,
+            None => Vec::new(),
+        };
+        let pii = if pii_detectors.is_empty() && pii_patterns.is_empty() {
+            None
+        } else {
+            let restore = vars.bool("PII_RESTORE", true)?;
+            Some(
+                Redactor::new(&pii_detectors, &pii_patterns, restore)
+                    .map_err(|e| invalid("PII_PATTERNS", &pii_patterns.join(", "), e))?,
+            )
+        };
+
+        let attribution_defaults = Attribution::default();
+        let attribution = Attribution {
+            referer: match vars.string("OPENROUTER_REFERER") {
+                Some(raw) => match worker::Url::parse(&raw) {
+                    Ok(url) if matches!(url.scheme(), "http" | "https") => raw,
+                    _ => {
+                        return Err(invalid(
+                            "OPENROUTER_REFERER",
+                            &raw,
+                            "expected an http(s) URL",
+                        ))
+                    }
+                },
+                None => attribution_defaults.referer,
+            },
+            title: match vars.string("OPENROUTER_TITLE") {
+                Some(raw) if raw.chars().any(char::is_control) => {
+                    return Err(invalid(
+                        "OPENROUTER_TITLE",
+                        &raw,
+                        "contains control characters",
+                    ))
+                }
+                Some(raw) => raw,
+                None => attribution_defaults.title,
+            },
+        };
+
+        let header_patterns = |name: &str| match vars.string(name) {
+            Some(raw) => header_policy::parse_patterns(&raw).map_err(|e| invalid(name, &raw, e)),
+            None => Ok(Vec::new()),
+        };
+        let header_policy = HeaderPolicy {
+            forward: header_patterns("FORWARD_HEADERS")?,
+            expose: header_patterns("EXPOSE_HEADERS")?,
+            block: header_patterns("BLOCK_HEADERS")?,
+        };
+
+        let branding_defaults = Branding::default();
+        let branding = Branding {
+            name: vars.string_or("BRAND_NAME", branding_defaults.name),
+            public_url: match vars.string("PUBLIC_URL") {
+                Some(raw) => Some(
+                    branding::parse_public_url(&raw).map_err(|e| invalid("PUBLIC_URL", &raw, e))?,
+                ),
+                None => None,
+            },
+            contact_url: match vars.string("CONTACT_URL") {
+                Some(raw) => branding::parse_contact_url(&raw)
+                    .map_err(|e| invalid("CONTACT_URL", &raw, e))?,
+                None => branding_defaults.contact_url,
+            },
+            example_env: match vars.string("EXAMPLE_ENV") {
+                Some(raw) => serde_json::from_str(&raw)
+                    .map_err(|e| format!("expected a JSON object: {e}"))
+                    .and_then(branding::parse_example_env)
+                    .map_err(|e| invalid("EXAMPLE_ENV", &raw, e))?,
+                None => branding_defaults.example_env,
+            },
+        };
+
+        let moderation = match vars.string("MODERATION") {
+            Some(raw) if raw.trim().eq_ignore_ascii_case("off") => None,
+            Some(raw) => Some(ModerationConfig {
+                provider: ModerationProvider::parse(
+                    &raw,
+                    vars.string("MODERATION_MODEL"),
+                    vars.string("MODERATION_API_KEY"),
+                )
+                .map_err(|e| invalid("MODERATION", &raw, e))?,
+                action: vars.parse("MODERATION_ACTION", ModerationAction::Block)?,
+                categories: vars
+                    .string("MODERATION_CATEGORIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|category| category.trim().to_lowercase())
+                    .filter(|category| !category.is_empty())
+                    .collect(),
+            }),
+            None => None,
+        };
+
+        let error_reporting = match vars.string("SENTRY_DSN") {
+            Some(dsn) => Some(
+                ReportSink::from_sentry_dsn(&dsn)
+                    .map_err(|e| invalid("SENTRY_DSN", "[redacted]", e))?,
+            ),
+            None => vars.string("ERROR_WEBHOOK_URL").map(ReportSink::Webhook),
+        };
+
+        let alerts = match vars.string("ALERT_WEBHOOK_URL") {
+            Some(webhook_url) => {
+                let alert_defaults = AlertConfig::new(webhook_url);
+                Some(AlertConfig {
+                    window_secs: vars.parse("ALERT_WINDOW_SECS", alert_defaults.window_secs)?,
+                    error_rate_percent: vars.parse(
+                        "ALERT_ERROR_RATE_PERCENT",
+                        alert_defaults.error_rate_percent,
+                    )?,
+                    latency_p95_ms: vars
+                        .parse("ALERT_LATENCY_P95_MS", alert_defaults.latency_p95_ms)?,
+                    daily_spend_usd: vars
+                        .parse("ALERT_DAILY_SPEND_USD", alert_defaults.daily_spend_usd)?,
+                    ..alert_defaults
+                })
+            }
+            None => None,
+        };
+
+        let attachments = match vars.string("ATTACHMENT_SIGNING_KEY") {
+            Some(signing_key) => Some(AttachmentConfig {
+                signing_key,
+                min_bytes: vars.parse(
+                    "ATTACHMENT_OFFLOAD_BYTES",
+                    AttachmentConfig::DEFAULT_MIN_BYTES,
+                )?,
+                url_ttl_secs: vars.parse(
+                    "ATTACHMENT_URL_TTL_SECS",
+                    AttachmentConfig::DEFAULT_URL_TTL_SECS,
+                )?,
+            }),
+            None => None,
+        };
+
+        let config = Config {
+            openrouter_base_url: vars
+                .string_or("OPENROUTER_BASE_URL", defaults.openrouter_base_url)
+                .trim_end_matches('/')
+                .to_string(),
+            attribution,
+            default_max_tokens: vars.parse("DEFAULT_MAX_TOKENS", defaults.default_max_tokens)?,
+            hedge_model: vars.string("HEDGE_MODEL"),
+            hedge_delay_ms: vars.parse("HEDGE_DELAY_MS", defaults.hedge_delay_ms)?,
+            retry: RetryPolicy {
+                max_attempts: vars.parse("RETRY_MAX_ATTEMPTS", retry_defaults.max_attempts)?,
+                base_delay_ms: vars.parse("RETRY_BASE_DELAY_MS", retry_defaults.base_delay_ms)?,
+                max_delay_ms: vars.parse("RETRY_MAX_DELAY_MS", retry_defaults.max_delay_ms)?,
+                jitter: vars.bool("RETRY_JITTER", retry_defaults.jitter)?,
+                budget_ms: vars.parse("RETRY_BUDGET_MS", retry_defaults.budget_ms)?,
+            },
+            upstream_timeout_ms: vars.parse("UPSTREAM_TIMEOUT_MS", defaults.upstream_timeout_ms)?,
+            shadow_model: vars.string("SHADOW_MODEL"),
+            shadow_sample_percent: vars
+                .parse("SHADOW_SAMPLE_PERCENT", defaults.shadow_sample_percent)?,
+            auto_model: AutoModelConfig {
+                cheap_model: vars.string_or("AUTO_CHEAP_MODEL", auto_defaults.cheap_model),
+                strong_model: vars.string_or("AUTO_STRONG_MODEL", auto_defaults.strong_model),
+                max_cheap_prompt_chars: vars.parse(
+                    "AUTO_MAX_CHEAP_PROMPT_CHARS",
+                    auto_defaults.max_cheap_prompt_chars,
+                )?,
+                max_cheap_tools: vars
+                    .parse("AUTO_MAX_CHEAP_TOOLS", auto_defaults.max_cheap_tools)?,
+                code_requires_strong: vars.bool(
+                    "AUTO_CODE_REQUIRES_STRONG",
+                    auto_defaults.code_requires_strong,
+                )?,
+            },
+            regional_upstreams,
+            unsupported_params,
+            code_execution_model: vars.string("CODE_EXECUTION_MODEL"),
+            model_targets: ModelTargets {
+                haiku: vars.string_or("MODEL_HAIKU", target_defaults.haiku),
+                sonnet: vars.string_or("MODEL_SONNET", target_defaults.sonnet),
+                opus: vars.string_or("MODEL_OPUS", target_defaults.opus),
+            },
+            error_verbosity: vars.parse("ERROR_VERBOSITY", defaults.error_verbosity)?,
+            slow_request_warn_ms: vars
+                .parse("SLOW_REQUEST_WARN_MS", defaults.slow_request_warn_ms)?,
+            stream_coalesce_after_events: vars.parse(
+                "STREAM_COALESCE_AFTER_EVENTS",
+                defaults.stream_coalesce_after_events,
+            )?,
+            log_level: vars.parse("LOG_LEVEL", defaults.log_level)?,
+            debug_tracing: vars.bool("DEBUG_TRACING", defaults.debug_tracing)?,
+            features,
+            feature_flags,
+            server_api_key: vars
+                .string("OPENROUTER_API_KEY_STORE")
+                .map(|binding| Credential::SecretsStore { binding })
+                .or_else(|| {
+                    let keys: Vec<String> = vars
+                        .string("OPENROUTER_API_KEYS")?
+                        .split(',')
+                        .map(|key| key.trim().to_string())
+                        .filter(|key| !key.is_empty())
+                        .collect();
+                    (!keys.is_empty()).then_some(Credential::Pool(keys))
+                })
+                .or_else(|| vars.string("OPENROUTER_API_KEY").map(Credential::Static)),
+            model_keys: match vars.string("MODEL_KEYS") {
+                Some(raw) => {
+                    ModelKeys::parse(&raw).map_err(|e| invalid("MODEL_KEYS", "[REDACTED]", e))?
+                }
+                None => ModelKeys::default(),
+            },
+            key_cooldown_secs: vars.parse("KEY_COOLDOWN_SECS", defaults.key_cooldown_secs)?,
+            free_fallback: vars.bool("FREE_FALLBACK", defaults.free_fallback)?,
+            context_recovery: vars.parse("CONTEXT_RECOVERY", defaults.context_recovery)?,
+            long_context_model: vars.string("LONG_CONTEXT_MODEL"),
+            compare_models: vars
+                .string("COMPARE_MODELS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|model| model.trim().to_string())
+                .filter(|model| !model.is_empty())
+                .collect(),
+            force_server_key: vars.bool("FORCE_SERVER_KEY", defaults.force_server_key)?,
+            rate_limits: RateLimitConfig {
+                key_rpm: vars.parse("RATE_LIMIT_KEY_RPM", limit_defaults.key_rpm)?,
+                key_tpm: vars.parse("RATE_LIMIT_KEY_TPM", limit_defaults.key_tpm)?,
+                ip_rpm: vars.parse("RATE_LIMIT_IP_RPM", limit_defaults.ip_rpm)?,
+                ip_tpm: vars.parse("RATE_LIMIT_IP_TPM", limit_defaults.ip_tpm)?,
+                key_concurrency: vars
+                    .parse("RATE_LIMIT_KEY_CONCURRENCY", limit_defaults.key_concurrency)?,
+                concurrency_queue_ms: vars
+                    .parse("CONCURRENCY_QUEUE_MS", limit_defaults.concurrency_queue_ms)?,
+                admission_background_limit: vars.parse(
+                    "ADMISSION_BACKGROUND_LIMIT",
+                    limit_defaults.admission_background_limit,
+                )?,
+            },
+            request_limits: RequestLimits {
+                max_body_bytes: vars.parse("MAX_BODY_BYTES", size_defaults.max_body_bytes)?,
+                max_messages: vars.parse("MAX_MESSAGES", size_defaults.max_messages)?,
+                max_tools: vars.parse("MAX_TOOLS", size_defaults.max_tools)?,
+                max_image_bytes: vars.parse("MAX_IMAGE_BYTES", size_defaults.max_image_bytes)?,
+            },
+            access_token: vars.string("CCR_ACCESS_TOKEN"),
+            allowed_key_hashes: vars
+                .string("ALLOWED_KEY_HASHES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|hash| hash.trim().to_lowercase())
+                .filter(|hash| !hash.is_empty())
+                .collect(),
+            jwt: vars.string("JWT_JWKS_URL").map(|jwks_url| JwtConfig {
+                jwks_url,
+                header: vars.string_or("JWT_HEADER", jwt::CF_ACCESS_HEADER.to_string()),
+                issuer: vars.string("JWT_ISSUER"),
+                audience: vars.string("JWT_AUDIENCE"),
+            }),
+            cors_allowed_origins: vars
+                .string("CORS_ALLOWED_ORIGINS")
+                .map(|raw| cors::parse_origins(&raw))
+                .unwrap_or_default(),
+            header_policy,
+            branding,
+            idempotency_ttl_secs: vars
+                .parse("IDEMPOTENCY_TTL_SECS", defaults.idempotency_ttl_secs)?,
+            async_result_ttl_secs: vars
+                .parse("ASYNC_RESULT_TTL_SECS", defaults.async_result_ttl_secs)?,
+            response_cache_ttl_secs: vars
+                .parse("RESPONSE_CACHE_TTL_SECS", defaults.response_cache_ttl_secs)?,
+            compress_min_bytes: vars.parse("COMPRESS_MIN_BYTES", defaults.compress_min_bytes)?,
+            session_max_messages: vars
+                .parse("SESSION_MAX_MESSAGES", defaults.session_max_messages)?,
+            session_ttl_secs: vars.parse("SESSION_TTL_SECS", defaults.session_ttl_secs)?,
+            semantic_cache: SemanticCacheConfig {
+                models: match vars.string("SEMANTIC_CACHE_MODELS") {
+                    Some(raw) => raw
+                        .split(',')
+                        .map(|model| model.trim().to_lowercase())
+                        .filter(|model| !model.is_empty())
+                        .collect(),
+                    None => semantic_defaults.models,
+                },
+                threshold: vars.parse("SEMANTIC_CACHE_THRESHOLD", semantic_defaults.threshold)?,
+            },
+            analytics_sql: match (
+                vars.string("CF_ACCOUNT_ID"),
+                vars.string("CF_ANALYTICS_TOKEN"),
+            ) {
+                (Some(account_id), Some(api_token)) => Some(AnalyticsSqlConfig {
+                    account_id,
+                    api_token,
+                    dataset: vars.string_or("ANALYTICS_DATASET", "ccr_usage".to_string()),
+                }),
+                _ => None,
+            },
+            log_to_r2: vars.parse("LOG_TO_R2", defaults.log_to_r2)?,
+            error_reporting,
+            alerts,
+            digest_webhook_url: vars.string("DIGEST_WEBHOOK_URL"),
+            attachments,
+            response_signing_key: vars.string("RESPONSE_SIGNING_KEY"),
+            pii,
+            prompt_injection: vars.parse("PROMPT_INJECTION", defaults.prompt_injection)?,
+            image_fallback: vars.parse("IMAGE_FALLBACK", defaults.image_fallback)?,
+            image_caption_model: vars
+                .string_or("IMAGE_CAPTION_MODEL", defaults.image_caption_model),
+            prompt_cache_hints: vars.bool("PROMPT_CACHE_HINTS", defaults.prompt_cache_hints)?,
+            web_search: vars.bool("WEB_SEARCH", defaults.web_search)?,
+            moderation,
+            config_ttl_secs: vars.parse("CONFIG_TTL_SECS", defaults.config_ttl_secs)?,
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks cross-field and range constraints that parsing alone can't express
+    fn validate(&self) -> Result<()> {
+        let base_url = &self.openrouter_base_url;
+        if !base_url.starts_with("https://") && !base_url.starts_with("http://") {
+            return Err(invalid(
+                "OPENROUTER_BASE_URL",
+                base_url,
+                "must be an http(s) URL",
+            ));
+        }
+
+        if let Some((code, url)) = self
+            .regional_upstreams
+            .iter()
+            .find(|(_, url)| !url.starts_with("https://") && !url.starts_with("http://"))
+        {
+            return Err(invalid(
+                "REGIONAL_UPSTREAMS",
+                url,
+                format!("upstream for '{code}' must be an http(s) URL"),
+            ));
+        }
+
+        if let Some(ReportSink::Webhook(url)) = &self.error_reporting {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(invalid("ERROR_WEBHOOK_URL", url, "must be an http(s) URL"));
+            }
+        }
+
+        if let Some(attachments) = &self.attachments {
+            if attachments.url_ttl_secs < 60 {
+                return Err(invalid(
+                    "ATTACHMENT_URL_TTL_SECS",
+                    &attachments.url_ttl_secs.to_string(),
+                    "must be at least 60",
+                ));
+            }
+        }
+
+        if let Some(alerts) = &self.alerts {
+            let url = &alerts.webhook_url;
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(invalid("ALERT_WEBHOOK_URL", url, "must be an http(s) URL"));
+            }
+            if alerts.window_secs < 60 {
+                return Err(invalid(
+                    "ALERT_WINDOW_SECS",
+                    &alerts.window_secs.to_string(),
+                    "must be at least 60",
+                ));
+            }
+            if alerts.error_rate_percent > 100 {
+                return Err(invalid(
+                    "ALERT_ERROR_RATE_PERCENT",
+                    &alerts.error_rate_percent.to_string(),
+                    "must be between 0 and 100",
+                ));
+            }
+            if !alerts.daily_spend_usd.is_finite() || alerts.daily_spend_usd < 0.0 {
+                return Err(invalid(
+                    "ALERT_DAILY_SPEND_USD",
+                    &alerts.daily_spend_usd.to_string(),
+                    "must be a non-negative number",
+                ));
+            }
+        }
+
+        if let Some(url) = &self.digest_webhook_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(invalid("DIGEST_WEBHOOK_URL", url, "must be an http(s) URL"));
+            }
+            if self.analytics_sql.is_none() {
+                return Err(invalid(
+                    "DIGEST_WEBHOOK_URL",
+                    url,
+                    "requires CF_ACCOUNT_ID and CF_ANALYTICS_TOKEN",
+                ));
+            }
+        }
+
+        if self.shadow_sample_percent > 100 {
+            return Err(invalid(
+                "SHADOW_SAMPLE_PERCENT",
+                &self.shadow_sample_percent.to_string(),
+                "must be between 0 and 100",
+            ));
+        }
+
+        if let Some(analytics) = &self.analytics_sql {
+            if !usage::is_valid_dataset(&analytics.dataset) {
+                return Err(invalid(
+                    "ANALYTICS_DATASET",
+                    &analytics.dataset,
+                    "must contain only letters, digits and underscores",
+                ));
+            }
+        }
+
+        // KV rejects expirations shorter than a minute
+        if self.idempotency_ttl_secs < 60 {
+            return Err(invalid(
+                "IDEMPOTENCY_TTL_SECS",
+                &self.idempotency_ttl_secs.to_string(),
+                "must be at least 60",
+            ));
+        }
+        if self.async_result_ttl_secs < 60 {
+            return Err(invalid(
+                "ASYNC_RESULT_TTL_SECS",
+                &self.async_result_ttl_secs.to_string(),
+                "must be at least 60",
+            ));
+        }
+        // A queued request holds its isolate; keep the wait well inside a request's lifetime
+        if self.rate_limits.concurrency_queue_ms > 30_000 {
+            return Err(invalid(
+                "CONCURRENCY_QUEUE_MS",
+                &self.rate_limits.concurrency_queue_ms.to_string(),
+                "must be at most 30000",
+            ));
+        }
+        // A session needs room for at least one user turn and its reply
+        if self.session_max_messages < 2 {
+            return Err(invalid(
+                "SESSION_MAX_MESSAGES",
+                &self.session_max_messages.to_string(),
+                "must be at least 2",
+            ));
+        }
+        if self.session_ttl_secs == 0 {
+            return Err(invalid(
+                "SESSION_TTL_SECS",
+                &self.session_ttl_secs.to_string(),
+                "must be greater than 0",
+            ));
+        }
+        if self.response_cache_ttl_secs < 60 {
+            return Err(invalid(
+                "RESPONSE_CACHE_TTL_SECS",
+                &self.response_cache_ttl_secs.to_string(),
+                "must be at least 60",
+            ));
+        }
+
+        if !(self.semantic_cache.threshold > 0.0 && self.semantic_cache.threshold <= 1.0) {
+            return Err(invalid(
+                "SEMANTIC_CACHE_THRESHOLD",
+                &self.semantic_cache.threshold.to_string(),
+                "must be greater than 0 and at most 1",
+            ));
+        }
+
+        if self.force_server_key && self.server_api_key.is_none() {
+            return Err(invalid(
+                "FORCE_SERVER_KEY",
+                "true",
+                "requires OPENROUTER_API_KEY or OPENROUTER_API_KEY_STORE to be set",
+            ));
+        }
+
+        // An upstream key with nothing in front of it is spent by anyone who finds the URL
+        let has_upstream_key = self.server_api_key.is_some() || !self.model_keys.is_empty();
+        if has_upstream_key
+            && self.access_token.is_none()
+            && self.allowed_key_hashes.is_empty()
+            && self.jwt.is_none()
+        {
+            let name = if self.server_api_key.is_some() {
+                "OPENROUTER_API_KEY"
+            } else {
+                "MODEL_KEYS"
+            };
+            return Err(invalid(
+                name,
+                "[REDACTED]",
+                "requires CCR_ACCESS_TOKEN, ALLOWED_KEY_HASHES or JWT_JWKS_URL to be set",
+            ));
+        }
+
+        if let Some(hash) = self
+            .allowed_key_hashes
+            .iter()
+            .find(|hash| hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(invalid(
+                "ALLOWED_KEY_HASHES",
+                hash,
+                "entries must be hex SHA-256 digests",
+            ));
+        }
+
+        if let Some(jwt) = &self.jwt {
+            if !jwt.jwks_url.starts_with("https://") {
+                return Err(invalid(
+                    "JWT_JWKS_URL",
+                    &jwt.jwks_url,
+                    "must be an https URL",
+                ));
+            }
+        }
+
+        for origin in &self.cors_allowed_origins {
+            cors::validate_origin(origin)
+                .map_err(|reason| invalid("CORS_ALLOWED_ORIGINS", origin, reason))?;
+        }
+
+        if self.default_max_tokens == 0 {
+            return Err(invalid("DEFAULT_MAX_TOKENS", "0", "must be positive"));
+        }
+
+        if self.retry.max_attempts == 0 {
+            return Err(invalid("RETRY_MAX_ATTEMPTS", "0", "must be at least 1"));
+        }
+
+        if self.context_recovery == ContextRecovery::Reroute && self.long_context_model.is_none() {
+            return Err(invalid(
+                "CONTEXT_RECOVERY",
+                "reroute",
+                "requires LONG_CONTEXT_MODEL",
+            ));
+        }
+
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn new(openrouter_base_url: String) -> Self {
         Config {
             openrouter_base_url,
-            default_max_tokens: 4096,
+            ..Config::default()
+        }
+    }
+
+    /// Returns the upstream base URL to use for a request from `location`
+    pub fn upstream_base_url(&self, location: &RequestLocation) -> &str {
+        if !self.features.regional_upstreams {
+            return &self.openrouter_base_url;
+        }
+
+        geo::select_upstream(
+            &self.regional_upstreams,
+            location,
+            &self.openrouter_base_url,
+        )
+    }
+
+    /// Returns the model to hedge `primary_model` against, if hedging is enabled
+    ///
+    /// Hedging is skipped when the configured secondary model is the primary itself.
+    pub fn hedge_target(&self, primary_model: &str) -> Option<&str> {
+        self.hedge_model
+            .as_deref()
+            .filter(|_| self.features.hedging)
+            .filter(|hedge_model| *hedge_model != primary_model)
+    }
+
+    /// Returns the shadow model to mirror a request to, if it falls into the sample
+    ///
+    /// `seed` should vary per request; see [`crate::shadow::should_mirror`].
+    pub fn shadow_target(&self, primary_model: &str, seed: u64) -> Option<&str> {
+        self.shadow_model
+            .as_deref()
+            .filter(|_| self.features.shadow)
+            .filter(|shadow_model| *shadow_model != primary_model)
+            .filter(|_| crate::shadow::should_mirror(self.shadow_sample_percent, seed))
+    }
+
+    /// Whether requests for the Claude `model` may be answered from the semantic cache
+    pub fn semantic_cache_applies(&self, model: &str) -> bool {
+        self.features.semantic_cache && self.semantic_cache.applies_to(model)
+    }
+
+    /// Upstream models the configuration sends requests to, with the variable naming each
+    pub fn configured_models(&self) -> Vec<(&'static str, &str)> {
+        let mut models = vec![
+            ("MODEL_HAIKU", self.model_targets.haiku.as_str()),
+            ("MODEL_SONNET", self.model_targets.sonnet.as_str()),
+            ("MODEL_OPUS", self.model_targets.opus.as_str()),
+        ];
+        if self.features.auto_model {
+            models.push(("AUTO_CHEAP_MODEL", &self.auto_model.cheap_model));
+            models.push(("AUTO_STRONG_MODEL", &self.auto_model.strong_model));
+        }
+        let optional = [
+            ("HEDGE_MODEL", self.hedge_model.as_deref()),
+            ("SHADOW_MODEL", self.shadow_model.as_deref()),
+            ("CODE_EXECUTION_MODEL", self.code_execution_model.as_deref()),
+            ("LONG_CONTEXT_MODEL", self.long_context_model.as_deref()),
+        ];
+        models.extend(
+            optional
+                .into_iter()
+                .filter_map(|(name, model)| Some((name, model?))),
+        );
+        if self.image_fallback == ImageFallback::Describe {
+            models.push(("IMAGE_CAPTION_MODEL", &self.image_caption_model));
         }
+        models
     }
 }
 
@@ -40,6 +1141,14 @@ impl Config {
 mod tests {
     use super::*;
 
+    fn from_pairs(pairs: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
     #[test]
     fn test_config_new() {
         let config = Config::new("https://custom.openrouter.ai/api/v1".to_string());
@@ -58,8 +1167,909 @@ mod tests {
         assert_eq!(config.openrouter_base_url, "https://openrouter.ai/api/v1");
     }
 
-    // Note: Testing Config::from_env is difficult without mocking the worker::Env
-    // which is tightly coupled to the Cloudflare Workers runtime.
-    // In a real-world scenario, you might want to refactor this to accept
-    // a trait for environment variable access to make it more testable.
+    #[test]
+    fn test_from_vars_defaults() {
+        let config = from_pairs(&[]).unwrap();
+        assert_eq!(config.openrouter_base_url, "https://openrouter.ai/api/v1");
+        assert_eq!(config.default_max_tokens, 4096);
+        assert_eq!(config.hedge_model, None);
+        assert_eq!(config.error_verbosity, ErrorVerbosity::Basic);
+        assert_eq!(config.features, FeatureFlags::default());
+        assert_eq!(config.model_targets.sonnet, "anthropic/claude-sonnet-4");
+    }
+
+    #[test]
+    fn test_from_vars_overrides() {
+        let config = from_pairs(&[
+            ("OPENROUTER_BASE_URL", "https://proxy.example.com/v1/"),
+            ("DEFAULT_MAX_TOKENS", "8192"),
+            ("HEDGE_MODEL", "google/gemini-2.5-flash"),
+            ("HEDGE_DELAY_MS", "1500"),
+            ("ERROR_VERBOSITY", "Detailed"),
+            ("MODEL_SONNET", "deepseek/deepseek-chat"),
+            ("AUTO_CODE_REQUIRES_STRONG", "no"),
+            ("DISABLED_FEATURES", "shadow, hedging"),
+            ("RATE_LIMIT_KEY_RPM", "60"),
+            ("RATE_LIMIT_IP_TPM", "100000"),
+            ("MAX_TOOLS", "64"),
+            ("MAX_BODY_BYTES", "0"),
+            ("LOG_LEVEL", "debug"),
+            ("LOG_TO_R2", "hashed"),
+            ("ERROR_WEBHOOK_URL", "https://hooks.example.com/ccr"),
+            (
+                "ALERT_WEBHOOK_URL",
+                "https://hooks.slack.com/services/T0/B0/x",
+            ),
+            ("ALERT_DAILY_SPEND_USD", "25"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.openrouter_base_url, "https://proxy.example.com/v1");
+        assert_eq!(config.default_max_tokens, 8192);
+        assert_eq!(
+            config.hedge_model.as_deref(),
+            Some("google/gemini-2.5-flash")
+        );
+        assert_eq!(config.hedge_delay_ms, 1500);
+        assert_eq!(config.error_verbosity, ErrorVerbosity::Detailed);
+        assert_eq!(config.model_targets.sonnet, "deepseek/deepseek-chat");
+        assert!(!config.auto_model.code_requires_strong);
+        assert!(!config.features.shadow);
+        assert!(!config.features.hedging);
+        assert!(config.features.auto_model);
+        assert_eq!(config.hedge_target("moonshotai/kimi-k2:free"), None);
+        assert_eq!(config.rate_limits.key_rpm, 60);
+        assert_eq!(config.rate_limits.key_tpm, 0);
+        assert_eq!(config.rate_limits.ip_tpm, 100_000);
+        assert_eq!(config.request_limits.max_tools, 64);
+        assert_eq!(config.request_limits.max_body_bytes, 0);
+        assert_eq!(config.request_limits.max_messages, 100_000);
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(config.log_to_r2, TranscriptMode::Hashed);
+        assert_eq!(
+            config.error_reporting,
+            Some(ReportSink::Webhook(
+                "https://hooks.example.com/ccr".to_string()
+            ))
+        );
+        let alerts = config.alerts.unwrap();
+        assert_eq!(alerts.daily_spend_usd, 25.0);
+        assert_eq!(alerts.error_rate_percent, 20);
+        assert_eq!(alerts.window_secs, 300);
+    }
+
+    #[test]
+    fn test_from_vars_server_key() {
+        let config = from_pairs(&[
+            ("OPENROUTER_API_KEY", "sk-or-server"),
+            ("FORCE_SERVER_KEY", "true"),
+            ("CCR_ACCESS_TOKEN", "team-secret"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.server_api_key,
+            Some(Credential::Static("sk-or-server".to_string()))
+        );
+        assert!(config.force_server_key);
+    }
+
+    #[test]
+    fn test_from_vars_server_key_pool() {
+        let config = from_pairs(&[
+            ("OPENROUTER_API_KEY", "sk-or-single"),
+            ("OPENROUTER_API_KEYS", "sk-or-a, sk-or-b,"),
+            ("KEY_COOLDOWN_SECS", "300"),
+            ("CCR_ACCESS_TOKEN", "team-secret"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.server_api_key,
+            Some(Credential::Pool(vec![
+                "sk-or-a".to_string(),
+                "sk-or-b".to_string()
+            ]))
+        );
+        assert_eq!(config.key_cooldown_secs, 300);
+        assert_eq!(from_pairs(&[]).unwrap().key_cooldown_secs, 60);
+    }
+
+    #[test]
+    fn test_from_vars_free_fallback() {
+        assert!(!from_pairs(&[]).unwrap().free_fallback);
+        assert!(
+            from_pairs(&[("FREE_FALLBACK", "true")])
+                .unwrap()
+                .free_fallback
+        );
+    }
+
+    #[test]
+    fn test_from_vars_debug_tracing() {
+        assert!(!from_pairs(&[]).unwrap().debug_tracing);
+        assert!(
+            from_pairs(&[("DEBUG_TRACING", "true")])
+                .unwrap()
+                .debug_tracing
+        );
+    }
+
+    #[test]
+    fn test_from_vars_context_recovery() {
+        assert_eq!(
+            from_pairs(&[]).unwrap().context_recovery,
+            ContextRecovery::Off
+        );
+        let config = from_pairs(&[
+            ("CONTEXT_RECOVERY", "reroute"),
+            ("LONG_CONTEXT_MODEL", "google/gemini-2.5-pro"),
+        ])
+        .unwrap();
+        assert_eq!(config.context_recovery, ContextRecovery::Reroute);
+        assert_eq!(
+            config.long_context_model.as_deref(),
+            Some("google/gemini-2.5-pro")
+        );
+        assert!(config
+            .configured_models()
+            .contains(&("LONG_CONTEXT_MODEL", "google/gemini-2.5-pro")));
+    }
+
+    #[test]
+    fn test_from_vars_model_keys() {
+        assert!(from_pairs(&[]).unwrap().model_keys.is_empty());
+        let config = from_pairs(&[
+            (
+                "MODEL_KEYS",
+                r#"{"openai/": "sk-or-openai", "openai/o": "sk-or-reasoning", "moonshotai/": ["sk-or-free-1", "sk-or-free-2"]}"#,
+            ),
+            ("CCR_ACCESS_TOKEN", "team-secret"),
+        ])
+        .unwrap();
+        let keys = &config.model_keys;
+        assert_eq!(
+            keys.for_model("openai/gpt-4o"),
+            Some(&Credential::Static("sk-or-openai".to_string()))
+        );
+        // The most specific prefix wins
+        assert_eq!(
+            keys.for_model("openai/o3"),
+            Some(&Credential::Static("sk-or-reasoning".to_string()))
+        );
+        assert_eq!(
+            keys.for_model("moonshotai/kimi-k2:free"),
+            Some(&Credential::Pool(vec![
+                "sk-or-free-1".to_string(),
+                "sk-or-free-2".to_string()
+            ]))
+        );
+        assert_eq!(keys.for_model("anthropic/claude-sonnet-4"), None);
+
+        // Keys stay out of error messages
+        let error = from_pairs(&[("MODEL_KEYS", r#"{"openai/": ["sk-or-secret", 1]}"#)])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(!error.contains("sk-or-secret"));
+    }
+
+    #[test]
+    fn test_from_vars_compare_models() {
+        assert!(from_pairs(&[]).unwrap().compare_models.is_empty());
+        let config = from_pairs(&[(
+            "COMPARE_MODELS",
+            "sonnet, deepseek/deepseek-chat,,moonshotai/kimi-k2",
+        )])
+        .unwrap();
+        assert_eq!(
+            config.compare_models,
+            ["sonnet", "deepseek/deepseek-chat", "moonshotai/kimi-k2"]
+        );
+    }
+
+    #[test]
+    fn test_from_vars_server_key_from_secrets_store() {
+        let config = from_pairs(&[
+            ("OPENROUTER_API_KEY", "sk-or-server"),
+            ("OPENROUTER_API_KEY_STORE", "OPENROUTER_KEY_SECRET"),
+            (
+                "JWT_JWKS_URL",
+                "https://team.cloudflareaccess.com/cdn-cgi/access/certs",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.server_api_key,
+            Some(Credential::SecretsStore {
+                binding: "OPENROUTER_KEY_SECRET".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_vars_access_control() {
+        let hash = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let config = from_pairs(&[
+            ("CCR_ACCESS_TOKEN", "team-secret"),
+            ("ALLOWED_KEY_HASHES", &format!("{hash}, ")),
+        ])
+        .unwrap();
+        assert_eq!(config.access_token.as_deref(), Some("team-secret"));
+        assert_eq!(
+            config.allowed_key_hashes,
+            HashSet::from([hash.to_lowercase()])
+        );
+    }
+
+    #[test]
+    fn test_from_vars_jwt() {
+        assert_eq!(from_pairs(&[]).unwrap().jwt, None);
+
+        let config = from_pairs(&[
+            (
+                "JWT_JWKS_URL",
+                "https://team.cloudflareaccess.com/cdn-cgi/access/certs",
+            ),
+            ("JWT_AUDIENCE", "app-aud-tag"),
+        ])
+        .unwrap();
+        let jwt = config.jwt.unwrap();
+        assert_eq!(jwt.header, "Cf-Access-Jwt-Assertion");
+        assert_eq!(jwt.audience.as_deref(), Some("app-aud-tag"));
+        assert_eq!(jwt.issuer, None);
+    }
+
+    #[test]
+    fn test_from_vars_cors_origins() {
+        assert!(from_pairs(&[]).unwrap().cors_allowed_origins.is_empty());
+
+        let config = from_pairs(&[(
+            "CORS_ALLOWED_ORIGINS",
+            "https://app.example.com/, http://localhost:5173",
+        )])
+        .unwrap();
+        assert_eq!(
+            config.cors_allowed_origins,
+            ["https://app.example.com", "http://localhost:5173"]
+        );
+    }
+
+    #[test]
+    fn test_from_vars_attribution() {
+        assert_eq!(from_pairs(&[]).unwrap().attribution, Attribution::default());
+        let config = from_pairs(&[
+            ("OPENROUTER_REFERER", "https://router.example.com"),
+            ("OPENROUTER_TITLE", "Example Router"),
+        ])
+        .unwrap();
+        assert_eq!(config.attribution.referer, "https://router.example.com");
+        assert_eq!(config.attribution.title, "Example Router");
+    }
+
+    #[test]
+    fn test_from_vars_branding() {
+        assert_eq!(from_pairs(&[]).unwrap().branding, Branding::default());
+        let config = from_pairs(&[
+            ("BRAND_NAME", "Acme Router"),
+            ("PUBLIC_URL", "https://llm.acme.dev/"),
+            ("CONTACT_URL", "mailto:platform@acme.dev"),
+            (
+                "EXAMPLE_ENV",
+                r#"{"ANTHROPIC_SMALL_FAST_MODEL": "google/gemini-2.5-flash"}"#,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(config.branding.name, "Acme Router");
+        assert_eq!(
+            config.branding.public_url.as_deref(),
+            Some("https://llm.acme.dev")
+        );
+        assert_eq!(config.branding.contact_label(), "platform@acme.dev");
+        assert_eq!(
+            config.branding.example_env,
+            [(
+                "ANTHROPIC_SMALL_FAST_MODEL".to_string(),
+                "google/gemini-2.5-flash".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_from_vars_header_policy() {
+        assert_eq!(
+            from_pairs(&[]).unwrap().header_policy,
+            HeaderPolicy::default()
+        );
+        let config = from_pairs(&[
+            ("FORWARD_HEADERS", "x-stainless-*, X-Org-Id"),
+            ("EXPOSE_HEADERS", "x-ratelimit-*"),
+            ("BLOCK_HEADERS", "x-stainless-retry-count"),
+        ])
+        .unwrap();
+        assert_eq!(config.header_policy.forward, ["x-stainless-*", "x-org-id"]);
+        assert!(config.header_policy.exposes("X-RateLimit-Remaining"));
+        assert!(!config.header_policy.forwards("x-stainless-retry-count"));
+    }
+
+    #[test]
+    fn test_from_vars_analytics_sql() {
+        assert_eq!(
+            from_pairs(&[("CF_ACCOUNT_ID", "acc")])
+                .unwrap()
+                .analytics_sql,
+            None
+        );
+
+        let config =
+            from_pairs(&[("CF_ACCOUNT_ID", "acc"), ("CF_ANALYTICS_TOKEN", "tok")]).unwrap();
+        assert_eq!(config.analytics_sql.unwrap().dataset, "ccr_usage");
+
+        assert!(from_pairs(&[
+            ("CF_ACCOUNT_ID", "acc"),
+            ("CF_ANALYTICS_TOKEN", "tok"),
+            ("ANALYTICS_DATASET", "usage; DROP TABLE"),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_configured_models() {
+        let config = from_pairs(&[
+            ("MODEL_OPUS", "openai/gpt-4.1"),
+            ("HEDGE_MODEL", "google/gemini-2.5-flash"),
+            ("DISABLED_FEATURES", "auto_model"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.configured_models(),
+            [
+                ("MODEL_HAIKU", "anthropic/claude-3.5-haiku"),
+                ("MODEL_SONNET", "anthropic/claude-sonnet-4"),
+                ("MODEL_OPUS", "openai/gpt-4.1"),
+                ("HEDGE_MODEL", "google/gemini-2.5-flash"),
+            ]
+        );
+
+        let config = from_pairs(&[("IMAGE_FALLBACK", "describe")]).unwrap();
+        let names: Vec<&str> = config
+            .configured_models()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "MODEL_HAIKU",
+                "MODEL_SONNET",
+                "MODEL_OPUS",
+                "AUTO_CHEAP_MODEL",
+                "AUTO_STRONG_MODEL",
+                "IMAGE_CAPTION_MODEL"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_vars_digest_webhook() {
+        let analytics = [("CF_ACCOUNT_ID", "acc"), ("CF_ANALYTICS_TOKEN", "tok")];
+        assert_eq!(from_pairs(&analytics).unwrap().digest_webhook_url, None);
+
+        let mut vars = analytics.to_vec();
+        vars.push(("DIGEST_WEBHOOK_URL", "https://hooks.slack.com/services/x"));
+        assert_eq!(
+            from_pairs(&vars).unwrap().digest_webhook_url.as_deref(),
+            Some("https://hooks.slack.com/services/x")
+        );
+
+        vars.push(("DIGEST_WEBHOOK_URL", "hooks.slack.com/services/x"));
+        assert!(from_pairs(&vars).is_err());
+    }
+
+    #[test]
+    fn test_from_vars_blank_values_use_defaults() {
+        let config = from_pairs(&[("HEDGE_MODEL", "  "), ("DEFAULT_MAX_TOKENS", "")]).unwrap();
+        assert_eq!(config.hedge_model, None);
+        assert_eq!(config.default_max_tokens, 4096);
+    }
+
+    #[test]
+    fn test_from_vars_rejects_malformed_values() {
+        let cases = [
+            ("DEFAULT_MAX_TOKENS", "lots"),
+            ("DEFAULT_MAX_TOKENS", "0"),
+            ("HEDGE_DELAY_MS", "-5"),
+            ("RETRY_MAX_ATTEMPTS", "0"),
+            ("RETRY_JITTER", "sometimes"),
+            ("UPSTREAM_TIMEOUT_MS", "10s"),
+            ("SHADOW_SAMPLE_PERCENT", "150"),
+            ("ERROR_VERBOSITY", "loud"),
+            ("AUTO_CODE_REQUIRES_STRONG", "maybe"),
+            ("DISABLED_FEATURES", "streaming"),
+            ("FEATURE_FLAGS", r#"{"streaming_v2": true}"#),
+            ("FEATURE_FLAGS", r#"{"hedging": 150}"#),
+            ("REGIONAL_UPSTREAMS", "{not json"),
+            ("REGIONAL_UPSTREAMS", r#"{"EU": "eu.example.com"}"#),
+            ("UNSUPPORTED_PARAMS", r#"{"openai/": ["seed"]}"#),
+            ("MODEL_KEYS", "sk-or-openai"),
+            ("MODEL_KEYS", r#"{"openai/": ""}"#),
+            ("MODEL_KEYS", r#"{"openai/": 42}"#),
+            ("MODEL_KEYS", r#"{"": "sk-or-any"}"#),
+            ("OPENROUTER_BASE_URL", "openrouter.ai"),
+            ("FORCE_SERVER_KEY", "true"),
+            ("OPENROUTER_API_KEY", "sk-or-open-to-anyone"),
+            ("MODEL_KEYS", r#"{"openai/": "sk-or-open-to-anyone"}"#),
+            ("RATE_LIMIT_KEY_RPM", "-1"),
+            ("ALLOWED_KEY_HASHES", "not-a-hash"),
+            ("JWT_JWKS_URL", "http://team.cloudflareaccess.com/certs"),
+            ("CORS_ALLOWED_ORIGINS", "app.example.com"),
+            ("OPENROUTER_REFERER", "router.example.com"),
+            ("PUBLIC_URL", "llm.acme.dev"),
+            ("CONTACT_URL", "javascript:alert(1)"),
+            ("EXAMPLE_ENV", "{\"ANTHROPIC_MODEL\": 1}"),
+            ("FORWARD_HEADERS", "x-org id"),
+            ("EXPOSE_HEADERS", "x-*-id"),
+            ("IDEMPOTENCY_TTL_SECS", "30"),
+            ("RESPONSE_CACHE_TTL_SECS", "1h"),
+            ("COMPRESS_MIN_BYTES", "1KB"),
+            ("ASYNC_RESULT_TTL_SECS", "10"),
+            ("SESSION_MAX_MESSAGES", "1"),
+            ("PII_REDACTION", "email,phone"),
+            ("PII_PATTERNS", "EMP-\\d+"),
+            ("PII_PATTERNS", r#"["(unclosed"]"#),
+            ("MODERATION", "llama-guard"),
+            ("PROMPT_INJECTION", "strip"),
+            ("IMAGE_FALLBACK", "caption"),
+            ("PROMPT_CACHE_HINTS", "always"),
+            ("WEB_SEARCH", "online"),
+            ("RATE_LIMIT_KEY_CONCURRENCY", "many"),
+            ("CONCURRENCY_QUEUE_MS", "60000"),
+            ("ADMISSION_BACKGROUND_LIMIT", "-1"),
+            ("SESSION_TTL_SECS", "0"),
+            ("STREAM_COALESCE_AFTER_EVENTS", "1k"),
+            ("KEY_COOLDOWN_SECS", "-5"),
+            ("FREE_FALLBACK", "when-broke"),
+            ("CONTEXT_RECOVERY", "summarize"),
+            ("CONTEXT_RECOVERY", "reroute"),
+            ("SEMANTIC_CACHE_THRESHOLD", "1.5"),
+            ("SEMANTIC_CACHE_THRESHOLD", "high"),
+            ("CONFIG_TTL_SECS", "5m"),
+            ("MAX_IMAGE_BYTES", "5MB"),
+            ("LOG_LEVEL", "verbose"),
+            ("DEBUG_TRACING", "verbose"),
+            ("LOG_TO_R2", "everything"),
+            ("SENTRY_DSN", "https://o42.ingest.sentry.io/4501"),
+            ("ERROR_WEBHOOK_URL", "hooks.example.com/ccr"),
+            ("ALERT_WEBHOOK_URL", "hooks.slack.com/services/x"),
+            ("DIGEST_WEBHOOK_URL", "https://hooks.slack.com/services/x"),
+        ];
+
+        for (name, value) in cases {
+            let err = from_pairs(&[(name, value)]).err().unwrap_or_else(|| {
+                panic!("{name}={value} should be rejected");
+            });
+            assert!(err.to_string().contains(name), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_from_vars_retry() {
+        assert_eq!(from_pairs(&[]).unwrap().retry, RetryPolicy::default());
+        let config = from_pairs(&[
+            ("RETRY_MAX_ATTEMPTS", "1"),
+            ("RETRY_BASE_DELAY_MS", "100"),
+            ("RETRY_JITTER", "false"),
+            ("RETRY_BUDGET_MS", "5000"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.retry,
+            RetryPolicy {
+                max_attempts: 1,
+                base_delay_ms: 100,
+                max_delay_ms: 2000,
+                jitter: false,
+                budget_ms: 5000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_vars_upstream_timeout() {
+        assert_eq!(from_pairs(&[]).unwrap().upstream_timeout_ms, 0);
+        let config = from_pairs(&[("UPSTREAM_TIMEOUT_MS", "20000")]).unwrap();
+        assert_eq!(config.upstream_timeout_ms, 20000);
+    }
+
+    #[test]
+    fn test_from_vars_async_result_ttl() {
+        assert_eq!(from_pairs(&[]).unwrap().async_result_ttl_secs, 86400);
+        let config = from_pairs(&[("ASYNC_RESULT_TTL_SECS", "3600")]).unwrap();
+        assert_eq!(config.async_result_ttl_secs, 3600);
+    }
+
+    #[test]
+    fn test_from_vars_concurrency_limit() {
+        let config = from_pairs(&[]).unwrap();
+        assert_eq!(config.rate_limits.key_concurrency, 0);
+        assert_eq!(config.rate_limits.concurrency_queue_ms, 0);
+        let config = from_pairs(&[
+            ("RATE_LIMIT_KEY_CONCURRENCY", "8"),
+            ("CONCURRENCY_QUEUE_MS", "5000"),
+        ])
+        .unwrap();
+        assert_eq!(config.rate_limits.key_concurrency, 8);
+        assert_eq!(config.rate_limits.concurrency_queue_ms, 5000);
+    }
+
+    #[test]
+    fn test_from_vars_admission_background_limit() {
+        assert_eq!(
+            from_pairs(&[])
+                .unwrap()
+                .rate_limits
+                .admission_background_limit,
+            0
+        );
+        let config = from_pairs(&[("ADMISSION_BACKGROUND_LIMIT", "200")]).unwrap();
+        assert_eq!(config.rate_limits.admission_background_limit, 200);
+    }
+
+    #[test]
+    fn test_from_vars_stream_coalescing() {
+        assert_eq!(from_pairs(&[]).unwrap().stream_coalesce_after_events, 1000);
+        let config = from_pairs(&[("STREAM_COALESCE_AFTER_EVENTS", "0")]).unwrap();
+        assert_eq!(config.stream_coalesce_after_events, 0);
+    }
+
+    #[test]
+    fn test_from_vars_sessions() {
+        let config = from_pairs(&[]).unwrap();
+        assert_eq!(config.session_max_messages, 100);
+        assert_eq!(config.session_ttl_secs, 604800);
+        let config =
+            from_pairs(&[("SESSION_MAX_MESSAGES", "40"), ("SESSION_TTL_SECS", "3600")]).unwrap();
+        assert_eq!(config.session_max_messages, 40);
+        assert_eq!(config.session_ttl_secs, 3600);
+    }
+
+    #[test]
+    fn test_from_vars_response_cache_ttl() {
+        assert_eq!(from_pairs(&[]).unwrap().response_cache_ttl_secs, 3600);
+        let config = from_pairs(&[("RESPONSE_CACHE_TTL_SECS", "600")]).unwrap();
+        assert_eq!(config.response_cache_ttl_secs, 600);
+        assert!(from_pairs(&[("RESPONSE_CACHE_TTL_SECS", "30")]).is_err());
+    }
+
+    #[test]
+    fn test_from_vars_semantic_cache() {
+        let config = from_pairs(&[
+            ("SEMANTIC_CACHE_MODELS", "Haiku, gpt-4o-mini,"),
+            ("SEMANTIC_CACHE_THRESHOLD", "0.9"),
+        ])
+        .unwrap();
+        assert_eq!(config.semantic_cache.models, ["haiku", "gpt-4o-mini"]);
+        assert_eq!(config.semantic_cache.threshold, 0.9);
+        assert!(config.semantic_cache_applies("claude-3-5-haiku-latest"));
+        assert!(!config.semantic_cache_applies("claude-sonnet-4"));
+
+        let config = from_pairs(&[("DISABLED_FEATURES", "semantic_cache")]).unwrap();
+        assert!(!config.semantic_cache_applies("claude-3-5-haiku-latest"));
+    }
+
+    #[test]
+    fn test_from_vars_pii() {
+        assert!(from_pairs(&[]).unwrap().pii.is_none());
+
+        let config = from_pairs(&[("PII_REDACTION", "email, api_key")]).unwrap();
+        assert!(config.pii.unwrap().restore);
+
+        let config = from_pairs(&[
+            ("PII_PATTERNS", r#"["EMP-\\d{6}"]"#),
+            ("PII_RESTORE", "false"),
+        ])
+        .unwrap();
+        assert!(!config.pii.unwrap().restore);
+        assert!(from_pairs(&[("PII_REDACTION", "all")])
+            .unwrap()
+            .pii
+            .is_some());
+    }
+
+    #[test]
+    fn test_from_vars_compression() {
+        assert_eq!(from_pairs(&[]).unwrap().compress_min_bytes, 1024);
+        let config = from_pairs(&[("COMPRESS_MIN_BYTES", "0")]).unwrap();
+        assert_eq!(config.compress_min_bytes, 0);
+    }
+
+    #[test]
+    fn test_from_vars_prompt_injection() {
+        assert_eq!(
+            from_pairs(&[]).unwrap().prompt_injection,
+            InjectionMode::Off
+        );
+        let config = from_pairs(&[("PROMPT_INJECTION", "Neutralize")]).unwrap();
+        assert_eq!(config.prompt_injection, InjectionMode::Neutralize);
+    }
+
+    #[test]
+    fn test_from_vars_image_fallback() {
+        let config = from_pairs(&[]).unwrap();
+        assert_eq!(config.image_fallback, ImageFallback::Off);
+        assert_eq!(config.image_caption_model, vision::DEFAULT_CAPTION_MODEL);
+        let config = from_pairs(&[
+            ("IMAGE_FALLBACK", "describe"),
+            ("IMAGE_CAPTION_MODEL", "openai/gpt-4o-mini"),
+        ])
+        .unwrap();
+        assert_eq!(config.image_fallback, ImageFallback::Describe);
+        assert_eq!(config.image_caption_model, "openai/gpt-4o-mini");
+    }
+
+    #[test]
+    fn test_from_vars_prompt_cache_hints() {
+        assert!(!from_pairs(&[]).unwrap().prompt_cache_hints);
+        assert!(
+            from_pairs(&[("PROMPT_CACHE_HINTS", "true")])
+                .unwrap()
+                .prompt_cache_hints
+        );
+    }
+
+    #[test]
+    fn test_from_vars_unsupported_params() {
+        let defaults = from_pairs(&[]).unwrap().unsupported_params;
+        assert_eq!(
+            defaults.for_model("openai/gpt-4o").collect::<Vec<_>>(),
+            ["top_k"]
+        );
+
+        let params = from_pairs(&[(
+            "UNSUPPORTED_PARAMS",
+            r#"{"openai/": [], "anthropic/": ["stop"]}"#,
+        )])
+        .unwrap()
+        .unsupported_params;
+        assert_eq!(params.for_model("openai/gpt-4o").count(), 0);
+        assert_eq!(
+            params.for_model("openai/o3").collect::<Vec<_>>(),
+            ["temperature", "top_p"]
+        );
+        assert_eq!(
+            params
+                .for_model("anthropic/claude-sonnet-4")
+                .collect::<Vec<_>>(),
+            ["stop"]
+        );
+    }
+
+    #[test]
+    fn test_from_vars_code_execution_model() {
+        assert_eq!(from_pairs(&[]).unwrap().code_execution_model, None);
+        let config = from_pairs(&[("CODE_EXECUTION_MODEL", "openai/gpt-4.1")]).unwrap();
+        assert_eq!(config.code_execution_model(), Some("openai/gpt-4.1"));
+    }
+
+    #[test]
+    fn test_from_vars_web_search() {
+        assert!(!from_pairs(&[]).unwrap().web_search);
+        assert!(from_pairs(&[("WEB_SEARCH", "true")]).unwrap().web_search);
+    }
+
+    #[test]
+    fn test_from_vars_moderation() {
+        assert!(from_pairs(&[]).unwrap().moderation.is_none());
+        assert!(from_pairs(&[("MODERATION", "off")])
+            .unwrap()
+            .moderation
+            .is_none());
+
+        let moderation = from_pairs(&[("MODERATION", "workers_ai")])
+            .unwrap()
+            .moderation
+            .unwrap();
+        assert_eq!(
+            moderation.provider,
+            ModerationProvider::WorkersAi {
+                model: "@cf/meta/llama-guard-3-8b".to_string()
+            }
+        );
+        assert_eq!(moderation.action, ModerationAction::Block);
+
+        let moderation = from_pairs(&[
+            ("MODERATION", "https://api.openai.com/v1/moderations"),
+            ("MODERATION_API_KEY", "sk-mod"),
+            ("MODERATION_ACTION", "flag"),
+            ("MODERATION_CATEGORIES", "Violence, hate"),
+        ])
+        .unwrap()
+        .moderation
+        .unwrap();
+        assert_eq!(moderation.action, ModerationAction::Flag);
+        assert_eq!(moderation.categories, ["violence", "hate"]);
+        assert!(
+            from_pairs(&[("MODERATION", "workers_ai"), ("MODERATION_ACTION", "warn")]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_from_vars_config_ttl() {
+        assert_eq!(from_pairs(&[]).unwrap().config_ttl_secs, 300);
+        let config = from_pairs(&[("CONFIG_TTL_SECS", "0")]).unwrap();
+        assert_eq!(config.config_ttl_secs, 0);
+    }
+
+    #[test]
+    fn test_config_cache_refreshes_after_ttl() {
+        let cache = ConfigCache::default();
+        let loads = std::cell::Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok(Config {
+                config_ttl_secs: 60,
+                ..Config::default()
+            })
+        };
+
+        let first = cache.get(1_000, load).unwrap();
+        let reused = cache.get(60_999, load).unwrap();
+        assert!(Rc::ptr_eq(&first, &reused));
+        assert_eq!(loads.get(), 1);
+
+        let refreshed = cache.get(61_000, load).unwrap();
+        assert!(!Rc::ptr_eq(&first, &refreshed));
+        assert_eq!(loads.get(), 2);
+    }
+
+    #[test]
+    fn test_config_cache_does_not_cache_errors() {
+        let cache = ConfigCache::default();
+        assert!(cache
+            .get(0, || Err(worker::Error::RustError("bad".to_string())))
+            .is_err());
+
+        let never_expires = || {
+            Ok(Config {
+                config_ttl_secs: 0,
+                ..Config::default()
+            })
+        };
+        let config = cache.get(0, never_expires).unwrap();
+        let later = cache.get(u64::MAX - 1, never_expires).unwrap();
+        assert!(Rc::ptr_eq(&config, &later));
+    }
+
+    #[test]
+    fn test_from_vars_feature_flags() {
+        let config = from_pairs(&[
+            ("DISABLED_FEATURES", "shadow, hedging"),
+            (
+                "FEATURE_FLAGS",
+                r#"{"hedging": true, "model_transforms": false, "tool_schema_transform": 20}"#,
+            ),
+        ])
+        .unwrap();
+        // FEATURE_FLAGS wins over DISABLED_FEATURES
+        assert!(!config.features.shadow);
+        assert!(config.features.hedging);
+        assert!(!config.features.model_transforms);
+        // Partial rollouts are resolved per caller
+        assert!(!config.features.tool_schema_transform);
+        let on_for_some_keys = (0..100).any(|i| {
+            let mut features = config.features;
+            config
+                .feature_flags
+                .apply(&mut features, Some(&format!("key-{i}")));
+            features.tool_schema_transform
+        });
+        assert!(on_for_some_keys);
+    }
+
+    #[test]
+    fn test_from_vars_response_signing_key() {
+        assert!(from_pairs(&[]).unwrap().response_signing_key.is_none());
+        let config = from_pairs(&[("RESPONSE_SIGNING_KEY", "whsec_deploy")]).unwrap();
+        assert_eq!(config.response_signing_key.as_deref(), Some("whsec_deploy"));
+    }
+
+    #[test]
+    fn test_from_vars_attachments() {
+        assert!(from_pairs(&[]).unwrap().attachments.is_none());
+        let attachments = from_pairs(&[
+            ("ATTACHMENT_SIGNING_KEY", "secret"),
+            ("ATTACHMENT_OFFLOAD_BYTES", "500000"),
+        ])
+        .unwrap()
+        .attachments
+        .unwrap();
+        assert_eq!(attachments.signing_key, "secret");
+        assert_eq!(attachments.min_bytes, 500_000);
+        assert_eq!(
+            attachments.url_ttl_secs,
+            AttachmentConfig::DEFAULT_URL_TTL_SECS
+        );
+
+        for (name, value) in [
+            ("ATTACHMENT_OFFLOAD_BYTES", "1MB"),
+            ("ATTACHMENT_URL_TTL_SECS", "30"),
+        ] {
+            let err = from_pairs(&[("ATTACHMENT_SIGNING_KEY", "secret"), (name, value)])
+                .err()
+                .unwrap_or_else(|| panic!("{name}={value} should be rejected"));
+            assert!(err.to_string().contains(name), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_from_vars_rejects_malformed_alert_thresholds() {
+        let cases = [
+            ("ALERT_WINDOW_SECS", "10"),
+            ("ALERT_ERROR_RATE_PERCENT", "120"),
+            ("ALERT_LATENCY_P95_MS", "fast"),
+            ("ALERT_DAILY_SPEND_USD", "-5"),
+        ];
+
+        for (name, value) in cases {
+            let err = from_pairs(&[
+                ("ALERT_WEBHOOK_URL", "https://example.com/hook"),
+                (name, value),
+            ])
+            .err()
+            .unwrap_or_else(|| panic!("{name}={value} should be rejected"));
+            assert!(err.to_string().contains(name), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_upstream_base_url() {
+        let mut config = Config::new("https://openrouter.ai/api/v1".to_string());
+        let location = RequestLocation {
+            colo: Some("FRA".to_string()),
+            country: Some("DE".to_string()),
+            continent: Some("EU".to_string()),
+        };
+        assert_eq!(
+            config.upstream_base_url(&location),
+            "https://openrouter.ai/api/v1"
+        );
+
+        config.regional_upstreams =
+            geo::parse_regional_upstreams(r#"{"EU": "https://eu.example.com/api/v1"}"#).unwrap();
+        assert_eq!(
+            config.upstream_base_url(&location),
+            "https://eu.example.com/api/v1"
+        );
+
+        config.features.regional_upstreams = false;
+        assert_eq!(
+            config.upstream_base_url(&location),
+            "https://openrouter.ai/api/v1"
+        );
+    }
+
+    #[test]
+    fn test_hedge_target() {
+        let mut config = Config::new("https://openrouter.ai/api/v1".to_string());
+        assert_eq!(config.hedge_target("moonshotai/kimi-k2:free"), None);
+
+        config.hedge_model = Some("google/gemini-2.5-flash".to_string());
+        assert_eq!(
+            config.hedge_target("moonshotai/kimi-k2:free"),
+            Some("google/gemini-2.5-flash")
+        );
+        assert_eq!(config.hedge_target("google/gemini-2.5-flash"), None);
+    }
+
+    #[test]
+    fn test_shadow_target() {
+        let mut config = Config::new("https://openrouter.ai/api/v1".to_string());
+        config.shadow_model = Some("google/gemini-2.5-flash".to_string());
+        assert_eq!(config.shadow_target("moonshotai/kimi-k2:free", 42), None);
+
+        config.shadow_sample_percent = 100;
+        assert_eq!(
+            config.shadow_target("moonshotai/kimi-k2:free", 42),
+            Some("google/gemini-2.5-flash")
+        );
+        assert_eq!(config.shadow_target("google/gemini-2.5-flash", 42), None);
+    }
 }
@@ -0,0 +1,110 @@
+//! Recovery from prompts too long for the model's context window
+//!
+//! Long Claude Code sessions eventually outgrow the context window of the model
+//! they are mapped to, and the upstream refuses them. With `CONTEXT_RECOVERY`
+//! set, such a request is retried once before the error reaches the client:
+//! `truncate` drops the older half of the conversation, keeping the system
+//! prompt and the first user message, which usually states the task; `reroute`
+//! sends it unchanged to `LONG_CONTEXT_MODEL`. The error is returned only when
+//! the retry fails too, and a recovered response says how in
+//! `x-ccr-context-recovery`.
+
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Response header set to `truncated` or `rerouted` when a request was recovered
+pub const RECOVERY_HEADER: &str = "x-ccr-context-recovery";
+
+/// What is done with a request the upstream found too long (`CONTEXT_RECOVERY`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextRecovery {
+    /// The error goes back to the client
+    #[default]
+    Off,
+    /// Retried with the older half of the conversation dropped
+    Truncate,
+    /// Retried on `LONG_CONTEXT_MODEL`
+    Reroute,
+}
+
+impl ContextRecovery {
+    /// Value of [`RECOVERY_HEADER`] once the recovery was applied
+    pub fn applied(self) -> &'static str {
+        match self {
+            ContextRecovery::Off => "none",
+            ContextRecovery::Truncate => "truncated",
+            ContextRecovery::Reroute => "rerouted",
+        }
+    }
+}
+
+impl FromStr for ContextRecovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Ok(ContextRecovery::Off),
+            "truncate" => Ok(ContextRecovery::Truncate),
+            "reroute" => Ok(ContextRecovery::Reroute),
+            _ => Err("expected off, truncate or reroute".to_string()),
+        }
+    }
+}
+
+/// An OpenAI conversation with its older half dropped, or `None` when there is
+/// nothing to drop
+///
+/// Leading system messages and the first user message are kept. The kept turns
+/// never start with a `tool` message, whose call would have been dropped.
+pub fn drop_older_turns(messages: &[Value]) -> Option<Vec<Value>> {
+    let system = messages
+        .iter()
+        .take_while(|message| matches!(message["role"].as_str(), Some("system" | "developer")))
+        .count();
+    let task = usize::from(messages.get(system).is_some_and(|m| m["role"] == "user"));
+    let turns = &messages[system + task..];
+
+    let start = (turns.len() / 2..turns.len()).find(|&i| turns[i]["role"] != "tool")?;
+    if start == 0 {
+        return None;
+    }
+    let mut kept = messages[..system + task].to_vec();
+    kept.extend_from_slice(&turns[start..]);
+    Some(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_drop_older_turns() {
+        let messages = [
+            json!({"role": "system", "content": "You are Claude Code."}),
+            json!({"role": "user", "content": "Fix the failing tests"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [{"id": "a"}]}),
+            json!({"role": "tool", "tool_call_id": "a", "content": "3 failed"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [{"id": "b"}]}),
+            json!({"role": "tool", "tool_call_id": "b", "content": "diff"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [{"id": "c"}]}),
+            json!({"role": "tool", "tool_call_id": "c", "content": "ok"}),
+        ];
+        // Half of the six turns would start on a tool result, so one more goes
+        let kept = drop_older_turns(&messages).unwrap();
+        assert_eq!(kept[..2], messages[..2]);
+        assert_eq!(kept[2..], messages[6..]);
+
+        // What's left is a call and its result, which stay together
+        assert_eq!(drop_older_turns(&kept), None);
+        assert_eq!(drop_older_turns(&messages[..2]), None);
+        assert_eq!(drop_older_turns(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_recovery() {
+        assert_eq!("Truncate".parse(), Ok(ContextRecovery::Truncate));
+        assert_eq!("reroute".parse(), Ok(ContextRecovery::Reroute));
+        assert!("compact".parse::<ContextRecovery>().is_err());
+    }
+}
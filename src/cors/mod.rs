@@ -0,0 +1,132 @@
+/// Request headers browsers may send, when the preflight doesn't list its own
+pub const DEFAULT_ALLOWED_HEADERS: &str = "authorization, content-type, x-api-key, \
+     anthropic-version, anthropic-beta, anthropic-dangerous-direct-browser-access, \
+     x-goog-api-key, x-ccr-access-token, x-ccr-profile, x-ccr-priority, x-ccr-trace, x-ccr-model, idempotency-key";
+
+/// Response headers scripts are allowed to read
+pub const EXPOSED_HEADERS: &str = "x-request-id, retry-after, x-ccr-idempotent-replayed, \
+     x-ccr-route, x-ccr-cost-usd, x-ccr-signature, x-ccr-format, anthropic-ratelimit-requests-limit, \
+     anthropic-ratelimit-requests-remaining, anthropic-ratelimit-requests-reset, \
+     anthropic-ratelimit-tokens-limit, anthropic-ratelimit-tokens-remaining, \
+     anthropic-ratelimit-tokens-reset";
+
+/// How long browsers may cache a preflight response
+pub const MAX_AGE_SECS: u32 = 86_400;
+
+/// Parses a comma-separated `CORS_ALLOWED_ORIGINS` list
+///
+/// Trailing slashes are dropped, since browsers never send them in `Origin`.
+pub fn parse_origins(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect()
+}
+
+/// Checks a configured origin: `*`, or a scheme and host with no path
+pub fn validate_origin(origin: &str) -> Result<(), String> {
+    if origin == "*" {
+        return Ok(());
+    }
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or("origins must start with http:// or https://")?;
+    if host.is_empty() || host.contains('/') {
+        return Err("origins must be a scheme and host, without a path".to_string());
+    }
+    Ok(())
+}
+
+/// Returns the `Access-Control-Allow-Origin` value for a request's `Origin`, if allowed
+pub fn allow_origin(allowed: &[String], origin: &str) -> Option<String> {
+    if allowed.iter().any(|entry| entry == "*") {
+        return Some("*".to_string());
+    }
+    allowed
+        .iter()
+        .find(|entry| entry.eq_ignore_ascii_case(origin))
+        .map(|_| origin.to_string())
+}
+
+/// Headers answering a preflight for a route served with `method`
+pub fn preflight_headers(
+    allow_origin: &str,
+    method: &str,
+    requested_headers: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let allowed_headers = requested_headers
+        .filter(|headers| !headers.trim().is_empty())
+        .unwrap_or(DEFAULT_ALLOWED_HEADERS);
+    let mut headers = response_headers(allow_origin);
+    headers.extend([
+        ("Access-Control-Allow-Methods", format!("{method}, OPTIONS")),
+        ("Access-Control-Allow-Headers", allowed_headers.to_string()),
+        ("Access-Control-Max-Age", MAX_AGE_SECS.to_string()),
+    ]);
+    headers
+}
+
+/// Headers added to actual responses for an allowed origin
+pub fn response_headers(allow_origin: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("Access-Control-Allow-Origin", allow_origin.to_string()),
+        ("Access-Control-Expose-Headers", EXPOSED_HEADERS.to_string()),
+        ("Vary", "Origin".to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate_origins() {
+        let origins = parse_origins(" https://app.example.com/, http://localhost:5173 ,");
+        assert_eq!(
+            origins,
+            ["https://app.example.com", "http://localhost:5173"]
+        );
+        assert!(origins.iter().all(|origin| validate_origin(origin).is_ok()));
+
+        assert!(validate_origin("*").is_ok());
+        assert!(validate_origin("app.example.com").is_err());
+        assert!(validate_origin("https://app.example.com/chat").is_err());
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let allowed = parse_origins("https://app.example.com");
+        assert_eq!(
+            allow_origin(&allowed, "https://app.example.com").as_deref(),
+            Some("https://app.example.com")
+        );
+        assert_eq!(allow_origin(&allowed, "https://evil.example.com"), None);
+        assert_eq!(allow_origin(&[], "https://app.example.com"), None);
+
+        let any = parse_origins("*");
+        assert_eq!(
+            allow_origin(&any, "https://evil.example.com").as_deref(),
+            Some("*")
+        );
+    }
+
+    #[test]
+    fn test_preflight_headers() {
+        let headers = preflight_headers("https://app.example.com", "POST", Some("x-api-key"));
+        let get = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| *header == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(get("Access-Control-Allow-Methods"), Some("POST, OPTIONS"));
+        assert_eq!(get("Access-Control-Allow-Headers"), Some("x-api-key"));
+        assert_eq!(get("Vary"), Some("Origin"));
+
+        let headers = preflight_headers("*", "POST", None);
+        assert!(headers
+            .iter()
+            .any(|(_, value)| value.as_str() == DEFAULT_ALLOWED_HEADERS));
+    }
+}
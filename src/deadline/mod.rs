@@ -0,0 +1,140 @@
+//! Time budget of a request against the Workers runtime limit
+//!
+//! A Worker that runs past its limits is cancelled without a response, so the
+//! slow path has to give up on its own first. One [`Budget`] is created per
+//! request and handed to each phase: past the soft limit (`SLOW_REQUEST_WARN_MS`)
+//! no more retries are started and verbose diagnostics are skipped, and a stream
+//! still being relayed close to the hard limit is ended early with what has
+//! arrived, rather than lost.
+
+use crate::log::Logger;
+
+/// Elapsed time by which a request has to be answered
+pub const RUNTIME_LIMIT_MS: u64 = 30_000;
+
+/// Time left at which a stream still being read is closed
+pub const STREAM_CLOSE_MARGIN_MS: u64 = 2_000;
+
+/// When a request started and how long it may take
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    start_ms: u64,
+    soft_limit_ms: u64,
+    hard_limit_ms: u64,
+    /// Current time in Unix milliseconds
+    now: fn() -> u64,
+}
+
+impl Budget {
+    /// A budget for a request that started at `start_ms`, soft-limited to
+    /// `soft_limit_ms` and hard-limited to the runtime limit
+    pub fn new(start_ms: u64, soft_limit_ms: u64, now: fn() -> u64) -> Self {
+        Budget {
+            start_ms,
+            soft_limit_ms,
+            hard_limit_ms: RUNTIME_LIMIT_MS.max(soft_limit_ms),
+            now,
+        }
+    }
+
+    /// Unix milliseconds at which the request started
+    pub fn start_ms(&self) -> u64 {
+        self.start_ms
+    }
+
+    /// Current Unix time in milliseconds, from the budget's clock
+    pub fn now_ms(&self) -> u64 {
+        (self.now)()
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        (self.now)().saturating_sub(self.start_ms)
+    }
+
+    /// Time left before the hard limit
+    pub fn remaining_ms(&self) -> u64 {
+        self.hard_limit_ms.saturating_sub(self.elapsed_ms())
+    }
+
+    pub fn past_soft_limit(&self) -> bool {
+        self.elapsed_ms() >= self.soft_limit_ms
+    }
+
+    /// Whether a retry after `delay_ms` would still start within the soft limit
+    pub fn allows_retry(&self, delay_ms: u64) -> bool {
+        self.elapsed_ms().saturating_add(delay_ms) < self.soft_limit_ms
+    }
+
+    /// Whether there is time for diagnostics that only help debugging
+    pub fn verbose(&self) -> bool {
+        !self.past_soft_limit()
+    }
+
+    /// Whether a stream still being read should be closed now
+    pub fn should_close_stream(&self) -> bool {
+        self.remaining_ms() <= STREAM_CLOSE_MARGIN_MS
+    }
+
+    /// Logs reaching `step`, warning once the request is past the soft limit;
+    /// returns the elapsed time
+    pub fn check(&self, step: &str, log: &Logger) -> u64 {
+        let elapsed = self.elapsed_ms();
+        log.trace(
+            "step",
+            &[("step", step.into()), ("elapsed_ms", elapsed.into())],
+        );
+        if elapsed >= self.soft_limit_ms {
+            log.warn(
+                "request approaching timeout",
+                &[("step", step.into()), ("elapsed_ms", elapsed.into())],
+            );
+        }
+        elapsed
+    }
+}
+
+/// Current Unix time in milliseconds from the Workers runtime
+#[cfg(feature = "cloudflare")]
+pub fn worker_now() -> u64 {
+    worker::Date::now().as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_1000() -> u64 {
+        1_000
+    }
+
+    fn at_26000() -> u64 {
+        26_000
+    }
+
+    fn at_29000() -> u64 {
+        29_000
+    }
+
+    #[test]
+    fn test_budget_phases() {
+        let fresh = Budget::new(0, 25_000, at_1000);
+        assert_eq!(fresh.elapsed_ms(), 1_000);
+        assert_eq!(fresh.remaining_ms(), 29_000);
+        assert!(fresh.verbose());
+        assert!(fresh.allows_retry(2_000));
+        assert!(!fresh.allows_retry(24_000));
+        assert!(!fresh.should_close_stream());
+
+        let slow = Budget::new(0, 25_000, at_26000);
+        assert!(slow.past_soft_limit());
+        assert!(!slow.verbose());
+        assert!(!slow.allows_retry(0));
+        assert!(!slow.should_close_stream());
+
+        let closing = Budget::new(0, 25_000, at_29000);
+        assert!(closing.should_close_stream());
+
+        // A soft limit beyond the runtime limit raises the hard limit with it
+        assert_eq!(Budget::new(0, 40_000, at_1000).remaining_ms(), 39_000);
+    }
+}
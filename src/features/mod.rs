@@ -0,0 +1,285 @@
+//! Feature flags, for rolling out risky request-path behavior and switching it off
+//!
+//! Every feature is on by default. `DISABLED_FEATURES` switches some off by name,
+//! and `FEATURE_FLAGS` sets them as a JSON object of names to `true`, `false` or
+//! the percentage of API keys to turn them on for:
+//!
+//! ```json
+//! {"hedging": false, "tool_schema_transform": 25}
+//! ```
+//!
+//! The same object stored under `features` in the `FEATURES` KV namespace wins
+//! over both and takes effect within a minute, without a redeploy. A key stays on
+//! the same side of a percentage from one request to the next; which keys are in
+//! differs per feature. Work done for no particular key, like scheduled tasks, only
+//! gets features that are on for every key.
+
+use std::str::FromStr;
+
+/// KV namespace whose `features` flag set overrides the configured one
+#[cfg(feature = "cloudflare")]
+pub const FEATURES_BINDING: &str = "FEATURES";
+
+/// KV key of the overriding flag set
+#[cfg(feature = "cloudflare")]
+const FEATURES_KEY: &str = "features";
+
+/// Seconds Cloudflare may serve cached overrides before re-reading KV
+#[cfg(feature = "cloudflare")]
+const FEATURES_CACHE_TTL_SECS: u64 = 60;
+
+/// A feature that can be switched off or rolled out gradually
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// The `auto` model, routing by request complexity
+    AutoModel,
+    /// Racing `HEDGE_MODEL` against slow responses
+    Hedging,
+    /// Mirroring requests to `SHADOW_MODEL`
+    Shadow,
+    /// Upstreams chosen by the client's region
+    RegionalUpstreams,
+    /// Answers from `SEMANTIC_CACHE` for similar prompts
+    SemanticCache,
+    /// Tool input schemas rewritten into ones providers accept
+    ToolSchemaTransform,
+    /// Sampling adjustments for the models that need them, e.g. Kimi's temperature
+    ModelTransforms,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 7] = [
+        Feature::AutoModel,
+        Feature::Hedging,
+        Feature::Shadow,
+        Feature::RegionalUpstreams,
+        Feature::SemanticCache,
+        Feature::ToolSchemaTransform,
+        Feature::ModelTransforms,
+    ];
+
+    /// Name of the feature in `DISABLED_FEATURES` and flag sets
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::AutoModel => "auto_model",
+            Feature::Hedging => "hedging",
+            Feature::Shadow => "shadow",
+            Feature::RegionalUpstreams => "regional_upstreams",
+            Feature::SemanticCache => "semantic_cache",
+            Feature::ToolSchemaTransform => "tool_schema_transform",
+            Feature::ModelTransforms => "model_transforms",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == s.trim())
+            .ok_or_else(|| format!("unknown feature '{}'", s.trim()))
+    }
+}
+
+/// Which features are on for a request, all of them by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureFlags {
+    pub auto_model: bool,
+    pub hedging: bool,
+    pub shadow: bool,
+    pub regional_upstreams: bool,
+    pub semantic_cache: bool,
+    pub tool_schema_transform: bool,
+    pub model_transforms: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        FeatureFlags {
+            auto_model: true,
+            hedging: true,
+            shadow: true,
+            regional_upstreams: true,
+            semantic_cache: true,
+            tool_schema_transform: true,
+            model_transforms: true,
+        }
+    }
+}
+
+impl FeatureFlags {
+    fn flag_mut(&mut self, feature: Feature) -> &mut bool {
+        match feature {
+            Feature::AutoModel => &mut self.auto_model,
+            Feature::Hedging => &mut self.hedging,
+            Feature::Shadow => &mut self.shadow,
+            Feature::RegionalUpstreams => &mut self.regional_upstreams,
+            Feature::SemanticCache => &mut self.semantic_cache,
+            Feature::ToolSchemaTransform => &mut self.tool_schema_transform,
+            Feature::ModelTransforms => &mut self.model_transforms,
+        }
+    }
+}
+
+/// The percentage of keys each named feature is on for; features it doesn't
+/// name are left as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagSet {
+    rollouts: Vec<(Feature, u8)>,
+}
+
+impl FlagSet {
+    /// Reads a comma-separated `DISABLED_FEATURES` list, rejecting unknown names
+    pub fn from_disabled_list(raw: &str) -> Result<Self, String> {
+        let mut flags = FlagSet::default();
+        for name in raw.split(',').filter(|name| !name.trim().is_empty()) {
+            flags.set(name.parse()?, 0);
+        }
+        Ok(flags)
+    }
+
+    /// Reads a JSON object of feature names to `true`, `false` or a percentage
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        let mut flags = FlagSet::default();
+        for (name, value) in object {
+            let feature: Feature = name.parse()?;
+            let percent = match value {
+                serde_json::Value::Bool(on) => u8::from(on) * 100,
+                serde_json::Value::Number(n) => match n.as_u64() {
+                    Some(percent @ 0..=100) => percent as u8,
+                    _ => return Err(format!("'{name}' must be a percentage from 0 to 100")),
+                },
+                _ => return Err(format!("'{name}' must be true, false or a percentage")),
+            };
+            flags.set(feature, percent);
+        }
+        Ok(flags)
+    }
+
+    fn set(&mut self, feature: Feature, percent: u8) {
+        self.rollouts.retain(|(named, _)| *named != feature);
+        self.rollouts.push((feature, percent));
+    }
+
+    /// This set with the features `overrides` names set as it does
+    pub fn with(&self, overrides: &FlagSet) -> FlagSet {
+        let mut flags = self.clone();
+        for &(feature, percent) in &overrides.rollouts {
+            flags.set(feature, percent);
+        }
+        flags
+    }
+
+    /// Sets the named features in `flags` for the key hashing to `subject`, or
+    /// for no key in particular
+    pub fn apply(&self, flags: &mut FeatureFlags, subject: Option<&str>) {
+        for &(feature, percent) in &self.rollouts {
+            *flags.flag_mut(feature) = match (percent, subject) {
+                (0, _) => false,
+                (100.., _) => true,
+                (_, Some(subject)) => bucket(feature, subject) < percent,
+                (_, None) => false,
+            };
+        }
+    }
+}
+
+/// Where `subject` falls in a percentage rollout of `feature`, from 0 to 99
+fn bucket(feature: Feature, subject: &str) -> u8 {
+    // FNV-1a, which unlike std's hasher is the same in every build
+    let hash = format!("{}:{subject}", feature.name())
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    (hash % 100) as u8
+}
+
+/// The flag set stored in the `FEATURES` namespace, when it is bound
+///
+/// Unreadable or invalid overrides are logged and ignored, leaving the
+/// configured flags in effect.
+#[cfg(feature = "cloudflare")]
+pub async fn load_overrides(env: &worker::Env, log: &crate::log::Logger) -> FlagSet {
+    let Ok(kv) = env.kv(FEATURES_BINDING) else {
+        return FlagSet::default();
+    };
+    let overrides = match kv
+        .get(FEATURES_KEY)
+        .cache_ttl(FEATURES_CACHE_TTL_SECS)
+        .text()
+        .await
+    {
+        Ok(Some(raw)) => FlagSet::parse(&raw),
+        Ok(None) => return FlagSet::default(),
+        Err(e) => Err(e.to_string()),
+    };
+    overrides.unwrap_or_else(|e| {
+        log.warn("feature flag overrides ignored", &[("error", e.into())]);
+        FlagSet::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flag_sets() {
+        let flags = FlagSet::parse(r#"{"hedging": false, "tool_schema_transform": 25}"#).unwrap();
+        let mut features = FeatureFlags::default();
+        flags.apply(&mut features, None);
+        assert!(!features.hedging);
+        // Partial rollouts need a key to be on for
+        assert!(!features.tool_schema_transform);
+        assert!(features.shadow);
+
+        let overridden = flags.with(&FlagSet::parse(r#"{"tool_schema_transform": true}"#).unwrap());
+        let mut features = FeatureFlags::default();
+        overridden.apply(&mut features, None);
+        assert!(features.tool_schema_transform);
+        assert!(!features.hedging);
+
+        assert!(FlagSet::parse(r#"{"streaming": true}"#).is_err());
+        assert!(FlagSet::parse(r#"{"hedging": 101}"#).is_err());
+        assert!(FlagSet::parse(r#"{"hedging": "off"}"#).is_err());
+        assert!(FlagSet::parse("hedging").is_err());
+    }
+
+    #[test]
+    fn test_from_disabled_list() {
+        let flags = FlagSet::from_disabled_list(" shadow, model_transforms ,").unwrap();
+        let mut features = FeatureFlags::default();
+        flags.apply(&mut features, Some("key"));
+        assert!(!features.shadow);
+        assert!(!features.model_transforms);
+        assert!(features.auto_model);
+        assert_eq!(
+            FlagSet::from_disabled_list("shadow,cache").unwrap_err(),
+            "unknown feature 'cache'"
+        );
+    }
+
+    #[test]
+    fn test_percentage_rollouts() {
+        let flags = FlagSet::parse(r#"{"model_transforms": 30}"#).unwrap();
+        let enabled_for = |subject: &str| {
+            let mut features = FeatureFlags::default();
+            flags.apply(&mut features, Some(subject));
+            features.model_transforms
+        };
+        let enabled = (0..1000)
+            .filter(|i| enabled_for(&format!("key-{i}")))
+            .count();
+        assert!((250..350).contains(&enabled), "{enabled} of 1000");
+        // Each feature picks its own keys
+        assert!((0..100).any(|i| {
+            let key = format!("key-{i}");
+            bucket(Feature::Hedging, &key) != bucket(Feature::ModelTransforms, &key)
+        }));
+    }
+}
@@ -0,0 +1,364 @@
+use crate::transform::check_completion_count;
+use serde_json::{json, Map, Value};
+
+/// Header Gemini clients send their API key in
+pub const GOOGLE_API_KEY_HEADER: &str = "x-goog-api-key";
+
+/// Gemini API methods served under `/v1beta/models/{model}:{method}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    GenerateContent,
+    StreamGenerateContent,
+}
+
+/// Splits a `/v1beta/models/` path remainder into the model and method
+///
+/// OpenRouter model IDs contain a slash, so the model is everything before the
+/// last `:`.
+pub fn parse_model_path(rest: &str) -> Option<(&str, Method)> {
+    let (model, method) = rest.rsplit_once(':')?;
+    let method = match method {
+        "generateContent" => Method::GenerateContent,
+        "streamGenerateContent" => Method::StreamGenerateContent,
+        _ => return None,
+    };
+    (!model.is_empty()).then_some((model, method))
+}
+
+/// Gemini schemas use upper-case type names (`OBJECT`, `STRING`)
+fn lowercase_schema_types(schema: &mut Value) {
+    match schema {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(name) if key == "type" => *name = name.to_lowercase(),
+                    _ => lowercase_schema_types(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(lowercase_schema_types),
+        _ => {}
+    }
+}
+
+fn text_of(parts: &Value) -> String {
+    parts
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Converts a Gemini `generateContent` request into an OpenAI chat request for `model`
+///
+/// Gemini function calls carry no IDs, so calls are numbered in order and each
+/// function response answers the oldest unanswered call with the same name.
+pub fn gemini_to_openai(request: &Value, model: &str) -> Result<Value, String> {
+    let contents = request["contents"]
+        .as_array()
+        .ok_or("contents must be an array")?;
+    let mut messages = Vec::new();
+
+    let system = text_of(&request["systemInstruction"]["parts"]);
+    if !system.is_empty() {
+        messages.push(json!({"role": "system", "content": system}));
+    }
+
+    let mut pending_calls: Vec<(String, String)> = Vec::new();
+    let mut call_count = 0;
+    for (index, content) in contents.iter().enumerate() {
+        let role = content["role"].as_str().unwrap_or("user");
+        let parts = content["parts"]
+            .as_array()
+            .ok_or_else(|| format!("contents.{index}.parts must be an array"))?;
+
+        let mut blocks = Vec::new();
+        let mut tool_calls = Vec::new();
+        for part in parts {
+            if let Some(text) = part["text"].as_str() {
+                blocks.push(json!({"type": "text", "text": text}));
+            } else if let Some(data) = part.get("inlineData") {
+                let mime_type = data["mimeType"].as_str().unwrap_or("image/png");
+                let payload = data["data"].as_str().unwrap_or_default();
+                blocks.push(json!({
+                    "type": "image_url",
+                    "image_url": {"url": format!("data:{mime_type};base64,{payload}")}
+                }));
+            } else if let Some(call) = part.get("functionCall") {
+                let name = call["name"].as_str().unwrap_or_default().to_string();
+                let id = format!("call_{call_count}");
+                call_count += 1;
+                tool_calls.push(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "arguments": call.get("args").unwrap_or(&json!({})).to_string()
+                    }
+                }));
+                pending_calls.push((name, id));
+            } else if let Some(response) = part.get("functionResponse") {
+                let name = response["name"].as_str().unwrap_or_default();
+                let id = match pending_calls.iter().position(|(call, _)| call == name) {
+                    Some(position) => pending_calls.remove(position).1,
+                    None => format!("call_{name}"),
+                };
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": id,
+                    "content": response.get("response").unwrap_or(&Value::Null).to_string()
+                }));
+            }
+        }
+
+        if role == "model" {
+            let mut message = json!({"role": "assistant", "content": text_of(&content["parts"])});
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = Value::Array(tool_calls);
+            }
+            messages.push(message);
+        } else if !blocks.is_empty() {
+            messages.push(json!({"role": "user", "content": blocks}));
+        }
+    }
+
+    let mut openai_request = json!({"model": model, "messages": messages});
+
+    let tools: Vec<Value> = request["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|tool| {
+            tool["functionDeclarations"]
+                .as_array()
+                .into_iter()
+                .flatten()
+        })
+        .map(|declaration| {
+            let mut parameters = declaration
+                .get("parametersJsonSchema")
+                .or_else(|| declaration.get("parameters"))
+                .cloned()
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            lowercase_schema_types(&mut parameters);
+            json!({
+                "type": "function",
+                "function": {
+                    "name": declaration["name"],
+                    "description": declaration["description"],
+                    "parameters": parameters
+                }
+            })
+        })
+        .collect();
+    if !tools.is_empty() {
+        openai_request["tools"] = Value::Array(tools);
+    }
+
+    let generation = &request["generationConfig"];
+    check_completion_count(
+        "generationConfig.candidateCount",
+        generation.get("candidateCount"),
+    )?;
+    for (gemini, openai) in [
+        ("temperature", "temperature"),
+        ("topP", "top_p"),
+        ("maxOutputTokens", "max_tokens"),
+        ("stopSequences", "stop"),
+        ("seed", "seed"),
+    ] {
+        if let Some(value) = generation.get(gemini) {
+            openai_request[openai] = value.clone();
+        }
+    }
+    if generation["responseMimeType"] == "application/json" {
+        openai_request["response_format"] = json!({"type": "json_object"});
+    }
+
+    Ok(openai_request)
+}
+
+fn finish_reason(reason: Option<&str>) -> &'static str {
+    match reason {
+        Some("stop") | Some("tool_calls") => "STOP",
+        Some("length") => "MAX_TOKENS",
+        Some("content_filter") => "SAFETY",
+        _ => "OTHER",
+    }
+}
+
+/// Converts an OpenAI chat completion into a Gemini `generateContent` response
+pub fn openai_to_gemini(response: &Value, model: &str) -> Value {
+    let choice = &response["choices"][0];
+    let message = &choice["message"];
+
+    let mut parts = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|text| !text.is_empty()) {
+        parts.push(json!({"text": text}));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        let args: Value = serde_json::from_str(arguments).unwrap_or_else(|_| json!({}));
+        parts.push(json!({
+            "functionCall": {"name": call["function"]["name"], "args": args}
+        }));
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert(
+        "candidates".to_string(),
+        json!([{
+            "content": {"role": "model", "parts": parts},
+            "finishReason": finish_reason(choice["finish_reason"].as_str()),
+            "index": 0
+        }]),
+    );
+    if let Some(usage) = response.get("usage") {
+        gemini.insert(
+            "usageMetadata".to_string(),
+            json!({
+                "promptTokenCount": usage["prompt_tokens"],
+                "candidatesTokenCount": usage["completion_tokens"],
+                "totalTokenCount": usage["total_tokens"]
+            }),
+        );
+    }
+    gemini.insert("modelVersion".to_string(), model.into());
+    Value::Object(gemini)
+}
+
+/// Builds a Gemini-format error body
+pub fn error_body(status: u16, message: &str) -> Value {
+    let status_name = match status {
+        400 | 413 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        429 => "RESOURCE_EXHAUSTED",
+        503 => "UNAVAILABLE",
+        _ => "INTERNAL",
+    };
+    json!({"error": {"code": status, "message": message, "status": status_name}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_model_path() {
+        assert_eq!(
+            parse_model_path("gemini-2.5-pro:generateContent"),
+            Some(("gemini-2.5-pro", Method::GenerateContent))
+        );
+        assert_eq!(
+            parse_model_path("moonshotai/kimi-k2:free:streamGenerateContent"),
+            Some(("moonshotai/kimi-k2:free", Method::StreamGenerateContent))
+        );
+        assert_eq!(parse_model_path("gemini-2.5-pro:countTokens"), None);
+        assert_eq!(parse_model_path(":generateContent"), None);
+    }
+
+    #[test]
+    fn test_gemini_to_openai() {
+        let request = json!({
+            "systemInstruction": {"parts": [{"text": "Be brief"}]},
+            "contents": [
+                {"role": "user", "parts": [{"text": "Weather in Paris?"}]},
+                {"role": "model", "parts": [{"functionCall": {"name": "weather", "args": {"city": "Paris"}}}]},
+                {"role": "user", "parts": [{"functionResponse": {"name": "weather", "response": {"temp": 21}}}]}
+            ],
+            "tools": [{"functionDeclarations": [{
+                "name": "weather",
+                "description": "Current weather",
+                "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}}
+            }]}],
+            "generationConfig": {"temperature": 0.2, "maxOutputTokens": 256, "responseMimeType": "application/json"}
+        });
+
+        let openai = gemini_to_openai(&request, "google/gemini-2.5-flash").unwrap();
+        let messages = openai["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[0],
+            json!({"role": "system", "content": "Be brief"})
+        );
+        assert_eq!(messages[1]["content"][0]["text"], "Weather in Paris?");
+        assert_eq!(messages[2]["role"], "assistant");
+        assert_eq!(messages[2]["tool_calls"][0]["id"], "call_0");
+        assert_eq!(
+            messages[2]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(
+            messages[3],
+            json!({"role": "tool", "tool_call_id": "call_0", "content": r#"{"temp":21}"#})
+        );
+        assert_eq!(messages.len(), 4);
+
+        let parameters = &openai["tools"][0]["function"]["parameters"];
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["properties"]["city"]["type"], "string");
+        assert_eq!(openai["max_tokens"], 256);
+        assert_eq!(openai["response_format"]["type"], "json_object");
+
+        assert!(gemini_to_openai(&json!({}), "m").is_err());
+
+        // Only one candidate is translated back
+        let request = json!({
+            "contents": [{"role": "user", "parts": [{"text": "Hi"}]}],
+            "generationConfig": {"candidateCount": 2}
+        });
+        assert_eq!(
+            gemini_to_openai(&request, "m").unwrap_err(),
+            "generationConfig.candidateCount: only one completion per request is supported, got 2"
+        );
+    }
+
+    #[test]
+    fn test_inline_data_becomes_data_url() {
+        let request = json!({"contents": [{"parts": [
+            {"inlineData": {"mimeType": "image/jpeg", "data": "AAAA"}}
+        ]}]});
+        let openai = gemini_to_openai(&request, "m").unwrap();
+        assert_eq!(
+            openai["messages"][0]["content"][0]["image_url"]["url"],
+            "data:image/jpeg;base64,AAAA"
+        );
+    }
+
+    #[test]
+    fn test_openai_to_gemini() {
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "content": "Checking",
+                    "tool_calls": [{"id": "c1", "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}}]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        });
+
+        let gemini = openai_to_gemini(&response, "google/gemini-2.5-flash");
+        let candidate = &gemini["candidates"][0];
+        assert_eq!(candidate["content"]["parts"][0]["text"], "Checking");
+        assert_eq!(
+            candidate["content"]["parts"][1]["functionCall"]["args"]["city"],
+            "Paris"
+        );
+        assert_eq!(candidate["finishReason"], "STOP");
+        assert_eq!(gemini["usageMetadata"]["totalTokenCount"], 15);
+        assert_eq!(gemini["modelVersion"], "google/gemini-2.5-flash");
+    }
+
+    #[test]
+    fn test_error_body() {
+        assert_eq!(
+            error_body(429, "slow down")["error"]["status"],
+            "RESOURCE_EXHAUSTED"
+        );
+        assert_eq!(error_body(401, "no key")["error"]["code"], 401);
+    }
+}
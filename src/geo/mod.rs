@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use worker::Request;
+
+/// Where a request entered Cloudflare's network, taken from `request.cf`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestLocation {
+    /// Cloudflare data center IATA code (e.g. `FRA`)
+    pub colo: Option<String>,
+    /// ISO 3166-1 alpha-2 country code (e.g. `DE`)
+    pub country: Option<String>,
+    /// Continent code (e.g. `EU`)
+    pub continent: Option<String>,
+}
+
+impl RequestLocation {
+    /// Reads the location from the request's Cloudflare properties, if present
+    pub fn from_request(req: &Request) -> Self {
+        match req.cf() {
+            Some(cf) => RequestLocation {
+                colo: Some(cf.colo()).filter(|colo| !colo.is_empty()),
+                country: cf.country(),
+                continent: cf.continent(),
+            },
+            None => RequestLocation::default(),
+        }
+    }
+}
+
+/// Parses the `REGIONAL_UPSTREAMS` JSON map of location code to base URL
+///
+/// Keys are normalized to uppercase so `"eu"` and `"EU"` are equivalent.
+pub fn parse_regional_upstreams(raw: &str) -> serde_json::Result<HashMap<String, String>> {
+    let map = serde_json::from_str::<HashMap<String, String>>(raw)?;
+    Ok(map
+        .into_iter()
+        .map(|(code, url)| (code.to_uppercase(), url.trim_end_matches('/').to_string()))
+        .collect())
+}
+
+/// Picks the upstream base URL for a request location
+///
+/// The most specific match wins: data center, then country, then continent.
+/// Falls back to `default_url` when nothing matches.
+pub fn select_upstream<'a>(
+    regional_upstreams: &'a HashMap<String, String>,
+    location: &RequestLocation,
+    default_url: &'a str,
+) -> &'a str {
+    [&location.colo, &location.country, &location.continent]
+        .into_iter()
+        .flatten()
+        .find_map(|code| regional_upstreams.get(&code.to_uppercase()))
+        .map(String::as_str)
+        .unwrap_or(default_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_URL: &str = "https://openrouter.ai/api/v1";
+
+    fn location(colo: &str, country: &str, continent: &str) -> RequestLocation {
+        RequestLocation {
+            colo: Some(colo.to_string()),
+            country: Some(country.to_string()),
+            continent: Some(continent.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_regional_upstreams() {
+        let map = parse_regional_upstreams(
+            r#"{"eu": "https://eu.example.com/api/v1/", "SG": "https://sg.example.com/v1"}"#,
+        )
+        .unwrap();
+        assert_eq!(map["EU"], "https://eu.example.com/api/v1");
+        assert_eq!(map["SG"], "https://sg.example.com/v1");
+
+        assert!(parse_regional_upstreams("not json").is_err());
+        assert!(parse_regional_upstreams(r#"["EU"]"#).is_err());
+    }
+
+    #[test]
+    fn test_select_upstream_precedence() {
+        let map = parse_regional_upstreams(
+            r#"{"EU": "https://eu.example.com", "DE": "https://de.example.com", "FRA": "https://fra.example.com"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_upstream(&map, &location("FRA", "DE", "EU"), DEFAULT_URL),
+            "https://fra.example.com"
+        );
+        assert_eq!(
+            select_upstream(&map, &location("MUC", "DE", "EU"), DEFAULT_URL),
+            "https://de.example.com"
+        );
+        assert_eq!(
+            select_upstream(&map, &location("CDG", "FR", "EU"), DEFAULT_URL),
+            "https://eu.example.com"
+        );
+        assert_eq!(
+            select_upstream(&map, &location("SJC", "US", "NA"), DEFAULT_URL),
+            DEFAULT_URL
+        );
+    }
+
+    #[test]
+    fn test_select_upstream_without_location() {
+        let map = parse_regional_upstreams(r#"{"EU": "https://eu.example.com"}"#).unwrap();
+        assert_eq!(
+            select_upstream(&map, &RequestLocation::default(), DEFAULT_URL),
+            DEFAULT_URL
+        );
+    }
+}
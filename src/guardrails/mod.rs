@@ -0,0 +1,231 @@
+use crate::models::AnthropicRequest;
+use serde_json::Value;
+
+/// Size limits enforced on incoming requests before anything is sent upstream
+///
+/// A limit of 0 disables that check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Largest accepted request body
+    pub max_body_bytes: usize,
+    /// Most messages in one request
+    pub max_messages: usize,
+    /// Most tool definitions in one request
+    pub max_tools: usize,
+    /// Largest decoded base64 image, wherever it appears in the conversation
+    pub max_image_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    /// Mirrors the limits of the Anthropic API itself
+    fn default() -> Self {
+        RequestLimits {
+            max_body_bytes: 32 * 1024 * 1024,
+            max_messages: 100_000,
+            max_tools: 0,
+            max_image_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+fn exceeds(limit: usize, value: usize) -> bool {
+    limit > 0 && value > limit
+}
+
+/// Checks a request body's size, from `Content-Length` or the body itself
+pub fn check_body_size(limits: &RequestLimits, bytes: usize) -> Result<(), String> {
+    if exceeds(limits.max_body_bytes, bytes) {
+        return Err(format!(
+            "Request body is {bytes} bytes, exceeding the limit of {} bytes",
+            limits.max_body_bytes
+        ));
+    }
+    Ok(())
+}
+
+/// Checks message count, tool count and image sizes
+///
+/// Errors name the offending field, e.g. `messages.3.content.1`.
+pub fn check_request(limits: &RequestLimits, request: &AnthropicRequest) -> Result<(), String> {
+    if exceeds(limits.max_messages, request.messages.len()) {
+        return Err(format!(
+            "messages: {} messages exceed the limit of {}",
+            request.messages.len(),
+            limits.max_messages
+        ));
+    }
+
+    let tools = request.tools.as_ref().map_or(0, Vec::len);
+    if exceeds(limits.max_tools, tools) {
+        return Err(format!(
+            "tools: {tools} tools exceed the limit of {}",
+            limits.max_tools
+        ));
+    }
+
+    if limits.max_image_bytes > 0 {
+        for (index, message) in request.messages.iter().enumerate() {
+            check_images(
+                limits,
+                &message["content"],
+                &format!("messages.{index}.content"),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Walks content blocks, including those nested in tool results
+fn check_images(limits: &RequestLimits, content: &Value, path: &str) -> Result<(), String> {
+    let Some(blocks) = content.as_array() else {
+        return Ok(());
+    };
+
+    for (index, block) in blocks.iter().enumerate() {
+        let path = format!("{path}.{index}");
+        match block["type"].as_str() {
+            Some("image") => {
+                if let Some(data) = block["source"]["data"].as_str() {
+                    let bytes = base64_decoded_len(data);
+                    if exceeds(limits.max_image_bytes, bytes) {
+                        return Err(format!(
+                            "{path}: image is {bytes} bytes, exceeding the limit of {} bytes",
+                            limits.max_image_bytes
+                        ));
+                    }
+                }
+            }
+            Some("tool_result") => {
+                check_images(limits, &block["content"], &format!("{path}.content"))?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Size of base64 data once decoded, without decoding it
+fn base64_decoded_len(data: &str) -> usize {
+    let data = data.trim_end();
+    let padding = data.bytes().rev().take_while(|&b| b == b'=').count();
+    (data.len() / 4 * 3 + (data.len() % 4) * 3 / 4).saturating_sub(padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(messages: Vec<Value>, tools: usize) -> AnthropicRequest {
+        AnthropicRequest {
+            model: "claude-sonnet-4".to_string(),
+            messages,
+            system: None,
+            temperature: None,
+            tools: (tools > 0).then(|| vec![json!({"name": "t"}); tools]),
+            stream: None,
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        }
+    }
+
+    fn image(data: &str) -> Value {
+        json!({"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": data}})
+    }
+
+    #[test]
+    fn test_base64_decoded_len() {
+        assert_eq!(base64_decoded_len(""), 0);
+        assert_eq!(base64_decoded_len("YQ=="), 1);
+        assert_eq!(base64_decoded_len("YWI="), 2);
+        assert_eq!(base64_decoded_len("YWJj"), 3);
+        assert_eq!(base64_decoded_len("YWJjZA"), 4);
+    }
+
+    #[test]
+    fn test_check_body_size() {
+        let limits = RequestLimits {
+            max_body_bytes: 100,
+            ..RequestLimits::default()
+        };
+        assert!(check_body_size(&limits, 100).is_ok());
+        assert_eq!(
+            check_body_size(&limits, 101).unwrap_err(),
+            "Request body is 101 bytes, exceeding the limit of 100 bytes"
+        );
+
+        let unlimited = RequestLimits {
+            max_body_bytes: 0,
+            ..RequestLimits::default()
+        };
+        assert!(check_body_size(&unlimited, usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_check_counts() {
+        let limits = RequestLimits {
+            max_messages: 2,
+            max_tools: 1,
+            ..RequestLimits::default()
+        };
+        let message = json!({"role": "user", "content": "hi"});
+
+        assert!(check_request(&limits, &request(vec![message.clone(); 2], 1)).is_ok());
+        assert_eq!(
+            check_request(&limits, &request(vec![message.clone(); 3], 0)).unwrap_err(),
+            "messages: 3 messages exceed the limit of 2"
+        );
+        assert_eq!(
+            check_request(&limits, &request(vec![message], 2)).unwrap_err(),
+            "tools: 2 tools exceed the limit of 1"
+        );
+    }
+
+    #[test]
+    fn test_check_images() {
+        let limits = RequestLimits {
+            max_image_bytes: 3,
+            ..RequestLimits::default()
+        };
+
+        let small = request(
+            vec![
+                json!({"role": "user", "content": [{"type": "text", "text": "look"}, image("YWJj")]}),
+            ],
+            0,
+        );
+        assert!(check_request(&limits, &small).is_ok());
+
+        let large = request(
+            vec![
+                json!({"role": "user", "content": "hi"}),
+                json!({"role": "user", "content": [{"type": "text", "text": "look"}, image("YWJjZA==")]}),
+            ],
+            0,
+        );
+        assert_eq!(
+            check_request(&limits, &large).unwrap_err(),
+            "messages.1.content.1: image is 4 bytes, exceeding the limit of 3 bytes"
+        );
+
+        let nested = request(
+            vec![json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "t1", "content": [image("YWJjZA==")]}
+            ]})],
+            0,
+        );
+        assert_eq!(
+            check_request(&limits, &nested).unwrap_err(),
+            "messages.0.content.0.content.0: image is 4 bytes, exceeding the limit of 3 bytes"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Which headers travel between the client and the upstream
+//!
+//! By default CCR sends upstream only the headers it sets itself (see
+//! [`crate::utils::upstream_headers`]) and returns none of the upstream's.
+//! `FORWARD_HEADERS` and `EXPOSE_HEADERS` name further headers to pass through in
+//! each direction, and `BLOCK_HEADERS` carves exceptions out of them. Patterns are
+//! header names, optionally ending in `*` to match a prefix (`x-stainless-*`).
+
+/// Inbound headers never forwarded: credentials, headers CCR sets itself and
+/// headers the runtime manages
+const RESERVED_INBOUND: &[&str] = &[
+    "authorization",
+    "x-api-key",
+    "x-goog-api-key",
+    "cookie",
+    "cf-*",
+    "x-ccr-*",
+    "content-type",
+    "content-length",
+    "accept-encoding",
+    "host",
+    "connection",
+    "transfer-encoding",
+    "http-referer",
+    "x-title",
+];
+
+/// Upstream headers never exposed, since they describe the upstream's body and
+/// connection rather than CCR's response
+const RESERVED_OUTBOUND: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+    "set-cookie",
+];
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+fn matches_any(patterns: &[impl AsRef<str>], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| matches(pattern.as_ref(), name))
+}
+
+/// Reads a comma-separated pattern list, lowercased
+pub fn parse_patterns(raw: &str) -> Result<Vec<String>, String> {
+    raw.split(',')
+        .map(|pattern| pattern.trim().to_lowercase())
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| {
+            let name = pattern.strip_suffix('*').unwrap_or(&pattern);
+            let valid = name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if valid {
+                Ok(pattern)
+            } else {
+                Err(format!("'{pattern}' is not a header name or prefix*"))
+            }
+        })
+        .collect()
+}
+
+/// Header forwarding allowlists and denylist
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderPolicy {
+    /// Inbound headers sent upstream (`FORWARD_HEADERS`)
+    pub forward: Vec<String>,
+    /// Upstream response headers returned to the client (`EXPOSE_HEADERS`)
+    pub expose: Vec<String>,
+    /// Headers neither forwarded nor exposed (`BLOCK_HEADERS`)
+    pub block: Vec<String>,
+}
+
+impl HeaderPolicy {
+    /// Whether the client's header `name` is sent upstream
+    pub fn forwards(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        matches_any(&self.forward, &name)
+            && !matches_any(&self.block, &name)
+            && !matches_any(RESERVED_INBOUND, &name)
+    }
+
+    /// Whether the upstream's header `name` is returned to the client
+    pub fn exposes(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        matches_any(&self.expose, &name)
+            && !matches_any(&self.block, &name)
+            && !matches_any(RESERVED_OUTBOUND, &name)
+    }
+
+    /// The client headers to send upstream
+    pub fn forwarded(
+        &self,
+        headers: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<(String, String)> {
+        headers
+            .into_iter()
+            .filter(|(name, _)| self.forwards(name))
+            .collect()
+    }
+
+    /// The upstream headers to return to the client
+    pub fn exposed<'a>(&self, headers: &'a [(String, String)]) -> Vec<&'a (String, String)> {
+        headers
+            .iter()
+            .filter(|(name, _)| self.exposes(name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(names: &[&str]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), "v".to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_forwards_and_exposes_nothing() {
+        let policy = HeaderPolicy::default();
+        assert!(policy
+            .forwarded(headers(&["x-stainless-os", "anthropic-beta"]))
+            .is_empty());
+        assert!(!policy.exposes("x-ratelimit-remaining"));
+    }
+
+    #[test]
+    fn test_forward_patterns_and_block_list() {
+        let policy = HeaderPolicy {
+            forward: parse_patterns("x-stainless-*, X-Org-Id, x-*").unwrap(),
+            block: parse_patterns("x-stainless-retry-count").unwrap(),
+            ..HeaderPolicy::default()
+        };
+        let forwarded = policy.forwarded(headers(&[
+            "X-Stainless-OS",
+            "x-stainless-retry-count",
+            "x-org-id",
+            "x-api-key",
+            "x-ccr-session",
+            "user-agent",
+        ]));
+        let names: Vec<_> = forwarded.iter().map(|(name, _)| name.as_str()).collect();
+        // Keys and CCR's own headers stay behind even when a pattern covers them
+        assert_eq!(names, ["X-Stainless-OS", "x-org-id"]);
+    }
+
+    #[test]
+    fn test_exposed_response_headers() {
+        let policy = HeaderPolicy {
+            expose: parse_patterns("x-ratelimit-*, content-length").unwrap(),
+            ..HeaderPolicy::default()
+        };
+        let upstream = headers(&["x-ratelimit-remaining", "content-length", "server"]);
+        let exposed: Vec<_> = policy
+            .exposed(&upstream)
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(exposed, ["x-ratelimit-remaining"]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_patterns() {
+        assert!(parse_patterns("x-org id").is_err());
+        assert!(parse_patterns("x-*-id").is_err());
+        assert_eq!(parse_patterns(" , ").unwrap(), Vec::<String>::new());
+    }
+}
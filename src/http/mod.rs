@@ -0,0 +1,157 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Display;
+use worker::{AbortController, ByteStream, Fetch, Headers, Method, RequestInit, Result};
+
+/// Outbound HTTP client backed by the Workers `fetch` API
+///
+/// A thin builder over `worker::Fetch` so outbound calls share one shape without
+/// bundling a general-purpose HTTP client into the WASM binary. Response bodies
+/// are read or streamed straight from the runtime, never buffered twice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Client;
+
+impl Client {
+    pub fn new() -> Self {
+        Client
+    }
+
+    pub fn get(&self, url: impl Into<String>) -> RequestBuilder {
+        RequestBuilder::new(Method::Get, url.into())
+    }
+
+    pub fn post(&self, url: impl Into<String>) -> RequestBuilder {
+        RequestBuilder::new(Method::Post, url.into())
+    }
+}
+
+/// An outbound request being assembled
+#[derive(Debug)]
+pub struct RequestBuilder {
+    method: Method,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    /// A body serialization failure, reported by `send`
+    error: Option<String>,
+}
+
+impl RequestBuilder {
+    fn new(method: Method, url: String) -> Self {
+        RequestBuilder {
+            method,
+            url,
+            headers: Vec::new(),
+            body: None,
+            error: None,
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn bearer_auth(self, token: impl Display) -> Self {
+        self.header("Authorization", format!("Bearer {token}"))
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Serializes `body` as the JSON request body
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        match serde_json::to_string(body) {
+            Ok(json) => {
+                if !self
+                    .headers
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+                {
+                    self.headers
+                        .push(("Content-Type".to_string(), "application/json".to_string()));
+                }
+                self.body = Some(json);
+            }
+            Err(e) => self.error = Some(format!("Failed to serialize request body: {e}")),
+        }
+        self
+    }
+
+    /// Sends the request, resolving once the response headers arrive
+    ///
+    /// Dropping the returned future before then aborts the fetch, so a request
+    /// that loses a race (e.g. hedging) stops consuming upstream tokens.
+    pub async fn send(self) -> Result<Response> {
+        if let Some(error) = self.error {
+            return Err(worker::Error::RustError(error));
+        }
+
+        let headers = Headers::new();
+        for (name, value) in &self.headers {
+            headers.set(name, value)?;
+        }
+        let mut init = RequestInit::new();
+        init.with_method(self.method)
+            .with_headers(headers)
+            .with_body(self.body.map(Into::into));
+        let request = worker::Request::new_with_init(&self.url, &init)?;
+
+        let controller = AbortController::default();
+        let signal = controller.signal();
+        let mut guard = AbortOnDrop(Some(controller));
+        let response = Fetch::Request(request).send_with_signal(&signal).await?;
+        // Disarm: aborting now would cut off the body being read or streamed
+        guard.0 = None;
+
+        Ok(Response { inner: response })
+    }
+}
+
+/// Aborts a fetch whose future is dropped before it completes
+struct AbortOnDrop(Option<AbortController>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        if let Some(controller) = self.0.take() {
+            controller.abort();
+        }
+    }
+}
+
+/// A response to an outbound request
+pub struct Response {
+    inner: worker::Response,
+}
+
+impl Response {
+    pub fn status(&self) -> u16 {
+        self.inner.status_code()
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status())
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.inner.headers().get(name).ok().flatten()
+    }
+
+    pub fn headers(&self) -> Vec<(String, String)> {
+        self.inner.headers().entries().collect()
+    }
+
+    pub async fn text(mut self) -> Result<String> {
+        self.inner.text().await
+    }
+
+    pub async fn json<T: DeserializeOwned>(mut self) -> Result<T> {
+        self.inner.json().await
+    }
+
+    /// The body as a stream of chunks, as they arrive
+    pub fn bytes_stream(mut self) -> Result<ByteStream> {
+        self.inner.stream()
+    }
+}
@@ -0,0 +1,105 @@
+use crate::auth::hash_key;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// KV namespace binding holding stored responses; idempotency is off while unbound
+pub const IDEMPOTENCY_BINDING: &str = "IDEMPOTENCY";
+
+/// Request header carrying the client's idempotency key
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// Response header set on a response replayed from a previous request
+pub const REPLAYED_HEADER: &str = "x-ccr-idempotent-replayed";
+
+/// A completed response stored under its idempotency key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    /// SHA-256 hex of the request body the response was produced for
+    pub request_hash: String,
+    /// Anthropic-formatted response body
+    pub response: Value,
+}
+
+/// Result of looking up an idempotency key
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup {
+    /// First use of the key; the response should be stored once it completes
+    Miss,
+    /// Retry of an identical request; return the stored response
+    Replay(Value),
+    /// The key was already used for a different request body
+    Conflict,
+}
+
+/// KV key for an idempotency key, scoped to the caller so keys can't collide across callers
+pub fn storage_key(subject: &str, idempotency_key: &str) -> String {
+    format!(
+        "idem:{}",
+        hash_key(&format!("{subject}\n{idempotency_key}"))
+    )
+}
+
+/// Compares a stored response against the current request body
+pub fn classify(stored: Option<StoredResponse>, request_hash: &str) -> Lookup {
+    match stored {
+        None => Lookup::Miss,
+        Some(stored) if stored.request_hash == request_hash => Lookup::Replay(stored.response),
+        Some(_) => Lookup::Conflict,
+    }
+}
+
+/// Looks up a stored response for the request
+pub async fn lookup(kv: &KvStore, storage_key: &str, request_hash: &str) -> Result<Lookup> {
+    let stored = kv
+        .get(storage_key)
+        .json::<StoredResponse>()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to read idempotency key: {e}")))?;
+    Ok(classify(stored, request_hash))
+}
+
+/// Stores a completed response so retries within `ttl_secs` replay it
+pub async fn store(
+    kv: &KvStore,
+    storage_key: &str,
+    stored: &StoredResponse,
+    ttl_secs: u64,
+) -> Result<()> {
+    kv.put(storage_key, serde_json::to_string(stored)?)?
+        .expiration_ttl(ttl_secs)
+        .execute()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to store idempotency key: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_key_is_scoped_to_subject() {
+        let key = storage_key("key:abc", "retry-1");
+        assert!(key.starts_with("idem:"));
+        assert_eq!(key.len(), "idem:".len() + 64);
+        assert_eq!(key, storage_key("key:abc", "retry-1"));
+        assert_ne!(key, storage_key("key:def", "retry-1"));
+        assert_ne!(key, storage_key("key:abc", "retry-2"));
+    }
+
+    #[test]
+    fn test_classify() {
+        let stored = StoredResponse {
+            request_hash: "h1".to_string(),
+            response: serde_json::json!({"id": "msg_1"}),
+        };
+
+        assert_eq!(classify(None, "h1"), Lookup::Miss);
+        assert_eq!(
+            classify(Some(stored.clone()), "h1"),
+            Lookup::Replay(serde_json::json!({"id": "msg_1"}))
+        );
+        assert_eq!(classify(Some(stored), "h2"), Lookup::Conflict);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::config::Config;
+use crate::injection::InjectionMode;
+use crate::transcripts::TranscriptMode;
+use crate::vision::ImageFallback;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use worker::Url;
+
+/// Public endpoints served by every deployment
+const ENDPOINTS: &[&str] = &[
+    "/v1/messages",
+    "/v1/chat/completions",
+    "/v1/embeddings",
+    "/v1/models",
+    "/v1beta/models/{model}:generateContent",
+];
+
+/// Deployment metadata served by `GET /api/info` and rendered by the home page
+///
+/// Only settings a client could observe anyway are included; keys, tokens and
+/// webhook URLs never are.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeploymentInfo {
+    pub version: &'static str,
+    /// Host of the configured upstream, e.g. `openrouter.ai`
+    pub upstream_host: Option<String>,
+    /// Targets of the Claude short model names
+    pub model_map: BTreeMap<&'static str, String>,
+    pub default_max_tokens: u32,
+    /// Optional features that are configured and not switched off
+    pub features: Vec<&'static str>,
+    /// Whether clients must send their own upstream key
+    pub client_key_required: bool,
+    /// Whether an access token or allowlisted key is needed to use the deployment
+    pub access_restricted: bool,
+    pub endpoints: &'static [&'static str],
+}
+
+impl DeploymentInfo {
+    pub fn from_config(config: &Config) -> Self {
+        let flags = &config.features;
+        let features = [
+            ("auto_model", flags.auto_model),
+            ("hedging", flags.hedging && config.hedge_model.is_some()),
+            (
+                "shadow",
+                flags.shadow && config.shadow_model.is_some() && config.shadow_sample_percent > 0,
+            ),
+            (
+                "regional_upstreams",
+                flags.regional_upstreams && !config.regional_upstreams.is_empty(),
+            ),
+            ("sso", config.jwt.is_some()),
+            ("cors", !config.cors_allowed_origins.is_empty()),
+            (
+                "rate_limits",
+                [
+                    config.rate_limits.key_rpm,
+                    config.rate_limits.key_tpm,
+                    config.rate_limits.ip_rpm,
+                    config.rate_limits.ip_tpm,
+                ]
+                .iter()
+                .any(|limit| *limit > 0),
+            ),
+            ("concurrency_limit", config.rate_limits.key_concurrency > 0),
+            ("moderation", config.moderation.is_some()),
+            (
+                "prompt_injection",
+                config.prompt_injection != InjectionMode::Off,
+            ),
+            (
+                "image_fallback",
+                config.image_fallback != ImageFallback::Off,
+            ),
+            ("prompt_cache_hints", config.prompt_cache_hints),
+            ("web_search", config.web_search),
+            ("transcripts", config.log_to_r2 != TranscriptMode::Off),
+            ("error_reporting", config.error_reporting.is_some()),
+            ("alerts", config.alerts.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect();
+
+        let targets = &config.model_targets;
+        DeploymentInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            upstream_host: Url::parse(&config.openrouter_base_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string)),
+            model_map: BTreeMap::from([
+                ("haiku", targets.haiku.clone()),
+                ("sonnet", targets.sonnet.clone()),
+                ("opus", targets.opus.clone()),
+            ]),
+            default_max_tokens: config.default_max_tokens,
+            features,
+            client_key_required: config.server_api_key.is_none(),
+            access_restricted: config.access_token.is_some()
+                || !config.allowed_key_hashes.is_empty()
+                || config.jwt.is_some(),
+            endpoints: ENDPOINTS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config_defaults() {
+        let info = DeploymentInfo::from_config(&Config::default());
+        assert_eq!(info.upstream_host.as_deref(), Some("openrouter.ai"));
+        assert_eq!(info.model_map["sonnet"], "anthropic/claude-sonnet-4");
+        assert_eq!(info.features, ["auto_model"]);
+        assert!(info.client_key_required);
+        assert!(!info.access_restricted);
+    }
+
+    #[test]
+    fn test_from_config_reports_enabled_features() {
+        let mut config = Config {
+            hedge_model: Some("openai/gpt-4o-mini".to_string()),
+            access_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        config.features.auto_model = false;
+        config.rate_limits.key_rpm = 60;
+
+        let info = DeploymentInfo::from_config(&config);
+        assert_eq!(info.features, ["hedging", "rate_limits"]);
+        assert!(info.access_restricted);
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert!(!json.to_string().contains("secret"));
+    }
+}
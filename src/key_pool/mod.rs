@@ -0,0 +1,130 @@
+//! Rotation across several upstream API keys (`OPENROUTER_API_KEYS`)
+//!
+//! Requests take the pool's keys in turn. A key that answers with a rate limit
+//! or an out-of-credit error is benched for a cooldown and skipped until it ends.
+//! The rotation and the benches are kept per isolate.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static POOL: RefCell<PoolState> = RefCell::new(PoolState::default());
+}
+
+/// Whether an upstream status means the key itself should sit out: 429 rate
+/// limited or 402 out of credit
+pub fn should_bench(status: u16) -> bool {
+    matches!(status, 402 | 429)
+}
+
+/// Rotation position and benched keys of a pool
+#[derive(Debug, Default)]
+pub struct PoolState {
+    next: usize,
+    /// Key → time (ms) its bench ends
+    benched_until: HashMap<String, u64>,
+}
+
+impl PoolState {
+    fn is_benched(&self, key: &str, now_ms: u64) -> bool {
+        self.benched_until
+            .get(key)
+            .is_some_and(|until| *until > now_ms)
+    }
+
+    /// The next key in turn that isn't benched
+    pub fn pick_available(&mut self, keys: &[String], now_ms: u64) -> Option<String> {
+        self.benched_until.retain(|_, until| *until > now_ms);
+        for offset in 0..keys.len() {
+            let index = (self.next + offset) % keys.len();
+            if !self.is_benched(&keys[index], now_ms) {
+                self.next = index + 1;
+                return Some(keys[index].clone());
+            }
+        }
+        None
+    }
+
+    /// The next key in turn; when every key is benched, the one whose bench ends first
+    pub fn pick(&mut self, keys: &[String], now_ms: u64) -> Option<String> {
+        self.pick_available(keys, now_ms).or_else(|| {
+            keys.iter()
+                .min_by_key(|key| self.benched_until.get(*key))
+                .cloned()
+        })
+    }
+
+    /// Benches `key` until `until_ms`
+    pub fn bench(&mut self, key: &str, until_ms: u64) {
+        self.benched_until.insert(key.to_string(), until_ms);
+    }
+}
+
+/// Takes the next key from the isolate's pool rotation
+pub fn next_key(keys: &[String], now_ms: u64) -> Option<String> {
+    POOL.with(|pool| pool.borrow_mut().pick(keys, now_ms))
+}
+
+/// Benches a failed `key` for `cooldown_ms`, returning another key that isn't benched
+pub fn fail_over(keys: &[String], key: &str, now_ms: u64, cooldown_ms: u64) -> Option<String> {
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        pool.bench(key, now_ms.saturating_add(cooldown_ms));
+        pool.pick_available(keys, now_ms)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        vec!["sk-a".to_string(), "sk-b".to_string(), "sk-c".to_string()]
+    }
+
+    #[test]
+    fn test_bench_statuses() {
+        assert!(should_bench(429));
+        assert!(should_bench(402));
+        assert!(!should_bench(500));
+        assert!(!should_bench(401));
+    }
+
+    #[test]
+    fn test_keys_rotate_in_turn() {
+        let mut pool = PoolState::default();
+        let picked: Vec<_> = (0..4).map(|_| pool.pick(&keys(), 0).unwrap()).collect();
+        assert_eq!(picked, ["sk-a", "sk-b", "sk-c", "sk-a"]);
+        assert_eq!(pool.pick(&[], 0), None);
+    }
+
+    #[test]
+    fn test_benched_keys_are_skipped_until_cooldown_ends() {
+        let mut pool = PoolState::default();
+        pool.bench("sk-b", 1000);
+        let picked: Vec<_> = (0..3).map(|_| pool.pick(&keys(), 500).unwrap()).collect();
+        assert_eq!(picked, ["sk-a", "sk-c", "sk-a"]);
+        assert_eq!(pool.pick(&keys(), 1000).unwrap(), "sk-b");
+    }
+
+    #[test]
+    fn test_fully_benched_pool_uses_the_key_back_soonest() {
+        let mut pool = PoolState::default();
+        pool.bench("sk-a", 3000);
+        pool.bench("sk-b", 2000);
+        pool.bench("sk-c", 4000);
+        assert_eq!(pool.pick_available(&keys(), 0), None);
+        assert_eq!(pool.pick(&keys(), 0).unwrap(), "sk-b");
+    }
+
+    #[test]
+    fn test_fail_over_benches_and_moves_on() {
+        let keys = vec!["sk-fail-1".to_string(), "sk-fail-2".to_string()];
+        assert_eq!(
+            fail_over(&keys, "sk-fail-1", 0, 60_000).as_deref(),
+            Some("sk-fail-2")
+        );
+        assert_eq!(fail_over(&keys, "sk-fail-2", 0, 60_000), None);
+        assert_eq!(next_key(&keys, 30_000).as_deref(), Some("sk-fail-1"));
+    }
+}
@@ -0,0 +1,303 @@
+//! Request ledger: one row per proxied request in a D1 database
+//!
+//! Analytics Engine samples and only keeps three months; the ledger keeps every
+//! request for as long as the database does and can be queried with plain SQL,
+//! either through `GET /admin/ledger` or `wrangler d1 execute`. The table is
+//! created by `migrations/0001_request_ledger.sql`.
+
+use crate::metrics::RequestMetrics;
+use serde_json::Value;
+use wasm_bindgen::JsValue;
+use worker::d1::D1PreparedStatement;
+use worker::{D1Database, Result};
+
+/// D1 database binding holding the ledger; nothing is recorded while unbound
+pub const LEDGER_BINDING: &str = "LEDGER_DB";
+
+/// Days covered by a query when `?days=` isn't given
+pub const DEFAULT_DAYS: u32 = 7;
+
+/// Rows returned when `?limit=` isn't given
+pub const DEFAULT_LIMIT: u32 = 100;
+
+/// Most rows a query returns
+pub const MAX_LIMIT: u32 = 1000;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// One request as recorded in the `requests` table; never includes message content
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerRow {
+    pub request_id: String,
+    /// Unix timestamp (ms) the request finished
+    pub ts: u64,
+    pub metrics: RequestMetrics,
+}
+
+impl LedgerRow {
+    /// Column values in the order of [`INSERT_SQL`]
+    fn values(&self) -> Vec<Param> {
+        let metrics = &self.metrics;
+        vec![
+            Param::Text(self.request_id.clone()),
+            Param::Number(self.ts as f64),
+            Param::Text(metrics.key_hash.clone()),
+            Param::Text(metrics.model.clone()),
+            Param::Text(metrics.requested_model.clone()),
+            Param::Number(f64::from(metrics.status)),
+            Param::Number(metrics.latency_ms),
+            Param::Number(f64::from(metrics.input_tokens)),
+            Param::Number(f64::from(metrics.output_tokens)),
+            metrics.cost_usd.map_or(Param::Null, Param::Number),
+        ]
+    }
+}
+
+const INSERT_SQL: &str = "INSERT INTO requests (request_id, ts, key_hash, model, requested_model, \
+     status, latency_ms, input_tokens, output_tokens, cost_usd) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// A value bound to a statement placeholder
+#[derive(Debug, Clone, PartialEq)]
+enum Param {
+    Null,
+    Number(f64),
+    Text(String),
+}
+
+impl From<&Param> for JsValue {
+    fn from(param: &Param) -> Self {
+        match param {
+            Param::Null => JsValue::NULL,
+            Param::Number(number) => JsValue::from(*number),
+            Param::Text(text) => JsValue::from(text.as_str()),
+        }
+    }
+}
+
+fn statement(db: &D1Database, sql: &str, params: &[Param]) -> Result<D1PreparedStatement> {
+    let values: Vec<JsValue> = params.iter().map(JsValue::from).collect();
+    db.prepare(sql).bind(&values)
+}
+
+/// Records a request
+pub async fn insert(db: &D1Database, row: &LedgerRow) -> Result<()> {
+    statement(db, INSERT_SQL, &row.values())?.run().await?;
+    Ok(())
+}
+
+/// When request `request_id` was recorded, if it was
+pub async fn timestamp_of(db: &D1Database, request_id: &str) -> Result<Option<u64>> {
+    let sql = "SELECT ts FROM requests WHERE request_id = ? LIMIT 1";
+    let ts = statement(db, sql, &[Param::Text(request_id.to_string())])?
+        .first::<f64>(Some("ts"))
+        .await?;
+    Ok(ts.map(|ts| ts as u64))
+}
+
+/// What rows of a ledger query are aggregated by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// UTC day, as `YYYY-MM-DD`
+    Day,
+    Model,
+    Key,
+}
+
+impl GroupBy {
+    fn column(self) -> &'static str {
+        match self {
+            GroupBy::Day => "strftime('%Y-%m-%d', ts / 1000, 'unixepoch')",
+            GroupBy::Model => "model",
+            GroupBy::Key => "key_hash",
+        }
+    }
+
+    /// Days in order, other groups by spend
+    fn order(self) -> &'static str {
+        match self {
+            GroupBy::Day => "name DESC",
+            GroupBy::Model | GroupBy::Key => "cost_usd DESC",
+        }
+    }
+}
+
+/// Filters of `GET /admin/ledger`, read from its query string
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerQuery {
+    pub since_ms: u64,
+    pub key_hash: Option<String>,
+    pub model: Option<String>,
+    pub status: Option<u16>,
+    /// Totals per group instead of individual requests
+    pub group_by: Option<GroupBy>,
+    pub limit: u32,
+}
+
+impl LedgerQuery {
+    /// Reads `days`, `key_hash`, `model`, `status`, `group_by` and `limit`
+    pub fn from_params<'a>(
+        params: impl IntoIterator<Item = (&'a str, &'a str)>,
+        now_ms: u64,
+    ) -> std::result::Result<Self, String> {
+        let mut days = DEFAULT_DAYS;
+        let mut query = LedgerQuery {
+            since_ms: 0,
+            key_hash: None,
+            model: None,
+            status: None,
+            group_by: None,
+            limit: DEFAULT_LIMIT,
+        };
+        for (name, value) in params {
+            match name {
+                "days" => {
+                    days = value
+                        .parse()
+                        .ok()
+                        .filter(|days| *days > 0)
+                        .ok_or("days must be a positive number")?
+                }
+                "key_hash" => query.key_hash = Some(value.to_lowercase()),
+                "model" => query.model = Some(value.to_string()),
+                "status" => {
+                    query.status = Some(value.parse().map_err(|_| "status must be an HTTP status")?)
+                }
+                "group_by" => {
+                    query.group_by = Some(match value {
+                        "day" => GroupBy::Day,
+                        "model" => GroupBy::Model,
+                        "key" => GroupBy::Key,
+                        _ => return Err("group_by must be day, model or key".to_string()),
+                    })
+                }
+                "limit" => {
+                    query.limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or(format!("limit must be between 1 and {MAX_LIMIT}"))?
+                }
+                _ => {}
+            }
+        }
+        query.since_ms = now_ms.saturating_sub(u64::from(days) * DAY_MS);
+        Ok(query)
+    }
+
+    /// The statement and its parameters; every user-supplied value is bound, never
+    /// interpolated
+    fn sql(&self) -> (String, Vec<Param>) {
+        let mut conditions = vec!["ts >= ?"];
+        let mut params = vec![Param::Number(self.since_ms as f64)];
+        if let Some(key_hash) = &self.key_hash {
+            conditions.push("key_hash = ?");
+            params.push(Param::Text(key_hash.clone()));
+        }
+        if let Some(model) = &self.model {
+            conditions.push("model = ?");
+            params.push(Param::Text(model.clone()));
+        }
+        if let Some(status) = self.status {
+            conditions.push("status = ?");
+            params.push(Param::Number(f64::from(status)));
+        }
+        let filter = conditions.join(" AND ");
+
+        let sql = match self.group_by {
+            Some(group_by) => format!(
+                "SELECT {} AS name, COUNT(*) AS requests, SUM(input_tokens) AS input_tokens, \
+                 SUM(output_tokens) AS output_tokens, SUM(COALESCE(cost_usd, 0)) AS cost_usd, \
+                 SUM(status >= 400) AS errors, AVG(latency_ms) AS avg_latency_ms \
+                 FROM requests WHERE {filter} GROUP BY name ORDER BY {} LIMIT ?",
+                group_by.column(),
+                group_by.order()
+            ),
+            None => format!(
+                "SELECT request_id, ts, key_hash, model, requested_model, status, latency_ms, \
+                 input_tokens, output_tokens, cost_usd \
+                 FROM requests WHERE {filter} ORDER BY ts DESC LIMIT ?"
+            ),
+        };
+        params.push(Param::Number(f64::from(self.limit)));
+        (sql, params)
+    }
+}
+
+/// Runs a ledger query, returning one JSON object per row
+pub async fn query(db: &D1Database, query: &LedgerQuery) -> Result<Vec<Value>> {
+    let (sql, params) = query.sql();
+    statement(db, &sql, &params)?.all().await?.results()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    #[test]
+    fn test_row_values_follow_insert_columns() {
+        let row = LedgerRow {
+            request_id: "8f1c2d".to_string(),
+            ts: NOW,
+            metrics: RequestMetrics {
+                model: "deepseek/deepseek-chat".to_string(),
+                requested_model: "claude-sonnet-4".to_string(),
+                key_hash: "ab12".to_string(),
+                status: 200,
+                latency_ms: 812.0,
+                input_tokens: 1200,
+                output_tokens: 300,
+                cost_usd: None,
+                prompt_cache_key: None,
+                cached_input_tokens: 0,
+            },
+        };
+        let values = row.values();
+        assert_eq!(values.len(), INSERT_SQL.matches('?').count());
+        assert_eq!(values[3], Param::Text("deepseek/deepseek-chat".to_string()));
+        assert_eq!(values[9], Param::Null);
+    }
+
+    #[test]
+    fn test_query_defaults_and_validation() {
+        let query = LedgerQuery::from_params([], NOW).unwrap();
+        assert_eq!(query.since_ms, NOW - 7 * DAY_MS);
+        assert_eq!(query.limit, DEFAULT_LIMIT);
+        assert_eq!(query.group_by, None);
+
+        let query = LedgerQuery::from_params([("days", "30"), ("group_by", "model")], NOW).unwrap();
+        assert_eq!(query.since_ms, NOW - 30 * DAY_MS);
+        assert_eq!(query.group_by, Some(GroupBy::Model));
+
+        assert!(LedgerQuery::from_params([("group_by", "ip")], NOW).is_err());
+        assert!(LedgerQuery::from_params([("limit", "5000")], NOW).is_err());
+        assert!(LedgerQuery::from_params([("days", "0")], NOW).is_err());
+    }
+
+    #[test]
+    fn test_filters_are_bound_not_interpolated() {
+        let query = LedgerQuery::from_params(
+            [
+                ("model", "x' OR '1'='1"),
+                ("key_hash", "AB12"),
+                ("status", "429"),
+            ],
+            NOW,
+        )
+        .unwrap();
+        let (sql, params) = query.sql();
+        assert!(!sql.contains("OR '1'"), "{sql}");
+        assert!(sql.contains("WHERE ts >= ? AND key_hash = ? AND model = ? AND status = ?"));
+        assert_eq!(sql.matches('?').count(), params.len());
+        assert_eq!(params[1], Param::Text("ab12".to_string()));
+    }
+
+    #[test]
+    fn test_grouped_query() {
+        let query = LedgerQuery::from_params([("group_by", "day")], NOW).unwrap();
+        let (sql, _) = query.sql();
+        assert!(sql.contains("strftime('%Y-%m-%d', ts / 1000, 'unixepoch') AS name"));
+        assert!(sql.contains("GROUP BY name"));
+    }
+}
@@ -1,118 +1,348 @@
+#[cfg(feature = "cloudflare")]
 use worker::*;
 
-// Module declarations
-pub mod config;
+// The transform core, which builds without the Workers runtime
+pub mod admission;
+pub mod api_version;
+pub mod auto_model;
+pub mod clock;
+pub mod context_recovery;
+pub mod cors;
+pub mod deadline;
+pub mod features;
+pub mod gemini;
+pub mod guardrails;
+pub mod key_pool;
+pub mod log;
+pub mod model_route;
 pub mod models;
-mod routes;
+pub mod retry;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod transform;
 pub mod utils;
 
+// The Worker itself
+#[cfg(feature = "cloudflare")]
+pub mod alerts;
+#[cfg(feature = "cloudflare")]
+pub mod async_jobs;
+#[cfg(feature = "cloudflare")]
+pub mod attachments;
+#[cfg(feature = "cloudflare")]
+pub mod auth;
+#[cfg(feature = "cloudflare")]
+pub mod betas;
+#[cfg(feature = "cloudflare")]
+pub mod branding;
+#[cfg(feature = "cloudflare")]
+pub mod budget;
+#[cfg(feature = "cloudflare")]
+pub mod catalog;
+#[cfg(feature = "cloudflare")]
+pub mod compression;
+#[cfg(feature = "cloudflare")]
+pub mod concurrency;
+#[cfg(feature = "cloudflare")]
+pub mod config;
+#[cfg(feature = "cloudflare")]
+pub mod geo;
+#[cfg(feature = "cloudflare")]
+pub mod header_policy;
+#[cfg(feature = "cloudflare")]
+pub mod http;
+#[cfg(feature = "cloudflare")]
+pub mod idempotency;
+#[cfg(feature = "cloudflare")]
+pub mod info;
+#[cfg(feature = "cloudflare")]
+pub mod injection;
+#[cfg(feature = "cloudflare")]
+pub mod ledger;
+#[cfg(feature = "cloudflare")]
+pub mod maintenance;
+#[cfg(feature = "cloudflare")]
+pub mod metrics;
+#[cfg(feature = "cloudflare")]
+pub mod moderation;
+#[cfg(feature = "cloudflare")]
+pub mod pii;
+#[cfg(feature = "cloudflare")]
+pub mod pricing;
+#[cfg(feature = "cloudflare")]
+pub mod profiles;
+#[cfg(feature = "cloudflare")]
+pub mod prompt_cache;
+#[cfg(feature = "cloudflare")]
+pub mod rate_limit;
+#[cfg(feature = "cloudflare")]
+pub mod replay;
+#[cfg(feature = "cloudflare")]
+pub mod reporting;
+#[cfg(feature = "cloudflare")]
+pub mod response_cache;
+#[cfg(feature = "cloudflare")]
+mod routes;
+#[cfg(feature = "cloudflare")]
+pub mod seed;
+#[cfg(feature = "cloudflare")]
+pub mod selftest;
+#[cfg(feature = "cloudflare")]
+pub mod semantic_cache;
+#[cfg(feature = "cloudflare")]
+pub mod session_model;
+#[cfg(feature = "cloudflare")]
+pub mod sessions;
+#[cfg(feature = "cloudflare")]
+pub mod shadow;
+#[cfg(feature = "cloudflare")]
+pub mod signing;
+#[cfg(feature = "cloudflare")]
+pub mod tail;
+#[cfg(feature = "cloudflare")]
+pub mod transcripts;
+#[cfg(feature = "cloudflare")]
+pub mod upstream;
+#[cfg(feature = "cloudflare")]
+pub mod usage;
+#[cfg(feature = "cloudflare")]
+pub mod vision;
+
+#[cfg(feature = "cloudflare")]
 use config::Config;
+#[cfg(feature = "cloudflare")]
+use deadline::Budget;
+#[cfg(feature = "cloudflare")]
+use log::Logger;
+#[cfg(feature = "cloudflare")]
+use routes::middleware::{Pipeline, RequestContext};
+#[cfg(feature = "cloudflare")]
+use routes::router::{resolve, Resolution, Route};
+#[cfg(feature = "cloudflare")]
+use upstream::FetchClient;
 
 /// Main entry point for the Cloudflare Worker
 ///
 /// This function handles all incoming HTTP requests and routes them to appropriate handlers
 /// based on the URL path and HTTP method. It acts as a proxy between Anthropic's Claude API
 /// and OpenAI-compatible APIs (specifically OpenRouter).
+#[cfg(feature = "cloudflare")]
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
     // Add performance monitoring
     let start_time = Date::now().as_millis() as f64;
 
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("🚀 Request started at: {}", start_time).into());
+    // The Ray ID ties log lines to the request in the Cloudflare dashboard
+    let request_id = req
+        .headers()
+        .get("cf-ray")?
+        .unwrap_or_else(|| format!("{:x}", Date::now().as_millis()));
+    let log = Logger::new(request_id);
 
     // Set up request monitoring with timeout detection
-    let result = handle_request_with_monitoring(req, env, ctx, start_time).await;
-
-    let end_time = Date::now().as_millis() as f64;
-    let duration = end_time - start_time;
+    let result = handle_request_with_monitoring(req, env, ctx, start_time, &log).await;
 
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("✅ Request completed in: {}ms", duration).into());
+    // Answered requests are logged by the RequestLog middleware
+    if let Err(e) = &result {
+        let end_time = Date::now().as_millis() as f64;
+        log.error(
+            "request failed",
+            &[
+                ("error", e.to_string().into()),
+                ("latency_ms", (end_time - start_time).into()),
+            ],
+        );
+    }
 
     result
 }
 
+/// Queue consumer running requests sent with `x-ccr-async: true`
+#[cfg(feature = "cloudflare")]
+#[event(queue)]
+pub async fn queue(batch: MessageBatch<async_jobs::Job>, env: Env, _ctx: Context) -> Result<()> {
+    routes::jobs::consume(batch, env).await
+}
+
+/// Cron triggers, running the scheduled tasks such as the spend digest
+#[cfg(feature = "cloudflare")]
+#[event(scheduled)]
+pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    let now_ms = event.schedule() as u64;
+    let log = Logger::new(format!("cron-{now_ms:x}"));
+    if let Err(e) = routes::cron::run(&env, now_ms, &log).await {
+        log.error("scheduled run failed", &[("error", e.to_string().into())]);
+    }
+}
+
+#[cfg(feature = "cloudflare")]
 async fn handle_request_with_monitoring(
     req: Request,
     env: Env,
-    _ctx: Context,
+    ctx: Context,
     start_time: f64,
+    log: &Logger,
 ) -> Result<Response> {
-    // Add periodic time checks to detect when we're approaching limits
-    let check_time = || {
-        let current_time = Date::now().as_millis() as f64;
-        let elapsed = current_time - start_time;
-        if elapsed > 25000.0 {
-            // 25 seconds - approaching 30s limit
-            #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(
-                &format!(
-                    "⚠️  WARNING: Request running for {}ms, approaching timeout",
-                    elapsed
-                )
-                .into(),
-            );
+    let url = req.url()?;
+    let method = req.method();
+
+    log.debug(
+        "routing",
+        &[
+            ("method", method.to_string().into()),
+            ("path", url.path().into()),
+        ],
+    );
+
+    let route = match resolve(url.path(), &method) {
+        Resolution::Found(route) => route,
+        Resolution::MethodNotAllowed(allowed) => {
+            let mut response = Response::error("Method Not Allowed", 405)?;
+            response.headers_mut().set("Allow", allowed.as_ref())?;
+            return Ok(response);
         }
-        elapsed
+        Resolution::NotFound => return Response::error("Not Found", 404),
     };
 
-    // Load configuration from environment variables
-    let _elapsed = check_time();
-    let config = Config::from_env(&env)?;
+    // The self-test reports configuration errors itself, so it runs before config loading
+    if route == Route::AdminSelftest {
+        return routes::admin::selftest(req, &env).await;
+    }
 
-    let _elapsed = check_time();
-    let url = req.url()?;
-    let method = req.method();
+    // Load configuration (parsed and validated once per isolate)
+    let config = match Config::cached(&env) {
+        Ok(config) => config,
+        Err(e) => {
+            log.error("invalid configuration", &[("error", e.to_string().into())]);
+            return Response::error(format!("{e}"), 500);
+        }
+    };
+    log::set_max_level(config.log_level);
+
+    // Requests can be traced step by step without raising LOG_LEVEL for everyone
+    let traced = log.clone().with_tracing(
+        config.debug_tracing
+            || log::trace_requested(req.headers().get(log::TRACE_HEADER)?.as_deref()),
+    );
+    let log = &traced;
+
+    // Phases consult the budget as the request nears the runtime limit
+    let budget = Budget::new(
+        start_time as u64,
+        config.slow_request_warn_ms,
+        deadline::worker_now,
+    );
+    budget.check("routed", log);
+    let pipeline = Pipeline::standard();
+    let mut cx = RequestContext {
+        route,
+        env: &env,
+        ctx: &ctx,
+        config: &config,
+        log,
+        budget,
+        caller: None,
+        origin: req.headers().get("Origin")?,
+    };
+    let response = match pipeline.before(&req, &mut cx).await? {
+        Some(response) => response,
+        None => dispatch(req, &mut cx).await?,
+    };
+    let response = pipeline.after(&cx, response)?;
+    match &config.response_signing_key {
+        Some(key) if cx.route.is_authenticated() => {
+            signing::sign(response, key, Date::now().as_millis() / 1000).await
+        }
+        _ => Ok(response),
+    }
+}
 
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("🔍 Routing: {} {}", method, url.path()).into());
+/// Calls the route's handler once the middleware has let the request through
+#[cfg(feature = "cloudflare")]
+async fn dispatch(req: Request, cx: &mut RequestContext<'_>) -> Result<Response> {
+    let RequestContext {
+        env,
+        ctx,
+        config,
+        log,
+        budget,
+        ..
+    } = *cx;
 
-    // Route requests based on path and method
-    let _elapsed = check_time();
-    match (url.path(), method) {
+    match cx.route.clone() {
         // Static documentation pages
-        ("/", Method::Get) => routes::static_pages::home().await,
-        ("/terms", Method::Get) => routes::static_pages::terms().await,
-        ("/privacy", Method::Get) => routes::static_pages::privacy().await,
+        Route::Home => {
+            let origin = req.url()?.origin().ascii_serialization();
+            let branding = branding::load(env, &config.branding, log).await;
+            routes::static_pages::home(config, &branding, &origin).await
+        }
+        Route::Terms => {
+            let branding = branding::load(env, &config.branding, log).await;
+            routes::static_pages::terms(&branding).await
+        }
+        Route::Privacy => {
+            let branding = branding::load(env, &config.branding, log).await;
+            routes::static_pages::privacy(&branding).await
+        }
+        Route::Asset(name) => routes::static_pages::asset(&name).await,
+        Route::ApiInfo => routes::static_pages::info(config).await,
+        Route::Health => routes::static_pages::health(env, log).await,
 
-        // Main API endpoint - translates Anthropic format to OpenAI format
-        ("/v1/messages", Method::Post) => {
-            #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(&"🔄 Handling /v1/messages request".into());
+        // Usage report from the analytics dataset, behind the admin token
+        Route::Usage => routes::admin::usage(req, env, config).await,
+        Route::UsageForecast => routes::admin::forecast(req, env, config).await,
+
+        // Live request console, behind the admin token
+        Route::AdminTail => routes::admin::tail(req, env).await,
+
+        // Queries over the D1 request ledger, behind the admin token
+        Route::AdminLedger => routes::admin::ledger(req, env).await,
+
+        // Re-runs a stored transcript against the current mapping, behind the admin token
+        Route::AdminReplay(id) => routes::replay::replay(req, &id, env, config, &budget, log).await,
 
-            let _elapsed = check_time();
+        // OpenRouter's model catalog in the Anthropic models-list shape
+        Route::Models => routes::models::list(req, env, config).await,
+        Route::Model(id) => routes::models::retrieve(&id, env, config).await,
+
+        // Main API endpoint - translates Anthropic format to OpenAI format
+        Route::Messages => {
+            budget.check("dispatch", log);
 
             // Wrap in error handling to catch cancellations
-            match routes::proxy::handle_messages(req, &config).await {
-                Ok(response) => {
-                    #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(&"✅ handle_messages completed successfully".into());
-                    Ok(response)
-                }
+            let caller = cx.take_caller()?;
+            let mut forwarded_headers = caller
+                .config
+                .header_policy
+                .forwarded(req.headers().entries());
+            forwarded_headers.extend(betas::upstream_headers(
+                req.headers().get(betas::BETA_HEADER)?.as_deref(),
+            ));
+            let upstream = FetchClient::new()
+                .with_timeout(caller.config.upstream_timeout_ms)
+                .with_headers(forwarded_headers)
+                .with_attribution(caller.config.attribution.clone());
+            match routes::proxy::handle_messages(req, env, ctx, caller, &upstream, &budget, log)
+                .await
+            {
+                Ok(response) => Ok(response),
                 Err(e) => {
-                    let current_time = Date::now().as_millis() as f64;
-                    let total_elapsed = current_time - start_time;
-
-                    #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(
-                        &format!("🚨 handle_messages ERROR after {}ms: {}", total_elapsed, e)
-                            .into(),
-                    );
+                    let total_elapsed = budget.elapsed_ms();
 
                     // Check if this looks like a cancellation
                     let error_msg = format!("{e}");
-                    if error_msg.contains("canceled") || error_msg.contains("cancelled") {
-                        #[cfg(target_arch = "wasm32")]
-                        web_sys::console::log_1(
-                            &format!(
-                                "🛑 CANCELLATION DETECTED: Runtime cancelled request after {}ms",
-                                total_elapsed
-                            )
-                            .into(),
-                        );
+                    let cancelled =
+                        error_msg.contains("canceled") || error_msg.contains("cancelled");
+                    log.error(
+                        "handle_messages failed",
+                        &[
+                            ("error", error_msg.as_str().into()),
+                            ("cancelled", cancelled.into()),
+                            ("latency_ms", total_elapsed.into()),
+                        ],
+                    );
 
+                    if cancelled {
                         // Return a more descriptive error
                         Response::error(format!("Request cancelled by Workers runtime after {total_elapsed}ms. This usually means the request exceeded resource limits (CPU/memory/time)."), 500)
                     } else {
@@ -122,7 +352,60 @@ async fn handle_request_with_monitoring(
             }
         }
 
-        // 404 for all other routes
-        _ => Response::error("Not Found", 404),
+        // OpenAI-compatible endpoint for tools that don't speak the Anthropic API
+        Route::ChatCompletions => {
+            let caller = cx.take_caller()?;
+            routes::chat::handle_chat_completions(req, env, ctx, caller, log).await
+        }
+        Route::Embeddings => {
+            let caller = cx.take_caller()?;
+            routes::embeddings::handle_embeddings(req, env, ctx, caller, log).await
+        }
+
+        // The same prompt sent to several models, for comparing them
+        Route::ExperimentsCompare => {
+            let caller = cx.take_caller()?;
+            routes::compare::handle_compare(req, env, ctx, caller, &budget, log).await
+        }
+
+        // Conversation exports translated between formats, without calling upstream
+        Route::Convert => {
+            let caller = cx.take_caller()?;
+            routes::convert::handle_convert(req, caller).await
+        }
+
+        // Gemini API ingress for Gemini CLI and other Gemini clients
+        Route::Gemini(path) => {
+            let caller = cx.take_caller()?;
+            routes::gemini::handle_generate_content(req, &path, env, ctx, caller, log).await
+        }
+
+        // Result of a request made with x-ccr-async
+        Route::AsyncJob(id) => {
+            let caller = cx.take_caller()?;
+            routes::jobs::poll(&id, env, caller).await
+        }
+
+        // Saved transcript of an opted-in session
+        Route::SessionExport(id) => {
+            let caller = cx.take_caller()?;
+            routes::sessions::handle_export(req, &id, env, caller).await
+        }
+
+        // Offloaded attachment, fetched by the upstream through a signed link
+        Route::Attachment(id) => {
+            let (Some(attachments), Ok(bucket)) = (
+                &config.attachments,
+                env.bucket(attachments::ATTACHMENTS_BINDING),
+            ) else {
+                return Response::error("Not Found", 404);
+            };
+            let query: Vec<(String, String)> = req.url()?.query_pairs().into_owned().collect();
+            let now = Date::now().as_millis() / 1000;
+            attachments::serve(&bucket, attachments, &id, &query, now).await
+        }
+
+        // Normally answered before configuration is loaded, above
+        Route::AdminSelftest => routes::admin::selftest(req, env).await,
     }
 }
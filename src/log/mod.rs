@@ -0,0 +1,283 @@
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Severity of a log event, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err("expected error, warn, info, debug or trace".to_string()),
+        }
+    }
+}
+
+/// Request header asking for every event of the request to be logged, with the
+/// elapsed time at each step (`x-ccr-trace: 1`)
+pub const TRACE_HEADER: &str = "x-ccr-trace";
+
+/// Whether a [`TRACE_HEADER`] value turns tracing on
+pub fn trace_requested(value: Option<&str>) -> bool {
+    matches!(value.map(str::trim), Some("1" | "true"))
+}
+
+/// Most verbose level emitted; set from `LOG_LEVEL` when the configuration loads
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if events at `level` are emitted, to skip building costly fields
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Field names whose values never reach the logs: prompt content and credentials
+const REDACTED_FIELDS: &[&str] = &[
+    "content",
+    "messages",
+    "system",
+    "prompt",
+    "api_key",
+    "authorization",
+    "token",
+    "access_token",
+];
+
+const REDACTED: &str = "[redacted]";
+
+fn is_redacted(name: &str) -> bool {
+    REDACTED_FIELDS
+        .iter()
+        .any(|field| name.eq_ignore_ascii_case(field))
+}
+
+/// Formats an event as a single JSON line
+pub fn format_event(
+    level: Level,
+    message: &str,
+    request_id: Option<&str>,
+    fields: &[(&str, Value)],
+) -> String {
+    let mut event = Map::new();
+    event.insert("level".to_string(), level.as_str().into());
+    event.insert("msg".to_string(), message.into());
+    if let Some(request_id) = request_id {
+        event.insert("request_id".to_string(), request_id.into());
+    }
+    for (name, value) in fields {
+        let value = if is_redacted(name) {
+            REDACTED.into()
+        } else {
+            value.clone()
+        };
+        event.insert(name.to_string(), value);
+    }
+    Value::Object(event).to_string()
+}
+
+fn emit(level: Level, message: &str, request_id: Option<&str>, fields: &[(&str, Value)]) {
+    if enabled(level) {
+        write(level, message, request_id, fields);
+    }
+}
+
+fn write(level: Level, message: &str, request_id: Option<&str>, fields: &[(&str, Value)]) {
+    let _line = format_event(level, message, request_id, fields);
+
+    #[cfg(all(target_arch = "wasm32", feature = "cloudflare"))]
+    match level {
+        Level::Error => web_sys::console::error_1(&_line.into()),
+        Level::Warn => web_sys::console::warn_1(&_line.into()),
+        _ => web_sys::console::log_1(&_line.into()),
+    }
+    #[cfg(not(all(target_arch = "wasm32", feature = "cloudflare")))]
+    eprintln!("{_line}");
+}
+
+pub fn error(message: &str, fields: &[(&str, Value)]) {
+    emit(Level::Error, message, None, fields);
+}
+
+pub fn warn(message: &str, fields: &[(&str, Value)]) {
+    emit(Level::Warn, message, None, fields);
+}
+
+pub fn info(message: &str, fields: &[(&str, Value)]) {
+    emit(Level::Info, message, None, fields);
+}
+
+pub fn debug(message: &str, fields: &[(&str, Value)]) {
+    emit(Level::Debug, message, None, fields);
+}
+
+pub fn trace(message: &str, fields: &[(&str, Value)]) {
+    emit(Level::Trace, message, None, fields);
+}
+
+/// Logs events tagged with the ID of the request being handled
+///
+/// A traced logger emits every event of its request whatever `LOG_LEVEL` is,
+/// so one request can be followed step by step without raising the level for all.
+#[derive(Debug, Clone)]
+pub struct Logger {
+    request_id: String,
+    tracing: bool,
+}
+
+impl Logger {
+    pub fn new(request_id: impl Into<String>) -> Self {
+        Logger {
+            request_id: request_id.into(),
+            tracing: false,
+        }
+    }
+
+    /// Traces the request, from `DEBUG_TRACING` or the [`TRACE_HEADER`]
+    pub fn with_tracing(mut self, tracing: bool) -> Self {
+        self.tracing = tracing;
+        self
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Returns true if this request's events at `level` are emitted
+    pub fn enabled(&self, level: Level) -> bool {
+        self.tracing || enabled(level)
+    }
+
+    fn log(&self, level: Level, message: &str, fields: &[(&str, Value)]) {
+        if self.enabled(level) {
+            write(level, message, Some(&self.request_id), fields);
+        }
+    }
+
+    pub fn error(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(Level::Error, message, fields);
+    }
+
+    pub fn warn(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(Level::Warn, message, fields);
+    }
+
+    pub fn info(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(Level::Info, message, fields);
+    }
+
+    pub fn debug(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(Level::Debug, message, fields);
+    }
+
+    pub fn trace(&self, message: &str, fields: &[(&str, Value)]) {
+        self.log(Level::Trace, message, fields);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!("WARN".parse::<Level>(), Ok(Level::Warn));
+        assert_eq!("trace".parse::<Level>(), Ok(Level::Trace));
+        assert!("verbose".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Debug);
+    }
+
+    #[test]
+    fn test_format_event() {
+        let line = format_event(
+            Level::Info,
+            "upstream response",
+            Some("req-1"),
+            &[
+                ("model", "deepseek/deepseek-chat".into()),
+                ("latency_ms", 420.into()),
+            ],
+        );
+        let event: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "level": "info",
+                "msg": "upstream response",
+                "request_id": "req-1",
+                "model": "deepseek/deepseek-chat",
+                "latency_ms": 420
+            })
+        );
+    }
+
+    #[test]
+    fn test_traced_logger_emits_every_level() {
+        let log = Logger::new("req-1");
+        assert!(log.enabled(Level::Error));
+        assert!(!log.enabled(Level::Trace));
+        assert!(log.clone().with_tracing(true).enabled(Level::Trace));
+
+        assert!(trace_requested(Some("1")));
+        assert!(trace_requested(Some("true")));
+        assert!(!trace_requested(Some("0")));
+        assert!(!trace_requested(None));
+    }
+
+    #[test]
+    fn test_format_event_redacts_content_and_keys() {
+        let line = format_event(
+            Level::Debug,
+            "request",
+            None,
+            &[
+                (
+                    "messages",
+                    serde_json::json!([{"role": "user", "content": "secret plan"}]),
+                ),
+                ("api_key", "sk-or-v1-abcdef".into()),
+                ("Authorization", "Bearer sk-or-v1-abcdef".into()),
+                ("message_count", 1.into()),
+            ],
+        );
+        assert!(!line.contains("secret plan"));
+        assert!(!line.contains("sk-or"));
+        assert!(line.contains(r#""message_count":1"#));
+        assert!(!line.contains("request_id"));
+    }
+}
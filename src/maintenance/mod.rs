@@ -0,0 +1,116 @@
+//! Maintenance mode, for draining traffic without taking the Worker down
+//!
+//! Storing a notice under `maintenance` in the `MAINTENANCE` KV namespace turns
+//! the model APIs away with a 503 and `retry-after`, while the pages, `/health`,
+//! `/api/info` and `/v1/models` keep answering and requests already in flight finish.
+//! Deleting the key ends it. Either change reaches every location within a minute.
+//!
+//! ```sh
+//! wrangler kv key put --binding MAINTENANCE maintenance '{"message": "Upgrading", "retry_after_secs": 600}'
+//! wrangler kv key delete --binding MAINTENANCE maintenance
+//! ```
+
+use crate::log::Logger;
+use serde::Deserialize;
+use worker::Env;
+
+/// Optional KV namespace holding the maintenance notice
+pub const MAINTENANCE_BINDING: &str = "MAINTENANCE";
+
+/// KV key of the notice; maintenance lasts while it exists
+const MAINTENANCE_KEY: &str = "maintenance";
+
+/// Seconds Cloudflare may serve a cached notice (or its absence) before re-reading KV
+const MAINTENANCE_CACHE_TTL_SECS: u64 = 60;
+
+/// What rejected clients are told, as stored in KV
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Maintenance {
+    /// Added to the error message, e.g. what is being done
+    pub message: Option<String>,
+    /// Sent as `retry-after`
+    pub retry_after_secs: u64,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            message: None,
+            retry_after_secs: 300,
+        }
+    }
+}
+
+impl Maintenance {
+    /// The error message of rejected requests
+    pub fn error_message(&self) -> String {
+        let retry = format!(
+            "CCR is down for maintenance; please retry in {} seconds",
+            self.retry_after_secs
+        );
+        match self.message.as_deref().map(str::trim) {
+            Some(message) if !message.is_empty() => format!("{retry}. {message}"),
+            _ => retry,
+        }
+    }
+}
+
+/// The maintenance notice in effect, when the namespace is bound
+///
+/// A notice that can't be parsed is logged and still counts, since an
+/// operator stored it to stop traffic; a failed KV read leaves the APIs up.
+pub async fn load(env: &Env, log: &Logger) -> Option<Maintenance> {
+    let kv = env.kv(MAINTENANCE_BINDING).ok()?;
+    let notice = match kv
+        .get(MAINTENANCE_KEY)
+        .cache_ttl(MAINTENANCE_CACHE_TTL_SECS)
+        .text()
+        .await
+    {
+        Ok(notice) => notice?,
+        Err(e) => {
+            log.warn(
+                "maintenance notice unreadable",
+                &[("error", e.to_string().into())],
+            );
+            return None;
+        }
+    };
+    Some(parse(&notice).unwrap_or_else(|e| {
+        log.warn("maintenance notice invalid", &[("error", e.into())]);
+        Maintenance::default()
+    }))
+}
+
+/// Reads a stored notice; an empty one uses the defaults
+pub fn parse(notice: &str) -> std::result::Result<Maintenance, String> {
+    if notice.trim().is_empty() {
+        return Ok(Maintenance::default());
+    }
+    serde_json::from_str(notice).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notice() {
+        let notice =
+            parse(r#"{"message": "Moving to a new account.", "retry_after_secs": 600}"#).unwrap();
+        assert_eq!(notice.retry_after_secs, 600);
+        assert_eq!(
+            notice.error_message(),
+            "CCR is down for maintenance; please retry in 600 seconds. Moving to a new account."
+        );
+
+        assert_eq!(parse("").unwrap(), Maintenance::default());
+        assert_eq!(parse("{}").unwrap(), Maintenance::default());
+        assert_eq!(
+            Maintenance::default().error_message(),
+            "CCR is down for maintenance; please retry in 300 seconds"
+        );
+        assert!(parse("on").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use worker::{AnalyticsEngineDataPointBuilder, AnalyticsEngineDataset, Result};
+
+/// Analytics Engine dataset binding that receives one data point per proxied request
+pub const ANALYTICS_BINDING: &str = "USAGE_ANALYTICS";
+
+/// Usage and outcome of one proxied request; never includes message content
+///
+/// Written to Analytics Engine with this column layout, which usage queries rely on:
+///
+/// | column  | value                          |
+/// |---------|--------------------------------|
+/// | index1  | key hash (sampling key)        |
+/// | blob1   | upstream model                 |
+/// | blob2   | key hash                       |
+/// | blob3   | requested model                |
+/// | blob4   | prompt cache key (empty when none was sent) |
+/// | double1 | HTTP status                    |
+/// | double2 | latency in milliseconds        |
+/// | double3 | input tokens                   |
+/// | double4 | output tokens                  |
+/// | double5 | estimated cost in USD (0 when unpriced) |
+/// | double6 | input tokens read from the upstream's prompt cache |
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestMetrics {
+    pub model: String,
+    pub requested_model: String,
+    /// SHA-256 hex of the caller's key, so usage can be attributed without the key
+    pub key_hash: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cost_usd: Option<f64>,
+    /// The `prompt_cache_key` sent upstream, see [`crate::prompt_cache`]
+    pub prompt_cache_key: Option<String>,
+    pub cached_input_tokens: u32,
+}
+
+impl RequestMetrics {
+    fn blobs(&self) -> [&str; 4] {
+        [
+            &self.model,
+            &self.key_hash,
+            &self.requested_model,
+            self.prompt_cache_key.as_deref().unwrap_or(""),
+        ]
+    }
+
+    fn doubles(&self) -> [f64; 6] {
+        [
+            f64::from(self.status),
+            self.latency_ms,
+            f64::from(self.input_tokens),
+            f64::from(self.output_tokens),
+            self.cost_usd.unwrap_or(0.0),
+            f64::from(self.cached_input_tokens),
+        ]
+    }
+
+    /// Writes the data point; Analytics Engine buffers it without blocking the request
+    pub fn write(&self, dataset: &AnalyticsEngineDataset) -> Result<()> {
+        let mut point = AnalyticsEngineDataPointBuilder::new().indexes([self.key_hash.as_str()]);
+        for blob in self.blobs() {
+            point = point.add_blob(blob);
+        }
+        for double in self.doubles() {
+            point = point.add_double(double);
+        }
+        dataset.write_data_point(&point.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_layout() {
+        let metrics = RequestMetrics {
+            model: "deepseek/deepseek-chat".to_string(),
+            requested_model: "claude-sonnet-4".to_string(),
+            key_hash: "ab12".to_string(),
+            status: 200,
+            latency_ms: 812.0,
+            input_tokens: 1200,
+            output_tokens: 300,
+            cost_usd: None,
+            prompt_cache_key: Some("ccr-5f0c".to_string()),
+            cached_input_tokens: 1024,
+        };
+        assert_eq!(
+            metrics.blobs(),
+            [
+                "deepseek/deepseek-chat",
+                "ab12",
+                "claude-sonnet-4",
+                "ccr-5f0c"
+            ]
+        );
+        assert_eq!(
+            metrics.doubles(),
+            [200.0, 812.0, 1200.0, 300.0, 0.0, 1024.0]
+        );
+    }
+}
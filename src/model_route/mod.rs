@@ -0,0 +1,85 @@
+//! How the upstream model of a request was chosen
+//!
+//! A requested model can pass through several rules before it reaches the
+//! upstream: code execution routing, the `auto` tier selection, the short name
+//! aliases and, once the upstream refuses it, the free fallback. Each rule that
+//! changes the model is recorded, and the chain is returned in `x-ccr-route`
+//! (`claude-3-5-haiku -> alias.haiku -> anthropic/claude-3.5-haiku`). Header
+//! values have to be ASCII, so the steps are joined with `->`.
+
+/// Response header describing the model mapping decisions
+pub const ROUTE_HEADER: &str = "x-ccr-route";
+
+/// The model a request asked for and each rule that replaced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    requested: String,
+    /// Rule and the model it chose, in the order applied
+    steps: Vec<(String, String)>,
+}
+
+impl Route {
+    pub fn new(requested: impl Into<String>) -> Self {
+        Route {
+            requested: requested.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Records that `rule` replaced the current model with `model`; a rule that
+    /// leaves the model as it is isn't recorded
+    pub fn push(&mut self, rule: impl Into<String>, model: impl Into<String>) {
+        let model = model.into();
+        if model != self.model() {
+            self.steps.push((rule.into(), model));
+        }
+    }
+
+    /// The model the request is sent to
+    pub fn model(&self) -> &str {
+        self.steps
+            .last()
+            .map_or(self.requested.as_str(), |(_, model)| model.as_str())
+    }
+
+    /// Whether `rule` replaced the model on the way to the upstream
+    pub fn applied(&self, rule: &str) -> bool {
+        self.steps.iter().any(|(applied, _)| applied == rule)
+    }
+
+    /// The `x-ccr-route` value: the requested model, then each rule and its model
+    pub fn header_value(&self) -> String {
+        let mut value = self.requested.clone();
+        for (rule, model) in &self.steps {
+            value.push_str(" -> ");
+            value.push_str(rule);
+            value.push_str(" -> ");
+            value.push_str(model);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_records_model_changes() {
+        let mut route = Route::new("auto");
+        route.push("auto.long_prompt", "sonnet");
+        route.push("alias.sonnet", "anthropic/claude-sonnet-4");
+        route.push("passthrough", "anthropic/claude-sonnet-4");
+        assert_eq!(route.model(), "anthropic/claude-sonnet-4");
+        assert!(route.applied("alias.sonnet"));
+        assert!(!route.applied("passthrough"));
+        assert_eq!(
+            route.header_value(),
+            "auto -> auto.long_prompt -> sonnet -> alias.sonnet -> anthropic/claude-sonnet-4"
+        );
+
+        let direct = Route::new("deepseek/deepseek-chat");
+        assert_eq!(direct.model(), "deepseek/deepseek-chat");
+        assert_eq!(direct.header_value(), "deepseek/deepseek-chat");
+    }
+}
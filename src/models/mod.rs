@@ -9,6 +9,29 @@ pub struct AnthropicRequest {
     pub tools: Option<Vec<serde_json::Value>>,
     pub stream: Option<bool>,
     pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    /// Request metadata; CCR reads its `seed` extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Remote MCP servers for the Anthropic API's MCP connector, kept as sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_servers: Option<Vec<serde_json::Value>>,
+    /// Code execution container to reuse, kept as sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<serde_json::Value>,
+    /// Number of completions, an OpenAI parameter some clients send anyway; only
+    /// 1 is served, and it is never forwarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<serde_json::Value>,
+    /// OpenRouter request fields, e.g. `transforms` or `provider`, forwarded upstream
+    /// as sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub openrouter: Option<serde_json::Map<String, serde_json::Value>>,
     // Capture but ignore cache_control fields that OpenRouter doesn't support
     #[serde(skip_serializing)]
     pub cache_control: Option<serde_json::Value>,
@@ -24,6 +47,8 @@ pub struct AnthropicResponse {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
     pub model: String,
+    #[serde(default)]
+    pub usage: Usage,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +63,24 @@ pub struct OpenAIRequest {
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Makes sampling repeatable on upstreams that support it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// OpenRouter plugins, such as `web` for search grounding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugins: Option<Vec<serde_json::Value>>,
+    /// Groups requests sharing a prompt prefix onto the same upstream cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    /// Fields from the client's `openrouter` extension, merged into the body
+    #[serde(flatten, default)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
 }
 
 /// Streaming event models for Anthropic format
@@ -69,10 +112,25 @@ pub struct MessageInfo {
     pub usage: Usage,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Token usage in the Anthropic shape, where `input_tokens` excludes the tokens
+/// written to or read from the prompt cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Input tokens the upstream wrote to its prompt cache
+    #[serde(default)]
+    pub cache_creation_input_tokens: u32,
+    /// Input tokens the upstream read from its prompt cache
+    #[serde(default)]
+    pub cache_read_input_tokens: u32,
+}
+
+impl Usage {
+    /// All input tokens, cached or not, as OpenAI-format upstreams count them
+    pub fn prompt_tokens(&self) -> u32 {
+        self.input_tokens + self.cache_creation_input_tokens + self.cache_read_input_tokens
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +192,61 @@ pub struct MessageStop {
     pub event_type: String,
 }
 
+/// A non-streaming OpenAI chat completion, with the fields the translation reads
+///
+/// Deserializing the response body straight into this skips building a `Value`
+/// for the whole response and lets the translation move strings rather than
+/// copy them. Fields whose shape varies between providers stay `Value`s.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenAICompletion {
+    pub choices: Option<Vec<OpenAICompletionChoice>>,
+    #[serde(default)]
+    pub usage: serde_json::Value,
+    #[serde(default)]
+    pub system_fingerprint: serde_json::Value,
+    /// Set when the provider reports an error in a `200` response
+    #[serde(default)]
+    pub error: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenAICompletionChoice {
+    #[serde(default)]
+    pub message: OpenAIMessage,
+    #[serde(default)]
+    pub finish_reason: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenAIMessage {
+    #[serde(default)]
+    pub content: serde_json::Value,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<OpenAIMessageToolCall>>,
+    #[serde(default)]
+    pub refusal: serde_json::Value,
+    /// URL citations of OpenRouter's web plugin
+    #[serde(default)]
+    pub annotations: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenAIMessageToolCall {
+    #[serde(default)]
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub function: OpenAIMessageFunction,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenAIMessageFunction {
+    #[serde(default)]
+    pub name: serde_json::Value,
+    /// The JSON text of the arguments, or an object from some providers
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
 /// OpenAI streaming delta structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIStreamDelta {
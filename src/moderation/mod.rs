@@ -0,0 +1,350 @@
+//! Pre-flight content moderation of incoming prompts
+//!
+//! The newest user turn of each request is classified, either by a Workers AI
+//! safety model (Llama Guard) or by an OpenAI-compatible `/moderations` endpoint,
+//! before anything is sent upstream. Requests violating the operator's policy are
+//! answered with a refusal or only logged, depending on `MODERATION_ACTION`.
+
+use crate::http;
+use crate::models::{AnthropicRequest, AnthropicResponse, Usage};
+use crate::semantic_cache::AI_BINDING;
+use crate::transform::text_message_events;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use worker::{Env, Result};
+
+/// Workers AI model used when `MODERATION=workers_ai` and `MODERATION_MODEL` is unset
+pub const DEFAULT_WORKERS_AI_MODEL: &str = "@cf/meta/llama-guard-3-8b";
+
+/// Response header telling the client its request was blocked
+pub const MODERATION_HEADER: &str = "x-ccr-moderation";
+
+/// Longest prompt text sent for classification; longer turns are cut at the end
+const MAX_MODERATED_CHARS: usize = 10_000;
+
+/// The text of the refusal returned for blocked requests
+pub const REFUSAL_TEXT: &str =
+    "I can't help with this request: it was blocked by this deployment's content policy.";
+
+/// Who classifies prompts
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModerationProvider {
+    /// A Llama Guard model run through the `AI` binding
+    WorkersAi { model: String },
+    /// An OpenAI-compatible moderation endpoint
+    Endpoint {
+        url: String,
+        api_key: Option<String>,
+        model: Option<String>,
+    },
+}
+
+impl ModerationProvider {
+    /// Reads `MODERATION` (`workers_ai` or an endpoint URL) with its model and key
+    pub fn parse(
+        raw: &str,
+        model: Option<String>,
+        api_key: Option<String>,
+    ) -> std::result::Result<Self, String> {
+        let raw = raw.trim();
+        if raw.eq_ignore_ascii_case("workers_ai") {
+            return Ok(ModerationProvider::WorkersAi {
+                model: model.unwrap_or_else(|| DEFAULT_WORKERS_AI_MODEL.to_string()),
+            });
+        }
+        if !raw.starts_with("https://") && !raw.starts_with("http://") {
+            return Err("expected workers_ai or an http(s) moderation endpoint".to_string());
+        }
+        Ok(ModerationProvider::Endpoint {
+            url: raw.to_string(),
+            api_key,
+            model,
+        })
+    }
+}
+
+/// What happens to a request that violates the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// Answer with a refusal instead of forwarding
+    Block,
+    /// Forward the request and log a warning
+    Flag,
+}
+
+impl FromStr for ModerationAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "block" => Ok(ModerationAction::Block),
+            "flag" => Ok(ModerationAction::Flag),
+            _ => Err("expected block or flag".to_string()),
+        }
+    }
+}
+
+/// The moderation policy, enabled by `MODERATION`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationConfig {
+    pub provider: ModerationProvider,
+    pub action: ModerationAction,
+    /// Lowercase categories acted on (`MODERATION_CATEGORIES`); empty means any
+    pub categories: Vec<String>,
+}
+
+impl ModerationConfig {
+    /// The categories of a verdict the policy acts on; empty if the prompt passes
+    pub fn violations(&self, verdict: &Verdict) -> Vec<String> {
+        if !verdict.flagged {
+            return Vec::new();
+        }
+        if self.categories.is_empty() {
+            return if verdict.categories.is_empty() {
+                vec!["flagged".to_string()]
+            } else {
+                verdict.categories.clone()
+            };
+        }
+        verdict
+            .categories
+            .iter()
+            .filter(|category| self.categories.contains(&category.to_lowercase()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A classifier's judgement of a prompt
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Verdict {
+    pub flagged: bool,
+    pub categories: Vec<String>,
+}
+
+/// Reads a Llama Guard result, returned either as `{safe, categories}` or as
+/// the raw `safe` / `unsafe\nS1,S10` completion
+fn verdict_from_llama_guard(result: &Value) -> std::result::Result<Verdict, String> {
+    let response = &result["response"];
+    if let Some(safe) = response["safe"].as_bool() {
+        let categories = response["categories"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|category| category.as_str().map(str::to_string))
+            .collect();
+        return Ok(Verdict {
+            flagged: !safe,
+            categories,
+        });
+    }
+    let text = response
+        .as_str()
+        .ok_or_else(|| format!("unexpected Llama Guard result: {result}"))?;
+    let mut lines = text.trim().lines();
+    match lines.next().map(str::trim) {
+        Some("safe") => Ok(Verdict::default()),
+        Some("unsafe") => Ok(Verdict {
+            flagged: true,
+            categories: lines
+                .flat_map(|line| line.split(','))
+                .map(|category| category.trim().to_string())
+                .filter(|category| !category.is_empty())
+                .collect(),
+        }),
+        _ => Err(format!("unexpected Llama Guard result: {text}")),
+    }
+}
+
+/// Reads an OpenAI moderation result, flagged if any of its inputs was
+fn verdict_from_moderations(result: &Value) -> std::result::Result<Verdict, String> {
+    let results = result["results"]
+        .as_array()
+        .ok_or_else(|| "moderation response has no results".to_string())?;
+    let mut verdict = Verdict::default();
+    for item in results {
+        verdict.flagged |= item["flagged"].as_bool().unwrap_or(false);
+        let flagged_categories = item["categories"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+            .map(|(category, _)| category.clone());
+        for category in flagged_categories {
+            if !verdict.categories.contains(&category) {
+                verdict.categories.push(category);
+            }
+        }
+    }
+    Ok(verdict)
+}
+
+/// The text of the request's newest user turn, if it has any
+///
+/// Earlier turns were checked when they were new, and tool results are output of
+/// the client's own tools rather than prompts, so neither is classified again.
+pub fn moderated_text(request: &AnthropicRequest) -> Option<String> {
+    let message = request.messages.last().filter(|m| m["role"] == "user")?;
+    let text = match &message["content"] {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => return None,
+    };
+    let text: String = text.chars().take(MAX_MODERATED_CHARS).collect();
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Classifies `text` with the configured provider
+pub async fn classify(provider: &ModerationProvider, env: &Env, text: &str) -> Result<Verdict> {
+    let verdict = match provider {
+        ModerationProvider::WorkersAi { model } => {
+            let result: Value = env
+                .ai(AI_BINDING)?
+                .run(
+                    model,
+                    json!({ "messages": [{"role": "user", "content": text}] }),
+                )
+                .await?;
+            verdict_from_llama_guard(&result)
+        }
+        ModerationProvider::Endpoint {
+            url,
+            api_key,
+            model,
+        } => {
+            let mut body = json!({ "input": text });
+            if let Some(model) = model {
+                body["model"] = json!(model);
+            }
+            let mut request = http::Client::new().post(url).json(&body);
+            if let Some(api_key) = api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let response = request.send().await?;
+            if !response.is_success() {
+                return Err(worker::Error::RustError(format!(
+                    "Moderation endpoint returned HTTP {}",
+                    response.status()
+                )));
+            }
+            verdict_from_moderations(&response.json().await?)
+        }
+    };
+    verdict.map_err(worker::Error::RustError)
+}
+
+/// The refusal returned in place of a blocked request's response
+pub fn refusal_message(id: &str, model: &str) -> AnthropicResponse {
+    AnthropicResponse {
+        id: id.to_string(),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        content: vec![json!({"type": "text", "text": REFUSAL_TEXT})],
+        stop_reason: Some("refusal".to_string()),
+        stop_sequence: None,
+        model: model.to_string(),
+        usage: Usage::default(),
+    }
+}
+
+/// The refusal as the stream events of a streaming response
+pub fn refusal_events(id: &str, model: &str) -> String {
+    text_message_events(id, model, REFUSAL_TEXT, "refusal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(categories: &[&str]) -> ModerationConfig {
+        ModerationConfig {
+            provider: ModerationProvider::parse("workers_ai", None, None).unwrap(),
+            action: ModerationAction::Block,
+            categories: categories.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_llama_guard_verdicts() {
+        let structured = json!({"response": {"safe": false, "categories": ["S1", "S10"]}});
+        assert_eq!(
+            verdict_from_llama_guard(&structured).unwrap(),
+            Verdict {
+                flagged: true,
+                categories: vec!["S1".to_string(), "S10".to_string()],
+            }
+        );
+        let raw = json!({"response": "\n\nunsafe\nS2,S9"});
+        assert_eq!(
+            verdict_from_llama_guard(&raw).unwrap().categories,
+            ["S2", "S9"]
+        );
+        assert!(
+            !verdict_from_llama_guard(&json!({"response": "safe"}))
+                .unwrap()
+                .flagged
+        );
+        assert!(verdict_from_llama_guard(&json!({"response": "maybe"})).is_err());
+    }
+
+    #[test]
+    fn test_moderations_verdicts() {
+        let result = json!({"results": [{
+            "flagged": true,
+            "categories": {"hate": false, "violence": true, "harassment": true}
+        }]});
+        let verdict = verdict_from_moderations(&result).unwrap();
+        assert!(verdict.flagged);
+        assert_eq!(verdict.categories.len(), 2);
+        assert!(verdict_from_moderations(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_policy_categories() {
+        let verdict = Verdict {
+            flagged: true,
+            categories: vec!["S1".to_string(), "S6".to_string()],
+        };
+        assert_eq!(config(&[]).violations(&verdict), ["S1", "S6"]);
+        assert_eq!(config(&["s1", "s10"]).violations(&verdict), ["S1"]);
+        assert!(config(&["s10"]).violations(&verdict).is_empty());
+        assert!(config(&[]).violations(&Verdict::default()).is_empty());
+    }
+
+    #[test]
+    fn test_moderated_text_is_the_newest_user_turn() {
+        let request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "messages": [
+                {"role": "user", "content": "earlier prompt"},
+                {"role": "assistant", "content": "ok"},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "file contents"},
+                    {"type": "text", "text": "now do this"}
+                ]}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(moderated_text(&request).as_deref(), Some("now do this"));
+
+        let mut tool_turn = request.clone();
+        tool_turn.messages[2]["content"]
+            .as_array_mut()
+            .unwrap()
+            .pop();
+        assert_eq!(moderated_text(&tool_turn), None);
+    }
+
+    #[test]
+    fn test_refusal_events_are_a_complete_stream() {
+        let events = refusal_events("msg_1", "claude-sonnet-4");
+        assert!(events.starts_with("event: message_start\n"));
+        assert!(events.contains(r#""stop_reason":"refusal""#));
+        assert!(events.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+    }
+}
@@ -0,0 +1,315 @@
+//! Masking of personal data and secrets before requests leave for the upstream
+//!
+//! Matches in message content and the system prompt are replaced by numbered
+//! placeholders such as `[EMAIL_1]`. The same value always gets the same
+//! placeholder within a request, so the model can still tell values apart, and
+//! placeholders the model repeats are swapped back for the original values in
+//! the response.
+
+use crate::models::AnthropicRequest;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Object keys whose values are structure rather than content and are never masked
+const STRUCTURAL_KEYS: &[&str] = &[
+    "type",
+    "id",
+    "tool_use_id",
+    "name",
+    "role",
+    "source",
+    "cache_control",
+];
+
+/// A built-in kind of sensitive value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detector {
+    Email,
+    /// Provider keys and tokens: OpenAI/OpenRouter/Anthropic `sk-`, AWS access
+    /// keys, GitHub and Slack tokens
+    ApiKey,
+    /// Card numbers that pass the Luhn check
+    CreditCard,
+}
+
+impl Detector {
+    pub const ALL: [Detector; 3] = [Detector::Email, Detector::ApiKey, Detector::CreditCard];
+
+    fn label(self) -> &'static str {
+        match self {
+            Detector::Email => "EMAIL",
+            Detector::ApiKey => "API_KEY",
+            Detector::CreditCard => "CARD",
+        }
+    }
+
+    fn pattern(self) -> &'static str {
+        match self {
+            Detector::Email => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+            Detector::ApiKey => {
+                r"\b(?:sk-[A-Za-z0-9_-]{16,}|AKIA[0-9A-Z]{16}|gh[pousr]_[A-Za-z0-9]{36,}|xox[abprs]-[A-Za-z0-9-]{10,})\b"
+            }
+            Detector::CreditCard => r"\b\d(?:[ -]?\d){12,18}\b",
+        }
+    }
+}
+
+impl FromStr for Detector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "email" => Ok(Detector::Email),
+            "api_key" => Ok(Detector::ApiKey),
+            "credit_card" => Ok(Detector::CreditCard),
+            other => Err(format!(
+                "unknown detector '{other}'; expected email, api_key or credit_card"
+            )),
+        }
+    }
+}
+
+/// Whether a run of digits (separators allowed) passes the Luhn checksum
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// The compiled detectors and custom patterns of `PII_REDACTION` and `PII_PATTERNS`
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    rules: Vec<(&'static str, Regex, Option<Detector>)>,
+    /// Whether placeholders in responses are swapped back (`PII_RESTORE`)
+    pub restore: bool,
+}
+
+impl Redactor {
+    /// Compiles the detectors and custom patterns; custom matches are labelled `PII`
+    pub fn new(detectors: &[Detector], patterns: &[String], restore: bool) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for detector in detectors {
+            let regex = Regex::new(detector.pattern()).map_err(|e| e.to_string())?;
+            rules.push((detector.label(), regex, Some(*detector)));
+        }
+        for pattern in patterns {
+            let regex = Regex::new(pattern).map_err(|e| format!("'{pattern}': {e}"))?;
+            rules.push(("PII", regex, None));
+        }
+        Ok(Redactor { rules, restore })
+    }
+
+    /// Masks the request's messages and system prompt, returning what was masked
+    pub fn redact_request(&self, request: &mut AnthropicRequest) -> Placeholders {
+        let mut placeholders = Placeholders::default();
+        for message in &mut request.messages {
+            self.redact_value(message, &mut placeholders);
+        }
+        if let Some(system) = &mut request.system {
+            self.redact_value(system, &mut placeholders);
+        }
+        placeholders
+    }
+
+    fn redact_value(&self, value: &mut Value, placeholders: &mut Placeholders) {
+        match value {
+            Value::String(text) => {
+                if let Some(masked) = self.redact_text(text, placeholders) {
+                    *text = masked;
+                }
+            }
+            Value::Array(items) => items
+                .iter_mut()
+                .for_each(|item| self.redact_value(item, placeholders)),
+            Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    if !STRUCTURAL_KEYS.contains(&key.as_str()) {
+                        self.redact_value(item, placeholders);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Masks `text`, or returns `None` if nothing matched
+    fn redact_text(&self, text: &str, placeholders: &mut Placeholders) -> Option<String> {
+        let mut masked = None;
+        for (label, regex, detector) in &self.rules {
+            let current: &str = masked.as_deref().unwrap_or(text);
+            if !regex.is_match(current) {
+                continue;
+            }
+            let replaced = regex.replace_all(current, |captures: &regex::Captures| {
+                let found = &captures[0];
+                if *detector == Some(Detector::CreditCard) && !luhn_valid(found) {
+                    return found.to_string();
+                }
+                placeholders.placeholder(label, found)
+            });
+            if replaced != current {
+                masked = Some(replaced.into_owned());
+            }
+        }
+        masked
+    }
+}
+
+/// Placeholders handed out for one request and the values they stand for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Placeholders {
+    originals: HashMap<String, String>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl Placeholders {
+    fn placeholder(&mut self, label: &'static str, original: &str) -> String {
+        if let Some((placeholder, _)) = self
+            .originals
+            .iter()
+            .find(|(_, value)| value.as_str() == original)
+        {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(label).or_default();
+        *count += 1;
+        let placeholder = format!("[{label}_{count}]");
+        self.originals
+            .insert(placeholder.clone(), original.to_string());
+        placeholder
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+
+    /// Number of values masked
+    pub fn len(&self) -> usize {
+        self.originals.len()
+    }
+
+    /// Puts the original values back into every string of a response
+    pub fn restore_value(&self, value: &mut Value) {
+        match value {
+            Value::String(text) if text.contains('[') => *text = self.restore_text(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.restore_value(item)),
+            Value::Object(map) => map.values_mut().for_each(|item| self.restore_value(item)),
+            _ => {}
+        }
+    }
+
+    fn restore_text(&self, text: &str) -> String {
+        self.originals
+            .iter()
+            .fold(text.to_string(), |text, (placeholder, original)| {
+                text.replace(placeholder, original)
+            })
+    }
+
+    /// Puts the original values back into serialized stream events
+    ///
+    /// Only placeholders that arrived whole in a single delta can be restored; one
+    /// split across deltas reaches the client as is.
+    pub fn restore_events(&self, events: &str) -> String {
+        self.originals
+            .iter()
+            .fold(events.to_string(), |events, (placeholder, original)| {
+                // Events hold JSON strings, so the value goes in escaped
+                let escaped = serde_json::to_string(original).unwrap_or_default();
+                events.replace(placeholder, &escaped[1..escaped.len() - 1])
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&Detector::ALL, &[r"EMP-\d{6}".to_string()], true).unwrap()
+    }
+
+    fn request(content: Value) -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "system": "Support agent for alice@example.com",
+            "messages": [{"role": "user", "content": content}]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_detectors_and_custom_patterns() {
+        let mut request = request(json!(
+            "Mail alice@example.com and bob@corp.io about EMP-123456, key sk-or-v1-0123456789abcdef, card 4111 1111 1111 1111"
+        ));
+        let placeholders = redactor().redact_request(&mut request);
+
+        assert_eq!(
+            request.messages[0]["content"],
+            "Mail [EMAIL_1] and [EMAIL_2] about [PII_1], key [API_KEY_1], card [CARD_1]"
+        );
+        // The same address gets the same placeholder everywhere in the request
+        assert_eq!(request.system, Some(json!("Support agent for [EMAIL_1]")));
+        assert_eq!(placeholders.len(), 5);
+    }
+
+    #[test]
+    fn test_non_luhn_numbers_and_structure_are_kept() {
+        let mut request = request(json!([
+            {"type": "text", "text": "Order 1234 5678 9012 3456 shipped"},
+            {"type": "tool_result", "tool_use_id": "toolu_alice@example.com", "content": "to alice@example.com"},
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "sk-AAAAAAAAAAAAAAAAAAAA"}}
+        ]));
+        redactor().redact_request(&mut request);
+
+        let blocks = &request.messages[0]["content"];
+        assert_eq!(blocks[0]["text"], "Order 1234 5678 9012 3456 shipped");
+        assert_eq!(blocks[1]["tool_use_id"], "toolu_alice@example.com");
+        assert_eq!(blocks[1]["content"], "to [EMAIL_1]");
+        assert_eq!(blocks[2]["source"]["data"], "sk-AAAAAAAAAAAAAAAAAAAA");
+    }
+
+    #[test]
+    fn test_restore_response_and_events() {
+        let mut request = request(json!("Reply to alice@example.com"));
+        let placeholders = redactor().redact_request(&mut request);
+
+        let mut response = json!({"content": [
+            {"type": "text", "text": "Drafted a reply to [EMAIL_1]."},
+            {"type": "tool_use", "name": "send", "input": {"to": "[EMAIL_1]"}}
+        ]});
+        placeholders.restore_value(&mut response);
+        assert_eq!(
+            response["content"][0]["text"],
+            "Drafted a reply to alice@example.com."
+        );
+        assert_eq!(response["content"][1]["input"]["to"], "alice@example.com");
+
+        let events = r#"data: {"delta":{"type":"text_delta","text":"Sent to [EMAIL_1]"}}"#;
+        assert_eq!(
+            placeholders.restore_events(events),
+            r#"data: {"delta":{"type":"text_delta","text":"Sent to alice@example.com"}}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        assert!("phone".parse::<Detector>().is_err());
+        assert!(Redactor::new(&[], &["(unclosed".to_string()], true).is_err());
+        assert!(luhn_valid("4111-1111-1111-1111"));
+        assert!(!luhn_valid("4111-1111-1111-1112"));
+    }
+}
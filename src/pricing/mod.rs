@@ -0,0 +1,201 @@
+//! Per-model token prices and the estimated cost of each request
+//!
+//! Prices come from the OpenRouter catalog. With a `PRICING` KV namespace bound,
+//! the first isolate to need them stores the price table there for a day, so
+//! other isolates read one small key instead of the whole catalog; without it
+//! every isolate fetches the catalog. Either way each isolate keeps the table in
+//! memory once loaded. Estimates price total prompt tokens at the input rate and
+//! completion tokens at the output rate, and feed spend limits, usage metrics and
+//! the `x-ccr-cost-usd` response header.
+
+use crate::catalog;
+use crate::http;
+use crate::models::Usage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// Optional KV namespace sharing the price table between isolates
+pub const PRICING_BINDING: &str = "PRICING";
+
+/// KV key of the price table
+pub const PRICING_KEY: &str = "pricing";
+
+/// Seconds the price table is kept in KV before the catalog is fetched again
+pub const PRICING_TTL_SECS: u64 = 86_400;
+
+/// Response header carrying the estimated cost of the request in USD
+pub const COST_HEADER: &str = "x-ccr-cost-usd";
+
+/// Price table loaded in this isolate
+static PRICES: OnceLock<PriceTable> = OnceLock::new();
+
+/// Upstream price of a model in USD per token
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Prices by upstream model ID
+pub type PriceTable = HashMap<String, ModelPricing>;
+
+/// Extracts per-model pricing from an OpenRouter `/models` response
+///
+/// OpenRouter reports prices as decimal strings; models with missing or
+/// unparseable prices are skipped.
+pub fn parse_catalog(catalog: &Value) -> PriceTable {
+    let price = |pricing: &Value, field: &str| -> Option<f64> {
+        match &pricing[field] {
+            Value::String(raw) => raw.parse().ok(),
+            value => value.as_f64(),
+        }
+    };
+
+    catalog["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            let id = model["id"].as_str()?;
+            let pricing = &model["pricing"];
+            Some((
+                id.to_string(),
+                ModelPricing {
+                    prompt: price(pricing, "prompt")?,
+                    completion: price(pricing, "completion")?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Returns the price table, loading it from KV or `{base_url}/models` on first use
+pub async fn prices(
+    client: &http::Client,
+    base_url: &str,
+    kv: Option<&KvStore>,
+) -> Result<&'static PriceTable> {
+    if let Some(prices) = PRICES.get() {
+        return Ok(prices);
+    }
+
+    let stored = match kv {
+        Some(kv) => kv
+            .get(PRICING_KEY)
+            .json::<PriceTable>()
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let prices = match stored {
+        Some(prices) => prices,
+        None => sync(client, base_url, kv).await?,
+    };
+    Ok(PRICES.get_or_init(|| prices))
+}
+
+/// Fetches the price table from the catalog, storing it in KV when bound
+pub async fn sync(
+    client: &http::Client,
+    base_url: &str,
+    kv: Option<&KvStore>,
+) -> Result<PriceTable> {
+    let catalog = catalog::fetch(client, base_url).await?;
+    let prices = parse_catalog(&catalog);
+    if let Some(kv) = kv {
+        store(kv, &prices).await?;
+    }
+    Ok(prices)
+}
+
+/// Stores the price table in KV for the other isolates
+pub async fn store(kv: &KvStore, prices: &PriceTable) -> Result<()> {
+    kv.put(PRICING_KEY, serde_json::to_string(prices)?)?
+        .expiration_ttl(PRICING_TTL_SECS)
+        .execute()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to store prices: {e}")))
+}
+
+/// Cost of a request in micro-USD, rounded up so spend is never under-counted
+pub fn cost_micros(pricing: &ModelPricing, usage: &Usage) -> u64 {
+    (cost_usd(pricing, usage) * 1_000_000.0).ceil() as u64
+}
+
+/// Cost of a request's usage in USD
+pub fn cost_usd(pricing: &ModelPricing, usage: &Usage) -> f64 {
+    pricing.prompt * f64::from(usage.prompt_tokens())
+        + pricing.completion * f64::from(usage.output_tokens)
+}
+
+/// Estimated cost of `usage` on `model`, when the model has a listed price
+pub fn estimate(prices: &PriceTable, model: &str, usage: &Usage) -> Option<f64> {
+    prices.get(model).map(|pricing| cost_usd(pricing, usage))
+}
+
+/// Formats a cost for the `x-ccr-cost-usd` header, to a millionth of a dollar
+pub fn format_cost(usd: f64) -> String {
+    format!("{usd:.6}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = json!({
+            "data": [
+                {"id": "anthropic/claude-sonnet-4", "pricing": {"prompt": "0.000003", "completion": "0.000015"}},
+                {"id": "moonshotai/kimi-k2:free", "pricing": {"prompt": "0", "completion": "0"}},
+                {"id": "broken/model", "pricing": {"prompt": "n/a"}}
+            ]
+        });
+
+        let prices = parse_catalog(&catalog);
+        assert_eq!(prices.len(), 2);
+        assert_eq!(
+            prices["anthropic/claude-sonnet-4"],
+            ModelPricing {
+                prompt: 0.000003,
+                completion: 0.000015
+            }
+        );
+        assert_eq!(prices["moonshotai/kimi-k2:free"], ModelPricing::default());
+        assert!(parse_catalog(&json!({})).is_empty());
+
+        // The table round-trips through KV
+        let stored: PriceTable =
+            serde_json::from_str(&serde_json::to_string(&prices).unwrap()).unwrap();
+        assert_eq!(stored, prices);
+    }
+
+    #[test]
+    fn test_cost_estimates() {
+        let pricing = ModelPricing {
+            prompt: 0.000003,
+            completion: 0.000015,
+        };
+        let usage = Usage {
+            input_tokens: 1000,
+            output_tokens: 200,
+            ..Usage::default()
+        };
+        // $0.003 + $0.003
+        assert_eq!(cost_micros(&pricing, &usage), 6000);
+        assert_eq!(cost_micros(&ModelPricing::default(), &usage), 0);
+
+        let prices = PriceTable::from([("anthropic/claude-sonnet-4".to_string(), pricing)]);
+        assert_eq!(
+            estimate(&prices, "anthropic/claude-sonnet-4", &usage).map(format_cost),
+            Some("0.006000".to_string())
+        );
+        assert_eq!(estimate(&prices, "unknown/model", &usage), None);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::Config;
+use serde::Deserialize;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// KV namespace binding holding tenant profiles
+pub const PROFILES_BINDING: &str = "PROFILES";
+
+/// Header a client holding the access token can use to pick a profile when its
+/// key isn't bound to one
+pub const PROFILE_HEADER: &str = "x-ccr-profile";
+
+/// Seconds Cloudflare may serve a cached profile before re-reading KV (the minimum allowed)
+const PROFILE_CACHE_TTL_SECS: u64 = 60;
+
+/// Per-tenant policy layered over the deployment's configuration
+///
+/// Stored as JSON under `profile:<name>`; every field is optional and falls back to
+/// the deployment-wide setting:
+///
+/// ```json
+/// {"openrouter_base_url": "https://eu.openrouter.ai/api/v1",
+///  "models": {"sonnet": "deepseek/deepseek-chat"},
+///  "default_max_tokens": 2048, "rpm": 30, "monthly_budget_usd": 200.0}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub openrouter_base_url: Option<String>,
+    pub models: ModelOverrides,
+    pub default_max_tokens: Option<u32>,
+    /// Per-key requests per minute for keys using this profile
+    pub rpm: Option<u32>,
+    /// Per-key estimated prompt tokens per minute for keys using this profile
+    pub tpm: Option<u32>,
+    /// Monthly budget for keys that don't set their own
+    pub monthly_budget_usd: Option<f64>,
+}
+
+/// Replacement targets for the Claude short model names
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ModelOverrides {
+    pub haiku: Option<String>,
+    pub sonnet: Option<String>,
+    pub opus: Option<String>,
+}
+
+impl Profile {
+    /// Returns a copy of `base` with this profile's overrides applied
+    pub fn apply(&self, base: &Config) -> Result<Config> {
+        let mut config = base.clone();
+
+        if let Some(url) = &self.openrouter_base_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(worker::Error::RustError(format!(
+                    "Invalid profile: openrouter_base_url '{url}' must be an http(s) URL"
+                )));
+            }
+            config.openrouter_base_url = url.trim_end_matches('/').to_string();
+            // A tenant pinned to its own upstream shouldn't be rerouted by region
+            config.features.regional_upstreams = false;
+        }
+
+        let targets = &mut config.model_targets;
+        for (target, model) in [
+            (&mut targets.haiku, &self.models.haiku),
+            (&mut targets.sonnet, &self.models.sonnet),
+            (&mut targets.opus, &self.models.opus),
+        ] {
+            if let Some(model) = model {
+                *target = model.clone();
+            }
+        }
+
+        if let Some(max_tokens) = self.default_max_tokens.filter(|&tokens| tokens > 0) {
+            config.default_max_tokens = max_tokens;
+        }
+        if let Some(rpm) = self.rpm {
+            config.rate_limits.key_rpm = rpm;
+        }
+        if let Some(tpm) = self.tpm {
+            config.rate_limits.key_tpm = tpm;
+        }
+
+        Ok(config)
+    }
+}
+
+/// KV key under which a profile is stored
+pub fn storage_key(name: &str) -> String {
+    format!("profile:{name}")
+}
+
+/// Loads a profile by name, if one exists
+pub async fn lookup(kv: &KvStore, name: &str) -> Result<Option<Profile>> {
+    kv.get(&storage_key(name))
+        .cache_ttl(PROFILE_CACHE_TTL_SECS)
+        .json::<Profile>()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to read profile '{name}': {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_profile() {
+        let profile: Profile =
+            serde_json::from_str(r#"{"models": {"sonnet": "deepseek/deepseek-chat"}, "rpm": 30}"#)
+                .unwrap();
+        assert_eq!(
+            profile.models.sonnet.as_deref(),
+            Some("deepseek/deepseek-chat")
+        );
+        assert_eq!(profile.models.haiku, None);
+        assert_eq!(profile.rpm, Some(30));
+        assert_eq!(profile.openrouter_base_url, None);
+    }
+
+    #[test]
+    fn test_empty_profile_keeps_config() {
+        let base = Config::default();
+        let config = Profile::default().apply(&base).unwrap();
+        assert_eq!(config.openrouter_base_url, base.openrouter_base_url);
+        assert_eq!(config.model_targets.sonnet, base.model_targets.sonnet);
+        assert_eq!(config.rate_limits, base.rate_limits);
+        assert!(config.features.regional_upstreams);
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let profile = Profile {
+            openrouter_base_url: Some("https://eu.example.com/api/v1/".to_string()),
+            models: ModelOverrides {
+                sonnet: Some("deepseek/deepseek-chat".to_string()),
+                ..ModelOverrides::default()
+            },
+            default_max_tokens: Some(2048),
+            rpm: Some(30),
+            tpm: None,
+            monthly_budget_usd: Some(200.0),
+        };
+
+        let config = profile.apply(&Config::default()).unwrap();
+        assert_eq!(config.openrouter_base_url, "https://eu.example.com/api/v1");
+        assert!(!config.features.regional_upstreams);
+        assert_eq!(config.model_targets.sonnet, "deepseek/deepseek-chat");
+        assert_eq!(config.model_targets.haiku, "anthropic/claude-3.5-haiku");
+        assert_eq!(config.default_max_tokens, 2048);
+        assert_eq!(config.rate_limits.key_rpm, 30);
+        assert_eq!(config.rate_limits.key_tpm, 0);
+    }
+
+    #[test]
+    fn test_apply_rejects_bad_upstream() {
+        let profile = Profile {
+            openrouter_base_url: Some("eu.example.com".to_string()),
+            ..Profile::default()
+        };
+        assert!(profile.apply(&Config::default()).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Prompt caching hints derived from the conversation's stable prefix
+//!
+//! Claude Code resends the same system prompt and tool definitions with every
+//! turn of a session, which is the prefix upstream prompt caches reuse. With
+//! `PROMPT_CACHE_HINTS` enabled, CCR hashes that prefix and sends the hash as
+//! `prompt_cache_key`, so providers that route by key keep a session's turns on
+//! the same cache, and marks the system prompt with a `cache_control` breakpoint
+//! for providers that only cache what is marked. The key is also recorded with
+//! the usage metrics, next to the cached token count, to measure hit rates.
+
+use crate::models::{AnthropicRequest, OpenAIRequest};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Upstream model prefixes whose providers cache only marked content
+const BREAKPOINT_PREFIXES: &[&str] = &["anthropic/", "google/gemini"];
+
+/// Hex digits of the prefix hash kept in the key
+const KEY_HEX_LEN: usize = 32;
+
+/// Hashes of the parts of a request that stay the same across a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixHashes {
+    /// SHA-256 hex of the system prompt, when there is one
+    pub system: Option<String>,
+    /// SHA-256 hex of the tool definitions, when there are any
+    pub tools: Option<String>,
+}
+
+impl PrefixHashes {
+    /// Hashes `request`'s system prompt and tools, ignoring `cache_control` markers
+    /// so clients moving their breakpoints doesn't change the key
+    pub fn of(request: &AnthropicRequest) -> Self {
+        PrefixHashes {
+            system: request.system.as_ref().map(stable_hash),
+            tools: request
+                .tools
+                .as_ref()
+                .filter(|tools| !tools.is_empty())
+                .map(|tools| stable_hash(&Value::Array(tools.clone()))),
+        }
+    }
+
+    /// The `prompt_cache_key` for the prefix; `None` when the request has neither
+    /// a system prompt nor tools, as there is nothing worth caching
+    pub fn cache_key(&self) -> Option<String> {
+        if self.system.is_none() && self.tools.is_none() {
+            return None;
+        }
+        let combined = format!(
+            "{}:{}",
+            self.system.as_deref().unwrap_or(""),
+            self.tools.as_deref().unwrap_or("")
+        );
+        Some(format!("ccr-{}", &hex_sha256(&combined)[..KEY_HEX_LEN]))
+    }
+}
+
+fn hex_sha256(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Hash of `value` with every `cache_control` removed; object keys serialize in
+/// sorted order, so the hash doesn't depend on how the client ordered them
+fn stable_hash(value: &Value) -> String {
+    let mut value = value.clone();
+    strip_cache_control(&mut value);
+    hex_sha256(&value.to_string())
+}
+
+fn strip_cache_control(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("cache_control");
+            map.values_mut().for_each(strip_cache_control);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_cache_control),
+        _ => {}
+    }
+}
+
+/// Whether the upstream model needs an explicit breakpoint to cache the prefix
+fn needs_breakpoint(model: &str) -> bool {
+    BREAKPOINT_PREFIXES
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+}
+
+/// Marks the end of the system message as a cache breakpoint
+fn mark_system(request: &mut OpenAIRequest) {
+    let Some(message) = request
+        .messages
+        .first_mut()
+        .filter(|message| message["role"] == "system")
+    else {
+        return;
+    };
+    let content = &mut message["content"];
+    if let Some(text) = content.as_str() {
+        *content = json!([{"type": "text", "text": text}]);
+    }
+    if let Some(last) = content
+        .as_array_mut()
+        .and_then(|blocks| blocks.last_mut())
+        .and_then(Value::as_object_mut)
+    {
+        last.insert("cache_control".to_string(), json!({"type": "ephemeral"}));
+    }
+}
+
+/// Adds the cache hints for `anthropic`'s prefix to the translated request,
+/// returning the key sent
+pub fn apply(anthropic: &AnthropicRequest, request: &mut OpenAIRequest) -> Option<String> {
+    let key = PrefixHashes::of(anthropic).cache_key()?;
+    if needs_breakpoint(&request.model) {
+        mark_system(request);
+    }
+    request.prompt_cache_key = Some(key.clone());
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(system: Value) -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "system": system,
+            "tools": [{"name": "Read", "description": "Read a file", "input_schema": {"type": "object"}}],
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap()
+    }
+
+    fn translated(model: &str, system: Value) -> OpenAIRequest {
+        serde_json::from_value(json!({
+            "model": model,
+            "messages": [
+                {"role": "system", "content": system},
+                {"role": "user", "content": "Hello"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key_is_stable_across_turns_and_breakpoints() {
+        let first = request(json!([{"type": "text", "text": "You are Claude Code."}]));
+        let mut later = request(json!([{
+            "type": "text",
+            "text": "You are Claude Code.",
+            "cache_control": {"type": "ephemeral"}
+        }]));
+        later
+            .messages
+            .push(json!({"role": "assistant", "content": "Hi"}));
+        let key = PrefixHashes::of(&first).cache_key().unwrap();
+        assert!(key.starts_with("ccr-"));
+        assert_eq!(key.len(), 4 + KEY_HEX_LEN);
+        assert_eq!(PrefixHashes::of(&later).cache_key(), Some(key.clone()));
+
+        let other = request(json!("You are a different agent."));
+        assert_ne!(PrefixHashes::of(&other).cache_key(), Some(key));
+
+        let mut bare = first.clone();
+        bare.system = None;
+        bare.tools = Some(Vec::new());
+        assert_eq!(PrefixHashes::of(&bare).cache_key(), None);
+    }
+
+    #[test]
+    fn test_apply_marks_system_for_breakpoint_providers() {
+        let anthropic = request(json!("You are Claude Code."));
+        let mut marked = translated("anthropic/claude-sonnet-4", json!("You are Claude Code."));
+        let key = apply(&anthropic, &mut marked).unwrap();
+        assert_eq!(marked.prompt_cache_key.as_deref(), Some(key.as_str()));
+        assert_eq!(
+            marked.messages[0]["content"],
+            json!([{"type": "text", "text": "You are Claude Code.", "cache_control": {"type": "ephemeral"}}])
+        );
+
+        let mut unmarked = translated("deepseek/deepseek-chat", json!("You are Claude Code."));
+        apply(&anthropic, &mut unmarked);
+        assert_eq!(unmarked.prompt_cache_key, Some(key));
+        assert_eq!(unmarked.messages[0]["content"], "You are Claude Code.");
+    }
+}
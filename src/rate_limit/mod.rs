@@ -0,0 +1,318 @@
+use crate::utils::format_timestamp;
+use serde::{Deserialize, Serialize};
+use worker::{
+    durable_object, Date, Env, Method, ObjectNamespace, Request, RequestInit, Response, Result,
+    State,
+};
+
+/// Durable Object namespace binding backing the rate limiter
+pub const RATE_LIMITER_BINDING: &str = "RATE_LIMITER";
+
+/// Limits are expressed per minute
+const WINDOW_MS: f64 = 60_000.0;
+
+/// Storage key of the bucket state inside each Durable Object
+const BUCKETS_KEY: &str = "buckets";
+
+/// Default limits, applied per API key and per client IP; 0 disables a limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub key_rpm: u32,
+    pub key_tpm: u32,
+    pub ip_rpm: u32,
+    pub ip_tpm: u32,
+    /// Requests per API key in flight at once (see [`crate::concurrency`])
+    pub key_concurrency: u32,
+    /// Milliseconds a request waits for a free slot before it is rejected
+    pub concurrency_queue_ms: u64,
+    /// Requests in flight in an isolate at which background requests are shed
+    /// (see [`crate::admission`])
+    pub admission_background_limit: u32,
+}
+
+/// Requests and estimated prompt tokens allowed per minute; 0 disables a limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    pub rpm: u32,
+    pub tpm: u32,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        self.rpm == 0 && self.tpm == 0
+    }
+}
+
+/// Leaky bucket whose level drains at `limit` units per minute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub level: f64,
+    pub updated_ms: u64,
+}
+
+impl Bucket {
+    fn drain(&mut self, limit: u32, now: u64) {
+        let elapsed = now.saturating_sub(self.updated_ms) as f64;
+        self.level = (self.level - elapsed * f64::from(limit) / WINDOW_MS).max(0.0);
+        self.updated_ms = now;
+    }
+
+    /// Milliseconds until `cost` more units fit under `limit`
+    fn wait_ms(&self, limit: u32, cost: f64) -> u64 {
+        let excess = self.level + cost - f64::from(limit);
+        if excess <= 0.0 {
+            0
+        } else {
+            (excess * WINDOW_MS / f64::from(limit)).ceil() as u64
+        }
+    }
+
+    fn status(&self, limit: u32, now: u64) -> LimitStatus {
+        LimitStatus {
+            limit,
+            remaining: (f64::from(limit) - self.level).floor().max(0.0) as u32,
+            reset_at_ms: now + (self.level * WINDOW_MS / f64::from(limit)).ceil() as u64,
+        }
+    }
+}
+
+/// Usage of one limit after a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// When the bucket will be fully drained
+    pub reset_at_ms: u64,
+}
+
+/// Outcome of a rate limit check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// `"requests"` or `"tokens"` when the request was rejected
+    pub limited_by: Option<String>,
+    pub retry_after_ms: u64,
+    pub requests: Option<LimitStatus>,
+    pub tokens: Option<LimitStatus>,
+}
+
+/// Per-subject bucket state persisted by the Durable Object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Buckets {
+    pub requests: Bucket,
+    pub tokens: Bucket,
+}
+
+impl Buckets {
+    /// Admits one request costing `tokens` if both limits have room for it
+    ///
+    /// Nothing is consumed when the request is rejected. A single request larger than
+    /// the whole token limit is charged at the limit so it can run on an idle bucket.
+    pub fn check(&mut self, limits: Limits, tokens: u32, now: u64) -> RateLimitDecision {
+        let tokens = f64::from(tokens.min(limits.tpm));
+        let mut requests_wait = 0;
+        let mut tokens_wait = 0;
+
+        if limits.rpm > 0 {
+            self.requests.drain(limits.rpm, now);
+            requests_wait = self.requests.wait_ms(limits.rpm, 1.0);
+        }
+        if limits.tpm > 0 {
+            self.tokens.drain(limits.tpm, now);
+            tokens_wait = self.tokens.wait_ms(limits.tpm, tokens);
+        }
+
+        let allowed = requests_wait == 0 && tokens_wait == 0;
+        if allowed {
+            self.requests.level += 1.0;
+            self.tokens.level += tokens;
+        }
+
+        let limited_by = if requests_wait > 0 {
+            Some("requests".to_string())
+        } else if tokens_wait > 0 {
+            Some("tokens".to_string())
+        } else {
+            None
+        };
+
+        RateLimitDecision {
+            allowed,
+            limited_by,
+            retry_after_ms: requests_wait.max(tokens_wait),
+            requests: (limits.rpm > 0).then(|| self.requests.status(limits.rpm, now)),
+            tokens: (limits.tpm > 0).then(|| self.tokens.status(limits.tpm, now)),
+        }
+    }
+}
+
+/// Body sent from the proxy to a rate limiter Durable Object
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CheckRequest {
+    limits: Limits,
+    tokens: u32,
+}
+
+/// Token bucket Durable Object, one instance per rate-limited subject
+///
+/// Durable Objects process requests for one instance serially, which makes the
+/// read-modify-write of the bucket state race-free across isolates.
+#[durable_object]
+pub struct RateLimitBucket {
+    state: State,
+}
+
+impl DurableObject for RateLimitBucket {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let check: CheckRequest = req.json().await?;
+        let storage = self.state.storage();
+        // A bucket that was never stored reads as an error
+        let mut buckets = storage
+            .get::<Buckets>(BUCKETS_KEY)
+            .await
+            .unwrap_or_default();
+
+        let decision = buckets.check(check.limits, check.tokens, Date::now().as_millis());
+        storage.put(BUCKETS_KEY, buckets).await?;
+
+        Response::from_json(&decision)
+    }
+}
+
+/// Checks and consumes the limits of `subject` (e.g. `key:<hash>` or `ip:<addr>`)
+pub async fn check(
+    namespace: &ObjectNamespace,
+    subject: &str,
+    limits: Limits,
+    tokens: u32,
+) -> Result<RateLimitDecision> {
+    let stub = namespace.id_from_name(subject)?.get_stub()?;
+    let body = serde_json::to_string(&CheckRequest { limits, tokens })?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post).with_body(Some(body.into()));
+    let req = Request::new_with_init("https://rate-limiter/check", &init)?;
+
+    stub.fetch_with_request(req).await?.json().await
+}
+
+/// Rough prompt token count for TPM limits, at about four bytes per token
+pub fn estimate_tokens(serialized_len: usize) -> u32 {
+    u32::try_from(serialized_len.div_ceil(4)).unwrap_or(u32::MAX)
+}
+
+/// Anthropic-style rate limit headers describing a decision
+pub fn headers(decision: &RateLimitDecision) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    if !decision.allowed {
+        let retry_after_secs = decision.retry_after_ms.div_ceil(1000).max(1);
+        headers.push(("retry-after".to_string(), retry_after_secs.to_string()));
+    }
+
+    for (name, status) in [("requests", decision.requests), ("tokens", decision.tokens)] {
+        if let Some(status) = status {
+            let prefix = format!("anthropic-ratelimit-{name}");
+            headers.push((format!("{prefix}-limit"), status.limit.to_string()));
+            headers.push((format!("{prefix}-remaining"), status.remaining.to_string()));
+            headers.push((
+                format!("{prefix}-reset"),
+                format_timestamp(status.reset_at_ms),
+            ));
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    #[test]
+    fn test_unlimited_always_allows() {
+        let mut buckets = Buckets::default();
+        for _ in 0..100 {
+            let decision = buckets.check(Limits::default(), 10_000, NOW);
+            assert!(decision.allowed);
+            assert_eq!(decision.requests, None);
+            assert_eq!(decision.tokens, None);
+        }
+    }
+
+    #[test]
+    fn test_rpm_limit_and_refill() {
+        let limits = Limits { rpm: 2, tpm: 0 };
+        let mut buckets = Buckets::default();
+
+        assert!(buckets.check(limits, 0, NOW).allowed);
+        let second = buckets.check(limits, 0, NOW);
+        assert!(second.allowed);
+        assert_eq!(second.requests.unwrap().remaining, 0);
+
+        let rejected = buckets.check(limits, 0, NOW);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.limited_by.as_deref(), Some("requests"));
+        assert_eq!(rejected.retry_after_ms, 30_000);
+
+        // Half a minute drains one request at 2 RPM
+        assert!(buckets.check(limits, 0, NOW + 30_000).allowed);
+    }
+
+    #[test]
+    fn test_tpm_limit_does_not_consume_on_reject() {
+        let limits = Limits { rpm: 0, tpm: 1000 };
+        let mut buckets = Buckets::default();
+
+        assert!(buckets.check(limits, 800, NOW).allowed);
+        let rejected = buckets.check(limits, 400, NOW);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.limited_by.as_deref(), Some("tokens"));
+        assert_eq!(rejected.tokens.unwrap().remaining, 200);
+
+        assert!(buckets.check(limits, 200, NOW).allowed);
+    }
+
+    #[test]
+    fn test_oversized_request_runs_on_idle_bucket() {
+        let limits = Limits { rpm: 0, tpm: 1000 };
+        let mut buckets = Buckets::default();
+        assert!(buckets.check(limits, 5000, NOW).allowed);
+        assert!(!buckets.check(limits, 1, NOW).allowed);
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(0), 0);
+        assert_eq!(estimate_tokens(4), 1);
+        assert_eq!(estimate_tokens(5), 2);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut buckets = Buckets::default();
+        let limits = Limits { rpm: 1, tpm: 0 };
+        buckets.check(limits, 0, 0);
+        let decision = buckets.check(limits, 0, 0);
+
+        let headers = headers(&decision);
+        let get = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(get("retry-after"), Some("60"));
+        assert_eq!(get("anthropic-ratelimit-requests-limit"), Some("1"));
+        assert_eq!(get("anthropic-ratelimit-requests-remaining"), Some("0"));
+        assert_eq!(
+            get("anthropic-ratelimit-requests-reset"),
+            Some("1970-01-01T00:01:00Z")
+        );
+        assert_eq!(get("anthropic-ratelimit-tokens-limit"), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Replays of stored transcripts against another model, for regression-testing
+//! model mappings
+//!
+//! A transcript's request is translated again with the current configuration (or
+//! sent to an explicitly chosen upstream model) and the new response is compared
+//! with the one the client originally received.
+
+use crate::models::AnthropicRequest;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Longest text, in lines, that is diffed line by line; longer texts are only
+/// reported as changed or not
+pub const MAX_DIFF_LINES: usize = 500;
+
+/// The parts of a stored transcript a replay needs
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub id: String,
+    pub upstream_model: String,
+    pub request: AnthropicRequest,
+    /// The Anthropic response sent to the client; absent for streamed requests
+    pub response: Option<Value>,
+}
+
+impl Transcript {
+    /// Reads a record written by [`crate::transcripts::transcript_record`]
+    ///
+    /// Transcripts stored with `LOG_TO_R2=hashed` no longer hold the prompt and
+    /// can't be replayed.
+    pub fn from_record(record: &Value) -> Result<Self, String> {
+        let mut request: AnthropicRequest = serde_json::from_value(record["request"].clone())
+            .map_err(|e| format!("transcript request is unreadable: {e}"))?;
+        if is_hashed(&record["request"]["messages"]) {
+            return Err("transcript was stored with hashed content and can't be replayed".into());
+        }
+        // Replays are compared as whole messages
+        request.stream = Some(false);
+        Ok(Transcript {
+            id: record["id"].as_str().unwrap_or_default().to_string(),
+            upstream_model: record["upstream_model"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            request,
+            response: record["response"]
+                .as_object()
+                .map(|_| record["response"].clone()),
+        })
+    }
+}
+
+/// Whether content was replaced by `sha256:<hex>` digests
+fn is_hashed(value: &Value) -> bool {
+    match value {
+        Value::String(text) => text
+            .strip_prefix("sha256:")
+            .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit())),
+        Value::Array(items) => items.iter().any(is_hashed),
+        Value::Object(map) => map.values().any(is_hashed),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    Removed,
+    Added,
+}
+
+/// One line of a text diff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub line: String,
+}
+
+/// How the replayed response differs from the original
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResponseDiff {
+    pub identical: bool,
+    /// Original and replayed `stop_reason`
+    pub stop_reason: [Value; 2],
+    /// Original and replayed tool calls, as `name(input)`
+    pub tool_calls: [Vec<String>; 2],
+    /// Line diff of the text blocks; empty when either text is too long to diff
+    pub text: Vec<DiffLine>,
+}
+
+fn text_of(response: Option<&Value>) -> String {
+    let blocks = response.and_then(|response| response["content"].as_array());
+    blocks
+        .into_iter()
+        .flatten()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn tool_calls_of(response: Option<&Value>) -> Vec<String> {
+    let blocks = response.and_then(|response| response["content"].as_array());
+    blocks
+        .into_iter()
+        .flatten()
+        .filter(|block| block["type"] == "tool_use")
+        .map(|block| {
+            format!(
+                "{}({})",
+                block["name"].as_str().unwrap_or_default(),
+                block["input"]
+            )
+        })
+        .collect()
+}
+
+/// Compares the original response (if it was stored) with the replayed one
+pub fn diff_responses(original: Option<&Value>, replayed: &Value) -> ResponseDiff {
+    let stop_reason = [
+        original.map_or(Value::Null, |response| response["stop_reason"].clone()),
+        replayed["stop_reason"].clone(),
+    ];
+    let tool_calls = [tool_calls_of(original), tool_calls_of(Some(replayed))];
+    let (old_text, new_text) = (text_of(original), text_of(Some(replayed)));
+
+    ResponseDiff {
+        identical: stop_reason[0] == stop_reason[1]
+            && tool_calls[0] == tool_calls[1]
+            && old_text == new_text,
+        stop_reason,
+        tool_calls,
+        text: diff_lines(&old_text, &new_text),
+    }
+}
+
+/// Line diff of two texts from their longest common subsequence
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    if old.len() > MAX_DIFF_LINES || new.len() > MAX_DIFF_LINES {
+        return Vec::new();
+    }
+
+    // common[i][j]: length of the LCS of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let line = |op, line: &str| DiffLine {
+        op,
+        line: line.to_string(),
+    };
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(line(DiffOp::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            lines.push(line(DiffOp::Removed, old[i]));
+            i += 1;
+        } else {
+            lines.push(line(DiffOp::Added, new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|text| line(DiffOp::Removed, text)));
+    lines.extend(new[j..].iter().map(|text| line(DiffOp::Added, text)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcripts::{transcript_record, TranscriptMode};
+    use serde_json::json;
+
+    fn request() -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "stream": true,
+            "messages": [{"role": "user", "content": "List the files"}]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transcript_from_record() {
+        let record = transcript_record(
+            "ray-1",
+            0,
+            TranscriptMode::Full,
+            &request(),
+            "deepseek/deepseek-chat",
+            None,
+            None,
+        );
+        let transcript = Transcript::from_record(&record).unwrap();
+        assert_eq!(transcript.id, "ray-1");
+        assert_eq!(transcript.upstream_model, "deepseek/deepseek-chat");
+        assert_eq!(transcript.request.stream, Some(false));
+        assert_eq!(transcript.response, None);
+
+        let hashed = transcript_record(
+            "ray-1",
+            0,
+            TranscriptMode::Hashed,
+            &request(),
+            "deepseek/deepseek-chat",
+            None,
+            None,
+        );
+        assert!(Transcript::from_record(&hashed).is_err());
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = diff_lines("a\nb\nc", "a\nx\nc\nd");
+        let ops: Vec<_> = lines
+            .iter()
+            .map(|line| (line.op, line.line.as_str()))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                (DiffOp::Equal, "a"),
+                (DiffOp::Removed, "b"),
+                (DiffOp::Added, "x"),
+                (DiffOp::Equal, "c"),
+                (DiffOp::Added, "d"),
+            ]
+        );
+        assert!(diff_lines(&"x\n".repeat(MAX_DIFF_LINES + 1), "x").is_empty());
+    }
+
+    #[test]
+    fn test_diff_responses() {
+        let original = json!({
+            "content": [
+                {"type": "text", "text": "Listing."},
+                {"type": "tool_use", "id": "t1", "name": "bash", "input": {"command": "ls"}}
+            ],
+            "stop_reason": "tool_use"
+        });
+        let same = diff_responses(Some(&original), &original);
+        assert!(same.identical);
+        assert_eq!(same.tool_calls[1], [r#"bash({"command":"ls"})"#]);
+
+        let replayed = json!({
+            "content": [{"type": "text", "text": "I can't list files."}],
+            "stop_reason": "end_turn"
+        });
+        let changed = diff_responses(Some(&original), &replayed);
+        assert!(!changed.identical);
+        assert_eq!(changed.stop_reason, [json!("tool_use"), json!("end_turn")]);
+        assert!(changed.tool_calls[1].is_empty());
+
+        // Streamed requests have no stored response to compare with
+        assert!(!diff_responses(None, &replayed).identical);
+    }
+}
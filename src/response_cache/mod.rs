@@ -0,0 +1,115 @@
+use crate::auth::hash_key;
+use crate::models::OpenAIRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::kv::KvStore;
+use worker::{Response, Result};
+
+/// KV namespace binding holding cached responses; caching is off while unbound
+pub const RESPONSE_CACHE_BINDING: &str = "RESPONSE_CACHE";
+
+/// Request header that skips the cache lookup (`bypass`); set to `hit` or
+/// `semantic` on cached responses
+pub const CACHE_HEADER: &str = "x-ccr-cache";
+
+/// A successful response stored for identical requests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "body", rename_all = "snake_case")]
+pub enum CachedResponse {
+    /// Anthropic-formatted message of a non-streaming request
+    Message(Value),
+    /// Anthropic stream events of a streaming request
+    Events(String),
+}
+
+impl CachedResponse {
+    /// The response for the client, with `x-ccr-cache` set to `cache_status`
+    pub fn into_response(self, cache_status: &str) -> Result<Response> {
+        let mut response = match self {
+            CachedResponse::Message(message) => Response::from_json(&message)?,
+            CachedResponse::Events(events) => crate::transform::event_stream_response(events)?,
+        };
+        response.headers_mut().set(CACHE_HEADER, cache_status)?;
+        Ok(response)
+    }
+}
+
+/// Whether a request's response can be reused: only deterministic (`temperature: 0`) ones
+pub fn is_cacheable(request: &OpenAIRequest) -> bool {
+    request.temperature == Some(0.0)
+}
+
+/// KV key for a request as sent upstream, scoped to the caller
+///
+/// Keying on the transformed request means anything that changes what is sent
+/// (model mapping, profile settings, max_tokens caps) also changes the key, and
+/// scoping keeps one caller from being served responses another paid for.
+pub fn storage_key(subject: &str, request: &OpenAIRequest) -> Result<String> {
+    let body = serde_json::to_string(request)?;
+    Ok(format!("cache:{}", hash_key(&format!("{subject}\n{body}"))))
+}
+
+/// Looks up a cached response
+pub async fn lookup(kv: &KvStore, storage_key: &str) -> Result<Option<CachedResponse>> {
+    kv.get(storage_key)
+        .json::<CachedResponse>()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to read response cache: {e}")))
+}
+
+/// Caches a response for `ttl_secs`
+pub async fn store(
+    kv: &KvStore,
+    storage_key: &str,
+    response: &CachedResponse,
+    ttl_secs: u64,
+) -> Result<()> {
+    kv.put(storage_key, serde_json::to_string(response)?)?
+        .expiration_ttl(ttl_secs)
+        .execute()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to store cached response: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(temperature: Option<f32>) -> OpenAIRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "anthropic/claude-3.5-haiku",
+            "messages": [{"role": "user", "content": "Write a 5-word title"}],
+            "temperature": temperature
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_only_zero_temperature_is_cacheable() {
+        assert!(is_cacheable(&request(Some(0.0))));
+        assert!(!is_cacheable(&request(Some(0.7))));
+        assert!(!is_cacheable(&request(None)));
+    }
+
+    #[test]
+    fn test_storage_key_covers_request_and_subject() {
+        let key = storage_key("key:abc", &request(Some(0.0))).unwrap();
+        assert!(key.starts_with("cache:"));
+        assert_eq!(key, storage_key("key:abc", &request(Some(0.0))).unwrap());
+        assert_ne!(key, storage_key("key:def", &request(Some(0.0))).unwrap());
+
+        let mut other = request(Some(0.0));
+        other.max_tokens = Some(10);
+        assert_ne!(key, storage_key("key:abc", &other).unwrap());
+    }
+
+    #[test]
+    fn test_cached_response_round_trips() {
+        let cached = CachedResponse::Events("event: message_stop\n\n".to_string());
+        let json = serde_json::to_string(&cached).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CachedResponse>(&json).unwrap(),
+            cached
+        );
+    }
+}
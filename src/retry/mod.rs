@@ -0,0 +1,120 @@
+//! Retry policy for transient upstream failures
+
+use crate::utils::splitmix64;
+
+/// How often and how patiently failed upstream requests are retried
+///
+/// Applies to requests that fail with a retryable status, and to non-streaming
+/// ones that fail with a network error. Delays double from `base_delay_ms` up to `max_delay_ms`; a
+/// retry is skipped when waiting for it would end past `budget_ms` from the
+/// first attempt, leaving the Worker time to answer before its runtime limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first; 1 disables retries
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Randomize each delay between half and all of its backoff
+    pub jitter: bool,
+    /// Time from the first attempt after which no retry is started
+    pub budget_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 250,
+            max_delay_ms: 2000,
+            jitter: true,
+            budget_ms: 15_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether an upstream status is worth retrying: timeouts, rate limits and server errors
+    pub fn is_retryable_status(status: u16) -> bool {
+        matches!(status, 408 | 429 | 500..=599)
+    }
+
+    /// Delay before retry number `retry` (1 for the first retry)
+    ///
+    /// `seed` should vary per request; it picks the jittered delay.
+    pub fn delay_ms(&self, retry: u32, seed: u64) -> u64 {
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(20))
+            .min(self.max_delay_ms);
+        if !self.jitter || backoff < 2 {
+            return backoff;
+        }
+        let half = backoff / 2;
+        half + splitmix64(seed.wrapping_add(u64::from(retry))) % (backoff - half + 1)
+    }
+
+    /// The delay before the next attempt, or `None` when the request should fail now
+    ///
+    /// `attempts` is the number of attempts made so far and `elapsed_ms` the time
+    /// since the first one started.
+    pub fn next_delay(&self, attempts: u32, elapsed_ms: u64, seed: u64) -> Option<u64> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = self.delay_ms(attempts, seed);
+        (elapsed_ms.saturating_add(delay) < self.budget_ms).then_some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_statuses() {
+        for status in [408, 429, 500, 502, 503, 529] {
+            assert!(RetryPolicy::is_retryable_status(status), "{status}");
+        }
+        for status in [200, 400, 401, 403, 404, 413] {
+            assert!(!RetryPolicy::is_retryable_status(status), "{status}");
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        let delays: Vec<_> = (1..=5).map(|retry| policy.delay_ms(retry, 0)).collect();
+        assert_eq!(delays, [250, 500, 1000, 2000, 2000]);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_half_to_full_backoff() {
+        let policy = RetryPolicy::default();
+        for seed in 0..200 {
+            let delay = policy.delay_ms(2, seed);
+            assert!((250..=500).contains(&delay), "{delay}");
+        }
+        assert_ne!(policy.delay_ms(2, 1), policy.delay_ms(2, 2));
+    }
+
+    #[test]
+    fn test_next_delay_respects_attempts_and_budget() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.next_delay(1, 0, 0), Some(250));
+        assert_eq!(policy.next_delay(2, 0, 0), Some(500));
+        assert_eq!(policy.next_delay(3, 0, 0), None);
+        assert_eq!(policy.next_delay(1, 14_800, 0), None);
+
+        let disabled = RetryPolicy {
+            max_attempts: 1,
+            ..policy
+        };
+        assert_eq!(disabled.next_delay(1, 0, 0), None);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::auth;
+use crate::config::Config;
+use crate::http;
+use crate::ledger::{self, LedgerQuery};
+use crate::pricing;
+use crate::selftest;
+use crate::tail;
+use crate::usage::{self, forecast::Forecast};
+use worker::{Date, Env, Request, Response, Result, Url};
+
+/// Secret that unlocks the `/admin/*` endpoints, which return 404 while it is unset
+pub const ADMIN_TOKEN_VAR: &str = "ADMIN_TOKEN";
+
+/// Returns the response to send instead when the request lacks the admin token
+pub(crate) fn require_admin(req: &Request, env: &Env) -> Result<Option<Response>> {
+    let presented = bearer_token(req)?;
+    check_admin_token(env, presented)
+}
+
+fn bearer_token(req: &Request) -> Result<Option<String>> {
+    Ok(req
+        .headers()
+        .get("Authorization")?
+        .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string)))
+}
+
+fn check_admin_token(env: &Env, presented: Option<String>) -> Result<Option<Response>> {
+    let expected = env
+        .secret(ADMIN_TOKEN_VAR)
+        .map(|secret| secret.to_string())
+        .or_else(|_| env.var(ADMIN_TOKEN_VAR).map(|var| var.to_string()))
+        .ok()
+        .filter(|token| !token.trim().is_empty());
+    let Some(expected) = expected else {
+        return Response::error("Not Found", 404).map(Some);
+    };
+
+    match presented {
+        Some(token) if auth::constant_time_eq(&expected, &token) => Ok(None),
+        _ => Response::error("Unauthorized", 401).map(Some),
+    }
+}
+
+/// Handles GET /admin/selftest
+///
+/// Returns the self-test report with status 200 when every check passed or was
+/// skipped, and 503 otherwise.
+pub async fn selftest(req: Request, env: &Env) -> Result<Response> {
+    if let Some(denied) = require_admin(&req, env)? {
+        return Ok(denied);
+    }
+
+    let report = selftest::run(env).await;
+    let status = if report.ok { 200 } else { 503 };
+    Ok(Response::from_json(&report)?.with_status(status))
+}
+
+/// Handles GET /usage
+///
+/// Aggregates token counts and costs per day, model and key over `?days=` days
+/// (default 7). Returns HTML with `?format=html` or an `Accept: text/html` header,
+/// and JSON otherwise.
+pub async fn usage(req: Request, env: &Env, config: &Config) -> Result<Response> {
+    if let Some(denied) = require_admin(&req, env)? {
+        return Ok(denied);
+    }
+    let Some(analytics) = &config.analytics_sql else {
+        return Response::error(
+            "Usage reporting requires CF_ACCOUNT_ID and CF_ANALYTICS_TOKEN",
+            501,
+        );
+    };
+
+    let url = req.url()?;
+    let days = match days_param(&url, 1, usage::DEFAULT_DAYS) {
+        Ok(days) => days,
+        Err(message) => return Response::error(message, 400),
+    };
+    let html = query_param(&url, "format").as_deref() == Some("html")
+        || req
+            .headers()
+            .get("Accept")?
+            .is_some_and(|accept| accept.contains("text/html"));
+
+    let client = http::Client::new();
+    let report = match usage::fetch(&client, analytics, days).await {
+        Ok(report) => report,
+        Err(e) => return Response::error(e.to_string(), 502),
+    };
+    let prices_kv = env.kv(pricing::PRICING_BINDING).ok();
+    let report =
+        match pricing::prices(&client, &config.openrouter_base_url, prices_kv.as_ref()).await {
+            Ok(prices) => report.with_prices(prices),
+            Err(_) => report,
+        };
+    if html {
+        Response::from_html(report.to_html())
+    } else {
+        Response::from_json(&report)
+    }
+}
+
+/// Handles GET /usage/forecast
+///
+/// Daily and weekly spend per key and model over `?days=` days (default 14),
+/// with projections and the keys and models spending far above their average.
+pub async fn forecast(req: Request, env: &Env, config: &Config) -> Result<Response> {
+    if let Some(denied) = require_admin(&req, env)? {
+        return Ok(denied);
+    }
+    let Some(analytics) = &config.analytics_sql else {
+        return Response::error(
+            "Usage reporting requires CF_ACCOUNT_ID and CF_ANALYTICS_TOKEN",
+            501,
+        );
+    };
+
+    // A forecast needs at least one complete day before today
+    let days = match days_param(&req.url()?, 2, usage::forecast::DEFAULT_DAYS) {
+        Ok(days) => days,
+        Err(message) => return Response::error(message, 400),
+    };
+    match usage::fetch(&http::Client::new(), analytics, days).await {
+        Ok(report) => {
+            Response::from_json(&Forecast::new(&report.rows, days, Date::now().as_millis()))
+        }
+        Err(e) => Response::error(e.to_string(), 502),
+    }
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// The `?days=` parameter, between `min` and the dataset's retention
+fn days_param(url: &Url, min: u32, default: u32) -> std::result::Result<u32, String> {
+    match query_param(url, "days") {
+        Some(raw) => match raw.parse::<u32>() {
+            Ok(days) if (min..=usage::MAX_DAYS).contains(&days) => Ok(days),
+            _ => Err(format!(
+                "days must be between {min} and {}",
+                usage::MAX_DAYS
+            )),
+        },
+        None => Ok(default),
+    }
+}
+
+/// Handles GET /admin/ledger
+///
+/// Lists recorded requests, newest first, or totals per `?group_by=day|model|key`,
+/// over `?days=` days (default 7) and filtered by `key_hash`, `model` and `status`.
+pub async fn ledger(req: Request, env: &Env) -> Result<Response> {
+    if let Some(denied) = require_admin(&req, env)? {
+        return Ok(denied);
+    }
+    let Ok(db) = env.d1(ledger::LEDGER_BINDING) else {
+        return Response::error("The request ledger requires a LEDGER_DB binding", 501);
+    };
+
+    let url = req.url()?;
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let query = match LedgerQuery::from_params(
+        params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+        Date::now().as_millis(),
+    ) {
+        Ok(query) => query,
+        Err(message) => return Response::error(message, 400),
+    };
+
+    match ledger::query(&db, &query).await {
+        Ok(rows) => Response::from_json(&serde_json::json!({ "rows": rows })),
+        Err(e) => Response::error(e.to_string(), 502),
+    }
+}
+
+/// Handles GET /admin/tail
+///
+/// Serves the live console page, or with `Upgrade: websocket`, connects to the
+/// stream of request summaries. Browsers can't set headers on either, so the admin
+/// token may also be given as `?token=`.
+pub async fn tail(req: Request, env: &Env) -> Result<Response> {
+    let presented = match bearer_token(&req)? {
+        Some(token) => Some(token),
+        None => req
+            .url()?
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, value)| value.into_owned()),
+    };
+    if let Some(denied) = check_admin_token(env, presented)? {
+        return Ok(denied);
+    }
+
+    let upgrade = req
+        .headers()
+        .get("Upgrade")?
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return Response::from_html(tail::CONSOLE_HTML);
+    }
+    let Ok(namespace) = env.durable_object(tail::REQUEST_TAIL_BINDING) else {
+        return Response::error(
+            format!(
+                "The tail console requires the {} binding",
+                tail::REQUEST_TAIL_BINDING
+            ),
+            501,
+        );
+    };
+    tail::connect(&namespace, req).await
+}
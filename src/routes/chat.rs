@@ -0,0 +1,288 @@
+use super::proxy::{
+    acquire_slot, admit, charge_spend, record_metrics, record_spend, report_error, Caller,
+    Rejection,
+};
+use crate::attachments;
+use crate::config::Config;
+use crate::geo::RequestLocation;
+use crate::guardrails;
+use crate::http;
+use crate::log::Logger;
+use crate::metrics::RequestMetrics;
+use crate::reporting::ErrorReport;
+use crate::transform::{check_completion_count, openai_usage, SseUsageScanner};
+use crate::utils::{map_model, upstream_headers};
+use futures::{StreamExt, TryStreamExt};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use worker::{Context, Date, Env, Request, Response, Result};
+
+/// Handles POST requests to /v1/chat/completions
+///
+/// Accepts OpenAI-format chat requests so tools that don't speak the Anthropic API
+/// can share the deployment. Callers go through the same authentication, profiles,
+/// model mapping, key allowlists, rate limits and budgets as `/v1/messages`, and
+/// the request is forwarded to OpenRouter as-is, so every OpenAI parameter
+/// OpenRouter supports works. Responses, including streams, are passed through.
+///
+/// Hedging, shadowing, idempotency and transcripts only apply to `/v1/messages`.
+pub async fn handle_chat_completions(
+    mut req: Request,
+    env: &Env,
+    ctx: &Context,
+    caller: Caller<'_>,
+    log: &Logger,
+) -> Result<Response> {
+    let start_time = Date::now().as_millis() as f64;
+    let client = http::Client::new();
+
+    let config: &Config = &caller.config;
+
+    let location = RequestLocation::from_request(&req);
+    let origin = req.url()?.origin().ascii_serialization();
+    let client_ip = req.headers().get("CF-Connecting-IP")?;
+
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return openai_error(413, "invalid_request_error", &message);
+    }
+    let mut chat_request: Value = match serde_json::from_str(&body) {
+        Ok(Value::Object(fields)) => Value::Object(fields),
+        Ok(_) => return openai_error(400, "invalid_request_error", "Body must be a JSON object"),
+        Err(e) => return openai_error(400, "invalid_request_error", &e.to_string()),
+    };
+    let Some(requested_model) = chat_request["model"].as_str().map(str::to_string) else {
+        return openai_error(400, "invalid_request_error", "model is required");
+    };
+    let Some(messages) = chat_request["messages"].as_array().cloned() else {
+        return openai_error(400, "invalid_request_error", "messages must be an array");
+    };
+    // Usage and spend are accounted for one choice
+    if let Err(message) = check_completion_count("n", chat_request.get("n")) {
+        return openai_error(400, "invalid_request_error", &message);
+    }
+    let stream = chat_request["stream"].as_bool().unwrap_or(false);
+
+    let model = map_model(&requested_model, config);
+    chat_request["model"] = model.clone().into();
+    // OpenRouter falls back to the models in `models`, which are mapped the same way
+    for entry in chat_request
+        .get_mut("models")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(name) = entry.as_str() {
+            *entry = map_model(name, config).into();
+        }
+    }
+
+    // Enforce the virtual key's model allowlist and max_tokens cap
+    if let Some(record) = &caller.virtual_key {
+        if !record.allows_model(&model) {
+            return openai_error(
+                403,
+                "permission_error",
+                &format!("Model '{model}' is not allowed for this API key"),
+            );
+        }
+        // Each fallback model must be allowed as well
+        if let Some(fallback) = chat_request.get("models").and_then(Value::as_array) {
+            if let Some(disallowed) = fallback
+                .iter()
+                .find(|entry| !entry.as_str().is_some_and(|name| record.allows_model(name)))
+            {
+                return openai_error(
+                    403,
+                    "permission_error",
+                    &format!("Model {disallowed} in models is not allowed for this API key"),
+                );
+            }
+        }
+        // Newer clients send max_completion_tokens in place of max_tokens
+        let field = if chat_request.get("max_completion_tokens").is_some() {
+            "max_completion_tokens"
+        } else {
+            "max_tokens"
+        };
+        let requested = chat_request[field].as_u64().map(|tokens| tokens as u32);
+        if let Some(capped) = record.cap_max_tokens(requested) {
+            chat_request[field] = capped.into();
+        }
+    }
+
+    let ledger = match admit(env, &caller, client_ip, &messages).await? {
+        Ok(ledger) => ledger,
+        Err(rejection) => return into_openai(rejection),
+    };
+    // Counts against the key's requests in flight until the upstream answers
+    let _slot = match acquire_slot(env, ctx, &caller, log).await? {
+        Ok(slot) => slot,
+        Err(rejection) => return into_openai(rejection),
+    };
+
+    attachments::offload_request(
+        env,
+        config.attachments.as_ref(),
+        &origin,
+        &mut chat_request,
+        log,
+    )
+    .await;
+
+    let url = format!("{}/chat/completions", config.upstream_base_url(&location));
+    let api_key = caller.upstream_key(env, &model).await?;
+    let mut upstream = client.post(&url);
+    for (name, value) in upstream_headers(&api_key, &config.attribution) {
+        upstream = upstream.header(name, value);
+    }
+    let response = match upstream.json(&chat_request).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            report_error(
+                ctx,
+                &client,
+                config,
+                log,
+                ErrorReport::new(
+                    "upstream",
+                    format!("Request failed: {e}"),
+                    &model,
+                    log.request_id(),
+                    Date::now().as_millis(),
+                ),
+            );
+            return openai_error(502, "api_error", &format!("Request failed: {e}"));
+        }
+    };
+
+    let status = response.status();
+    let request_metrics = |status: u16| RequestMetrics {
+        model: model.clone(),
+        requested_model: requested_model.clone(),
+        key_hash: caller.key_hash.clone(),
+        status,
+        latency_ms: Date::now().as_millis() as f64 - start_time,
+        input_tokens: 0,
+        output_tokens: 0,
+        cost_usd: None,
+        prompt_cache_key: None,
+        cached_input_tokens: 0,
+    };
+
+    // Upstream errors are already in OpenAI format
+    if !response.is_success() {
+        record_metrics(
+            ctx,
+            env,
+            &client,
+            config,
+            request_metrics(status),
+            None,
+            log,
+        );
+        let error_text = response.text().await.unwrap_or_default();
+        report_error(
+            ctx,
+            &client,
+            config,
+            log,
+            ErrorReport::new(
+                "upstream",
+                format!("OpenRouter returned HTTP {status}"),
+                &model,
+                log.request_id(),
+                Date::now().as_millis(),
+            )
+            .with_status(status)
+            .with_body(&error_text),
+        );
+        let mut response = Response::ok(error_text)?.with_status(status);
+        response
+            .headers_mut()
+            .set("Content-Type", "application/json")?;
+        return Ok(response);
+    }
+
+    if stream {
+        // Streams are relayed as they arrive. OpenRouter reports usage in the last
+        // event, so a budgeted key's spend is charged once the stream has ended.
+        record_metrics(
+            ctx,
+            env,
+            &client,
+            config,
+            request_metrics(status),
+            None,
+            log,
+        );
+        let scanner = Rc::new(RefCell::new(SseUsageScanner::default()));
+        let tap = scanner.clone();
+        let body = response.bytes_stream()?.inspect_ok(move |chunk| {
+            tap.borrow_mut().push(chunk);
+        });
+
+        let base_url = config.openrouter_base_url.clone();
+        let log = log.clone();
+        let finish = futures::stream::once(async move {
+            let usage = scanner.borrow().usage();
+            match (ledger, usage) {
+                (Some(ledger), Some(usage)) => {
+                    charge_spend(&client, &base_url, &ledger, &model, &usage, &log).await;
+                }
+                (Some(_), None) => {
+                    log.warn(
+                        "no usage reported, spend not recorded",
+                        &[("model", model.as_str().into())],
+                    );
+                }
+                (None, _) => {}
+            }
+            Ok(Vec::new())
+        });
+        let mut response = Response::from_stream(body.chain(finish))?;
+        response
+            .headers_mut()
+            .set("Content-Type", "text/event-stream")?;
+        response.headers_mut().set("Cache-Control", "no-cache")?;
+        return Ok(response);
+    }
+
+    let completion: Value = response
+        .json()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to parse OpenAI response: {e}")))?;
+    let usage = openai_usage(&completion);
+    record_metrics(
+        ctx,
+        env,
+        &client,
+        config,
+        request_metrics(status),
+        usage.as_ref(),
+        log,
+    );
+    record_spend(ctx, &client, config, ledger, &model, usage, log);
+    Response::from_json(&completion)
+}
+
+/// Builds an OpenAI-format error response
+pub(crate) fn openai_error(status: u16, error_type: &str, message: &str) -> Result<Response> {
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "code": null
+        }
+    });
+    Ok(Response::from_json(&body)?.with_status(status))
+}
+
+pub(crate) fn into_openai(rejection: Rejection) -> Result<Response> {
+    let mut response = openai_error(rejection.status, rejection.error_type, &rejection.message)?;
+    for (name, value) in &rejection.headers {
+        response.headers_mut().set(name, value)?;
+    }
+    Ok(response)
+}
@@ -0,0 +1,277 @@
+use super::proxy::{
+    acquire_slot, admit, client_status, error_response, forward, record_metrics, record_spend,
+    Caller, Forwarded,
+};
+use crate::config::Config;
+use crate::deadline::Budget;
+use crate::geo::RequestLocation;
+use crate::guardrails;
+use crate::http;
+use crate::log::Logger;
+use crate::metrics::RequestMetrics;
+use crate::models::{AnthropicRequest, OpenAIRequest};
+use crate::pricing;
+use crate::transform::{anthropic_to_openai, check_translatable, completion_to_anthropic};
+use crate::upstream::FetchClient;
+use futures::future::join_all;
+use serde_json::{json, Value};
+use worker::{Context, Date, Env, Request, Response, Result};
+
+/// Most models one prompt can be compared across
+pub const MAX_COMPARE_MODELS: usize = 5;
+
+/// Handles POST /v1/experiments/compare
+///
+/// Sends one Anthropic-format prompt to several models at once and returns
+/// their responses side by side, each with its status, latency and estimated
+/// cost. The models are the body's `models` array, or `COMPARE_MODELS` without
+/// one, given as Claude short names or upstream IDs. A comparison is admitted
+/// against rate limits as one request per model, and against the budget; every
+/// model's usage is recorded as spend.
+pub async fn handle_compare(
+    mut req: Request,
+    env: &Env,
+    ctx: &Context,
+    caller: Caller<'_>,
+    budget: &Budget,
+    log: &Logger,
+) -> Result<Response> {
+    let client = http::Client::new();
+    let config: &Config = &caller.config;
+
+    let location = RequestLocation::from_request(&req);
+    let client_ip = req.headers().get("CF-Connecting-IP")?;
+
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return error_response(413, "invalid_request_error", &message);
+    }
+    let mut fields = match serde_json::from_str(&body) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(_) => return error_response(400, "invalid_request_error", "Body must be a JSON object"),
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string()),
+    };
+    let models: Vec<String> = match fields.remove("models") {
+        Some(Value::Array(models)) => match models
+            .iter()
+            .map(|model| model.as_str().map(str::to_string))
+            .collect()
+        {
+            Some(models) => models,
+            None => {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    "models must be an array of model names",
+                )
+            }
+        },
+        Some(_) => {
+            return error_response(
+                400,
+                "invalid_request_error",
+                "models must be an array of model names",
+            )
+        }
+        None => config.compare_models.clone(),
+    };
+    if models.is_empty() {
+        return error_response(
+            400,
+            "invalid_request_error",
+            "models is required when COMPARE_MODELS is not set",
+        );
+    }
+    if models.len() > MAX_COMPARE_MODELS {
+        return error_response(
+            400,
+            "invalid_request_error",
+            &format!("At most {MAX_COMPARE_MODELS} models can be compared at once"),
+        );
+    }
+
+    // The model field is optional, since each comparison sets its own
+    fields
+        .entry("model")
+        .or_insert_with(|| models[0].clone().into());
+    let prompt: AnthropicRequest = match serde_json::from_value(Value::Object(fields)) {
+        Ok(prompt) => prompt,
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string()),
+    };
+    if prompt.stream.unwrap_or(false) {
+        return error_response(
+            400,
+            "invalid_request_error",
+            "Comparisons can't stream; send stream: false",
+        );
+    }
+    if let Err(message) = guardrails::check_request(&config.request_limits, &prompt)
+        .and_then(|()| check_translatable(&prompt, config))
+    {
+        return error_response(400, "invalid_request_error", &message);
+    }
+
+    let mut requests: Vec<(AnthropicRequest, OpenAIRequest, String, String)> =
+        Vec::with_capacity(models.len());
+    for model in models {
+        let anthropic_request = AnthropicRequest {
+            model,
+            ..prompt.clone()
+        };
+        let mut openai_request = anthropic_to_openai(&anthropic_request, config)?;
+        if let Some(record) = &caller.virtual_key {
+            if !record.allows_model(&openai_request.model) {
+                return error_response(
+                    403,
+                    "permission_error",
+                    &format!(
+                        "Model '{}' is not allowed for this API key",
+                        openai_request.model
+                    ),
+                );
+            }
+            openai_request.max_tokens = record.cap_max_tokens(openai_request.max_tokens);
+        }
+        let api_key = caller.upstream_key(env, &openai_request.model).await?;
+        let hedge_key = caller.hedge_key(env, &openai_request.model).await?;
+        requests.push((anthropic_request, openai_request, api_key, hedge_key));
+    }
+
+    // Each model's request counts towards the caller's requests and tokens per minute
+    let mut ledger = None;
+    for (_, openai_request, ..) in &requests {
+        ledger = match admit(env, &caller, client_ip.clone(), &openai_request.messages).await? {
+            Ok(ledger) => ledger,
+            Err(rejection) => return rejection.into_anthropic(),
+        };
+    }
+    // The comparison holds one of the key's slots until every model has answered
+    let slot = match acquire_slot(env, ctx, &caller, log).await? {
+        Ok(slot) => slot,
+        Err(rejection) => return rejection.into_anthropic(),
+    };
+
+    log.info(
+        "comparing models",
+        &[(
+            "models",
+            requests
+                .iter()
+                .map(|(_, openai_request, ..)| openai_request.model.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+                .into(),
+        )],
+    );
+    let upstream = FetchClient::new()
+        .with_timeout(config.upstream_timeout_ms)
+        .with_attribution(config.attribution.clone());
+    let url = format!("{}/chat/completions", config.upstream_base_url(&location));
+    let outcomes = join_all(requests.iter().map(
+        |(anthropic_request, openai_request, api_key, hedge_key)| async {
+            let started_at = Date::now().as_millis();
+            let forwarded = forward(
+                &upstream,
+                &url,
+                api_key,
+                hedge_key,
+                anthropic_request,
+                openai_request,
+                config,
+                budget,
+                log,
+            )
+            .await;
+            (forwarded, Date::now().as_millis() - started_at)
+        },
+    ))
+    .await;
+    drop(slot);
+
+    let prices_kv = env.kv(pricing::PRICING_BINDING).ok();
+    let prices = pricing::prices(&client, &config.openrouter_base_url, prices_kv.as_ref())
+        .await
+        .ok();
+
+    let mut results = Vec::with_capacity(outcomes.len());
+    for ((anthropic_request, openai_request, ..), (forwarded, latency_ms)) in
+        requests.iter().zip(outcomes)
+    {
+        // The hedge model is priced and recorded when it answered
+        let hedge_model = forwarded
+            .as_ref()
+            .ok()
+            .and_then(Forwarded::hedge_model)
+            .map(str::to_string);
+        let model = hedge_model.as_deref().unwrap_or(&openai_request.model);
+        let mut result = json!({
+            "model": anthropic_request.model,
+            "upstream_model": model,
+            "latency_ms": latency_ms,
+        });
+        let (status, usage) = match forwarded {
+            Ok(Forwarded::Message {
+                openai_response,
+                usage,
+                ..
+            }) => match completion_to_anthropic(openai_response, &anthropic_request.model) {
+                Ok(message) => {
+                    result["response"] = serde_json::to_value(message)?;
+                    (200, usage)
+                }
+                Err(e) => {
+                    result["error"] = json!({"type": "api_error", "message": e.to_string()});
+                    (502, usage)
+                }
+            },
+            Ok(Forwarded::Error { status, body, .. }) => {
+                result["error"] = body["error"].clone();
+                (client_status(status), None)
+            }
+            Ok(Forwarded::Stream { .. }) => {
+                result["error"] =
+                    json!({"type": "api_error", "message": "Comparisons can't stream"});
+                (500, None)
+            }
+            Err(e) => {
+                result["error"] = json!({"type": "api_error", "message": e.message});
+                (e.status.unwrap_or(502), None)
+            }
+        };
+        let cost_usd = prices
+            .zip(usage.as_ref())
+            .and_then(|(prices, usage)| pricing::estimate(prices, model, usage));
+        result["status"] = status.into();
+        result["usage"] = json!(usage);
+        result["cost_usd"] = json!(cost_usd);
+        results.push(result);
+
+        record_metrics(
+            ctx,
+            env,
+            &client,
+            config,
+            RequestMetrics {
+                model: model.to_string(),
+                requested_model: anthropic_request.model.clone(),
+                key_hash: caller.key_hash.clone(),
+                status,
+                latency_ms: latency_ms as f64,
+                input_tokens: 0,
+                output_tokens: 0,
+                cost_usd,
+                prompt_cache_key: None,
+                cached_input_tokens: 0,
+            },
+            usage.as_ref(),
+            log,
+        );
+        let ledger = match &ledger {
+            Some(ledger) => Some(ledger.reopen(env)?),
+            None => None,
+        };
+        record_spend(ctx, &client, config, ledger, model, usage, log);
+    }
+
+    Response::from_json(&json!({ "results": results }))
+}
@@ -0,0 +1,56 @@
+use super::proxy::{error_response, Caller};
+use crate::config::Config;
+use crate::guardrails;
+use crate::transform::{convert_conversation, Format};
+use serde_json::Value;
+use worker::{Request, Response, Result};
+
+/// Response header naming the format a conversation was converted to
+pub const FORMAT_HEADER: &str = "x-ccr-format";
+
+/// Handles POST /v1/convert
+///
+/// Converts a conversation, shaped like an Anthropic Messages request or an
+/// OpenAI chat completions request, to the other format with the translation
+/// the proxy applies. The target is the `to` query parameter (`anthropic` or
+/// `openai`), or else the format the body isn't in, and is echoed in
+/// `x-ccr-format`. Nothing is sent upstream.
+pub async fn handle_convert(mut req: Request, caller: Caller<'_>) -> Result<Response> {
+    let config: &Config = &caller.config;
+
+    let to = req
+        .url()?
+        .query_pairs()
+        .find(|(name, _)| name == "to")
+        .map(|(_, value)| value.parse::<Format>());
+    let to = match to.transpose() {
+        Ok(to) => to,
+        Err(message) => {
+            return error_response(400, "invalid_request_error", &format!("to: {message}"))
+        }
+    };
+
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return error_response(413, "invalid_request_error", &message);
+    }
+    let conversation: Value = match serde_json::from_str(&body) {
+        Ok(conversation @ Value::Object(_)) => conversation,
+        Ok(_) => return error_response(400, "invalid_request_error", "Body must be a JSON object"),
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string()),
+    };
+
+    let to = to.unwrap_or_else(|| Format::detect(&conversation).other());
+    match convert_conversation(&conversation, to, config) {
+        Ok(converted) => {
+            let mut response = Response::from_json(&converted)?;
+            let format = match to {
+                Format::Anthropic => "anthropic",
+                Format::OpenAI => "openai",
+            };
+            response.headers_mut().set(FORMAT_HEADER, format)?;
+            Ok(response)
+        }
+        Err(e) => error_response(400, "invalid_request_error", &e.0),
+    }
+}
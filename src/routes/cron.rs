@@ -0,0 +1,72 @@
+use crate::alerts::{self, Alert};
+use crate::catalog;
+use crate::config::Config;
+use crate::http;
+use crate::log::Logger;
+use crate::pricing;
+use crate::usage::{self, forecast::Forecast};
+use worker::{Env, Result};
+
+/// Runs the tasks of a cron trigger firing at `now_ms`
+///
+/// Each task is enabled by its own configuration and fails on its own: a
+/// failure is logged and the other tasks still run.
+pub async fn run(env: &Env, now_ms: u64, log: &Logger) -> Result<()> {
+    let config = Config::cached(env)?;
+
+    if let Err(e) = refresh_catalog(env, &config, log).await {
+        log.error("catalog refresh failed", &[("error", e.to_string().into())]);
+    }
+
+    if let Some(webhook_url) = &config.digest_webhook_url {
+        match post_digest(&config, webhook_url, now_ms).await {
+            Ok(()) => log.info("spend digest posted", &[]),
+            Err(e) => log.error("spend digest failed", &[("error", e.to_string().into())]),
+        }
+    }
+    Ok(())
+}
+
+/// Stores the catalog and prices in KV, so requests don't fetch them, and
+/// checks the configured models are still listed
+///
+/// Missing and deprecated models are logged, and alerted on each run while
+/// `ALERT_WEBHOOK_URL` is set.
+async fn refresh_catalog(env: &Env, config: &Config, log: &Logger) -> Result<()> {
+    let client = http::Client::new();
+    let catalog_kv = env.kv(catalog::CATALOG_BINDING).ok();
+    let catalog =
+        catalog::refresh(&client, &config.openrouter_base_url, catalog_kv.as_ref()).await?;
+    if let Ok(kv) = env.kv(pricing::PRICING_BINDING) {
+        pricing::store(&kv, &pricing::parse_catalog(&catalog)).await?;
+    }
+
+    let checks = catalog::check_models(&catalog, config.configured_models());
+    for check in &checks {
+        log.warn(
+            "configured model needs attention",
+            &[
+                ("setting", check.setting.clone().into()),
+                ("model", check.model.clone().into()),
+                ("problem", format!("{:?}", check.problem).into()),
+            ],
+        );
+    }
+    if let Some(alert_config) = config.alerts.as_ref().filter(|_| !checks.is_empty()) {
+        let alerts: Vec<Alert> = checks.into_iter().map(Alert::ConfiguredModel).collect();
+        alerts::send(alert_config, &alerts).await?;
+    }
+    Ok(())
+}
+
+/// Posts the spend forecast over the default window to the digest webhook
+async fn post_digest(config: &Config, webhook_url: &str, now_ms: u64) -> Result<()> {
+    // Config validation guarantees the SQL API is configured alongside the webhook
+    let Some(analytics) = &config.analytics_sql else {
+        return Ok(());
+    };
+    let days = usage::forecast::DEFAULT_DAYS;
+    let report = usage::fetch(&http::Client::new(), analytics, days).await?;
+    let forecast = Forecast::new(&report.rows, days, now_ms);
+    alerts::post_text(webhook_url, &forecast.digest()).await
+}
@@ -0,0 +1,164 @@
+use super::chat::{into_openai, openai_error};
+use super::proxy::{admit, record_metrics, record_spend, report_error, Caller};
+use crate::config::Config;
+use crate::geo::RequestLocation;
+use crate::guardrails;
+use crate::http;
+use crate::log::Logger;
+use crate::metrics::RequestMetrics;
+use crate::reporting::ErrorReport;
+use crate::transform::openai_usage;
+use crate::utils::{map_model, upstream_headers};
+use serde_json::Value;
+use worker::{Context, Date, Env, Request, Response, Result};
+
+/// Handles POST requests to /v1/embeddings
+///
+/// Forwards OpenAI-format embedding requests to OpenRouter behind the same
+/// authentication, key allowlists, rate limits and budgets as the chat endpoints.
+/// Bare OpenAI model names such as `text-embedding-3-small` are given their
+/// `openai/` prefix, and prompt tokens are recorded in metrics and spend.
+pub async fn handle_embeddings(
+    mut req: Request,
+    env: &Env,
+    ctx: &Context,
+    caller: Caller<'_>,
+    log: &Logger,
+) -> Result<Response> {
+    let start_time = Date::now().as_millis() as f64;
+    let client = http::Client::new();
+
+    let config: &Config = &caller.config;
+
+    let location = RequestLocation::from_request(&req);
+    let client_ip = req.headers().get("CF-Connecting-IP")?;
+
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return openai_error(413, "invalid_request_error", &message);
+    }
+    let mut embeddings_request: Value = match serde_json::from_str(&body) {
+        Ok(Value::Object(fields)) => Value::Object(fields),
+        Ok(_) => return openai_error(400, "invalid_request_error", "Body must be a JSON object"),
+        Err(e) => return openai_error(400, "invalid_request_error", &e.to_string()),
+    };
+    let Some(requested_model) = embeddings_request["model"].as_str().map(str::to_string) else {
+        return openai_error(400, "invalid_request_error", "model is required");
+    };
+    if embeddings_request.get("input").is_none() {
+        return openai_error(400, "invalid_request_error", "input is required");
+    }
+
+    let model = match map_model(&requested_model, config) {
+        model if model.starts_with("text-embedding-") => format!("openai/{model}"),
+        model => model,
+    };
+    embeddings_request["model"] = model.clone().into();
+
+    if let Some(record) = &caller.virtual_key {
+        if !record.allows_model(&model) {
+            return openai_error(
+                403,
+                "permission_error",
+                &format!("Model '{model}' is not allowed for this API key"),
+            );
+        }
+    }
+
+    // The input stands in for messages when estimating tokens for rate limits
+    let input = std::slice::from_ref(&embeddings_request["input"]);
+    let ledger = match admit(env, &caller, client_ip, input).await? {
+        Ok(ledger) => ledger,
+        Err(rejection) => return into_openai(rejection),
+    };
+
+    let url = format!("{}/embeddings", config.upstream_base_url(&location));
+    let api_key = caller.upstream_key(env, &model).await?;
+    let mut upstream = client.post(&url);
+    for (name, value) in upstream_headers(&api_key, &config.attribution) {
+        upstream = upstream.header(name, value);
+    }
+    let response = match upstream.json(&embeddings_request).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            report_error(
+                ctx,
+                &client,
+                config,
+                log,
+                ErrorReport::new(
+                    "upstream",
+                    format!("Request failed: {e}"),
+                    &model,
+                    log.request_id(),
+                    Date::now().as_millis(),
+                ),
+            );
+            return openai_error(502, "api_error", &format!("Request failed: {e}"));
+        }
+    };
+
+    let status = response.status();
+    let request_metrics = |status: u16| RequestMetrics {
+        model: model.clone(),
+        requested_model: requested_model.clone(),
+        key_hash: caller.key_hash.clone(),
+        status,
+        latency_ms: Date::now().as_millis() as f64 - start_time,
+        input_tokens: 0,
+        output_tokens: 0,
+        cost_usd: None,
+        prompt_cache_key: None,
+        cached_input_tokens: 0,
+    };
+
+    // Upstream errors are already in OpenAI format
+    if !response.is_success() {
+        record_metrics(
+            ctx,
+            env,
+            &client,
+            config,
+            request_metrics(status),
+            None,
+            log,
+        );
+        let error_text = response.text().await.unwrap_or_default();
+        report_error(
+            ctx,
+            &client,
+            config,
+            log,
+            ErrorReport::new(
+                "upstream",
+                format!("OpenRouter returned HTTP {status}"),
+                &model,
+                log.request_id(),
+                Date::now().as_millis(),
+            )
+            .with_status(status)
+            .with_body(&error_text),
+        );
+        let mut response = Response::ok(error_text)?.with_status(status);
+        response
+            .headers_mut()
+            .set("Content-Type", "application/json")?;
+        return Ok(response);
+    }
+
+    let embeddings: Value = response.json().await.map_err(|e| {
+        worker::Error::RustError(format!("Failed to parse embeddings response: {e}"))
+    })?;
+    let usage = openai_usage(&embeddings);
+    record_metrics(
+        ctx,
+        env,
+        &client,
+        config,
+        request_metrics(status),
+        usage.as_ref(),
+        log,
+    );
+    record_spend(ctx, &client, config, ledger, &model, usage, log);
+    Response::from_json(&embeddings)
+}
@@ -0,0 +1,221 @@
+use super::proxy::{
+    acquire_slot, admit, record_metrics, record_spend, report_error, Caller, Rejection,
+};
+use crate::attachments;
+use crate::config::Config;
+use crate::gemini::{self, Method};
+use crate::geo::RequestLocation;
+use crate::guardrails;
+use crate::http;
+use crate::log::Logger;
+use crate::metrics::RequestMetrics;
+use crate::reporting::ErrorReport;
+use crate::transform::openai_usage;
+use crate::utils::{map_model, percent_decode, upstream_headers};
+use serde_json::Value;
+use worker::{Context, Date, Env, Request, Response, Result};
+
+/// Handles POST requests to /v1beta/models/{model}:generateContent and :streamGenerateContent
+///
+/// Accepts Gemini-format requests so Gemini CLI and other Gemini API clients can
+/// share the deployment. Requests are translated to the OpenAI format and go
+/// through the same authentication, model mapping, key allowlists, rate limits and
+/// budgets as `/v1/messages`; the key may also be sent as `x-goog-api-key`.
+///
+/// `:streamGenerateContent` answers with the whole response as a single chunk,
+/// as server-sent events with `?alt=sse` and as a JSON array otherwise.
+pub async fn handle_generate_content(
+    mut req: Request,
+    path: &str,
+    env: &Env,
+    ctx: &Context,
+    caller: Caller<'_>,
+    log: &Logger,
+) -> Result<Response> {
+    let start_time = Date::now().as_millis() as f64;
+    let client = http::Client::new();
+
+    let Some((requested_model, method)) = gemini::parse_model_path(path) else {
+        return gemini_error(404, &format!("Unknown method in '{path}'"));
+    };
+    let requested_model = percent_decode(requested_model);
+    let sse = req
+        .url()?
+        .query_pairs()
+        .any(|(name, value)| name == "alt" && value == "sse");
+
+    let config: &Config = &caller.config;
+
+    let location = RequestLocation::from_request(&req);
+    let origin = req.url()?.origin().ascii_serialization();
+    let client_ip = req.headers().get("CF-Connecting-IP")?;
+
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return gemini_error(413, &message);
+    }
+    let gemini_request: Value = match serde_json::from_str(&body) {
+        Ok(value) => value,
+        Err(e) => return gemini_error(400, &e.to_string()),
+    };
+
+    let model = map_model(&requested_model, config);
+    let mut chat_request = match gemini::gemini_to_openai(&gemini_request, &model) {
+        Ok(chat_request) => chat_request,
+        Err(message) => return gemini_error(400, &message),
+    };
+
+    // Enforce the virtual key's model allowlist and max_tokens cap
+    if let Some(record) = &caller.virtual_key {
+        if !record.allows_model(&model) {
+            return gemini_error(
+                403,
+                &format!("Model '{model}' is not allowed for this API key"),
+            );
+        }
+        let requested = chat_request["max_tokens"]
+            .as_u64()
+            .map(|tokens| tokens as u32);
+        if let Some(capped) = record.cap_max_tokens(requested) {
+            chat_request["max_tokens"] = capped.into();
+        }
+    }
+
+    let messages = chat_request["messages"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let ledger = match admit(env, &caller, client_ip, &messages).await? {
+        Ok(ledger) => ledger,
+        Err(rejection) => return into_gemini(rejection),
+    };
+    // Counts against the key's requests in flight until the upstream answers
+    let _slot = match acquire_slot(env, ctx, &caller, log).await? {
+        Ok(slot) => slot,
+        Err(rejection) => return into_gemini(rejection),
+    };
+
+    attachments::offload_request(
+        env,
+        config.attachments.as_ref(),
+        &origin,
+        &mut chat_request,
+        log,
+    )
+    .await;
+
+    let url = format!("{}/chat/completions", config.upstream_base_url(&location));
+    let api_key = caller.upstream_key(env, &model).await?;
+    let mut upstream = client.post(&url);
+    for (name, value) in upstream_headers(&api_key, &config.attribution) {
+        upstream = upstream.header(name, value);
+    }
+    let response = match upstream.json(&chat_request).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            report_error(
+                ctx,
+                &client,
+                config,
+                log,
+                ErrorReport::new(
+                    "upstream",
+                    format!("Request failed: {e}"),
+                    &model,
+                    log.request_id(),
+                    Date::now().as_millis(),
+                ),
+            );
+            return gemini_error(502, &format!("Request failed: {e}"));
+        }
+    };
+
+    let status = response.status();
+    let request_metrics = |status: u16| RequestMetrics {
+        model: model.clone(),
+        requested_model: requested_model.clone(),
+        key_hash: caller.key_hash.clone(),
+        status,
+        latency_ms: Date::now().as_millis() as f64 - start_time,
+        input_tokens: 0,
+        output_tokens: 0,
+        cost_usd: None,
+        prompt_cache_key: None,
+        cached_input_tokens: 0,
+    };
+
+    if !response.is_success() {
+        record_metrics(
+            ctx,
+            env,
+            &client,
+            config,
+            request_metrics(status),
+            None,
+            log,
+        );
+        let error_text = response.text().await.unwrap_or_default();
+        report_error(
+            ctx,
+            &client,
+            config,
+            log,
+            ErrorReport::new(
+                "upstream",
+                format!("OpenRouter returned HTTP {status}"),
+                &model,
+                log.request_id(),
+                Date::now().as_millis(),
+            )
+            .with_status(status)
+            .with_body(&error_text),
+        );
+        let message = serde_json::from_str::<Value>(&error_text)
+            .ok()
+            .and_then(|error| error["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or(error_text);
+        return gemini_error(status, &message);
+    }
+
+    let completion: Value = response
+        .json()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Failed to parse OpenAI response: {e}")))?;
+    let usage = openai_usage(&completion);
+    record_metrics(
+        ctx,
+        env,
+        &client,
+        config,
+        request_metrics(status),
+        usage.as_ref(),
+        log,
+    );
+    record_spend(ctx, &client, config, ledger, &model, usage, log);
+
+    let gemini_response = gemini::openai_to_gemini(&completion, &model);
+    match method {
+        Method::GenerateContent => Response::from_json(&gemini_response),
+        Method::StreamGenerateContent if sse => {
+            let mut response = Response::ok(format!("data: {gemini_response}\n\n"))?;
+            response
+                .headers_mut()
+                .set("Content-Type", "text/event-stream")?;
+            Ok(response)
+        }
+        Method::StreamGenerateContent => Response::from_json(&[gemini_response]),
+    }
+}
+
+/// Builds a Gemini-format error response
+fn gemini_error(status: u16, message: &str) -> Result<Response> {
+    Ok(Response::from_json(&gemini::error_body(status, message))?.with_status(status))
+}
+
+pub(crate) fn into_gemini(rejection: Rejection) -> Result<Response> {
+    let mut response = gemini_error(rejection.status, &rejection.message)?;
+    for (name, value) in &rejection.headers {
+        response.headers_mut().set(name, value)?;
+    }
+    Ok(response)
+}
@@ -0,0 +1,192 @@
+use super::proxy::{
+    charge_spend, client_status, error_response, forward, Caller, Forwarded, SpendLedger,
+};
+use crate::async_jobs::{self, Job, JobRecord, KeySource};
+use crate::auth::virtual_keys;
+use crate::config::Config;
+use crate::deadline::{self, Budget};
+use crate::http;
+use crate::log::Logger;
+use crate::transform::completion_to_anthropic;
+use crate::upstream::FetchClient;
+use serde_json::json;
+use worker::{Date, Env, MessageBatch, MessageExt, Response, Result};
+
+/// Handles GET /v1/async/{id}: the status of a job, and its result once finished
+///
+/// Jobs are only visible to the caller that enqueued them; others get a 404, as
+/// for unknown or expired jobs.
+pub async fn poll(id: &str, env: &Env, caller: Caller<'_>) -> Result<Response> {
+    let not_found = || error_response(404, "not_found_error", &format!("async job: {id}"));
+    if !async_jobs::is_valid_id(id) {
+        return not_found();
+    }
+    let kv = env.kv(async_jobs::ASYNC_RESULTS_BINDING)?;
+    match async_jobs::lookup(&kv, id).await? {
+        Some(record) if record.is_owned_by(&caller.limit_subject()) => {
+            Response::from_json(&record.public())
+        }
+        _ => not_found(),
+    }
+}
+
+/// Runs queued jobs, storing each result for polling
+///
+/// A job whose result can't be stored is retried by the queue; a failed upstream
+/// request is a result like any other and is not.
+pub async fn consume(batch: MessageBatch<Job>, env: Env) -> Result<()> {
+    let config = Config::cached(&env)?;
+    let kv = env.kv(async_jobs::ASYNC_RESULTS_BINDING)?;
+    for message in batch.messages()? {
+        let job = message.body();
+        let log = Logger::new(job.request_id.clone());
+        let record = match async_jobs::lookup(&kv, &job.id).await {
+            Ok(Some(record)) => record,
+            // Expired before it ran: nobody can poll it any more
+            Ok(None) => {
+                message.ack();
+                continue;
+            }
+            Err(e) => {
+                log.warn("async job not read", &[("error", e.to_string().into())]);
+                message.retry();
+                continue;
+            }
+        };
+
+        let record = run(job, record, &env, &config, &log).await;
+        match async_jobs::store(&kv, &record, config.async_result_ttl_secs).await {
+            Ok(()) => message.ack(),
+            Err(e) => {
+                log.warn(
+                    "async result not stored",
+                    &[("error", e.to_string().into())],
+                );
+                message.retry();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The upstream key of a job, resolved as the request that enqueued it would
+///
+/// A virtual key revoked since is refused.
+async fn upstream_key(
+    source: &KeySource,
+    model: &str,
+    env: &Env,
+    config: &Config,
+) -> Result<Option<String>> {
+    if let KeySource::VirtualKey(key_hash) = source {
+        let kv = env.kv(virtual_keys::VIRTUAL_KEYS_BINDING)?;
+        match virtual_keys::lookup_hash(&kv, key_hash).await? {
+            Some(record) if !record.disabled => {
+                if let Some(upstream_key) = record.upstream_key {
+                    return Ok(Some(upstream_key));
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+    if let Some(credential) = config.model_keys.for_model(model) {
+        if let Some(api_key) = credential.resolve(env).await? {
+            return Ok(Some(api_key));
+        }
+    }
+    match &config.server_api_key {
+        Some(credential) => credential.resolve(env).await,
+        None => Ok(None),
+    }
+}
+
+/// Forwards a job's request upstream and records the outcome
+async fn run(job: &Job, record: JobRecord, env: &Env, config: &Config, log: &Logger) -> JobRecord {
+    let error = |kind: &str, message: &str| json!({"type": "error", "error": {"type": kind, "message": message}});
+    let model = &job.openai_request.model;
+    let api_key = match upstream_key(&job.key_source, model, env, config).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => {
+            let error = error(
+                "authentication_error",
+                "No upstream key is available for this job",
+            );
+            return record.fail(401, error, Date::now().as_millis());
+        }
+        Err(e) => {
+            let error = error("api_error", &e.to_string());
+            return record.fail(500, error, Date::now().as_millis());
+        }
+    };
+    let upstream = FetchClient::new()
+        .with_timeout(config.upstream_timeout_ms)
+        .with_attribution(config.attribution.clone());
+    // The hedge model may belong to a family with keys of its own
+    let hedge_key = match config.hedge_target(model) {
+        Some(hedge_model) => upstream_key(&job.key_source, hedge_model, env, config)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| api_key.clone()),
+        None => api_key.clone(),
+    };
+    let forwarded = forward(
+        &upstream,
+        &job.url,
+        &api_key,
+        &hedge_key,
+        &job.anthropic_request,
+        &job.openai_request,
+        config,
+        &Budget::new(
+            deadline::worker_now(),
+            config.slow_request_warn_ms,
+            deadline::worker_now,
+        ),
+        log,
+    )
+    .await;
+    let now = Date::now().as_millis();
+    let api_error = |message: &str| error("api_error", message);
+
+    let (status, error) = match forwarded {
+        Ok(Forwarded::Message {
+            openai_response,
+            usage,
+            hedge_model,
+            ..
+        }) => match completion_to_anthropic(openai_response, &job.anthropic_request.model)
+            .map_err(|e| e.to_string())
+            .and_then(|message| serde_json::to_value(message).map_err(|e| e.to_string()))
+        {
+            Ok(message) => {
+                if let (Some((subject, month)), Some(usage)) = (&job.ledger, &usage) {
+                    match SpendLedger::open(env, subject, month) {
+                        Ok(ledger) => {
+                            let client = http::Client::new();
+                            let model = hedge_model.as_deref().unwrap_or(&job.openai_request.model);
+                            let base_url = &config.openrouter_base_url;
+                            charge_spend(&client, base_url, &ledger, model, usage, log).await;
+                        }
+                        Err(e) => {
+                            log.warn("spend not recorded", &[("error", e.to_string().into())])
+                        }
+                    }
+                }
+                return record.complete(message, now);
+            }
+            Err(e) => (502, api_error(&e)),
+        },
+        Ok(Forwarded::Error { status, body, .. }) => (client_status(status), body),
+        Ok(Forwarded::Stream { .. }) => (500, api_error("Async jobs can't stream")),
+        Err(e) => (e.status.unwrap_or(502), api_error(&e.message)),
+    };
+    log.warn(
+        "async job failed",
+        &[
+            ("job_id", job.id.as_str().into()),
+            ("status", status.into()),
+        ],
+    );
+    record.fail(status, error, now)
+}
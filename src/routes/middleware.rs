@@ -0,0 +1,290 @@
+use super::chat::into_openai;
+use super::gemini::into_gemini;
+use super::proxy::{authenticate, Caller, Rejection};
+use super::router::Route;
+use crate::config::Config;
+use crate::cors;
+use crate::deadline::Budget;
+use crate::guardrails;
+use crate::http;
+use crate::log::Logger;
+use crate::maintenance;
+use futures::future::LocalBoxFuture;
+use worker::{Context, Env, Method, Request, Response, Result};
+
+/// Response header echoing the request ID used in logs
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Per-request state shared by the middleware and the route handler
+pub(crate) struct RequestContext<'a> {
+    pub route: Route,
+    pub env: &'a Env,
+    pub ctx: &'a Context,
+    pub config: &'a Config,
+    pub log: &'a Logger,
+    /// Time the request has left, created once the configuration is loaded
+    pub budget: Budget,
+    /// The authenticated caller, set by `Authenticate` on API routes
+    pub caller: Option<Caller<'a>>,
+    /// The request's `Origin` header, for CORS
+    pub origin: Option<String>,
+}
+
+impl<'a> RequestContext<'a> {
+    /// Hands the authenticated caller to the handler
+    pub fn take_caller(&mut self) -> Result<Caller<'a>> {
+        self.caller.take().ok_or_else(|| {
+            worker::Error::RustError(format!("{:?} was dispatched unauthenticated", self.route))
+        })
+    }
+}
+
+/// A cross-cutting step run around every route handler
+///
+/// `before` hooks run in chain order and may answer the request themselves,
+/// skipping the rest of the chain and the handler. `after` hooks run in reverse
+/// order on every response, including those from a short-circuiting `before`.
+pub(crate) trait Middleware {
+    fn before<'r, 'a: 'r>(
+        &'r self,
+        _req: &'r Request,
+        _cx: &'r mut RequestContext<'a>,
+    ) -> LocalBoxFuture<'r, Result<Option<Response>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn after(&self, _cx: &RequestContext, response: Response) -> Result<Response> {
+        Ok(response)
+    }
+}
+
+/// The middleware chain, outermost first
+pub(crate) struct Pipeline {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    /// Request logging, request IDs, CORS, maintenance mode, authentication, then
+    /// request validation
+    ///
+    /// Rate limits and budgets are applied by the handlers through
+    /// `proxy::admit`, since their token estimates need the parsed body.
+    pub fn standard() -> Self {
+        Pipeline {
+            middleware: vec![
+                Box::new(RequestLog),
+                Box::new(RequestId),
+                Box::new(Cors),
+                Box::new(Maintenance),
+                Box::new(Authenticate),
+                Box::new(BodyLimit),
+            ],
+        }
+    }
+
+    /// Runs the `before` hooks, returning the response of one that short-circuits
+    pub async fn before(
+        &self,
+        req: &Request,
+        cx: &mut RequestContext<'_>,
+    ) -> Result<Option<Response>> {
+        for middleware in &self.middleware {
+            if let Some(response) = middleware.before(req, cx).await? {
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs the `after` hooks, innermost first
+    pub fn after(&self, cx: &RequestContext, mut response: Response) -> Result<Response> {
+        for middleware in self.middleware.iter().rev() {
+            response = middleware.after(cx, response)?;
+        }
+        Ok(response)
+    }
+}
+
+/// Renders a rejection in the error format of the route's API
+fn reject(route: &Route, rejection: Rejection) -> Result<Response> {
+    match route {
+        Route::ChatCompletions | Route::Embeddings => into_openai(rejection),
+        Route::Gemini(_) => into_gemini(rejection),
+        _ => rejection.into_anthropic(),
+    }
+}
+
+/// Logs each request's route, status and latency once it is answered
+struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn after(&self, cx: &RequestContext, response: Response) -> Result<Response> {
+        cx.log.info(
+            "request completed",
+            &[
+                ("route", format!("{:?}", cx.route).into()),
+                ("status", response.status_code().into()),
+                ("latency_ms", cx.budget.elapsed_ms().into()),
+            ],
+        );
+        Ok(response)
+    }
+}
+
+/// Returns the request ID so clients can quote it when reporting problems
+struct RequestId;
+
+impl Middleware for RequestId {
+    fn after(&self, cx: &RequestContext, mut response: Response) -> Result<Response> {
+        response
+            .headers_mut()
+            .set(REQUEST_ID_HEADER, cx.log.request_id())?;
+        Ok(response)
+    }
+}
+
+/// Answers `OPTIONS` preflights and lets allowed browser origins read `/v1` responses
+///
+/// Every `OPTIONS` request is answered here, so it never reaches authentication
+/// or a handler; origins outside `CORS_ALLOWED_ORIGINS` get no CORS headers and
+/// are blocked by the browser.
+struct Cors;
+
+impl Cors {
+    fn allow_origin(cx: &RequestContext) -> Option<String> {
+        if !cx.route.is_cross_origin() {
+            return None;
+        }
+        cors::allow_origin(&cx.config.cors_allowed_origins, cx.origin.as_deref()?)
+    }
+}
+
+impl Middleware for Cors {
+    fn before<'r, 'a: 'r>(
+        &'r self,
+        req: &'r Request,
+        cx: &'r mut RequestContext<'a>,
+    ) -> LocalBoxFuture<'r, Result<Option<Response>>> {
+        Box::pin(async move {
+            if req.method() != Method::Options {
+                return Ok(None);
+            }
+            let method = cx.route.method().to_string();
+            let mut response = Response::empty()?.with_status(204);
+            response
+                .headers_mut()
+                .set("Allow", &format!("{method}, OPTIONS"))?;
+            if let Some(allow_origin) = Self::allow_origin(cx) {
+                let requested_headers = req.headers().get("Access-Control-Request-Headers")?;
+                for (name, value) in
+                    cors::preflight_headers(&allow_origin, &method, requested_headers.as_deref())
+                {
+                    response.headers_mut().set(name, &value)?;
+                }
+            }
+            Ok(Some(response))
+        })
+    }
+
+    fn after(&self, cx: &RequestContext, mut response: Response) -> Result<Response> {
+        // Preflight responses already carry their headers
+        if response.headers().has("Access-Control-Allow-Origin")? {
+            return Ok(response);
+        }
+        if let Some(allow_origin) = Self::allow_origin(cx) {
+            for (name, value) in cors::response_headers(&allow_origin) {
+                response.headers_mut().set(name, &value)?;
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Turns model API requests away while a maintenance notice is stored
+///
+/// Runs before authentication, so drained requests cost no key lookups.
+struct Maintenance;
+
+impl Middleware for Maintenance {
+    fn before<'r, 'a: 'r>(
+        &'r self,
+        _req: &'r Request,
+        cx: &'r mut RequestContext<'a>,
+    ) -> LocalBoxFuture<'r, Result<Option<Response>>> {
+        Box::pin(async move {
+            if !cx.route.is_api() {
+                return Ok(None);
+            }
+            let Some(notice) = maintenance::load(cx.env, cx.log).await else {
+                return Ok(None);
+            };
+            let mut rejection = Rejection::new(503, "api_error", notice.error_message());
+            rejection.headers.push((
+                "retry-after".to_string(),
+                notice.retry_after_secs.to_string(),
+            ));
+            reject(&cx.route, rejection).map(Some)
+        })
+    }
+}
+
+/// Identifies the caller of API routes, applying access control and profiles
+struct Authenticate;
+
+impl Middleware for Authenticate {
+    fn before<'r, 'a: 'r>(
+        &'r self,
+        req: &'r Request,
+        cx: &'r mut RequestContext<'a>,
+    ) -> LocalBoxFuture<'r, Result<Option<Response>>> {
+        Box::pin(async move {
+            if !cx.route.is_authenticated() {
+                return Ok(None);
+            }
+            let client = http::Client::new();
+            match authenticate(req, cx.env, cx.config, &client).await? {
+                Ok(mut caller) => {
+                    caller.resolve_features(cx.env, cx.log).await;
+                    caller.restrict_models();
+                    cx.caller = Some(caller);
+                    Ok(None)
+                }
+                Err(rejection) => reject(&cx.route, rejection).map(Some),
+            }
+        })
+    }
+}
+
+/// Rejects oversized API requests from their `Content-Length` before the body is read
+///
+/// Handlers check the body again once read, for clients that don't declare a length.
+struct BodyLimit;
+
+impl Middleware for BodyLimit {
+    fn before<'r, 'a: 'r>(
+        &'r self,
+        req: &'r Request,
+        cx: &'r mut RequestContext<'a>,
+    ) -> LocalBoxFuture<'r, Result<Option<Response>>> {
+        Box::pin(async move {
+            let Some(caller) = &cx.caller else {
+                return Ok(None);
+            };
+            let declared_length = req
+                .headers()
+                .get("Content-Length")?
+                .and_then(|length| length.parse::<usize>().ok());
+            let Some(length) = declared_length else {
+                return Ok(None);
+            };
+            match guardrails::check_body_size(&caller.config.request_limits, length) {
+                Ok(()) => Ok(None),
+                Err(message) => reject(
+                    &cx.route,
+                    Rejection::new(413, "invalid_request_error", message),
+                )
+                .map(Some),
+            }
+        })
+    }
+}
@@ -1,2 +1,15 @@
+pub mod admin;
+pub mod chat;
+pub mod compare;
+pub mod convert;
+pub mod cron;
+pub mod embeddings;
+pub mod gemini;
+pub mod jobs;
+pub mod middleware;
+pub mod models;
 pub mod proxy;
+pub mod replay;
+pub mod router;
+pub mod sessions;
 pub mod static_pages;
@@ -0,0 +1,77 @@
+use super::proxy::error_response;
+use crate::catalog::{self, PageParams};
+use crate::config::Config;
+use crate::http;
+use crate::utils::{map_model, percent_decode};
+use worker::{Env, Request, Response, Result};
+
+/// Handles GET /v1/models
+///
+/// Lists the OpenRouter catalog in the Anthropic models-list shape, paginated
+/// with `before_id`, `after_id` and `limit`. The catalog is public, so no key is
+/// needed.
+pub async fn list(req: Request, env: &Env, config: &Config) -> Result<Response> {
+    let url = req.url()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let limit = match param("limit") {
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(limit) if (1..=catalog::MAX_PAGE_LIMIT).contains(&limit) => Some(limit),
+            _ => {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    &format!("limit must be between 1 and {}", catalog::MAX_PAGE_LIMIT),
+                )
+            }
+        },
+        None => None,
+    };
+    let params = PageParams {
+        before_id: param("before_id"),
+        after_id: param("after_id"),
+        limit,
+    };
+
+    let models = match fetch_models(env, config).await {
+        Ok(models) => models,
+        Err(e) => return error_response(502, "api_error", &e.to_string()),
+    };
+    Response::from_json(&catalog::paginate(models, &params))
+}
+
+/// Handles GET /v1/models/{id}
+///
+/// OpenRouter IDs contain a slash, so `id` is the rest of the path. Claude short
+/// names resolve to the model they are mapped to.
+pub async fn retrieve(id: &str, env: &Env, config: &Config) -> Result<Response> {
+    let id = percent_decode(id);
+    let models = match fetch_models(env, config).await {
+        Ok(models) => models,
+        Err(e) => return error_response(502, "api_error", &e.to_string()),
+    };
+
+    let mapped = map_model(&id, config);
+    match models
+        .iter()
+        .find(|model| model.id == id)
+        .or_else(|| models.iter().find(|model| model.id == mapped))
+    {
+        Some(model) => Response::from_json(model),
+        None => error_response(404, "not_found_error", &format!("model: {id}")),
+    }
+}
+
+async fn fetch_models(env: &Env, config: &Config) -> Result<Vec<catalog::ModelInfo>> {
+    let kv = env.kv(catalog::CATALOG_BINDING).ok();
+    let catalog = catalog::cached(
+        &http::Client::new(),
+        &config.openrouter_base_url,
+        kv.as_ref(),
+    )
+    .await?;
+    Ok(catalog::anthropic_models(&catalog))
+}
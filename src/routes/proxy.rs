@@ -1,169 +1,2596 @@
-use crate::config::Config;
-use crate::models::AnthropicRequest;
-use crate::transform::{anthropic_to_openai, openai_to_anthropic, stream_openai_to_anthropic};
-use worker::{Date, Request, Response, Result};
+use crate::admission::{self, Priority};
+use crate::alerts::{self, Observation};
+use crate::api_version::{self, ApiVersion};
+use crate::async_jobs::{self, Job, JobRecord, KeySource};
+use crate::auth;
+use crate::auth::jwt::{self, Claims};
+use crate::auth::virtual_keys::{self, VirtualKey};
+use crate::budget;
+use crate::catalog;
+use crate::compression;
+use crate::concurrency::{self, Slot};
+use crate::config::{Config, Credential, ErrorVerbosity};
+use crate::context_recovery::{self, ContextRecovery};
+use crate::deadline::Budget;
+use crate::features;
+use crate::gemini;
+use crate::geo::RequestLocation;
+use crate::guardrails;
+use crate::http;
+use crate::idempotency::{self, Lookup, StoredResponse};
+use crate::injection::{self, InjectionMode};
+use crate::key_pool;
+use crate::ledger::{self, LedgerRow};
+use crate::log::{self, Logger};
+use crate::metrics::{self, RequestMetrics};
+use crate::model_route;
+use crate::models::{AnthropicRequest, OpenAICompletion, OpenAIRequest, Usage};
+use crate::moderation::{self, ModerationAction};
+use crate::pricing;
+use crate::profiles::{self, Profile};
+use crate::prompt_cache;
+use crate::rate_limit::{self, Limits, RateLimitDecision};
+use crate::reporting::{self, ErrorReport};
+use crate::response_cache::{self, CachedResponse};
+use crate::retry::RetryPolicy;
+use crate::seed;
+use crate::semantic_cache::{self, SemanticCache};
+use crate::session_model;
+use crate::sessions;
+use crate::shadow;
+use crate::tail::{self, RequestSummary};
+use crate::transcripts::{self, TranscriptMode};
+use crate::transform::{
+    anthropic_to_openai_routed, check_translatable, completion_to_anthropic, error_events,
+    event_stream_response, route_model, stream_openai_to_anthropic_profiled, text_message_events,
+    usage_counts, web_search_plugin, StreamProfile,
+};
+use crate::upstream::{UpstreamClient, UpstreamResponse};
+use crate::utils::upstream_headers;
+use crate::vision::{self, ImageFallback};
+use futures::future::{select, Either};
+use std::borrow::Cow;
+use std::time::Duration;
+use worker::kv::KvStore;
+use worker::{Context, Date, Delay, Env, ObjectNamespace, Request, Response, Result, Stub};
+
+/// Request header that asks for a dry run (`x-ccr-debug: transform`)
+const DEBUG_HEADER: &str = "x-ccr-debug";
+
+/// Response header naming the `:free` model that answered after a 402
+const FREE_FALLBACK_HEADER: &str = "x-ccr-free-fallback";
 
 /// Handles POST requests to /v1/messages endpoint
 ///
-/// This function acts as the core proxy logic:
-/// 1. Receives Anthropic-formatted request from client
-/// 2. Transforms it to OpenAI format
-/// 3. Forwards to OpenRouter API
-/// 4. Transforms response back to Anthropic format
-/// 5. Returns to client
-pub async fn handle_messages(mut req: Request, config: &Config) -> Result<Response> {
-    let start_time = Date::now().as_millis() as f64;
-
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("🎯 handle_messages started at: {}", start_time).into());
-
-    let check_time = |_step: &str| {
-        let current_time = Date::now().as_millis() as f64;
-        let elapsed = current_time - start_time;
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&format!("⏱️  {}: {}ms", _step, elapsed).into());
-        elapsed
-    };
-    // Extract API key from multiple possible headers
-    let _elapsed = check_time("API key extraction start");
-    let api_key = if let Some(x_api_key) = req.headers().get("x-api-key")? {
-        x_api_key.to_string()
-    } else if let Some(auth_header) = req.headers().get("Authorization")? {
-        auth_header
-            .strip_prefix("Bearer ")
-            .ok_or_else(|| {
-                worker::Error::RustError("Invalid Authorization header format".to_string())
-            })?
-            .to_string()
-    } else {
-        return Response::error("No API key found in x-api-key or Authorization header", 401);
+/// This function acts as the core proxy logic:
+/// 1. Receives Anthropic-formatted request from client
+/// 2. Transforms it to OpenAI format
+/// 3. Forwards to OpenRouter API
+/// 4. Transforms response back to Anthropic format
+/// 5. Returns to client
+///
+/// A sampled share of non-streaming requests is also mirrored to the shadow model
+/// in the background (see [`crate::shadow`]).
+///
+/// With `x-ccr-debug: transform` or `?dry_run=1`, the upstream call is skipped and
+/// the request CCR would send is returned instead, with the key redacted.
+///
+/// Upstream calls go through `upstream`; see [`forward`] for the part of this path
+/// that is tested against a mock client.
+pub async fn handle_messages<C: UpstreamClient>(
+    mut req: Request,
+    env: &Env,
+    ctx: &Context,
+    caller: Caller<'_>,
+    upstream: &C,
+    budget: &Budget,
+    log: &Logger,
+) -> Result<Response> {
+    // HTTP client for background reporting (timeout handled by Cloudflare Workers runtime)
+    let client = http::Client::new();
+
+    let config: &Config = &caller.config;
+
+    // Capture where the request came from before the body is consumed
+    let location = RequestLocation::from_request(&req);
+    let client_ip = req.headers().get("CF-Connecting-IP")?;
+    let dry_run = req
+        .headers()
+        .get(DEBUG_HEADER)?
+        .is_some_and(|value| value.eq_ignore_ascii_case("transform"))
+        || req
+            .url()?
+            .query_pairs()
+            .any(|(name, value)| name == "dry_run" && (value == "1" || value == "true"));
+    let idempotency_key = req
+        .headers()
+        .get(idempotency::IDEMPOTENCY_HEADER)?
+        .filter(|key| !key.trim().is_empty());
+    let cache_bypass = req
+        .headers()
+        .get(response_cache::CACHE_HEADER)?
+        .is_some_and(|value| value.eq_ignore_ascii_case("bypass"));
+    let run_async = req
+        .headers()
+        .get(async_jobs::ASYNC_HEADER)?
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    let session_id = req.headers().get(sessions::SESSION_HEADER)?;
+    let save_transcript = req
+        .headers()
+        .get(transcripts::sessions::TRANSCRIPT_HEADER)?
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+        || caller
+            .virtual_key
+            .as_ref()
+            .is_some_and(|key| key.save_transcripts);
+    let model_header = req
+        .headers()
+        .get(session_model::MODEL_HEADER)?
+        .map(|model| model.trim().to_string())
+        .filter(|model| !model.is_empty());
+    let priority_header = req.headers().get(admission::PRIORITY_HEADER)?;
+    let seed_header = req.headers().get(seed::SEED_HEADER)?;
+    // Signed bodies go out as signed, so clients can check the bytes they read
+    let encoding = compression::negotiate(req.headers().get("Accept-Encoding")?.as_deref())
+        .filter(|_| config.response_signing_key.is_none());
+    let version =
+        match ApiVersion::from_header(req.headers().get(api_version::VERSION_HEADER)?.as_deref()) {
+            Ok(version) => version,
+            Err(message) => return error_response(400, "invalid_request_error", &message),
+        };
+
+    // Parse incoming Anthropic-formatted request
+    let _elapsed = budget.check("Request parsing start", log);
+    let body = req.text().await?;
+    if let Err(message) = guardrails::check_body_size(&config.request_limits, body.len()) {
+        return error_response(413, "invalid_request_error", &message);
+    }
+    let mut anthropic_request: AnthropicRequest = serde_json::from_str(&body)?;
+    let seed = match seed::request_seed(seed_header.as_deref(), anthropic_request.metadata.as_ref())
+    {
+        Ok(seed) => seed,
+        Err(message) => return error_response(400, "invalid_request_error", &message),
+    };
+
+    // Stored conversations belong to one person, so callers sharing the server key
+    // or access token can't keep them
+    let personal_subject = caller.personal_subject();
+    if personal_subject.is_none() && (save_transcript || session_id.is_some()) {
+        return error_response(
+            403,
+            "permission_error",
+            "Saved transcripts and x-ccr-session need a virtual key, single sign-on or your own API key",
+        );
+    }
+
+    let conversation =
+        session_model::conversation_id(session_id.as_deref(), anthropic_request.metadata.as_ref());
+    // Opted-in conversations are saved thread by thread for export
+    let transcript = conversation.clone().filter(|_| save_transcript).map(|id| {
+        let thread =
+            transcripts::sessions::thread_id(session_id.is_some(), &anthropic_request.messages);
+        (id, thread)
+    });
+
+    // Session requests carry only the newest turn; earlier ones come from the
+    // session's stored history
+    let session = match session_id {
+        Some(id) => {
+            if !sessions::is_valid_id(&id) {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    "x-ccr-session must be 1-128 letters, digits, '-', '_', '.' or ':'",
+                );
+            }
+            if run_async {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    "x-ccr-session can't be used with x-ccr-async",
+                );
+            }
+            let Ok(namespace) = env.durable_object(sessions::SESSIONS_BINDING) else {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    "Sessions are not enabled on this deployment",
+                );
+            };
+            let subject = personal_subject.as_deref().unwrap_or_default();
+            let stub = sessions::session_stub(&namespace, subject, &id)?;
+            let new_messages = std::mem::take(&mut anthropic_request.messages);
+            let mut messages = sessions::history(&stub).await?;
+            messages.extend(new_messages.iter().cloned());
+            anthropic_request.messages = sessions::truncate(&messages, config.session_max_messages);
+            Some((stub, new_messages))
+        }
+        None => None,
+    };
+
+    // A model chosen with x-ccr-model or a `/model` directive sticks to the
+    // conversation when there's somewhere to remember it
+    let directive = session_model::directive(&anthropic_request);
+    let chosen_model = model_header.or_else(|| directive.clone());
+    let models_namespace = env.durable_object(session_model::SESSION_MODELS_BINDING);
+    let remembered = match (models_namespace, &conversation) {
+        (Ok(namespace), Some(id)) => {
+            let stub = sessions::session_stub(&namespace, &caller.limit_subject(), id)?;
+            match &chosen_model {
+                Some(model) => {
+                    session_model::remember(&stub, model, config.session_ttl_secs).await?
+                }
+                None => {
+                    if let Some(model) =
+                        session_model::recall(&stub, config.session_ttl_secs).await?
+                    {
+                        log.debug("session model applied", &[("model", model.as_str().into())]);
+                        anthropic_request.model = model;
+                    }
+                }
+            }
+            true
+        }
+        _ => false,
+    };
+    if let Some(model) = chosen_model.filter(|model| model != session_model::DEFAULT_MODEL) {
+        anthropic_request.model = model;
+    }
+    if let Some(model) = directive {
+        let text = session_model::acknowledgement(&model, remembered);
+        return directive_response(&anthropic_request, &text, log);
+    }
+
+    if let Err(message) = guardrails::check_request(&config.request_limits, &anthropic_request)
+        .and_then(|()| check_translatable(&anthropic_request, config))
+    {
+        return error_response(400, "invalid_request_error", &message);
+    }
+    let _elapsed = budget.check("Request parsing complete", log);
+
+    // Personal data is masked before the request is logged, cached or sent upstream;
+    // the placeholders the model repeats are swapped back in its response
+    let masked = config
+        .pii
+        .as_ref()
+        .map(|redactor| redactor.redact_request(&mut anthropic_request))
+        .filter(|placeholders| !placeholders.is_empty());
+    if let Some(placeholders) = &masked {
+        log.debug("request masked", &[("values", placeholders.len().into())]);
+    }
+    let restore = masked
+        .as_ref()
+        .filter(|_| config.pii.as_ref().is_some_and(|redactor| redactor.restore));
+
+    // Tool results carry untrusted web and file content into the conversation
+    let neutralize = config.prompt_injection == InjectionMode::Neutralize;
+    let injection_found = config.prompt_injection != InjectionMode::Off && {
+        let findings = injection::scan_request(&mut anthropic_request, neutralize);
+        if !findings.is_empty() {
+            log.warn(
+                "possible prompt injection in tool results",
+                &[
+                    ("matches", findings.matches.into()),
+                    ("tool_use_ids", findings.tool_use_ids.join(",").into()),
+                    ("neutralized", neutralize.into()),
+                ],
+            );
+        }
+        !findings.is_empty()
+    };
+
+    log.debug(
+        "request parsed",
+        &[
+            ("model", anthropic_request.model.as_str().into()),
+            ("message_count", anthropic_request.messages.len().into()),
+            ("stream", anthropic_request.stream.unwrap_or(false).into()),
+        ],
+    );
+
+    // Transform to OpenAI format for OpenRouter API
+    let _elapsed = budget.check("Transform start", log);
+    let mut route = route_model(&anthropic_request, config);
+    let mut openai_request = match anthropic_to_openai_routed(&anthropic_request, &route, config) {
+        Ok(openai_request) => openai_request,
+        Err(e) => {
+            report_error(
+                ctx,
+                &client,
+                config,
+                log,
+                ErrorReport::new(
+                    "transform",
+                    e.to_string(),
+                    &anthropic_request.model,
+                    log.request_id(),
+                    Date::now().as_millis(),
+                ),
+            );
+            if anthropic_request.stream.unwrap_or(false) {
+                return stream_error_response("api_error", &e.to_string());
+            }
+            return Err(e.into());
+        }
+    };
+    let _elapsed = budget.check("Transform complete", log);
+    let priority = match Priority::classify(priority_header.as_deref(), &route) {
+        Ok(priority) => priority,
+        Err(message) => return error_response(400, "invalid_request_error", &message),
+    };
+    openai_request.seed = seed;
+    if config.web_search && openai_request.plugins.is_none() {
+        openai_request.plugins = Some(vec![web_search_plugin()]);
+    }
+
+    // Keep a session's turns on the same upstream prompt cache
+    let prompt_cache_key = if config.prompt_cache_hints {
+        prompt_cache::apply(&anthropic_request, &mut openai_request)
+    } else {
+        None
+    };
+
+    // Enforce the virtual key's model allowlist and max_tokens cap
+    if let Some(record) = &caller.virtual_key {
+        if !record.allows_model(&openai_request.model) {
+            return error_response(
+                403,
+                "permission_error",
+                &format!(
+                    "Model '{}' is not allowed for this API key",
+                    openai_request.model
+                ),
+            );
+        }
+        openai_request.max_tokens = record.cap_max_tokens(openai_request.max_tokens);
+    }
+
+    // Dry runs stop here, before anything is counted against limits or spent
+    if dry_run {
+        let url = format!("{}/chat/completions", config.upstream_base_url(&location));
+        let headers: serde_json::Map<String, serde_json::Value> =
+            upstream_headers("[REDACTED]", &config.attribution)
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.into()))
+                .collect();
+        return Response::from_json(&serde_json::json!({
+            "dry_run": true,
+            "method": "POST",
+            "url": url,
+            "headers": headers,
+            "body": openai_request,
+            "hedge_model": config.hedge_target(&openai_request.model),
+        }));
+    }
+
+    // Prompts violating the moderation policy are refused or flagged before anything
+    // is served or spent; a failing classifier lets requests through
+    if let Some(policy) = &config.moderation {
+        if let Some(text) = moderation::moderated_text(&anthropic_request) {
+            match moderation::classify(&policy.provider, env, &text).await {
+                Ok(verdict) => {
+                    let violations = policy.violations(&verdict);
+                    if !violations.is_empty() {
+                        log.warn(
+                            "request flagged by moderation",
+                            &[("categories", violations.join(",").into())],
+                        );
+                        if policy.action == ModerationAction::Block {
+                            return refusal_response(&anthropic_request, log);
+                        }
+                    }
+                }
+                Err(e) => log.warn("moderation unavailable", &[("error", e.to_string().into())]),
+            }
+        }
+    }
+
+    let key_hash = caller.key_hash.clone();
+    let limit_subject = caller.limit_subject();
+
+    // Retries with the same Idempotency-Key replay the stored response instead of
+    // spending tokens again; only non-streaming responses are stored
+    let idempotency = match idempotency_key {
+        Some(key) if !anthropic_request.stream.unwrap_or(false) => env
+            .kv(idempotency::IDEMPOTENCY_BINDING)
+            .ok()
+            .map(|kv| (kv, idempotency::storage_key(&limit_subject, &key))),
+        _ => None,
+    };
+    let request_hash = auth::hash_key(&body);
+    if let Some((kv, storage_key)) = &idempotency {
+        match idempotency::lookup(kv, storage_key, &request_hash).await? {
+            Lookup::Miss => {}
+            Lookup::Replay(stored) => {
+                let mut response = Response::from_json(&stored)?;
+                response
+                    .headers_mut()
+                    .set(idempotency::REPLAYED_HEADER, "true")?;
+                return Ok(response);
+            }
+            Lookup::Conflict => {
+                return error_response(
+                    400,
+                    "invalid_request_error",
+                    "Idempotency-Key was already used for a different request body",
+                )
+            }
+        }
+    }
+
+    // Deterministic requests identical to an earlier one reuse its response; a
+    // bypassed lookup still refreshes the cached entry
+    let cache = match env.kv(response_cache::RESPONSE_CACHE_BINDING) {
+        Ok(kv) if response_cache::is_cacheable(&openai_request) => Some((
+            kv,
+            response_cache::storage_key(&limit_subject, &openai_request)?,
+        )),
+        _ => None,
+    };
+    if let Some((kv, storage_key)) = cache.as_ref().filter(|_| !cache_bypass) {
+        match response_cache::lookup(kv, storage_key).await {
+            Ok(Some(cached)) => return cached.into_response("hit"),
+            Ok(None) => {}
+            Err(e) => log.warn(
+                "response cache unavailable",
+                &[("error", e.to_string().into())],
+            ),
+        }
+    }
+
+    // Housekeeping requests close enough to an earlier prompt reuse its response
+    let semantic = match SemanticCache::from_env(env) {
+        Some(semantic) if config.semantic_cache_applies(&anthropic_request.model) => {
+            match semantic.entry(&limit_subject, &openai_request).await {
+                Ok(entry) => Some((semantic, entry)),
+                Err(e) => {
+                    log.warn("prompt not embedded", &[("error", e.to_string().into())]);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    if let Some((semantic, entry)) = semantic.as_ref().filter(|_| !cache_bypass) {
+        match semantic
+            .lookup(entry, config.semantic_cache.threshold)
+            .await
+        {
+            Ok(Some(cached)) => return cached.into_response("semantic"),
+            Ok(None) => {}
+            Err(e) => log.warn(
+                "semantic cache unavailable",
+                &[("error", e.to_string().into())],
+            ),
+        }
+    }
+
+    // Rate limits and budget, checked before anything is spent upstream
+    let ledger = match admit(env, &caller, client_ip, &openai_request.messages).await? {
+        Ok(ledger) => ledger,
+        Err(rejection) => return rejection.into_anthropic(),
+    };
+
+    // Minimal debug logging
+    // Regional upstreams keep traffic close to (or resident with) the caller
+    let url = format!("{}/chat/completions", config.upstream_base_url(&location));
+
+    // Asynchronous requests are run by the queue consumer and polled for
+    if run_async {
+        if anthropic_request.stream.unwrap_or(false) {
+            return error_response(
+                400,
+                "invalid_request_error",
+                "x-ccr-async can't be used with stream: true",
+            );
+        }
+        // The consumer looks the key up again, so only keys CCR holds can be queued
+        let key_source = if caller.uses_server_key {
+            KeySource::Server
+        } else if caller.virtual_key.is_some() {
+            KeySource::VirtualKey(caller.key_hash.clone())
+        } else {
+            return error_response(
+                400,
+                "invalid_request_error",
+                "x-ccr-async needs a virtual key or the deployment's upstream key",
+            );
+        };
+        let (Ok(queue), Ok(kv)) = (
+            env.queue(async_jobs::ASYNC_QUEUE_BINDING),
+            env.kv(async_jobs::ASYNC_RESULTS_BINDING),
+        ) else {
+            return error_response(
+                400,
+                "invalid_request_error",
+                "Asynchronous requests are not enabled on this deployment",
+            );
+        };
+        fallback_images(
+            env,
+            ctx,
+            upstream,
+            &caller,
+            &url,
+            ledger.as_ref(),
+            &route,
+            &mut anthropic_request,
+            &mut openai_request,
+            log,
+        )
+        .await?;
+        let now = Date::now().as_millis();
+        let id = async_jobs::job_id(&limit_subject, log.request_id(), now);
+        let record = JobRecord::queued(&id, &limit_subject, now);
+        async_jobs::store(&kv, &record, config.async_result_ttl_secs).await?;
+        queue
+            .send(Job {
+                id: id.clone(),
+                request_id: log.request_id().to_string(),
+                url,
+                key_source,
+                anthropic_request,
+                openai_request,
+                ledger: ledger.map(|ledger| (ledger.subject, ledger.month)),
+            })
+            .await?;
+        let mut response = Response::from_json(&record.public())?.with_status(202);
+        response
+            .headers_mut()
+            .set("Location", &format!("{}{id}", async_jobs::POLL_PATH))?;
+        return Ok(response);
+    }
+
+    // Under load the isolate keeps its headroom for interactive requests
+    let Some(ticket) = admission::admit(priority, config.rate_limits.admission_background_limit)
+    else {
+        log.info(
+            "background request shed",
+            &[("in_flight", admission::in_flight().into())],
+        );
+        let mut rejection = Rejection::new(
+            429,
+            "rate_limit_error",
+            "The proxy is under load and is deferring background requests; retry shortly",
+        );
+        rejection
+            .headers
+            .push(("retry-after".to_string(), "1".to_string()));
+        return rejection.into_anthropic();
+    };
+
+    // Cap the caller's requests in flight, so one runaway agent can't tie up a
+    // shared upstream key
+    let slot = match acquire_slot(env, ctx, &caller, log).await? {
+        Ok(slot) => slot,
+        Err(rejection) => return rejection.into_anthropic(),
+    };
+
+    let images_replaced = fallback_images(
+        env,
+        ctx,
+        upstream,
+        &caller,
+        &url,
+        ledger.as_ref(),
+        &route,
+        &mut anthropic_request,
+        &mut openai_request,
+        log,
+    )
+    .await?;
+
+    // Request shape only; message content and keys are redacted by the logger
+    if log.enabled(log::Level::Trace) && budget.verbose() {
+        log.trace(
+            "upstream request",
+            &[
+                ("url", url.as_str().into()),
+                ("model", openai_request.model.as_str().into()),
+                ("message_count", openai_request.messages.len().into()),
+                (
+                    "tool_count",
+                    openai_request.tools.as_ref().map_or(0, Vec::len).into(),
+                ),
+                ("max_tokens", openai_request.max_tokens.into()),
+            ],
+        );
+    }
+
+    // Send request to OpenRouter API, hedging against a secondary model if configured
+    let _elapsed = budget.check("HTTP request start", log);
+
+    // Model families with keys of their own (MODEL_KEYS) use them in place of the
+    // server key. A pooled key that is rate limited or out of credit is benched,
+    // and the request moves on to the next key in the pool that isn't
+    let mut api_key = caller.upstream_key(env, &openai_request.model).await?;
+    let mut hedge_key = caller.hedge_key(env, &openai_request.model).await?;
+    let mut pool = caller.key_pool(&openai_request.model, &api_key);
+    let mut free_model = None;
+    let mut context_recovered = None;
+    let forwarded = loop {
+        let forwarded = forward(
+            upstream,
+            &url,
+            &api_key,
+            &hedge_key,
+            &anthropic_request,
+            &openai_request,
+            config,
+            budget,
+            log,
+        )
+        .await;
+        let next_key = match &forwarded {
+            Ok(Forwarded::Error { status, .. })
+                if key_pool::should_bench(*status) && pool.contains(&api_key) =>
+            {
+                key_pool::fail_over(
+                    pool,
+                    &api_key,
+                    Date::now().as_millis(),
+                    config.key_cooldown_secs.saturating_mul(1000),
+                )
+            }
+            _ => None,
+        };
+        if let Some(next_key) = next_key {
+            log.warn(
+                "upstream key benched",
+                &[("key_hash", auth::hash_key(&api_key).into())],
+            );
+            api_key = next_key;
+            continue;
+        }
+
+        // Out of credit: try once more on the model's free variant, if it has one
+        let out_of_credit = matches!(&forwarded, Ok(Forwarded::Error { status: 402, .. }));
+        if out_of_credit && config.free_fallback && free_model.is_none() {
+            let prices_kv = env.kv(pricing::PRICING_BINDING).ok();
+            let free = free_variant(
+                &client,
+                config,
+                prices_kv.as_ref(),
+                &openai_request.model,
+                log,
+            );
+            // The free variant is another model ID, which the key's allowlist may not cover
+            if let Some(model) = free.await.filter(|model| caller.allows_model(model)) {
+                log.warn(
+                    "falling back to free model",
+                    &[
+                        ("model", openai_request.model.as_str().into()),
+                        ("free_model", model.as_str().into()),
+                    ],
+                );
+                route.push("free_fallback", model.as_str());
+                openai_request.model = model.clone();
+                free_model = Some(model);
+                // The free variant may belong to another family with keys of its own
+                api_key = caller.upstream_key(env, &openai_request.model).await?;
+                hedge_key = caller.hedge_key(env, &openai_request.model).await?;
+                pool = caller.key_pool(&openai_request.model, &api_key);
+                continue;
+            }
+        }
+
+        // Too long for the model's context window: try once more with the older
+        // turns dropped, or on the long-context model
+        let context_exceeded = matches!(
+            &forwarded,
+            Ok(Forwarded::Error { status, error_text, .. })
+                if upstream_error_code(*status, error_text) == Some(CODE_CONTEXT_EXCEEDED)
+        );
+        if context_exceeded && context_recovered.is_none() && budget.allows_retry(0) {
+            let recovery = config.context_recovery;
+            let recovered = match recovery {
+                ContextRecovery::Off => false,
+                ContextRecovery::Truncate => {
+                    match context_recovery::drop_older_turns(&openai_request.messages) {
+                        Some(messages) => {
+                            openai_request.messages = messages;
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                ContextRecovery::Reroute => match config
+                    .long_context_model
+                    .clone()
+                    .filter(|model| *model != openai_request.model)
+                    .filter(|model| caller.allows_model(model))
+                {
+                    Some(model) => {
+                        route.push("context_recovery", model.as_str());
+                        openai_request.model = model;
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if recovered {
+                // A rerouted request is sent with the long-context model's keys
+                api_key = caller.upstream_key(env, &openai_request.model).await?;
+                hedge_key = caller.hedge_key(env, &openai_request.model).await?;
+                pool = caller.key_pool(&openai_request.model, &api_key);
+                log.warn(
+                    "retrying after context length error",
+                    &[
+                        ("recovery", recovery.applied().into()),
+                        ("model", openai_request.model.as_str().into()),
+                        ("message_count", openai_request.messages.len().into()),
+                    ],
+                );
+                context_recovered = Some(recovery);
+                continue;
+            }
+        }
+        break forwarded;
+    };
+    drop(slot);
+    drop(ticket);
+
+    // Responses from the free variant aren't cached for the paid model's requests, nor
+    // responses to shortened or rerouted requests, nor responses to masked requests,
+    // whose placeholders stand for other values each time, nor responses adapted to an
+    // older API version
+    let (cache, semantic) = if free_model.is_some()
+        || context_recovered.is_some()
+        || masked.is_some()
+        || !version.is_current()
+    {
+        (None, None)
+    } else {
+        (cache, semantic)
+    };
+    let forwarded = match forwarded {
+        // Spend, metrics and the cost estimate follow the model that answered
+        Ok(forwarded) => {
+            if let Some(model) = forwarded.hedge_model() {
+                route.push("hedge", model);
+                openai_request.model = model.to_string();
+            }
+            forwarded
+        }
+        Err(failure) => {
+            log.error(
+                "upstream request failed",
+                &[
+                    ("model", openai_request.model.as_str().into()),
+                    ("stage", failure.stage.into()),
+                    ("error", failure.message.as_str().into()),
+                    ("latency_ms", budget.check("HTTP request ERROR", log).into()),
+                ],
+            );
+            let mut report = ErrorReport::new(
+                failure.stage,
+                failure.message.as_str(),
+                &openai_request.model,
+                log.request_id(),
+                Date::now().as_millis(),
+            );
+            if let Some(status) = failure.status {
+                report = report.with_status(status);
+            }
+            if let Some(body) = &failure.body {
+                report = report.with_body(body);
+            }
+            report_error(ctx, &client, config, log, report);
+            if anthropic_request.stream.unwrap_or(false) {
+                return stream_error_response("api_error", &failure.message);
+            }
+            return Err(worker::Error::RustError(failure.message));
+        }
+    };
+
+    let exposed: Vec<(String, String)> = config
+        .header_policy
+        .exposed(forwarded.headers())
+        .into_iter()
+        .cloned()
+        .collect();
+    let status = match &forwarded {
+        Forwarded::Error { status, .. } => *status,
+        Forwarded::Stream { .. } | Forwarded::Message { .. } => 200,
+    };
+    log.info(
+        "upstream response",
+        &[
+            ("model", openai_request.model.as_str().into()),
+            ("status", status.into()),
+            (
+                "latency_ms",
+                budget.check("HTTP request complete", log).into(),
+            ),
+        ],
+    );
+
+    // Per-request metrics, completed with usage once the response is read
+    let request_metrics = |status: u16| RequestMetrics {
+        model: openai_request.model.clone(),
+        requested_model: anthropic_request.model.clone(),
+        key_hash: key_hash.clone(),
+        status,
+        latency_ms: budget.elapsed_ms() as f64,
+        input_tokens: 0,
+        output_tokens: 0,
+        cost_usd: None,
+        prompt_cache_key: prompt_cache_key.clone(),
+        cached_input_tokens: 0,
+    };
+
+    // Estimated cost for the client, from the usage the upstream reported
+    let cost_usd = match &forwarded {
+        Forwarded::Stream {
+            usage: Some(usage), ..
+        }
+        | Forwarded::Message {
+            usage: Some(usage), ..
+        } => {
+            let prices_kv = env.kv(pricing::PRICING_BINDING).ok();
+            match pricing::prices(&client, &config.openrouter_base_url, prices_kv.as_ref()).await {
+                Ok(prices) => pricing::estimate(prices, &openai_request.model, usage),
+                Err(e) => {
+                    log.debug("cost not estimated", &[("error", e.to_string().into())]);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut system_fingerprint = None;
+    let mut response = match forwarded {
+        // Upstream errors, already in Anthropic format at the configured detail level
+        Forwarded::Error {
+            status,
+            error_text,
+            body,
+            ..
+        } => {
+            record_metrics(
+                ctx,
+                env,
+                &client,
+                config,
+                request_metrics(status),
+                None,
+                log,
+            );
+            let error_type = body["error"]["type"].as_str().unwrap_or("api_error");
+            log.warn(
+                "upstream error",
+                &[
+                    ("model", openai_request.model.as_str().into()),
+                    ("status", status.into()),
+                    ("error_type", error_type.into()),
+                    ("error", error_text.as_str().into()),
+                ],
+            );
+            // Policy blocks are grouped apart from API failures
+            let kind = if error_type == UPSTREAM_MODERATION {
+                "moderation"
+            } else {
+                "upstream"
+            };
+            report_error(
+                ctx,
+                &client,
+                config,
+                log,
+                ErrorReport::new(
+                    kind,
+                    format!("OpenRouter returned HTTP {status}"),
+                    &openai_request.model,
+                    log.request_id(),
+                    Date::now().as_millis(),
+                )
+                .with_status(status)
+                .with_body(&error_text),
+            );
+            Ok(Response::from_json(&body)?.with_status(client_status(status)))
+        }
+        Forwarded::Stream { events, usage, .. } => {
+            if let Some(transcript) = transcript {
+                record_session_transcript(
+                    ctx,
+                    env,
+                    personal_subject.as_deref().unwrap_or_default(),
+                    transcript,
+                    &anthropic_request,
+                    &openai_request.model,
+                    sessions::message_from_events(&events),
+                    log,
+                );
+            }
+            let events = match restore {
+                Some(placeholders) => placeholders.restore_events(&events),
+                None => events,
+            };
+            let events = version.adapt_events(events);
+            record_metrics(
+                ctx,
+                env,
+                &client,
+                config,
+                request_metrics(200),
+                usage.as_ref(),
+                log,
+            );
+            record_transcript(
+                ctx,
+                env,
+                config,
+                &anthropic_request,
+                &openai_request.model,
+                None,
+                usage.as_ref(),
+                log,
+            );
+            record_spend(
+                ctx,
+                &client,
+                config,
+                ledger,
+                &openai_request.model,
+                usage,
+                log,
+            );
+            if let Some(session) = session {
+                record_session_turn(
+                    ctx,
+                    config,
+                    session,
+                    sessions::message_from_events(&events),
+                    log,
+                );
+            }
+            if cache.is_some() || semantic.is_some() {
+                store_cached(
+                    ctx,
+                    config,
+                    cache,
+                    semantic,
+                    CachedResponse::Events(events.clone()),
+                    log,
+                );
+            }
+            event_stream_response(events)
+        }
+        Forwarded::Message {
+            openai_response,
+            openai_response_text,
+            usage,
+            ..
+        } => {
+            system_fingerprint = seed::system_fingerprint(&openai_response).map(str::to_string);
+            record_metrics(
+                ctx,
+                env,
+                &client,
+                config,
+                request_metrics(200),
+                usage.as_ref(),
+                log,
+            );
+            record_spend(
+                ctx,
+                &client,
+                config,
+                ledger,
+                &openai_request.model,
+                usage.clone(),
+                log,
+            );
+            // The shadow request takes ownership of the upstream request below
+            let upstream_model = openai_request.model.clone();
+
+            // Mirror a sample of traffic to the shadow model without delaying the response
+            let now = Date::now().as_millis();
+            let shadow_model = config
+                .shadow_target(&openai_request.model, now)
+                .filter(|model| caller.allows_model(model));
+            if let Some(shadow_model) = shadow_model {
+                match env.bucket(shadow::SHADOW_BUCKET_BINDING) {
+                    Ok(bucket) => {
+                        let mut shadow_request = openai_request.clone();
+                        shadow_request.model = shadow_model.to_string();
+                        let shadow_key = caller.upstream_key(env, shadow_model).await?;
+                        let shadow_call = upstream.send_json(&url, &shadow_key, &shadow_request);
+                        let shadow_model = shadow_model.to_string();
+                        let primary_response =
+                            serde_json::from_str(&openai_response_text).unwrap_or_default();
+                        let log = log.clone();
+
+                        ctx.wait_until(async move {
+                            let shadow_response = match shadow_call.await {
+                                Ok(response) => response.json::<serde_json::Value>().await.ok(),
+                                Err(_) => None,
+                            };
+                            let shadow_latency_ms = Date::now().as_millis().saturating_sub(now);
+
+                            let record = shadow::comparison_record(
+                                &openai_request,
+                                &shadow_model,
+                                primary_response,
+                                shadow_response,
+                                shadow_latency_ms,
+                                now,
+                            );
+                            if let Err(e) = shadow::store(&bucket, &record).await {
+                                log.warn("shadow store failed", &[("error", e.to_string().into())]);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log.warn(
+                            "shadow bucket unavailable",
+                            &[("error", e.to_string().into())],
+                        );
+                    }
+                }
+            }
+
+            // Transform back to Anthropic format
+            let mut anthropic_response =
+                match completion_to_anthropic(openai_response, &anthropic_request.model) {
+                    Ok(anthropic_response) => anthropic_response,
+                    Err(e) => {
+                        report_error(
+                            ctx,
+                            &client,
+                            config,
+                            log,
+                            ErrorReport::new(
+                                "transform",
+                                e.to_string(),
+                                &upstream_model,
+                                log.request_id(),
+                                Date::now().as_millis(),
+                            )
+                            .with_status(200)
+                            .with_body(&openai_response_text),
+                        );
+                        return Err(e.into());
+                    }
+                };
+            record_transcript(
+                ctx,
+                env,
+                config,
+                &anthropic_request,
+                &upstream_model,
+                Some(serde_json::to_value(&anthropic_response)?),
+                usage.as_ref(),
+                log,
+            );
+            if let Some(transcript) = transcript {
+                let reply = serde_json::json!({
+                    "role": "assistant",
+                    "content": serde_json::to_value(&anthropic_response.content)?,
+                });
+                record_session_transcript(
+                    ctx,
+                    env,
+                    personal_subject.as_deref().unwrap_or_default(),
+                    transcript,
+                    &anthropic_request,
+                    &upstream_model,
+                    Some(reply),
+                    log,
+                );
+            }
+            if let Some(placeholders) = restore {
+                anthropic_response
+                    .content
+                    .iter_mut()
+                    .for_each(|block| placeholders.restore_value(block));
+            }
+            version.adapt_response(&mut anthropic_response);
+
+            // Keep the response for retries of this request
+            if let Some((kv, storage_key)) = idempotency {
+                let stored = StoredResponse {
+                    request_hash,
+                    response: serde_json::to_value(&anthropic_response)?,
+                };
+                let ttl_secs = config.idempotency_ttl_secs;
+                let log = log.clone();
+                ctx.wait_until(async move {
+                    if let Err(e) = idempotency::store(&kv, &storage_key, &stored, ttl_secs).await {
+                        log.warn(
+                            "idempotent response not stored",
+                            &[("error", e.to_string().into())],
+                        );
+                    }
+                });
+            }
+
+            if let Some(session) = session {
+                let reply = serde_json::json!({
+                    "role": "assistant",
+                    "content": serde_json::to_value(&anthropic_response.content)?,
+                });
+                record_session_turn(ctx, config, session, Some(reply), log);
+            }
+            if cache.is_some() || semantic.is_some() {
+                store_cached(
+                    ctx,
+                    config,
+                    cache,
+                    semantic,
+                    CachedResponse::Message(serde_json::to_value(&anthropic_response)?),
+                    log,
+                );
+            }
+
+            // Return Anthropic-formatted response to client
+            compression::json_response(&anthropic_response, encoding, config.compress_min_bytes)
+        }
+    }?;
+    if let Some(model) = free_model {
+        response.headers_mut().set(FREE_FALLBACK_HEADER, &model)?;
+    }
+    if let Some(recovery) = context_recovered {
+        response
+            .headers_mut()
+            .set(context_recovery::RECOVERY_HEADER, recovery.applied())?;
+    }
+    response
+        .headers_mut()
+        .set(model_route::ROUTE_HEADER, &route.header_value())?;
+    if let Some(cost) = cost_usd {
+        response
+            .headers_mut()
+            .set(pricing::COST_HEADER, &pricing::format_cost(cost))?;
+    }
+    if let Some(fingerprint) = system_fingerprint {
+        response
+            .headers_mut()
+            .set(seed::FINGERPRINT_HEADER, &fingerprint)?;
+    }
+    for (name, value) in &exposed {
+        response.headers_mut().set(name, value)?;
+    }
+    if let Some(fallback) = images_replaced {
+        response
+            .headers_mut()
+            .set(vision::IMAGES_HEADER, fallback.as_str())?;
+    }
+    if injection_found {
+        let action = if neutralize { "neutralized" } else { "flagged" };
+        response
+            .headers_mut()
+            .set(injection::INJECTION_HEADER, action)?;
+    }
+    Ok(response)
+}
+
+/// The `:free` variant of an upstream model, if the catalog lists one
+async fn free_variant(
+    client: &http::Client,
+    config: &Config,
+    prices_kv: Option<&KvStore>,
+    model: &str,
+    log: &Logger,
+) -> Option<String> {
+    match pricing::prices(client, &config.openrouter_base_url, prices_kv).await {
+        Ok(prices) => catalog::free_variant(model, |id| prices.contains_key(id)),
+        Err(e) => {
+            log.warn(
+                "model catalog unavailable",
+                &[("error", e.to_string().into())],
+            );
+            None
+        }
+    }
+}
+
+/// The upstream reply to a `/v1/messages` request, translated for the client
+pub(crate) enum Forwarded {
+    /// Upstream answered with an error status, mapped to an Anthropic error body
+    Error {
+        status: u16,
+        error_text: String,
+        body: serde_json::Value,
+        headers: Vec<(String, String)>,
+        hedge_model: Option<String>,
+    },
+    /// Anthropic stream events for a streaming request
+    Stream {
+        events: String,
+        usage: Option<Usage>,
+        headers: Vec<(String, String)>,
+        hedge_model: Option<String>,
+    },
+    /// A complete OpenAI response, still to be translated
+    Message {
+        openai_response: OpenAICompletion,
+        /// The raw body, kept for error reports
+        openai_response_text: String,
+        usage: Option<Usage>,
+        headers: Vec<(String, String)>,
+        hedge_model: Option<String>,
+    },
+}
+
+impl Forwarded {
+    /// The upstream response's headers
+    pub fn headers(&self) -> &[(String, String)] {
+        match self {
+            Forwarded::Error { headers, .. }
+            | Forwarded::Stream { headers, .. }
+            | Forwarded::Message { headers, .. } => headers,
+        }
+    }
+
+    /// The hedge model, when it answered in place of the requested one
+    pub fn hedge_model(&self) -> Option<&str> {
+        match self {
+            Forwarded::Error { hedge_model, .. }
+            | Forwarded::Stream { hedge_model, .. }
+            | Forwarded::Message { hedge_model, .. } => hedge_model.as_deref(),
+        }
+    }
+}
+
+/// Why a request could not be forwarded, with what is known for the error report
+#[derive(Debug)]
+pub(crate) struct ForwardError {
+    /// `upstream` or `transform`, as in error reports
+    pub stage: &'static str,
+    pub message: String,
+    pub status: Option<u16>,
+    pub body: Option<String>,
+}
+
+impl ForwardError {
+    fn new(stage: &'static str, message: impl Into<String>) -> Self {
+        ForwardError {
+            stage,
+            message: message.into(),
+            status: None,
+            body: None,
+        }
+    }
+}
+
+/// Sends the translated request upstream and translates what comes back
+///
+/// Covers everything between admission and the client response that depends only
+/// on the upstream, so it runs against `upstream::MockClient` in tests.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn forward<C: UpstreamClient>(
+    upstream: &C,
+    url: &str,
+    api_key: &str,
+    hedge_key: &str,
+    anthropic_request: &AnthropicRequest,
+    openai_request: &OpenAIRequest,
+    config: &Config,
+    budget: &Budget,
+    log: &Logger,
+) -> std::result::Result<Forwarded, ForwardError> {
+    let streaming = anthropic_request.stream.unwrap_or(false);
+    let started_at = budget.now_ms();
+    let mut attempts = 0;
+    let mut anomaly_retried = false;
+    loop {
+        let response = loop {
+            attempts += 1;
+            let result = send_with_hedging(
+                upstream,
+                url,
+                api_key,
+                hedge_key,
+                openai_request,
+                streaming,
+                config,
+                log,
+            )
+            .await;
+
+            // Streams are only retried on an error status, before any event was
+            // read; whole responses on network errors too
+            let failure = match &result {
+                Ok((response, _)) if !RetryPolicy::is_retryable_status(response.status()) => {
+                    break result
+                }
+                Ok((response, _)) => format!("HTTP {}", response.status()),
+                Err(_) if streaming => break result,
+                Err(e) => e.to_string(),
+            };
+            let elapsed_ms = budget.now_ms().saturating_sub(started_at);
+            // Past the request's soft limit, failing now beats being cancelled later
+            let Some(delay_ms) = config
+                .retry
+                .next_delay(attempts, elapsed_ms, started_at)
+                .filter(|delay_ms| budget.allows_retry(*delay_ms))
+            else {
+                break result;
+            };
+            log.warn(
+                "retrying upstream request",
+                &[
+                    ("attempt", attempts.into()),
+                    ("error", failure.into()),
+                    ("delay_ms", delay_ms.into()),
+                ],
+            );
+            upstream.sleep(delay_ms).await;
+        };
+        let (response, hedge_model) =
+            response.map_err(|e| ForwardError::new("upstream", format!("Request failed: {e}")))?;
+
+        let headers = response.headers().to_vec();
+
+        // Handle error responses from OpenRouter
+        if !response.is_success() {
+            let status = response.status();
+            let error_text = response.text().await.map_err(|e| {
+                ForwardError::new("upstream", format!("Failed to read error response: {e}"))
+            })?;
+            let body = match config.error_verbosity {
+                ErrorVerbosity::Basic => {
+                    transform_openrouter_error_safe(&error_text, status, anthropic_request)
+                }
+                ErrorVerbosity::Detailed => {
+                    transform_openrouter_error(&error_text, status, anthropic_request)
+                }
+            };
+            return Ok(Forwarded::Error {
+                status,
+                error_text,
+                body,
+                headers,
+                hedge_model,
+            });
+        }
+
+        if streaming {
+            let (events, usage, profile) = stream_openai_to_anthropic_profiled(
+                response.into_stream(),
+                &anthropic_request.model,
+                Some(budget),
+                config.stream_coalesce_after_events,
+            )
+            .await
+            .map_err(|e| ForwardError {
+                status: Some(200),
+                ..ForwardError::new("transform", e.to_string())
+            })?;
+            log_stream_profile(&profile, log);
+            return Ok(Forwarded::Stream {
+                events,
+                usage,
+                headers,
+                hedge_model,
+            });
+        }
+
+        // Parse OpenRouter response, keeping the text for error reports
+        let openai_response_text = response.text().await.map_err(|e| {
+            ForwardError::new("upstream", format!("Failed to read OpenAI response: {e}"))
+        })?;
+        match completion(&openai_response_text) {
+            Ok(openai_response) => {
+                // Policy refusals are reported as such rather than as empty messages
+                if let Some(message) = refusal(&openai_response) {
+                    return Ok(Forwarded::Error {
+                        status: 403,
+                        body: serde_json::json!({
+                            "type": "error",
+                            "error": {
+                                "type": UPSTREAM_MODERATION,
+                                "code": CODE_MODERATION_BLOCKED,
+                                "message": message
+                            }
+                        }),
+                        error_text: openai_response_text,
+                        headers,
+                        hedge_model,
+                    });
+                }
+                return Ok(Forwarded::Message {
+                    usage: usage_counts(&openai_response.usage),
+                    openai_response,
+                    openai_response_text,
+                    headers,
+                    hedge_model,
+                });
+            }
+            // Providers occasionally answer 200 with nothing usable; a second try usually works
+            Err(anomaly) if !anomaly_retried && budget.allows_retry(0) => {
+                log.warn(
+                    "retrying anomalous upstream response",
+                    &[("anomaly", anomaly.into())],
+                );
+                anomaly_retried = true;
+            }
+            Err(anomaly) => {
+                let message = format!("Upstream returned an unusable response: {anomaly}");
+                return Ok(Forwarded::Error {
+                    status: 502,
+                    body: serde_json::json!({
+                        "type": "error",
+                        "error": {"type": "api_error", "message": message}
+                    }),
+                    error_text: openai_response_text,
+                    headers,
+                    hedge_model,
+                });
+            }
+        }
+    }
+}
+
+/// Logs the work a stream took by phase, warning when it had to be coalesced
+fn log_stream_profile(profile: &StreamProfile, log: &Logger) {
+    let fields = [
+        ("reads", profile.reads.into()),
+        ("upstream_bytes", profile.upstream_bytes.into()),
+        ("parsed_events", profile.parsed_events.into()),
+        ("malformed_events", profile.malformed_events.into()),
+        ("deltas", profile.deltas.into()),
+        ("coalesced_deltas", profile.coalesced_deltas.into()),
+        ("events", profile.events.into()),
+        ("event_bytes", profile.event_bytes.into()),
+        ("coalescing", profile.coalescing.as_str().into()),
+        ("degraded_after", profile.degraded_after.into()),
+    ];
+    match profile.degraded_after {
+        Some(_) => log.warn("stream coalesced under load", &fields),
+        None => log.debug("stream profile", &fields),
+    }
+}
+
+/// The completion in a successful response body, or what is wrong with it
+///
+/// Truncated JSON and responses without choices are anomalies; a body holding
+/// only an `error` object reports that error's message.
+fn completion(text: &str) -> std::result::Result<OpenAICompletion, String> {
+    let response: OpenAICompletion =
+        serde_json::from_str(text).map_err(|e| format!("malformed JSON ({e})"))?;
+    if let Some(message) = response.error["message"].as_str() {
+        return Err(format!("error in a 200 response ({message})"));
+    }
+    match &response.choices {
+        Some(choices) if !choices.is_empty() => Ok(response),
+        Some(_) => Err("empty choices".to_string()),
+        None => Err("no choices".to_string()),
+    }
+}
+
+/// A request CCR refused before calling upstream, formatted by each ingress API
+pub(crate) struct Rejection {
+    pub status: u16,
+    /// Anthropic error type, e.g. `authentication_error`
+    pub error_type: &'static str,
+    pub message: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Rejection {
+    pub fn new(status: u16, error_type: &'static str, message: impl ToString) -> Self {
+        Rejection {
+            status,
+            error_type,
+            message: message.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn into_anthropic(self) -> Result<Response> {
+        let mut response = error_response(self.status, self.error_type, &self.message)?;
+        for (name, value) in &self.headers {
+            response.headers_mut().set(name, value)?;
+        }
+        Ok(response)
+    }
+}
+
+/// An authenticated caller and the upstream key and configuration their requests use
+pub(crate) struct Caller<'a> {
+    pub identity: Option<Claims>,
+    pub virtual_key: Option<VirtualKey>,
+    pub profile: Option<Profile>,
+    /// Deployment configuration with the caller's profile applied
+    pub config: Cow<'a, Config>,
+    pub api_key: String,
+    /// Hash of the key the client presented (or of the upstream key when it sent none)
+    pub key_hash: String,
+    /// Whether `key_hash` is of a key the client presented
+    pub presented_key: bool,
+    /// Whether `api_key` is the server's, which `MODEL_KEYS` may replace per model
+    pub uses_server_key: bool,
+}
+
+impl Caller<'_> {
+    /// Settles the features on for the caller's key, applying the `FEATURES`
+    /// overrides and percentage rollouts
+    pub async fn resolve_features(&mut self, env: &Env, log: &Logger) {
+        let overrides = features::load_overrides(env, log).await;
+        let flags = self.config.feature_flags.with(&overrides);
+        let mut resolved = self.config.features;
+        flags.apply(&mut resolved, Some(&self.key_hash));
+        if resolved != self.config.features {
+            self.config.to_mut().features = resolved;
+        }
+    }
+
+    /// Whether the caller's virtual key, if they used one, allows `model`
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.virtual_key
+            .as_ref()
+            .is_none_or(|record| record.allows_model(model))
+    }
+
+    /// Stops requests being hedged to a model the caller's key doesn't allow
+    pub fn restrict_models(&mut self) {
+        let hedge_allowed = self
+            .config
+            .hedge_model
+            .as_deref()
+            .is_none_or(|model| self.allows_model(model));
+        if !hedge_allowed {
+            self.config.to_mut().hedge_model = None;
+        }
+    }
+
+    /// Spend and usage are attributed to the key
+    pub fn key_subject(&self) -> String {
+        format!("key:{}", self.key_hash)
+    }
+
+    /// SSO users are limited per user, however many keys they share
+    pub fn limit_subject(&self) -> String {
+        match &self.identity {
+            Some(claims) => format!("user:{}", claims.sub),
+            None => self.key_subject(),
+        }
+    }
+
+    /// Who the caller is when that is them alone: their SSO user, or the key they
+    /// presented. Callers sharing the server key or access token have no such subject
+    pub fn personal_subject(&self) -> Option<String> {
+        match &self.identity {
+            Some(_) => Some(self.limit_subject()),
+            None => self.presented_key.then(|| self.key_subject()),
+        }
+    }
+
+    /// The `MODEL_KEYS` credential of `model`'s family; keys clients or their
+    /// virtual keys bring are never replaced
+    pub fn model_credential(&self, model: &str) -> Option<&Credential> {
+        self.config
+            .model_keys
+            .for_model(model)
+            .filter(|_| self.uses_server_key)
+    }
+
+    /// The upstream key a request to `model` is sent with
+    pub async fn upstream_key(&self, env: &Env, model: &str) -> Result<String> {
+        let family_key = match self.model_credential(model) {
+            Some(credential) => credential.resolve(env).await?,
+            None => None,
+        };
+        Ok(family_key.unwrap_or_else(|| self.api_key.clone()))
+    }
+
+    /// The upstream key a request to `model` sends its hedge with, if it is hedged
+    pub async fn hedge_key(&self, env: &Env, model: &str) -> Result<String> {
+        let hedge_model = self.config.hedge_target(model).unwrap_or(model);
+        self.upstream_key(env, hedge_model).await
+    }
+
+    /// The pool `api_key` was drawn from for `model`, whose other keys take over
+    /// when it is benched
+    pub fn key_pool(&self, model: &str, api_key: &str) -> &[String] {
+        match self
+            .model_credential(model)
+            .or(self.config.server_api_key.as_ref())
+        {
+            Some(Credential::Pool(keys)) if keys.iter().any(|key| key == api_key) => keys,
+            _ => &[],
+        }
+    }
+}
+
+/// Identifies the caller and resolves the upstream key, applying access control
+///
+/// Checks SSO identity, virtual keys and the access token or key allowlist, then
+/// applies the caller's tenant profile.
+pub(crate) async fn authenticate<'a>(
+    req: &Request,
+    env: &Env,
+    config: &'a Config,
+    client: &http::Client,
+) -> Result<std::result::Result<Caller<'a>, Rejection>> {
+    // Identity from SSO (Cloudflare Access or another JWT issuer), required when configured
+    let identity = match &config.jwt {
+        Some(jwt_config) => match req.headers().get(&jwt_config.header)? {
+            Some(token) => match jwt::verify(&token, jwt_config, client).await {
+                Ok(claims) => Some(claims),
+                Err(e) => return Ok(Err(Rejection::new(401, "authentication_error", e))),
+            },
+            None => {
+                return Ok(Err(Rejection::new(
+                    401,
+                    "authentication_error",
+                    format!("Missing {} header", jwt_config.header),
+                )))
+            }
+        },
+        None => None,
+    };
+
+    // Extract API key from multiple possible headers, falling back to the server key
+    let x_api_key = match req.headers().get("x-api-key")? {
+        Some(key) => Some(key),
+        None => req.headers().get(gemini::GOOGLE_API_KEY_HEADER)?,
+    };
+    let client_key = auth::client_key(x_api_key, req.headers().get("Authorization")?)?;
+
+    // CCR-issued virtual keys are swapped for the upstream key they map to
+    let virtual_key = match client_key
+        .as_deref()
+        .filter(|key| virtual_keys::is_virtual_key(key))
+    {
+        Some(key) => {
+            let kv = env.kv(virtual_keys::VIRTUAL_KEYS_BINDING)?;
+            match virtual_keys::lookup(&kv, key).await? {
+                Some(record) if !record.disabled => Some(record),
+                _ => {
+                    return Ok(Err(Rejection::new(
+                        401,
+                        "authentication_error",
+                        "Invalid virtual API key",
+                    )))
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Refuse to act as an open proxy when access control is configured
+    let presented_token = req.headers().get(auth::ACCESS_TOKEN_HEADER)?;
+    if virtual_key.is_none()
+        && identity.is_none()
+        && !auth::is_authorized(
+            config.access_token.as_deref(),
+            &config.allowed_key_hashes,
+            presented_token.as_deref(),
+            client_key.as_deref(),
+        )
+    {
+        return Ok(Err(Rejection::new(
+            403,
+            "permission_error",
+            "This CCR deployment requires an access token or an allowlisted API key",
+        )));
+    }
+
+    // Tenant profile: bound to the virtual key, or chosen with the x-ccr-profile header
+    // by callers holding the access token, since a profile can lift rate limits
+    let requested_profile = req
+        .headers()
+        .get(profiles::PROFILE_HEADER)?
+        .filter(|name| !name.trim().is_empty());
+    let profile_name = match virtual_key
+        .as_ref()
+        .and_then(|record| record.profile.clone())
+    {
+        Some(name) => Some(name),
+        None if requested_profile.is_none() => None,
+        None => {
+            let holds_token = config.access_token.as_deref().is_some_and(|token| {
+                [presented_token.as_deref(), client_key.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .any(|presented| auth::constant_time_eq(token, presented))
+            });
+            if !holds_token {
+                return Ok(Err(Rejection::new(
+                    403,
+                    "permission_error",
+                    "x-ccr-profile requires the deployment's access token",
+                )));
+            }
+            requested_profile
+        }
+    };
+    let profile = match &profile_name {
+        Some(name) => {
+            let kv = env.kv(profiles::PROFILES_BINDING)?;
+            match profiles::lookup(&kv, name).await? {
+                Some(profile) => Some(profile),
+                None => {
+                    return Ok(Err(Rejection::new(
+                        400,
+                        "invalid_request_error",
+                        format!("Unknown profile '{name}'"),
+                    )))
+                }
+            }
+        }
+        None => None,
+    };
+    let config = match &profile {
+        Some(profile) => Cow::Owned(profile.apply(config)?),
+        None => Cow::Borrowed(config),
+    };
+
+    // An access token sent as the API key is never forwarded upstream
+    let client_key = client_key.filter(|key| {
+        !config
+            .access_token
+            .as_deref()
+            .is_some_and(|token| auth::constant_time_eq(token, key))
+    });
+
+    // Rate limits are keyed on the key the client presented, never the plaintext
+    let client_key_hash = client_key.as_deref().map(auth::hash_key);
+
+    // The server key may live in the Secrets Store, so it is resolved per request
+    let server_key = match &config.server_api_key {
+        Some(credential) => credential.resolve(env).await?,
+        None => None,
+    };
+
+    let api_key = match &virtual_key {
+        Some(record) => match record.upstream_key.as_deref().or(server_key.as_deref()) {
+            Some(api_key) => api_key.to_string(),
+            None => {
+                return Ok(Err(Rejection::new(
+                    500,
+                    "api_error",
+                    format!(
+                        "Virtual key '{}' has no upstream key configured",
+                        record.name
+                    ),
+                )))
+            }
+        },
+        None => match auth::resolve_upstream_key(
+            client_key,
+            server_key.as_deref(),
+            config.force_server_key,
+        ) {
+            Some(api_key) => api_key,
+            None => {
+                return Ok(Err(Rejection::new(
+                    401,
+                    "authentication_error",
+                    "No API key found in x-api-key or Authorization header",
+                )))
+            }
+        },
+    };
+
+    Ok(Ok(Caller {
+        identity,
+        virtual_key,
+        profile,
+        presented_key: client_key_hash.is_some(),
+        key_hash: client_key_hash.unwrap_or_else(|| auth::hash_key(&api_key)),
+        uses_server_key: server_key.as_deref() == Some(api_key.as_str()),
+        api_key,
+        config,
+    }))
+}
+
+/// Replaces the images of a request whose routed model can't view them, per
+/// `IMAGE_FALLBACK`, returning the fallback applied
+///
+/// Runs once the request is admitted, since captions are spent: their usage is
+/// added to the caller's budget, and a caption model outside the key's allowlist
+/// leaves placeholders. The request is translated again so the replacements
+/// reach the upstream.
+#[allow(clippy::too_many_arguments)]
+async fn fallback_images<C: UpstreamClient>(
+    env: &Env,
+    ctx: &Context,
+    upstream: &C,
+    caller: &Caller<'_>,
+    url: &str,
+    ledger: Option<&SpendLedger>,
+    route: &model_route::Route,
+    anthropic_request: &mut AnthropicRequest,
+    openai_request: &mut OpenAIRequest,
+    log: &Logger,
+) -> Result<Option<ImageFallback>> {
+    let config: &Config = &caller.config;
+    if config.image_fallback == ImageFallback::Off || vision::images(anthropic_request).is_empty() {
+        return Ok(None);
+    }
+    let client = http::Client::new();
+    let catalog_kv = env.kv(catalog::CATALOG_BINDING).ok();
+    let model = &openai_request.model;
+    match vision::supports_images(
+        &client,
+        &config.openrouter_base_url,
+        catalog_kv.as_ref(),
+        model,
+    )
+    .await
+    {
+        Ok(Some(false)) => {}
+        Ok(_) => return Ok(None),
+        Err(e) => {
+            log.warn(
+                "model catalog unavailable",
+                &[("error", e.to_string().into())],
+            );
+            return Ok(None);
+        }
+    }
+
+    let caption_model = &config.image_caption_model;
+    let fallback = match config.image_fallback {
+        ImageFallback::Describe if !caller.allows_model(caption_model) => ImageFallback::Strip,
+        fallback => fallback,
     };
+    let caption_key = caller.upstream_key(env, caption_model).await?;
+    let (replaced, caption_usage) = vision::apply(
+        fallback,
+        anthropic_request,
+        upstream,
+        url,
+        &caption_key,
+        caption_model,
+    )
+    .await;
+    if let (Some(ledger), Some(_)) = (ledger, &caption_usage) {
+        record_spend(
+            ctx,
+            &client,
+            config,
+            Some(ledger.reopen(env)?),
+            caption_model,
+            caption_usage,
+            log,
+        );
+    }
+    log.info(
+        "images replaced for text-only model",
+        &[
+            ("model", model.as_str().into()),
+            ("images", replaced.into()),
+            ("fallback", fallback.as_str().into()),
+        ],
+    );
 
-    let _elapsed = check_time("API key extraction complete");
+    openai_request.messages =
+        anthropic_to_openai_routed(anthropic_request, route, config)?.messages;
+    if config.prompt_cache_hints {
+        prompt_cache::apply(anthropic_request, openai_request);
+    }
+    Ok(Some(fallback))
+}
 
-    // Minimal debug logging
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("API key: {}...", &api_key[..8.min(api_key.len())]).into());
+/// Applies the caller's rate limits and monthly budget before anything is spent upstream
+///
+/// Returns where the request's spend is recorded when the caller has a budget.
+pub(crate) async fn admit(
+    env: &Env,
+    caller: &Caller<'_>,
+    client_ip: Option<String>,
+    messages: &[serde_json::Value],
+) -> Result<std::result::Result<Option<SpendLedger>, Rejection>> {
+    let config = &caller.config;
+    let virtual_key = &caller.virtual_key;
 
-    // Parse incoming Anthropic-formatted request
-    let _elapsed = check_time("Request parsing start");
-    let anthropic_request: AnthropicRequest = req.json().await?;
-    let _elapsed = check_time("Request parsing complete");
+    // Per-key and per-IP rate limits
+    let key_limits = Limits {
+        rpm: virtual_key
+            .as_ref()
+            .and_then(|record| record.rpm)
+            .unwrap_or(config.rate_limits.key_rpm),
+        tpm: virtual_key
+            .as_ref()
+            .and_then(|record| record.tpm)
+            .unwrap_or(config.rate_limits.key_tpm),
+    };
+    let ip_limits = Limits {
+        rpm: config.rate_limits.ip_rpm,
+        tpm: config.rate_limits.ip_tpm,
+    };
+    let mut subjects = vec![(caller.limit_subject(), key_limits)];
+    if let Some(client_ip) = client_ip {
+        subjects.push((format!("ip:{client_ip}"), ip_limits));
+    }
 
-    // Minimal debug logging
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(
-        &format!(
-            "Request: {} | {} msgs",
-            anthropic_request.model,
-            anthropic_request.messages.len()
-        )
-        .into(),
+    if let Some(decision) = check_rate_limits(env, &subjects, messages).await? {
+        let message = format!(
+            "Rate limit exceeded for {}; retry after {}s",
+            decision.limited_by.as_deref().unwrap_or("requests"),
+            decision.retry_after_ms.div_ceil(1000).max(1)
+        );
+        let mut rejection = Rejection::new(429, "rate_limit_error", message);
+        rejection.headers = rate_limit::headers(&decision);
+        return Ok(Err(rejection));
+    }
+
+    // Reject keys that have exhausted their monthly budget
+    let monthly_budget_usd = virtual_key
+        .as_ref()
+        .and_then(|record| record.monthly_budget_usd)
+        .or(caller
+            .profile
+            .as_ref()
+            .and_then(|profile| profile.monthly_budget_usd));
+    let Some(budget_usd) = monthly_budget_usd else {
+        return Ok(Ok(None));
+    };
+    let namespace = env.durable_object(budget::BUDGET_LEDGER_BINDING)?;
+    let month = budget::billing_month(Date::now().as_millis());
+    let key_subject = caller.key_subject();
+    let spent = budget::add_spend(&namespace, &key_subject, &month, 0).await?;
+    if spent >= budget::usd_to_micros(budget_usd) {
+        return Ok(Err(Rejection::new(
+            403,
+            "permission_error",
+            format!("Monthly budget of ${budget_usd:.2} exhausted for this API key"),
+        )));
+    }
+    Ok(Ok(Some(SpendLedger {
+        namespace,
+        subject: key_subject,
+        month,
+        prices: env.kv(pricing::PRICING_BINDING).ok(),
+    })))
+}
+
+/// One of the caller's concurrent request slots, given back when dropped, so no
+/// way out of a request leaves it held until its lease expires
+pub(crate) struct HeldSlot<'a> {
+    ctx: &'a Context,
+    /// `None` when the caller has no concurrency limit
+    slot: Option<Slot>,
+    log: Logger,
+}
+
+impl Drop for HeldSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            release_slot(self.ctx, slot, &self.log);
+        }
+    }
+}
+
+/// Takes one of the caller's concurrent request slots, waiting up to
+/// `CONCURRENCY_QUEUE_MS` for one to free up
+///
+/// The slot is leased to the request ID and held until the returned guard is dropped.
+pub(crate) async fn acquire_slot<'a>(
+    env: &Env,
+    ctx: &'a Context,
+    caller: &Caller<'_>,
+    log: &Logger,
+) -> Result<std::result::Result<HeldSlot<'a>, Rejection>> {
+    let config = &caller.config;
+    let limit = caller
+        .virtual_key
+        .as_ref()
+        .and_then(|record| record.concurrency)
+        .unwrap_or(config.rate_limits.key_concurrency);
+    let held = |slot| HeldSlot {
+        ctx,
+        slot,
+        log: log.clone(),
+    };
+    if limit == 0 {
+        return Ok(Ok(held(None)));
+    }
+
+    let namespace = env.durable_object(concurrency::CONCURRENCY_LIMITER_BINDING)?;
+    let subject = caller.limit_subject();
+    let lease = log.request_id();
+    let deadline = Date::now().as_millis() + config.rate_limits.concurrency_queue_ms;
+    loop {
+        if let Some(slot) = concurrency::acquire(&namespace, &subject, lease, limit).await? {
+            return Ok(Ok(held(Some(slot))));
+        }
+        if Date::now().as_millis() >= deadline {
+            break;
+        }
+        Delay::from(Duration::from_millis(concurrency::QUEUE_POLL_MS)).await;
+    }
+
+    let mut rejection = Rejection::new(
+        429,
+        "rate_limit_error",
+        format!("Too many concurrent requests for this API key (limit {limit})"),
     );
+    rejection
+        .headers
+        .push(("retry-after".to_string(), "1".to_string()));
+    Ok(Err(rejection))
+}
 
-    // Transform to OpenAI format for OpenRouter API
-    let _elapsed = check_time("Transform start");
-    let openai_request = anthropic_to_openai(&anthropic_request, config)?;
-    let _elapsed = check_time("Transform complete");
+/// Gives a concurrent request slot back once the upstream request has finished
+fn release_slot(ctx: &Context, slot: Slot, log: &Logger) {
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = slot.release().await {
+            log.warn(
+                "concurrency slot not released",
+                &[("error", e.to_string().into())],
+            );
+        }
+    });
+}
 
-    // Minimal debug logging
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("Mapped: {}", openai_request.model).into());
+/// Where a budgeted key's spend is recorded
+pub(crate) struct SpendLedger {
+    namespace: ObjectNamespace,
+    subject: String,
+    month: String,
+    /// Where the spend is priced from, when the price table is shared in KV
+    prices: Option<KvStore>,
+}
+
+impl SpendLedger {
+    /// Reopens the ledger a request was admitted against, e.g. in the queue consumer
+    pub(crate) fn open(env: &Env, subject: &str, month: &str) -> Result<Self> {
+        Ok(SpendLedger {
+            namespace: env.durable_object(budget::BUDGET_LEDGER_BINDING)?,
+            subject: subject.to_string(),
+            month: month.to_string(),
+            prices: env.kv(pricing::PRICING_BINDING).ok(),
+        })
+    }
+
+    /// Another handle on the same ledger, for a request that spends on several models
+    pub(crate) fn reopen(&self, env: &Env) -> Result<Self> {
+        Self::open(env, &self.subject, &self.month)
+    }
+}
+
+/// Prices the request's usage from the model catalog and adds it to the key's spend
+///
+/// Runs after the response is returned. Usage that can't be priced (no usage
+/// reported, or a model missing from the catalog) is logged and skipped.
+pub(crate) fn record_spend(
+    ctx: &Context,
+    client: &http::Client,
+    config: &Config,
+    ledger: Option<SpendLedger>,
+    model: &str,
+    usage: Option<Usage>,
+    log: &Logger,
+) {
+    let Some(ledger) = ledger else {
+        return;
+    };
+    let Some(usage) = usage else {
+        log.warn(
+            "no usage reported, spend not recorded",
+            &[("model", model.into())],
+        );
+        return;
+    };
 
-    // Create HTTP client (timeout handled by Cloudflare Workers runtime)
-    let client = reqwest::Client::new();
+    let client = *client;
+    let base_url = config.openrouter_base_url.clone();
+    let model = model.to_string();
+    let log = log.clone();
 
-    let url = format!("{}/chat/completions", config.openrouter_base_url);
+    ctx.wait_until(async move {
+        charge_spend(&client, &base_url, &ledger, &model, &usage, &log).await;
+    });
+}
 
-    // Debug logging for troubleshooting
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("→ OpenRouter: {}", openai_request.model).into());
+/// Prices usage from the model catalog and adds it to the ledger, logging failures
+pub(crate) async fn charge_spend(
+    client: &http::Client,
+    base_url: &str,
+    ledger: &SpendLedger,
+    model: &str,
+    usage: &Usage,
+    log: &Logger,
+) {
+    let prices = match pricing::prices(client, base_url, ledger.prices.as_ref()).await {
+        Ok(prices) => prices,
+        Err(e) => {
+            log.warn("spend not recorded", &[("error", e.to_string().into())]);
+            return;
+        }
+    };
+    let Some(model_pricing) = prices.get(model) else {
+        log.warn("no catalog price", &[("model", model.into())]);
+        return;
+    };
 
-    // Add detailed request logging for debugging
-    #[cfg(target_arch = "wasm32")]
+    let cost = pricing::cost_micros(model_pricing, usage);
+    if let Err(e) = budget::add_spend(&ledger.namespace, &ledger.subject, &ledger.month, cost).await
     {
-        let request_json = serde_json::to_string_pretty(&openai_request)
-            .unwrap_or_else(|_| "[failed to serialize]".to_string());
-        web_sys::console::log_1(&format!("🔍 Request JSON: {}", request_json).into());
-        web_sys::console::log_1(
-            &format!("🔑 API Key: {}...", &api_key[..15.min(api_key.len())]).into(),
-        );
+        log.warn("spend not recorded", &[("error", e.to_string().into())]);
     }
+}
 
-    // Send request to OpenRouter API with timeout
-    let _elapsed = check_time("HTTP request start");
+/// Writes the request's metrics to Analytics Engine, priced from the model catalog,
+/// publishes a summary to the `/admin/tail` console and reports the outcome to the
+/// alert monitor
+///
+/// Each part does nothing unless its binding exists. Runs after the response is
+/// returned; usage that can't be priced is written with a cost of zero.
+pub(crate) fn record_metrics(
+    ctx: &Context,
+    env: &Env,
+    client: &http::Client,
+    config: &Config,
+    mut request_metrics: RequestMetrics,
+    usage: Option<&Usage>,
+    log: &Logger,
+) {
+    let usage = usage.cloned();
+    if let Some(usage) = &usage {
+        request_metrics.input_tokens = usage.prompt_tokens();
+        request_metrics.output_tokens = usage.output_tokens;
+        request_metrics.cached_input_tokens = usage.cache_read_input_tokens;
+    }
 
-    let response = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {api_key}"))
-        .header("HTTP-Referer", "https://ccr.duyet.net")
-        .header("X-Title", "CCR - Claude Code Router")
-        .json(&openai_request)
-        .send()
-        .await
-        .map_err(|e| {
-            let _elapsed = check_time("HTTP request ERROR");
-            #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(
-                &format!(
-                    "🚨 HTTP Error: {} (timeout: {}, request: {})",
-                    e,
-                    e.is_timeout(),
-                    e.is_request()
-                )
-                .into(),
+    if let Ok(namespace) = env.durable_object(tail::REQUEST_TAIL_BINDING) {
+        let summary =
+            RequestSummary::new(log.request_id(), Date::now().as_millis(), &request_metrics);
+        let log = log.clone();
+        ctx.wait_until(async move {
+            if let Err(e) = tail::publish(&namespace, &summary).await {
+                log.debug("tail not published", &[("error", e.to_string().into())]);
+            }
+        });
+    }
+
+    let dataset = env.analytics_engine(metrics::ANALYTICS_BINDING).ok();
+    let ledger_db = env.d1(ledger::LEDGER_BINDING).ok();
+    let monitor = config
+        .alerts
+        .as_ref()
+        .and_then(|_| env.durable_object(alerts::ALERT_MONITOR_BINDING).ok());
+    if dataset.is_none() && ledger_db.is_none() && monitor.is_none() {
+        return;
+    }
+    let request_id = log.request_id().to_string();
+
+    let client = *client;
+    let base_url = config.openrouter_base_url.clone();
+    let prices_kv = env.kv(pricing::PRICING_BINDING).ok();
+    let log = log.clone();
+
+    ctx.wait_until(async move {
+        if let Some(usage) = usage {
+            if let Ok(prices) = pricing::prices(&client, &base_url, prices_kv.as_ref()).await {
+                request_metrics.cost_usd =
+                    pricing::estimate(prices, &request_metrics.model, &usage);
+            }
+        }
+        if let Some(dataset) = dataset {
+            if let Err(e) = request_metrics.write(&dataset) {
+                log.warn("metrics not written", &[("error", e.to_string().into())]);
+            }
+        }
+        if let Some(db) = ledger_db {
+            let row = LedgerRow {
+                request_id,
+                ts: Date::now().as_millis(),
+                metrics: request_metrics.clone(),
+            };
+            if let Err(e) = ledger::insert(&db, &row).await {
+                log.warn("ledger row not written", &[("error", e.to_string().into())]);
+            }
+        }
+        if let Some(monitor) = monitor {
+            let observation = Observation {
+                status: request_metrics.status,
+                latency_ms: request_metrics.latency_ms,
+                cost_usd: request_metrics.cost_usd.unwrap_or(0.0),
+            };
+            if let Err(e) = alerts::observe(&monitor, &observation).await {
+                log.warn(
+                    "alert monitor not updated",
+                    &[("error", e.to_string().into())],
+                );
+            }
+        }
+    });
+}
+
+/// Sends an error report to the configured sink without delaying the response
+pub(crate) fn report_error(
+    ctx: &Context,
+    client: &http::Client,
+    config: &Config,
+    log: &Logger,
+    report: ErrorReport,
+) {
+    let Some(sink) = config.error_reporting.clone() else {
+        return;
+    };
+    let client = *client;
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = reporting::send(&client, &sink, &report).await {
+            log.warn("error report not sent", &[("error", e.into())]);
+        }
+    });
+}
+
+/// Stores a sanitized transcript of the request in R2 when `LOG_TO_R2` is on
+#[allow(clippy::too_many_arguments)]
+fn record_transcript(
+    ctx: &Context,
+    env: &Env,
+    config: &Config,
+    request: &AnthropicRequest,
+    upstream_model: &str,
+    response: Option<serde_json::Value>,
+    usage: Option<&Usage>,
+    log: &Logger,
+) {
+    if config.log_to_r2 == TranscriptMode::Off {
+        return;
+    }
+    let bucket = match env.bucket(transcripts::TRANSCRIPT_BUCKET_BINDING) {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            log.warn(
+                "transcript bucket unavailable",
+                &[("error", e.to_string().into())],
             );
-            worker::Error::RustError(format!("Request failed: {e}"))
-        })?;
+            return;
+        }
+    };
+
+    let record = transcripts::transcript_record(
+        log.request_id(),
+        Date::now().as_millis(),
+        config.log_to_r2,
+        request,
+        upstream_model,
+        response,
+        usage,
+    );
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = transcripts::store(&bucket, &record).await {
+            log.warn("transcript not stored", &[("error", e.to_string().into())]);
+        }
+    });
+}
 
-    let _elapsed = check_time("HTTP request complete");
+/// Saves the conversation's thread so far to R2 for `/sessions/{id}/export`
+#[allow(clippy::too_many_arguments)]
+fn record_session_transcript(
+    ctx: &Context,
+    env: &Env,
+    subject: &str,
+    (session_id, thread): (String, String),
+    request: &AnthropicRequest,
+    upstream_model: &str,
+    reply: Option<serde_json::Value>,
+    log: &Logger,
+) {
+    let bucket = match env.bucket(transcripts::TRANSCRIPT_BUCKET_BINDING) {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            log.warn(
+                "transcript bucket unavailable",
+                &[("error", e.to_string().into())],
+            );
+            return;
+        }
+    };
 
-    // Minimal debug logging
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&format!("Response: {}", response.status()).into());
-
-    // Handle error responses from OpenRouter
-    if !response.status().is_success() {
-        let status = response.status().as_u16();
-        let error_text = response
-            .text()
-            .await
-            .map_err(|e| worker::Error::RustError(format!("Failed to read error response: {e}")))?;
+    let prefix = transcripts::sessions::session_prefix(subject, &session_id);
+    let record = transcripts::sessions::thread_record(
+        &session_id,
+        &thread,
+        Date::now().as_millis(),
+        request,
+        upstream_model,
+        reply,
+    );
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = transcripts::sessions::store(&bucket, &prefix, &record).await {
+            log.warn(
+                "session transcript not stored",
+                &[("error", e.to_string().into())],
+            );
+        }
+    });
+}
+
+/// Caches a response for identical or similar requests, after the response is sent
+fn store_cached(
+    ctx: &Context,
+    config: &Config,
+    exact: Option<(KvStore, String)>,
+    semantic: Option<(SemanticCache, semantic_cache::Entry)>,
+    response: CachedResponse,
+    log: &Logger,
+) {
+    let ttl_secs = config.response_cache_ttl_secs;
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Some((kv, storage_key)) = exact {
+            if let Err(e) = response_cache::store(&kv, &storage_key, &response, ttl_secs).await {
+                log.warn("response not cached", &[("error", e.to_string().into())]);
+            }
+        }
+        if let Some((semantic, entry)) = semantic {
+            if let Err(e) = semantic.store(&entry, &response, ttl_secs).await {
+                log.warn(
+                    "response not semantically cached",
+                    &[("error", e.to_string().into())],
+                );
+            }
+        }
+    });
+}
 
-        // Log error details for debugging
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&format!("OpenRouter Error {}: {}", status, error_text).into());
+/// Adds the new messages and the assistant's reply to the caller's session once the
+/// response is sent
+///
+/// A turn whose reply couldn't be rebuilt isn't stored, so the history keeps
+/// alternating between user and assistant.
+fn record_session_turn(
+    ctx: &Context,
+    config: &Config,
+    (stub, mut messages): (Stub, Vec<serde_json::Value>),
+    reply: Option<serde_json::Value>,
+    log: &Logger,
+) {
+    let Some(reply) = reply else {
+        log.warn(
+            "session turn not stored",
+            &[("error", "empty reply".into())],
+        );
+        return;
+    };
+    messages.push(reply);
+    let (max_messages, ttl_secs) = (config.session_max_messages, config.session_ttl_secs);
+    let log = log.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = sessions::append(&stub, messages, max_messages, ttl_secs).await {
+            log.warn(
+                "session turn not stored",
+                &[("error", e.to_string().into())],
+            );
+        }
+    });
+}
 
-        // Transform OpenRouter error to Anthropic format with safe fallback
-        let anthropic_error =
-            transform_openrouter_error_safe(&error_text, status, &anthropic_request);
+/// Sends the upstream request, hedging against a secondary model when configured
+///
+/// If the primary model hasn't responded within `HEDGE_DELAY_MS`, the same request is
+/// fired at `HEDGE_MODEL` and whichever responds first wins. Dropping the losing
+/// future aborts its in-flight fetch. A failed request falls back to the other one.
+/// The hedge model is returned with the response when it was the one to answer.
+/// It is sent with `hedge_key`, which may belong to another model family.
+///
+/// Streaming requests race on the response headers, others on the whole response.
+#[allow(clippy::too_many_arguments)]
+async fn send_with_hedging<C: UpstreamClient>(
+    client: &C,
+    url: &str,
+    api_key: &str,
+    hedge_key: &str,
+    openai_request: &OpenAIRequest,
+    streaming: bool,
+    config: &Config,
+    log: &Logger,
+) -> Result<(UpstreamResponse, Option<String>)> {
+    let send = |api_key: &str, request: &OpenAIRequest| {
+        if streaming {
+            client.send_streaming(url, api_key, request)
+        } else {
+            client.send_json(url, api_key, request)
+        }
+    };
+    let primary = send(api_key, openai_request);
 
-        // Create response with JSON and proper status code
-        let response = Response::from_json(&anthropic_error)?.with_status(status);
-        return Ok(response);
+    let Some(hedge_model) = config.hedge_target(&openai_request.model) else {
+        return Ok((primary.await?, None));
+    };
+
+    let delay = Delay::from(Duration::from_millis(config.hedge_delay_ms));
+    futures::pin_mut!(primary, delay);
+    let primary = match select(primary, delay).await {
+        Either::Left((result, _)) => return Ok((result?, None)),
+        Either::Right(((), primary)) => primary,
+    };
+
+    log.debug("hedging", &[("hedge_model", hedge_model.into())]);
+
+    let mut hedge_request = openai_request.clone();
+    hedge_request.model = hedge_model.to_string();
+    let secondary = send(hedge_key, &hedge_request);
+    futures::pin_mut!(secondary);
+
+    let hedged = Some(hedge_model.to_string());
+    match select(primary, secondary).await {
+        Either::Left((Ok(response), _)) => Ok((response, None)),
+        Either::Right((Ok(response), _)) => Ok((response, hedged)),
+        Either::Left((Err(_), secondary)) => Ok((secondary.await?, hedged)),
+        Either::Right((Err(_), primary)) => Ok((primary.await?, None)),
+    }
+}
+
+/// Checks each subject's limits in order, returning the first rejection
+///
+/// Subjects without limits are skipped, so the Durable Object binding is only
+/// required once a limit is configured.
+async fn check_rate_limits(
+    env: &Env,
+    subjects: &[(String, Limits)],
+    messages: &[serde_json::Value],
+) -> Result<Option<RateLimitDecision>> {
+    let subjects: Vec<_> = subjects
+        .iter()
+        .filter(|(_, limits)| !limits.is_unlimited())
+        .collect();
+    if subjects.is_empty() {
+        return Ok(None);
+    }
+
+    let namespace = env.durable_object(rate_limit::RATE_LIMITER_BINDING)?;
+    let messages_len = serde_json::to_vec(messages).map_or(0, |body| body.len());
+    let tokens = rate_limit::estimate_tokens(messages_len);
+
+    for (subject, limits) in subjects {
+        let decision = rate_limit::check(&namespace, subject, *limits, tokens).await?;
+        if !decision.allowed {
+            return Ok(Some(decision));
+        }
     }
+    Ok(None)
+}
 
-    // Handle streaming vs non-streaming responses
-    if anthropic_request.stream.unwrap_or(false) {
-        // Handle streaming response
-        stream_openai_to_anthropic(response, &anthropic_request.model).await
+/// Answers a request blocked by moderation with a refusal, streamed if it asked to be
+fn refusal_response(request: &AnthropicRequest, log: &Logger) -> Result<Response> {
+    let id = format!("msg_{}", log.request_id());
+    let mut response = if request.stream.unwrap_or(false) {
+        event_stream_response(moderation::refusal_events(&id, &request.model))?
     } else {
-        // Parse OpenRouter response
-        let openai_response: serde_json::Value = response.json().await.map_err(|e| {
-            worker::Error::RustError(format!("Failed to parse OpenAI response: {e}"))
-        })?;
+        Response::from_json(&moderation::refusal_message(&id, &request.model))?
+    };
+    response
+        .headers_mut()
+        .set(moderation::MODERATION_HEADER, "blocked")?;
+    Ok(response)
+}
+
+/// Answers a `/model` directive, streamed if the request asked to be
+fn directive_response(request: &AnthropicRequest, text: &str, log: &Logger) -> Result<Response> {
+    let id = format!("msg_{}", log.request_id());
+    if request.stream.unwrap_or(false) {
+        event_stream_response(text_message_events(&id, &request.model, text, "end_turn"))
+    } else {
+        Response::from_json(&session_model::acknowledgement_message(
+            &id,
+            &request.model,
+            text,
+        ))
+    }
+}
+
+/// Builds an Anthropic-format error response for errors raised by CCR itself
+pub(crate) fn error_response(status: u16, error_type: &str, message: &str) -> Result<Response> {
+    let body = serde_json::json!({
+        "type": "error",
+        "error": {
+            "type": error_type,
+            "message": message
+        }
+    });
+    Ok(Response::from_json(&body)?.with_status(status))
+}
+
+/// Reports a failure to a streaming client as a `200` stream holding an `error` event
+///
+/// Used when CCR fails before the upstream answered; errors the upstream returned
+/// keep their status and JSON body, which clients read before starting a stream.
+pub(crate) fn stream_error_response(error_type: &str, message: &str) -> Result<Response> {
+    event_stream_response(error_events(error_type, message))
+}
+
+/// Error type for requests the upstream's content moderation refused, so policy
+/// blocks can be told apart from API failures
+pub(crate) const UPSTREAM_MODERATION: &str = "upstream_moderation";
 
-        // Debug logging removed for performance
+/// Stable codes in the `code` field of upstream error bodies, for scripts to
+/// branch on rather than parse messages that change with the provider
+pub(crate) const CODE_RATE_LIMITED: &str = "ccr_upstream_rate_limited";
+pub(crate) const CODE_MODEL_NOT_FOUND: &str = "ccr_model_not_found";
+pub(crate) const CODE_CONTEXT_EXCEEDED: &str = "ccr_context_exceeded";
+pub(crate) const CODE_MODERATION_BLOCKED: &str = "ccr_moderation_blocked";
 
-        // Transform back to Anthropic format
-        let anthropic_response = openai_to_anthropic(&openai_response, &anthropic_request.model)?;
+/// The CCR code for an upstream error response, when it is one scripts act on
+///
+/// Providers word the same failure differently, so the upstream message is
+/// matched on the phrases OpenRouter and its providers use.
+fn upstream_error_code(status: u16, error_text: &str) -> Option<&'static str> {
+    let body = serde_json::from_str::<serde_json::Value>(error_text).unwrap_or_default();
+    let error = &body["error"];
+    if is_moderation_error(error) {
+        return Some(CODE_MODERATION_BLOCKED);
+    }
+    let message = error["message"]
+        .as_str()
+        .unwrap_or(error_text)
+        .to_ascii_lowercase();
+    let mentions = |phrases: &[&str]| phrases.iter().any(|phrase| message.contains(phrase));
+    if error["code"] == "context_length_exceeded"
+        || mentions(&["context length", "context window", "prompt is too long"])
+    {
+        Some(CODE_CONTEXT_EXCEEDED)
+    } else if status == 429 {
+        Some(CODE_RATE_LIMITED)
+    } else if status == 404 || mentions(&["not a valid model", "no endpoints found"]) {
+        Some(CODE_MODEL_NOT_FOUND)
+    } else {
+        None
+    }
+}
+
+/// Whether an upstream status means the provider (or the gateway in front of
+/// it) is overloaded: 502, 503, Cloudflare's 520-527 and Anthropic's own 529
+fn is_overloaded(status: u16) -> bool {
+    matches!(status, 502 | 503 | 520..=527 | 529)
+}
 
-        // Debug logging removed for performance
+/// The status an upstream error reaches clients with: overloaded upstreams
+/// answer 529, which Anthropic SDKs back off and retry on
+pub(crate) fn client_status(status: u16) -> u16 {
+    if is_overloaded(status) {
+        529
+    } else {
+        status
+    }
+}
+
+/// The Anthropic error type for an upstream error response
+fn upstream_error_type(status: u16, error_text: &str) -> &'static str {
+    let blocked = serde_json::from_str::<serde_json::Value>(error_text)
+        .is_ok_and(|body| is_moderation_error(&body["error"]));
+    match status {
+        _ if blocked => UPSTREAM_MODERATION,
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        429 => "rate_limit_error",
+        _ if is_overloaded(status) => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+/// Whether an OpenRouter error object reports a moderation flag, which carries
+/// the flagged input and reasons in its metadata, or a provider's content filter
+fn is_moderation_error(error: &serde_json::Value) -> bool {
+    let metadata = &error["metadata"];
+    metadata.get("flagged_input").is_some()
+        || metadata["reasons"].is_array()
+        || error["code"] == "content_filter"
+}
 
-        // Return Anthropic-formatted response to client
-        Response::from_json(&anthropic_response)
+/// Why the upstream refused a successful request: a refusal message, or a
+/// content filter stop with nothing generated
+fn refusal(response: &OpenAICompletion) -> Option<String> {
+    let choice = response.choices.as_deref()?.first()?;
+    let message = &choice.message;
+    if let Some(refusal) = message.refusal.as_str().filter(|r| !r.trim().is_empty()) {
+        return Some(refusal.to_string());
     }
+    let empty = message.content.as_str().is_none_or(|c| c.trim().is_empty())
+        && message
+            .tool_calls
+            .as_ref()
+            .is_none_or(|calls| calls.is_empty());
+    (choice.finish_reason == "content_filter" && empty)
+        .then(|| "The response was blocked by the provider's content filter".to_string())
 }
 
 /// Safe wrapper for error transformation that prevents worker crashes
@@ -181,21 +2608,17 @@ fn transform_openrouter_error_safe(
         error_text
     );
 
-    serde_json::json!({
+    let mut anthropic_error = serde_json::json!({
         "type": "error",
         "error": {
-            "type": match status_code {
-                400 => "invalid_request_error",
-                401 => "authentication_error",
-                403 => "permission_error",
-                404 => "not_found_error",
-                429 => "rate_limit_error",
-                500..=599 => "api_error",
-                _ => "api_error"
-            },
+            "type": upstream_error_type(status_code, error_text),
             "message": basic_message
         }
-    })
+    });
+    if let Some(code) = upstream_error_code(status_code, error_text) {
+        anthropic_error["error"]["code"] = code.into();
+    }
+    anthropic_error
 }
 
 /// Transform OpenRouter error response to Anthropic format with comprehensive diagnostics and request context
@@ -356,22 +2779,17 @@ fn transform_openrouter_error(
     let mut anthropic_error = serde_json::json!({
         "type": "error",
         "error": {
-            "type": match status_code {
-                400 => "invalid_request_error",
-                401 => "authentication_error",
-                403 => "permission_error",
-                404 => "not_found_error",
-                429 => "rate_limit_error",
-                500..=599 => "api_error",
-                _ => "api_error"
-            },
+            "type": upstream_error_type(status_code, error_text),
             "message": comprehensive_message
         }
     });
 
     // Add additional diagnostic fields if available
+    if let Some(code) = upstream_error_code(status_code, error_text) {
+        anthropic_error["error"]["code"] = code.into();
+    }
     if let Some(code) = error_code {
-        anthropic_error["error"]["code"] = code;
+        anthropic_error["error"]["upstream_code"] = code;
     }
 
     if let Some(param) = param_info {
@@ -391,3 +2809,337 @@ fn transform_openrouter_error(
 
     anthropic_error
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::transform::anthropic_to_openai;
+    use crate::upstream::MockClient;
+
+    /// The runtime's clock is JavaScript's, which native tests don't have
+    fn now_ms() -> u64 {
+        SystemClock.now_ms()
+    }
+
+    const URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+    const OK_RESPONSE: &str =
+        r#"{"choices":[{"message":{"role":"assistant","content":"Hi"},"finish_reason":"stop"}]}"#;
+
+    fn requests(stream: bool) -> (AnthropicRequest, OpenAIRequest, Config) {
+        let anthropic_request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 256,
+            "stream": stream,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap();
+        let config = Config::default();
+        let openai_request = anthropic_to_openai(&anthropic_request, &config).unwrap();
+        (anthropic_request, openai_request, config)
+    }
+
+    async fn run(
+        client: &MockClient,
+        stream: bool,
+    ) -> std::result::Result<Forwarded, ForwardError> {
+        let (_, _, config) = requests(stream);
+        run_with(client, stream, config).await
+    }
+
+    async fn run_with(
+        client: &MockClient,
+        stream: bool,
+        config: Config,
+    ) -> std::result::Result<Forwarded, ForwardError> {
+        let (anthropic_request, openai_request, _) = requests(stream);
+        forward(
+            client,
+            URL,
+            "sk-or-caller",
+            "sk-or-caller",
+            &anthropic_request,
+            &openai_request,
+            &config,
+            &Budget::new(now_ms(), config.slow_request_warn_ms, now_ms),
+            &Logger::new("test"),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_forward_sends_translated_request_with_caller_key() {
+        let client = MockClient::default().reply(
+            200,
+            &[r#"{"choices":[{"message":{"role":"assistant","content":"Hi"},"finish_reason":"stop"}]}"#],
+        );
+        run(&client, false).await.unwrap();
+
+        let sent = client.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].url, URL);
+        assert_eq!(sent[0].api_key, "sk-or-caller");
+        assert!(!sent[0].streaming);
+        assert_eq!(sent[0].body["messages"][0]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_forward_returns_message_with_usage() {
+        let client = MockClient::default().reply(
+            200,
+            &[r#"{"choices":[{"message":{"role":"assistant","content":"Hi"},"finish_reason":"stop"}],"usage":{"prompt_tokens":12,"completion_tokens":3}}"#],
+        );
+        let Forwarded::Message {
+            openai_response,
+            usage,
+            ..
+        } = run(&client, false).await.unwrap()
+        else {
+            panic!("expected a message");
+        };
+        assert_eq!(usage.unwrap().input_tokens, 12);
+        let message = completion_to_anthropic(openai_response, "claude-sonnet-4").unwrap();
+        assert_eq!(message.model, "claude-sonnet-4");
+    }
+
+    #[tokio::test]
+    async fn test_forward_maps_upstream_errors_to_anthropic() {
+        let client = MockClient::default().reply(
+            429,
+            &[r#"{"error":{"message":"Rate limit exceeded","code":429}}"#],
+        );
+        let config = Config {
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            },
+            ..Config::default()
+        };
+        let Forwarded::Error {
+            status,
+            error_text,
+            body,
+            ..
+        } = run_with(&client, false, config).await.unwrap()
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(status, 429);
+        assert!(error_text.contains("Rate limit exceeded"));
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["code"], CODE_RATE_LIMITED);
+    }
+
+    #[test]
+    fn test_upstream_errors_carry_ccr_codes() {
+        let cases = [
+            (
+                400,
+                r#"{"error":{"message":"This endpoint's maximum context length is 163840 tokens. However, you requested about 201337 tokens.","code":400}}"#,
+                Some(CODE_CONTEXT_EXCEEDED),
+            ),
+            (
+                400,
+                r#"{"error":{"message":"too long","code":"context_length_exceeded"}}"#,
+                Some(CODE_CONTEXT_EXCEEDED),
+            ),
+            (
+                400,
+                r#"{"error":{"message":"deepseek/deepseek-chatt is not a valid model ID","code":400}}"#,
+                Some(CODE_MODEL_NOT_FOUND),
+            ),
+            (
+                404,
+                r#"{"error":{"message":"No endpoints found for openai/gpt-9.","code":404}}"#,
+                Some(CODE_MODEL_NOT_FOUND),
+            ),
+            (429, "Too Many Requests", Some(CODE_RATE_LIMITED)),
+            (
+                403,
+                r#"{"error":{"code":403,"message":"flagged","metadata":{"reasons":["violence"]}}}"#,
+                Some(CODE_MODERATION_BLOCKED),
+            ),
+            (
+                400,
+                r#"{"error":{"message":"temperature must be at most 2","code":400}}"#,
+                None,
+            ),
+            (502, "Bad Gateway", None),
+        ];
+        let (request, _, _) = requests(false);
+        for (status, error_text, code) in cases {
+            assert_eq!(
+                upstream_error_code(status, error_text),
+                code,
+                "{error_text}"
+            );
+            let code = code.map_or(serde_json::Value::Null, Into::into);
+            let safe = transform_openrouter_error_safe(error_text, status, &request);
+            assert_eq!(safe["error"]["code"], code, "{error_text}");
+            let detailed = transform_openrouter_error(error_text, status, &request);
+            assert_eq!(detailed["error"]["code"], code, "{error_text}");
+        }
+
+        // The upstream's own code is kept alongside in detailed errors
+        let detailed = transform_openrouter_error(cases[0].1, 400, &request);
+        assert_eq!(detailed["error"]["upstream_code"], 400);
+    }
+
+    #[tokio::test]
+    async fn test_forward_distinguishes_moderation_blocks() {
+        let client = MockClient::default().reply(
+            403,
+            &[r#"{"error":{"code":403,"message":"openai/gpt-4o requires moderation on OpenAI. Your input was flagged for \"violence\".","metadata":{"reasons":["violence"],"flagged_input":"...","provider_name":"OpenAI","model_slug":"openai/gpt-4o"}}}"#],
+        );
+        let Forwarded::Error { status, body, .. } = run(&client, false).await.unwrap() else {
+            panic!("expected an error");
+        };
+        assert_eq!(status, 403);
+        assert_eq!(body["error"]["type"], UPSTREAM_MODERATION);
+
+        let client = MockClient::default().reply(
+            200,
+            &[r#"{"choices":[{"message":{"role":"assistant","content":null,"refusal":"I can't help with that."},"finish_reason":"stop"}]}"#],
+        );
+        let Forwarded::Error { status, body, .. } = run(&client, false).await.unwrap() else {
+            panic!("expected an error");
+        };
+        assert_eq!(status, 403);
+        assert_eq!(body["error"]["type"], UPSTREAM_MODERATION);
+        assert_eq!(body["error"]["code"], CODE_MODERATION_BLOCKED);
+        assert_eq!(body["error"]["message"], "I can't help with that.");
+
+        let filtered = completion(r#"{"choices": [{"message": {"role": "assistant", "content": ""}, "finish_reason": "content_filter"}]}"#).unwrap();
+        assert!(refusal(&filtered).is_some());
+        let partial = completion(r#"{"choices": [{"message": {"role": "assistant", "content": "Here is"}, "finish_reason": "content_filter"}]}"#).unwrap();
+        assert_eq!(refusal(&partial), None);
+
+        assert_eq!(upstream_error_type(403, "{}"), "permission_error");
+        assert_eq!(
+            upstream_error_type(503, "upstream down"),
+            "overloaded_error"
+        );
+        assert_eq!(upstream_error_type(520, "{}"), "overloaded_error");
+        assert_eq!(upstream_error_type(500, "{}"), "api_error");
+        assert_eq!(client_status(502), 529);
+        assert_eq!(client_status(529), 529);
+        assert_eq!(client_status(500), 500);
+    }
+
+    #[tokio::test]
+    async fn test_forward_streams_events_across_chunk_boundaries() {
+        let client = MockClient::default().reply(
+            200,
+            &[
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\ndata: {\"choi",
+                "ces\":[{\"delta\":{\"content\":\"lo\"}}],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}}\n\n",
+                "data: [DONE]\n\n",
+            ],
+        );
+        let Forwarded::Stream { events, usage, .. } = run(&client, true).await.unwrap() else {
+            panic!("expected a stream");
+        };
+        assert!(client.sent.borrow()[0].streaming);
+        assert!(events.starts_with("event: message_start"));
+        assert!(events.contains("\"text\":\"Hel\""));
+        assert!(events.contains("\"text\":\"lo\""));
+        assert!(events.contains("event: message_stop"));
+        assert_eq!(usage.unwrap().output_tokens, 2);
+    }
+
+    #[tokio::test]
+    async fn test_forward_reports_unparseable_responses() {
+        let client = MockClient::default()
+            .reply(200, &["<html>Bad Gateway</html>"])
+            .reply(200, &[r#"{"choices":[{"message":{"role":"assis"#]);
+        let Forwarded::Error {
+            status,
+            error_text,
+            body,
+            ..
+        } = run(&client, false).await.unwrap()
+        else {
+            panic!("expected an error");
+        };
+        assert_eq!(client.sent.borrow().len(), 2);
+        assert_eq!(status, 502);
+        assert!(error_text.starts_with("{\"choices\""));
+        assert_eq!(body["error"]["type"], "api_error");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Upstream returned an unusable response: malformed JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_retries_empty_choices_once() {
+        let client = MockClient::default()
+            .reply(200, &[r#"{"id":"gen-1","choices":[]}"#])
+            .reply(200, &[OK_RESPONSE]);
+        let forwarded = run(&client, false).await.unwrap();
+        assert!(matches!(forwarded, Forwarded::Message { .. }));
+        assert_eq!(client.sent.borrow().len(), 2);
+
+        assert_eq!(
+            completion(r#"{"choices":[]}"#),
+            Err("empty choices".to_string())
+        );
+        assert_eq!(
+            completion(r#"{"error":{"message":"Provider returned error","code":502}}"#),
+            Err("error in a 200 response (Provider returned error)".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_reports_failed_requests() {
+        let client = MockClient::default();
+        let failure = run(&client, false).await.err().unwrap();
+        assert_eq!(failure.stage, "upstream");
+        assert!(failure.message.starts_with("Request failed"));
+        assert_eq!(failure.status, None);
+        assert_eq!(client.sent.borrow().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_forward_retries_transient_failures_with_backoff() {
+        let client = MockClient::default()
+            .reply(503, &[r#"{"error":{"message":"Service unavailable"}}"#])
+            .reply(429, &[r#"{"error":{"message":"Rate limit exceeded"}}"#])
+            .reply(200, &[OK_RESPONSE]);
+        let forwarded = run(&client, false).await.unwrap();
+
+        assert!(matches!(forwarded, Forwarded::Message { .. }));
+        assert_eq!(client.sent.borrow().len(), 3);
+        let slept = client.slept.borrow();
+        assert_eq!(slept.len(), 2);
+        assert!((125..=250).contains(&slept[0]), "{slept:?}");
+        assert!((250..=500).contains(&slept[1]), "{slept:?}");
+    }
+
+    #[tokio::test]
+    async fn test_forward_does_not_retry_client_errors() {
+        let client = MockClient::default()
+            .reply(400, &[r#"{"error":{"message":"Bad request"}}"#])
+            .reply(200, &[OK_RESPONSE]);
+        let forwarded = run(&client, false).await.unwrap();
+        assert!(matches!(forwarded, Forwarded::Error { status: 400, .. }));
+        assert_eq!(client.sent.borrow().len(), 1);
+        assert!(client.slept.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forward_retries_overloaded_streams_before_any_event() {
+        let client = MockClient::default().reply(503, &["upstream down"]).reply(
+            200,
+            &[
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+                "data: [DONE]\n\n",
+            ],
+        );
+        let forwarded = run(&client, true).await.unwrap();
+        assert!(matches!(forwarded, Forwarded::Stream { .. }));
+        assert_eq!(client.sent.borrow().len(), 2);
+        assert_eq!(client.slept.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use super::admin::require_admin;
+use super::proxy::{forward, Forwarded};
+use crate::config::Config;
+use crate::deadline::Budget;
+use crate::ledger;
+use crate::log::Logger;
+use crate::replay::{diff_responses, Transcript};
+use crate::transcripts;
+use crate::transform::{anthropic_to_openai, completion_to_anthropic};
+use crate::upstream::FetchClient;
+use crate::utils::format_date;
+use serde_json::json;
+use worker::{Env, Request, Response, Result};
+
+/// Whether `date` has the `YYYY-MM-DD` shape transcript keys use
+fn is_valid_date(date: &str) -> bool {
+    date.len() == 10
+        && date.bytes().enumerate().all(|(i, b)| match i {
+            4 | 7 => b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+/// Handles POST /admin/replay/{id}
+///
+/// Sends the request of a stored transcript (see `LOG_TO_R2`) upstream again,
+/// translated with the current configuration or to the upstream model given as
+/// `?model=`, and returns both responses with a diff. The transcript is looked up
+/// on `?date=` (`YYYY-MM-DD`), or without it, on the day the request ledger
+/// recorded the request. Replays use the server's upstream key.
+pub async fn replay(
+    req: Request,
+    id: &str,
+    env: &Env,
+    config: &Config,
+    budget: &Budget,
+    log: &Logger,
+) -> Result<Response> {
+    if let Some(denied) = require_admin(&req, env)? {
+        return Ok(denied);
+    }
+    let Ok(bucket) = env.bucket(transcripts::TRANSCRIPT_BUCKET_BINDING) else {
+        return Response::error("Replays require a TRANSCRIPT_BUCKET binding", 501);
+    };
+    if id.contains('/') {
+        return Response::error("Not Found", 404);
+    }
+
+    let url = req.url()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let date = match param("date") {
+        Some(date) if is_valid_date(&date) => date,
+        Some(_) => return Response::error("date must be YYYY-MM-DD", 400),
+        None => {
+            let Ok(db) = env.d1(ledger::LEDGER_BINDING) else {
+                return Response::error("date is required without a LEDGER_DB binding", 400);
+            };
+            match ledger::timestamp_of(&db, id).await? {
+                Some(ts) => format_date(ts),
+                None => return Response::error(format!("Request {id} is not in the ledger"), 404),
+            }
+        }
+    };
+
+    let Some(record) = transcripts::load(&bucket, &date, id).await? else {
+        return Response::error(format!("No transcript for request {id} on {date}"), 404);
+    };
+    let transcript = match Transcript::from_record(&record) {
+        Ok(transcript) => transcript,
+        Err(message) => return Response::error(message, 422),
+    };
+
+    let mut openai_request = anthropic_to_openai(&transcript.request, config)?;
+    if let Some(model) = param("model") {
+        openai_request.model = model;
+    }
+    let api_key = match &config.server_api_key {
+        Some(credential) => credential.resolve(env).await?,
+        None => None,
+    };
+    let Some(api_key) = api_key else {
+        return Response::error("Replays require OPENROUTER_API_KEY", 501);
+    };
+
+    log.info(
+        "replaying transcript",
+        &[
+            ("replayed_id", id.into()),
+            ("model", openai_request.model.as_str().into()),
+        ],
+    );
+    let upstream = FetchClient::new()
+        .with_timeout(config.upstream_timeout_ms)
+        .with_attribution(config.attribution.clone());
+    let url = format!("{}/chat/completions", config.openrouter_base_url);
+    let forwarded = forward(
+        &upstream,
+        &url,
+        &api_key,
+        &api_key,
+        &transcript.request,
+        &openai_request,
+        config,
+        budget,
+        log,
+    )
+    .await;
+    let replayed = match forwarded {
+        Ok(Forwarded::Message {
+            openai_response, ..
+        }) => match completion_to_anthropic(openai_response, &transcript.request.model) {
+            Ok(message) => serde_json::to_value(message)?,
+            Err(e) => return Response::error(e.to_string(), 502),
+        },
+        Ok(Forwarded::Error { status, body, .. }) => {
+            let body = json!({
+                "id": transcript.id,
+                "replay": {"upstream_model": openai_request.model, "status": status, "error": body},
+            });
+            return Ok(Response::from_json(&body)?.with_status(502));
+        }
+        Ok(Forwarded::Stream { .. }) => return Response::error("Replays can't stream", 500),
+        Err(e) => return Response::error(e.message, 502),
+    };
+
+    let diff = diff_responses(transcript.response.as_ref(), &replayed);
+    Response::from_json(&json!({
+        "id": transcript.id,
+        "original": {
+            "upstream_model": transcript.upstream_model,
+            "response": transcript.response,
+        },
+        "replay": {
+            "upstream_model": openai_request.model,
+            "response": replayed,
+        },
+        "diff": diff,
+    }))
+}
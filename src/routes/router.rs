@@ -0,0 +1,298 @@
+use crate::async_jobs;
+use crate::attachments;
+use worker::Method;
+
+/// An endpoint served by the worker, with any parameters taken from its path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Home,
+    Terms,
+    Privacy,
+    /// A file the pages link to, e.g. `app.css`
+    Asset(String),
+    ApiInfo,
+    /// Liveness for uptime checks, answered during maintenance too
+    Health,
+    Usage,
+    UsageForecast,
+    AdminSelftest,
+    AdminTail,
+    AdminLedger,
+    /// Replay of a stored transcript; holds the request ID
+    AdminReplay(String),
+    Models,
+    /// A single model; the ID is still percent-encoded
+    Model(String),
+    Messages,
+    ChatCompletions,
+    Embeddings,
+    /// One prompt sent to several models, answered side by side
+    ExperimentsCompare,
+    /// A conversation converted between the Anthropic and OpenAI formats
+    Convert,
+    /// A Gemini method call; holds `{model}:{method}`
+    Gemini(String),
+    /// Status and result of an asynchronous request; holds the job ID
+    AsyncJob(String),
+    /// An offloaded attachment behind a signed link; holds its ID
+    Attachment(String),
+    /// A saved session transcript as Markdown or JSON; holds the session ID
+    SessionExport(String),
+}
+
+impl Route {
+    /// Whether the route is a model API, authenticated the way `/v1/messages` is
+    pub fn is_api(&self) -> bool {
+        matches!(
+            self,
+            Route::Messages
+                | Route::ChatCompletions
+                | Route::Embeddings
+                | Route::ExperimentsCompare
+                | Route::Gemini(_)
+        )
+    }
+
+    /// Whether the route identifies its caller, as the model APIs do
+    pub fn is_authenticated(&self) -> bool {
+        self.is_api()
+            || matches!(
+                self,
+                Route::Convert | Route::AsyncJob(_) | Route::SessionExport(_)
+            )
+    }
+
+    /// Whether the route is under `/v1` and so callable from browsers when CORS is on
+    pub fn is_cross_origin(&self) -> bool {
+        self.is_authenticated() || matches!(self, Route::Models | Route::Model(_))
+    }
+
+    /// The method the route is served with
+    pub fn method(&self) -> Method {
+        if self.is_api() || matches!(self, Route::Convert | Route::AdminReplay(_)) {
+            Method::Post
+        } else {
+            Method::Get
+        }
+    }
+}
+
+/// Outcome of matching a request against the routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The route for the path; also returned for `OPTIONS`, which the CORS middleware answers
+    Found(Route),
+    /// The path exists but not for this method; holds the method that is allowed
+    MethodNotAllowed(Method),
+    NotFound,
+}
+
+/// How a table entry matches a path
+enum Pattern {
+    Exact(&'static str, Route),
+    /// Matches paths with a non-empty remainder after the prefix
+    Prefix(&'static str, fn(String) -> Route),
+    /// Matches paths with a non-empty segment between the prefix and suffix
+    Enclosed(&'static str, &'static str, fn(String) -> Route),
+}
+
+/// The routing table, checked in order
+const ROUTES: &[Pattern] = &[
+    Pattern::Exact("/", Route::Home),
+    Pattern::Exact("/terms", Route::Terms),
+    Pattern::Exact("/privacy", Route::Privacy),
+    Pattern::Prefix("/assets/", Route::Asset),
+    Pattern::Exact("/api/info", Route::ApiInfo),
+    Pattern::Exact("/health", Route::Health),
+    Pattern::Exact("/usage", Route::Usage),
+    Pattern::Exact("/usage/forecast", Route::UsageForecast),
+    Pattern::Exact("/admin/selftest", Route::AdminSelftest),
+    Pattern::Exact("/admin/tail", Route::AdminTail),
+    Pattern::Exact("/admin/ledger", Route::AdminLedger),
+    Pattern::Prefix("/admin/replay/", Route::AdminReplay),
+    Pattern::Exact("/v1/models", Route::Models),
+    Pattern::Prefix("/v1/models/", Route::Model),
+    Pattern::Exact("/v1/messages", Route::Messages),
+    Pattern::Exact("/v1/chat/completions", Route::ChatCompletions),
+    Pattern::Exact("/v1/embeddings", Route::Embeddings),
+    Pattern::Exact("/v1/experiments/compare", Route::ExperimentsCompare),
+    Pattern::Exact("/v1/convert", Route::Convert),
+    Pattern::Prefix(async_jobs::POLL_PATH, Route::AsyncJob),
+    Pattern::Prefix(attachments::ATTACHMENT_PATH, Route::Attachment),
+    Pattern::Prefix("/v1beta/models/", Route::Gemini),
+    Pattern::Enclosed("/sessions/", "/export", Route::SessionExport),
+];
+
+/// Matches a request path and method to a route
+///
+/// A path that exists under a different method resolves to `MethodNotAllowed`
+/// so clients get a 405 rather than a misleading 404.
+pub fn resolve(path: &str, method: &Method) -> Resolution {
+    let route = ROUTES.iter().find_map(|pattern| match pattern {
+        Pattern::Exact(exact, route) if path == *exact => Some(route.clone()),
+        Pattern::Prefix(prefix, route) => match path.strip_prefix(prefix) {
+            Some(rest) if !rest.is_empty() => Some(route(rest.to_string())),
+            _ => None,
+        },
+        Pattern::Enclosed(prefix, suffix, route) => {
+            match path.strip_prefix(prefix)?.strip_suffix(suffix) {
+                Some(segment) if !segment.is_empty() && !segment.contains('/') => {
+                    Some(route(segment.to_string()))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    });
+
+    match route {
+        Some(route) if route.method() == *method || *method == Method::Options => {
+            Resolution::Found(route)
+        }
+        Some(route) => Resolution::MethodNotAllowed(route.method()),
+        None => Resolution::NotFound,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_exact_routes() {
+        assert_eq!(resolve("/", &Method::Get), Resolution::Found(Route::Home));
+        assert_eq!(
+            resolve("/v1/messages", &Method::Post),
+            Resolution::Found(Route::Messages)
+        );
+        assert_eq!(
+            resolve("/v1/models", &Method::Get),
+            Resolution::Found(Route::Models)
+        );
+        assert_eq!(
+            resolve("/admin/ledger", &Method::Get),
+            Resolution::Found(Route::AdminLedger)
+        );
+        assert_eq!(
+            resolve("/health", &Method::Get),
+            Resolution::Found(Route::Health)
+        );
+        assert_eq!(
+            resolve("/usage/forecast", &Method::Get),
+            Resolution::Found(Route::UsageForecast)
+        );
+        assert_eq!(
+            resolve("/v1/experiments/compare", &Method::Post),
+            Resolution::Found(Route::ExperimentsCompare)
+        );
+        assert_eq!(
+            resolve("/v1/convert", &Method::Post),
+            Resolution::Found(Route::Convert)
+        );
+    }
+
+    #[test]
+    fn test_resolve_prefix_routes() {
+        assert_eq!(
+            resolve("/v1/models/anthropic%2Fclaude-sonnet-4", &Method::Get),
+            Resolution::Found(Route::Model("anthropic%2Fclaude-sonnet-4".to_string()))
+        );
+        assert_eq!(
+            resolve(
+                "/v1beta/models/gemini-2.5-pro:generateContent",
+                &Method::Post
+            ),
+            Resolution::Found(Route::Gemini("gemini-2.5-pro:generateContent".to_string()))
+        );
+        assert_eq!(
+            resolve("/assets/app.css", &Method::Get),
+            Resolution::Found(Route::Asset("app.css".to_string()))
+        );
+        assert_eq!(
+            resolve("/v1/async/job_0123", &Method::Get),
+            Resolution::Found(Route::AsyncJob("job_0123".to_string()))
+        );
+        assert_eq!(
+            resolve("/v1/attachments/9f86d081", &Method::Get),
+            Resolution::Found(Route::Attachment("9f86d081".to_string()))
+        );
+        assert_eq!(
+            resolve("/admin/replay/8f1c2d", &Method::Post),
+            Resolution::Found(Route::AdminReplay("8f1c2d".to_string()))
+        );
+        assert_eq!(
+            resolve("/v1beta/models/", &Method::Post),
+            Resolution::NotFound
+        );
+    }
+
+    #[test]
+    fn test_resolve_enclosed_routes() {
+        assert_eq!(
+            resolve("/sessions/chat-1/export", &Method::Get),
+            Resolution::Found(Route::SessionExport("chat-1".to_string()))
+        );
+        assert!(Route::SessionExport("chat-1".to_string()).is_authenticated());
+        assert_eq!(
+            resolve("/sessions//export", &Method::Get),
+            Resolution::NotFound
+        );
+        assert_eq!(
+            resolve("/sessions/a/b/export", &Method::Get),
+            Resolution::NotFound
+        );
+        assert_eq!(
+            resolve("/sessions/chat-1", &Method::Get),
+            Resolution::NotFound
+        );
+    }
+
+    #[test]
+    fn test_resolve_wrong_method() {
+        assert_eq!(
+            resolve("/v1/messages", &Method::Get),
+            Resolution::MethodNotAllowed(Method::Post)
+        );
+        assert_eq!(
+            resolve("/v1/models/some-model", &Method::Delete),
+            Resolution::MethodNotAllowed(Method::Get)
+        );
+    }
+
+    #[test]
+    fn test_options_resolves_for_preflight() {
+        assert_eq!(
+            resolve("/v1/messages", &Method::Options),
+            Resolution::Found(Route::Messages)
+        );
+        assert_eq!(
+            resolve("/v2/messages", &Method::Options),
+            Resolution::NotFound
+        );
+    }
+
+    #[test]
+    fn test_is_api() {
+        assert!(Route::Messages.is_api());
+        assert!(Route::ExperimentsCompare.is_api());
+        assert!(Route::Gemini("m:generateContent".to_string()).is_api());
+        assert!(!Route::Models.is_api());
+        assert!(!Route::AdminTail.is_api());
+        // Uptime checks keep passing while maintenance drains the APIs
+        assert!(!Route::Health.is_api());
+        assert!(!Route::Health.is_authenticated());
+        assert!(!Route::AsyncJob("job_0123".to_string()).is_api());
+        assert!(Route::AsyncJob("job_0123".to_string()).is_authenticated());
+        // Conversions never reach the upstream, but still need a key
+        assert!(!Route::Convert.is_api());
+        assert!(Route::Convert.is_authenticated());
+        // Signed links are fetched by the upstream, which has no key
+        assert!(!Route::Attachment("9f86d081".to_string()).is_authenticated());
+    }
+
+    #[test]
+    fn test_resolve_unknown_path() {
+        assert_eq!(resolve("/v2/messages", &Method::Post), Resolution::NotFound);
+        assert_eq!(resolve("/terms/extra", &Method::Get), Resolution::NotFound);
+    }
+}
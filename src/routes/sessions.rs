@@ -0,0 +1,70 @@
+use super::proxy::{error_response, Caller};
+use crate::sessions::is_valid_id;
+use crate::transcripts::{self, sessions};
+use serde_json::json;
+use worker::{Env, Request, Response, Result};
+
+/// Handles GET /sessions/{id}/export: a saved session transcript
+///
+/// Returns the threads saved for the caller's session as a Markdown download,
+/// or as JSON with `?format=json`. Sessions are only visible to the caller that
+/// had them; others get a 404, as for sessions with nothing saved. Callers
+/// sharing the server key or access token have no transcripts of their own.
+pub async fn handle_export(
+    req: Request,
+    id: &str,
+    env: &Env,
+    caller: Caller<'_>,
+) -> Result<Response> {
+    let not_found = || error_response(404, "not_found_error", &format!("session: {id}"));
+    let Some(subject) = caller.personal_subject() else {
+        return error_response(
+            403,
+            "permission_error",
+            "Session exports need a virtual key, single sign-on or your own API key",
+        );
+    };
+    if !is_valid_id(id) {
+        return not_found();
+    }
+    let format = req
+        .url()?
+        .query_pairs()
+        .find(|(name, _)| name == "format")
+        .map(|(_, value)| value.into_owned());
+    let markdown = match format.as_deref() {
+        None | Some("markdown") => true,
+        Some("json") => false,
+        Some(other) => {
+            return error_response(
+                400,
+                "invalid_request_error",
+                &format!("format: expected 'markdown' or 'json', got '{other}'"),
+            )
+        }
+    };
+    let Ok(bucket) = env.bucket(transcripts::TRANSCRIPT_BUCKET_BINDING) else {
+        return error_response(
+            400,
+            "invalid_request_error",
+            "Session transcripts are not enabled on this deployment",
+        );
+    };
+
+    let prefix = sessions::session_prefix(&subject, id);
+    let threads = sessions::load(&bucket, &prefix).await?;
+    if threads.is_empty() {
+        return not_found();
+    }
+    if !markdown {
+        return Response::from_json(&json!({"session": id, "threads": threads}));
+    }
+    let mut response = Response::ok(sessions::markdown(id, &threads))?;
+    let headers = response.headers_mut();
+    headers.set("Content-Type", "text/markdown; charset=utf-8")?;
+    headers.set(
+        "Content-Disposition",
+        &format!("attachment; filename=\"session-{id}.md\""),
+    )?;
+    Ok(response)
+}
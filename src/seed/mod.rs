@@ -0,0 +1,72 @@
+//! Reproducible sampling for evaluation runs
+//!
+//! A seed from the `x-ccr-seed` header, or the `seed` extension in the request's
+//! `metadata`, is forwarded as OpenAI `seed`. Upstreams that honor it return the
+//! same completion for the same request and `system_fingerprint`, which is echoed
+//! in `x-ccr-system-fingerprint` so runs can tell when the backend changed.
+
+use crate::models::OpenAICompletion;
+use serde_json::Value;
+
+/// Request header carrying the seed, which takes precedence over `metadata.seed`
+pub const SEED_HEADER: &str = "x-ccr-seed";
+
+/// Response header echoing the upstream's `system_fingerprint`
+pub const FINGERPRINT_HEADER: &str = "x-ccr-system-fingerprint";
+
+/// The seed a request asks for, from the header or `metadata.seed`
+pub fn request_seed(header: Option<&str>, metadata: Option<&Value>) -> Result<Option<u64>, String> {
+    if let Some(header) = header {
+        return header
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{SEED_HEADER} must be a non-negative integer"));
+    }
+    match metadata.and_then(|metadata| metadata.get("seed")) {
+        None | Some(Value::Null) => Ok(None),
+        Some(seed) => seed
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| "metadata.seed must be a non-negative integer".to_string()),
+    }
+}
+
+/// The `system_fingerprint` of an OpenAI-format response, when the upstream sent one
+pub fn system_fingerprint(response: &OpenAICompletion) -> Option<&str> {
+    response
+        .system_fingerprint
+        .as_str()
+        .filter(|fingerprint| !fingerprint.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_seed() {
+        let metadata = json!({"user_id": "eval", "seed": 42});
+        assert_eq!(request_seed(None, Some(&metadata)), Ok(Some(42)));
+        assert_eq!(request_seed(Some(" 7 "), Some(&metadata)), Ok(Some(7)));
+        assert_eq!(
+            request_seed(None, Some(&json!({"user_id": "eval"}))),
+            Ok(None)
+        );
+        assert_eq!(request_seed(None, None), Ok(None));
+        assert!(request_seed(Some("-1"), None).is_err());
+        assert!(request_seed(None, Some(&json!({"seed": "42"}))).is_err());
+
+        let completion =
+            |response: Value| -> OpenAICompletion { serde_json::from_value(response).unwrap() };
+        assert_eq!(
+            system_fingerprint(&completion(json!({"system_fingerprint": "fp_44709d6fcb"}))),
+            Some("fp_44709d6fcb")
+        );
+        assert_eq!(
+            system_fingerprint(&completion(json!({"choices": []}))),
+            None
+        );
+    }
+}
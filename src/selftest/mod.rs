@@ -0,0 +1,302 @@
+use crate::alerts::ALERT_MONITOR_BINDING;
+use crate::attachments::ATTACHMENTS_BINDING;
+use crate::auth::virtual_keys::VIRTUAL_KEYS_BINDING;
+use crate::branding::BRANDING_BINDING;
+use crate::budget::BUDGET_LEDGER_BINDING;
+use crate::concurrency::CONCURRENCY_LIMITER_BINDING;
+use crate::config::Config;
+use crate::http;
+use crate::idempotency::IDEMPOTENCY_BINDING;
+use crate::metrics::ANALYTICS_BINDING;
+use crate::profiles::PROFILES_BINDING;
+use crate::rate_limit::RATE_LIMITER_BINDING;
+use crate::shadow::SHADOW_BUCKET_BINDING;
+use crate::tail::REQUEST_TAIL_BINDING;
+use crate::transcripts::{TranscriptMode, TRANSCRIPT_BUCKET_BINDING};
+use serde::Serialize;
+use worker::{Date, Env};
+
+/// Result of a single self-test check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    /// Not applicable with the current configuration
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Structured report returned by `GET /admin/selftest`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// True when no check failed
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(checks: Vec<Check>) -> Self {
+        Report {
+            ok: checks.iter().all(|check| check.status != Status::Fail),
+            checks,
+        }
+    }
+}
+
+/// Kind of binding a feature depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindingKind {
+    Kv,
+    DurableObject,
+    R2,
+    AnalyticsEngine,
+}
+
+/// Bindings CCR knows about and whether the configuration needs each one
+///
+/// KV-backed features are opt-in by the presence of their binding, so they are
+/// never required.
+fn expected_bindings(config: &Config) -> Vec<(&'static str, BindingKind, bool)> {
+    let limits = config.rate_limits;
+    let rate_limited = [limits.key_rpm, limits.key_tpm, limits.ip_rpm, limits.ip_tpm]
+        .iter()
+        .any(|&limit| limit > 0);
+
+    vec![
+        (VIRTUAL_KEYS_BINDING, BindingKind::Kv, false),
+        (PROFILES_BINDING, BindingKind::Kv, false),
+        (BRANDING_BINDING, BindingKind::Kv, false),
+        (IDEMPOTENCY_BINDING, BindingKind::Kv, false),
+        (
+            RATE_LIMITER_BINDING,
+            BindingKind::DurableObject,
+            rate_limited,
+        ),
+        (
+            CONCURRENCY_LIMITER_BINDING,
+            BindingKind::DurableObject,
+            limits.key_concurrency > 0,
+        ),
+        (BUDGET_LEDGER_BINDING, BindingKind::DurableObject, false),
+        (REQUEST_TAIL_BINDING, BindingKind::DurableObject, false),
+        (
+            ALERT_MONITOR_BINDING,
+            BindingKind::DurableObject,
+            config.alerts.is_some(),
+        ),
+        (
+            SHADOW_BUCKET_BINDING,
+            BindingKind::R2,
+            config.shadow_model.is_some() && config.shadow_sample_percent > 0,
+        ),
+        (
+            TRANSCRIPT_BUCKET_BINDING,
+            BindingKind::R2,
+            config.log_to_r2 != TranscriptMode::Off,
+        ),
+        (
+            ATTACHMENTS_BINDING,
+            BindingKind::R2,
+            config.attachments.is_some(),
+        ),
+        (ANALYTICS_BINDING, BindingKind::AnalyticsEngine, false),
+    ]
+}
+
+/// Turns a binding probe into a check
+fn binding_check(name: &str, required: bool, probe: Result<(), String>) -> Check {
+    let check_name = format!("binding:{name}");
+    match (probe, required) {
+        (Ok(()), _) => Check::new(check_name, Status::Pass, "bound"),
+        (Err(e), true) => Check::new(check_name, Status::Fail, format!("required but {e}")),
+        (Err(_), false) => Check::new(check_name, Status::Skip, "not bound (optional)"),
+    }
+}
+
+/// Runs every check against the live environment
+pub async fn run(env: &Env) -> Report {
+    let config = match Config::from_env(env) {
+        Ok(config) => config,
+        Err(e) => {
+            // Nothing else can be checked meaningfully without a configuration
+            return Report::new(vec![Check::new("config", Status::Fail, e.to_string())]);
+        }
+    };
+
+    let mut checks = vec![Check::new("config", Status::Pass, "valid")];
+    checks.push(check_upstream(&config, env).await);
+
+    for (name, kind, required) in expected_bindings(&config) {
+        let probe = match kind {
+            BindingKind::Kv => match env.kv(name) {
+                Ok(kv) => kv
+                    .get("ccr:selftest")
+                    .text()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("read failed: {e}")),
+                Err(e) => Err(format!("missing: {e}")),
+            },
+            BindingKind::DurableObject => env
+                .durable_object(name)
+                .and_then(|namespace| namespace.id_from_name("ccr:selftest").map(|_| ()))
+                .map_err(|e| format!("missing: {e}")),
+            BindingKind::R2 => env
+                .bucket(name)
+                .map(|_| ())
+                .map_err(|e| format!("missing: {e}")),
+            BindingKind::AnalyticsEngine => env
+                .analytics_engine(name)
+                .map(|_| ())
+                .map_err(|e| format!("missing: {e}")),
+        };
+        checks.push(binding_check(name, required, probe));
+    }
+
+    Report::new(checks)
+}
+
+/// Sends a one-token completion to the configured upstream with the server key
+async fn check_upstream(config: &Config, env: &Env) -> Check {
+    let Some(credential) = &config.server_api_key else {
+        return Check::new(
+            "upstream",
+            Status::Skip,
+            "OPENROUTER_API_KEY not set; clients supply their own keys",
+        );
+    };
+    let api_key = match credential.resolve(env).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => return Check::new("upstream", Status::Fail, "server key is empty"),
+        Err(e) => {
+            return Check::new(
+                "upstream",
+                Status::Fail,
+                format!("server key unavailable: {e}"),
+            )
+        }
+    };
+
+    let url = format!("{}/chat/completions", config.openrouter_base_url);
+    let body = serde_json::json!({
+        "model": config.model_targets.haiku,
+        "messages": [{"role": "user", "content": "ping"}],
+        "max_tokens": 1
+    });
+
+    let start = Date::now().as_millis();
+    let result = http::Client::new()
+        .post(&url)
+        .header("Authorization", format!("Bearer {api_key}"))
+        .json(&body)
+        .send()
+        .await;
+    let elapsed = Date::now().as_millis().saturating_sub(start);
+
+    match result {
+        Ok(response) if response.is_success() => Check::new(
+            "upstream",
+            Status::Pass,
+            format!("{} answered in {elapsed}ms", config.model_targets.haiku),
+        ),
+        Ok(response) => {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            Check::new(
+                "upstream",
+                Status::Fail,
+                format!("HTTP {status} from {url}: {text}"),
+            )
+        }
+        Err(e) => Check::new("upstream", Status::Fail, format!("{url} unreachable: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_ok() {
+        let report = Report::new(vec![
+            Check::new("config", Status::Pass, "valid"),
+            Check::new("upstream", Status::Skip, "no key"),
+        ]);
+        assert!(report.ok);
+
+        let report = Report::new(vec![
+            Check::new("config", Status::Pass, "valid"),
+            Check::new("binding:RATE_LIMITER", Status::Fail, "missing"),
+        ]);
+        assert!(!report.ok);
+    }
+
+    #[test]
+    fn test_report_serialization() {
+        let report = Report::new(vec![Check::new("config", Status::Pass, "valid")]);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "ok": true,
+                "checks": [{"name": "config", "status": "pass", "detail": "valid"}]
+            })
+        );
+    }
+
+    #[test]
+    fn test_expected_bindings_follow_config() {
+        let required = |config: &Config, binding: &str| {
+            expected_bindings(config)
+                .into_iter()
+                .find(|(name, _, _)| *name == binding)
+                .map(|(_, _, required)| required)
+                .unwrap()
+        };
+
+        let config = Config::default();
+        assert!(!required(&config, RATE_LIMITER_BINDING));
+        assert!(!required(&config, SHADOW_BUCKET_BINDING));
+        assert!(!required(&config, TRANSCRIPT_BUCKET_BINDING));
+
+        let mut config = Config::default();
+        config.rate_limits.ip_rpm = 60;
+        config.shadow_model = Some("google/gemini-2.5-flash".to_string());
+        config.shadow_sample_percent = 5;
+        config.log_to_r2 = TranscriptMode::Hashed;
+        assert!(required(&config, RATE_LIMITER_BINDING));
+        assert!(required(&config, SHADOW_BUCKET_BINDING));
+        assert!(required(&config, TRANSCRIPT_BUCKET_BINDING));
+    }
+
+    #[test]
+    fn test_binding_check() {
+        assert_eq!(
+            binding_check("PROFILES", false, Ok(())).status,
+            Status::Pass
+        );
+        assert_eq!(
+            binding_check("PROFILES", false, Err("missing".to_string())).status,
+            Status::Skip
+        );
+        let failed = binding_check("RATE_LIMITER", true, Err("missing".to_string()));
+        assert_eq!(failed.status, Status::Fail);
+        assert_eq!(failed.name, "binding:RATE_LIMITER");
+    }
+}
@@ -0,0 +1,311 @@
+//! Semantic response cache: reuses responses to near-identical prompts
+//!
+//! Prompts of eligible requests are embedded with Workers AI and looked up in a
+//! Vectorize index. A match above the similarity threshold is answered with the
+//! response cached for it in `RESPONSE_CACHE`; the index only holds the vectors.
+
+use crate::auth::hash_key;
+use crate::models::OpenAIRequest;
+use crate::response_cache::{self, CachedResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use worker::kv::KvStore;
+use worker::{Ai, Env, EnvBinding, Result};
+
+/// Vectorize index holding prompt embeddings; the semantic cache is off while unbound
+pub const SEMANTIC_CACHE_BINDING: &str = "SEMANTIC_CACHE";
+
+/// Workers AI binding used to embed prompts
+pub const AI_BINDING: &str = "AI";
+
+/// Embedding model; the index must be created with its 768 dimensions and the cosine metric
+pub const EMBEDDING_MODEL: &str = "@cf/baai/bge-base-en-v1.5";
+
+/// Longest prompt text embedded; longer prompts keep their end, where the
+/// request-specific text of housekeeping prompts is
+const MAX_PROMPT_CHARS: usize = 4000;
+
+/// Which requests the semantic cache applies to and how similar prompts must be
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticCacheConfig {
+    /// Lowercase substrings of the requested Claude model names that are eligible
+    pub models: Vec<String>,
+    /// Cosine similarity (0-1] a cached prompt needs to be reused
+    pub threshold: f32,
+}
+
+impl Default for SemanticCacheConfig {
+    fn default() -> Self {
+        SemanticCacheConfig {
+            models: vec!["haiku".to_string()],
+            threshold: 0.95,
+        }
+    }
+}
+
+impl SemanticCacheConfig {
+    /// Whether a request for the Claude `model` is eligible
+    pub fn applies_to(&self, model: &str) -> bool {
+        let model = model.to_lowercase();
+        self.models.iter().any(|pattern| model.contains(pattern))
+    }
+}
+
+/// The text of a request's messages, as embedded
+pub fn prompt_text(request: &OpenAIRequest) -> String {
+    let mut text = String::new();
+    for message in &request.messages {
+        let content = match &message["content"] {
+            Value::String(content) => content.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => continue,
+        };
+        let role = message["role"].as_str().unwrap_or("user");
+        text.push_str(&format!("{role}: {content}\n"));
+    }
+    let skip = text.chars().count().saturating_sub(MAX_PROMPT_CHARS);
+    text.chars().skip(skip).collect()
+}
+
+/// Vectorize namespace for a request: prompts are only compared with prompts
+/// from the same caller, for the same upstream model and response format
+pub fn namespace(subject: &str, request: &OpenAIRequest) -> String {
+    let stream = request.stream.unwrap_or(false);
+    hash_key(&format!("{subject}\n{}\n{stream}", request.model))
+}
+
+/// A Vectorize query match
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Match {
+    pub id: String,
+    pub score: f32,
+}
+
+/// The id of the most similar match at or above `threshold`
+pub fn best_match(matches: &[Match], threshold: f32) -> Option<&str> {
+    matches
+        .iter()
+        .filter(|m| m.score >= threshold)
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+        .map(|m| m.id.as_str())
+}
+
+fn storage_key(id: &str) -> String {
+    format!("semantic:{id}")
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Vectorize index binding
+    #[wasm_bindgen(extends = js_sys::Object)]
+    pub type VectorizeIndex;
+
+    #[wasm_bindgen(method, catch)]
+    fn query(
+        this: &VectorizeIndex,
+        vector: &JsValue,
+        options: &JsValue,
+    ) -> std::result::Result<js_sys::Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn upsert(
+        this: &VectorizeIndex,
+        vectors: &JsValue,
+    ) -> std::result::Result<js_sys::Promise, JsValue>;
+}
+
+impl EnvBinding for VectorizeIndex {
+    const TYPE_NAME: &'static str = "VectorizeIndexImpl";
+
+    fn get(value: JsValue) -> Result<Self> {
+        Ok(value.unchecked_into())
+    }
+}
+
+fn js_error(e: JsValue) -> worker::Error {
+    worker::Error::RustError(format!("Vectorize: {e:?}"))
+}
+
+/// Calls a Vectorize method with JSON arguments, returning its JSON result
+async fn call(
+    method: impl FnOnce() -> std::result::Result<js_sys::Promise, JsValue>,
+) -> Result<Value> {
+    let result = JsFuture::from(method().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    let json = js_sys::JSON::stringify(&result).map_err(js_error)?;
+    Ok(serde_json::from_str(&String::from(json)).unwrap_or(Value::Null))
+}
+
+fn to_js(value: &Value) -> Result<JsValue> {
+    js_sys::JSON::parse(&value.to_string()).map_err(js_error)
+}
+
+/// An embedded request prompt, kept to cache the response once it completes
+pub struct Entry {
+    namespace: String,
+    id: String,
+    vector: Vec<f32>,
+}
+
+/// The bindings backing the semantic cache
+pub struct SemanticCache {
+    index: VectorizeIndex,
+    ai: Ai,
+    kv: KvStore,
+}
+
+#[derive(Deserialize)]
+struct Embeddings {
+    data: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+struct QueryResult {
+    #[serde(default)]
+    matches: Vec<Match>,
+}
+
+impl SemanticCache {
+    /// The semantic cache, when the index, Workers AI and `RESPONSE_CACHE` are all bound
+    pub fn from_env(env: &Env) -> Option<Self> {
+        Some(SemanticCache {
+            index: env.get_binding(SEMANTIC_CACHE_BINDING).ok()?,
+            ai: env.ai(AI_BINDING).ok()?,
+            kv: env.kv(response_cache::RESPONSE_CACHE_BINDING).ok()?,
+        })
+    }
+
+    /// Embeds the request's prompt
+    pub async fn entry(&self, subject: &str, request: &OpenAIRequest) -> Result<Entry> {
+        let prompt = prompt_text(request);
+        let embeddings: Embeddings = self
+            .ai
+            .run(EMBEDDING_MODEL, json!({ "text": [prompt] }))
+            .await?;
+        let vector =
+            embeddings.data.into_iter().next().ok_or_else(|| {
+                worker::Error::RustError("Embedding model returned no data".into())
+            })?;
+        let namespace = namespace(subject, request);
+        Ok(Entry {
+            id: hash_key(&format!("{namespace}\n{prompt}")),
+            namespace,
+            vector,
+        })
+    }
+
+    /// Looks up the response cached for the most similar earlier prompt
+    pub async fn lookup(&self, entry: &Entry, threshold: f32) -> Result<Option<CachedResponse>> {
+        let vector = to_js(&json!(entry.vector))?;
+        let options = to_js(&json!({ "topK": 3, "namespace": entry.namespace }))?;
+        let result = call(|| self.index.query(&vector, &options)).await?;
+        let matches = serde_json::from_value::<QueryResult>(result)?.matches;
+        let Some(id) = best_match(&matches, threshold) else {
+            return Ok(None);
+        };
+        response_cache::lookup(&self.kv, &storage_key(id)).await
+    }
+
+    /// Caches the response for `ttl_secs` and indexes the prompt it answered
+    ///
+    /// The index has no expiry; matches whose response has expired are misses.
+    pub async fn store(
+        &self,
+        entry: &Entry,
+        response: &CachedResponse,
+        ttl_secs: u64,
+    ) -> Result<()> {
+        response_cache::store(&self.kv, &storage_key(&entry.id), response, ttl_secs).await?;
+        let vectors = to_js(&json!([{
+            "id": entry.id,
+            "values": entry.vector,
+            "namespace": entry.namespace,
+        }]))?;
+        call(|| self.index.upsert(&vectors)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(messages: Value) -> OpenAIRequest {
+        serde_json::from_value(json!({
+            "model": "anthropic/claude-3.5-haiku",
+            "messages": messages,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_applies_to_configured_models() {
+        let config = SemanticCacheConfig::default();
+        assert!(config.applies_to("claude-3-5-haiku-20241022"));
+        assert!(config.applies_to("Claude-Haiku-4-5"));
+        assert!(!config.applies_to("claude-sonnet-4"));
+    }
+
+    #[test]
+    fn test_prompt_text_joins_message_text() {
+        let request = request(json!([
+            {"role": "system", "content": "Summarize in 5 words"},
+            {"role": "user", "content": [
+                {"type": "text", "text": "Fix the login bug"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+            ]}
+        ]));
+        assert_eq!(
+            prompt_text(&request),
+            "system: Summarize in 5 words\nuser: Fix the login bug\n"
+        );
+    }
+
+    #[test]
+    fn test_prompt_text_keeps_the_end_of_long_prompts() {
+        let long = format!("{}tail", "x".repeat(MAX_PROMPT_CHARS * 2));
+        let text = prompt_text(&request(json!([{"role": "user", "content": long}])));
+        assert_eq!(text.chars().count(), MAX_PROMPT_CHARS);
+        assert!(text.ends_with("tail\n"));
+    }
+
+    #[test]
+    fn test_namespace_separates_callers_models_and_streaming() {
+        let base = request(json!([{"role": "user", "content": "Hi"}]));
+        let mut streaming = base.clone();
+        streaming.stream = Some(true);
+        let mut other_model = base.clone();
+        other_model.model = "openai/gpt-4o-mini".to_string();
+
+        let key = namespace("key:abc", &base);
+        assert_eq!(key.len(), 64);
+        assert_ne!(key, namespace("key:def", &base));
+        assert_ne!(key, namespace("key:abc", &streaming));
+        assert_ne!(key, namespace("key:abc", &other_model));
+    }
+
+    #[test]
+    fn test_best_match_honors_threshold() {
+        let matches = vec![
+            Match {
+                id: "a".to_string(),
+                score: 0.91,
+            },
+            Match {
+                id: "b".to_string(),
+                score: 0.97,
+            },
+        ];
+        assert_eq!(best_match(&matches, 0.95), Some("b"));
+        assert_eq!(best_match(&matches, 0.98), None);
+        assert_eq!(best_match(&[], 0.5), None);
+    }
+}
@@ -0,0 +1,355 @@
+//! A standalone HTTP server running the transform outside Cloudflare (`ccr serve`)
+//!
+//! Serves `/v1/messages` with the same model mapping and Anthropic ↔ OpenAI
+//! translation as the Worker, forwarding to OpenRouter with `reqwest`. The
+//! Worker-only features (authentication, rate limits, budgets, analytics and the
+//! rest of the KV/Durable Object backed middleware) are not available here.
+
+use crate::log;
+use crate::models::AnthropicRequest;
+use crate::transform::{
+    anthropic_to_openai, error_events, openai_to_anthropic, stream_openai_to_anthropic,
+};
+use crate::utils::{upstream_headers, Attribution, ModelTargets, Routing};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Settings for the standalone server, read from the same variables as the Worker
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub openrouter_base_url: String,
+    /// Used when the client sends no key of its own
+    pub openrouter_api_key: Option<String>,
+    pub routing: Routing,
+    pub attribution: Attribution,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            openrouter_base_url: "https://openrouter.ai/api/v1".to_string(),
+            openrouter_api_key: None,
+            routing: Routing::default(),
+            attribution: Attribution::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads `OPENROUTER_BASE_URL`, `OPENROUTER_API_KEY`, `OPENROUTER_REFERER`/`OPENROUTER_TITLE`
+    /// and `MODEL_HAIKU`/`MODEL_SONNET`/`MODEL_OPUS`
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = ServerConfig::default();
+        let target_defaults = ModelTargets::default();
+        ServerConfig {
+            openrouter_base_url: var("OPENROUTER_BASE_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or(defaults.openrouter_base_url),
+            openrouter_api_key: var("OPENROUTER_API_KEY").filter(|key| !key.trim().is_empty()),
+            routing: Routing {
+                targets: ModelTargets {
+                    haiku: var("MODEL_HAIKU").unwrap_or(target_defaults.haiku),
+                    sonnet: var("MODEL_SONNET").unwrap_or(target_defaults.sonnet),
+                    opus: var("MODEL_OPUS").unwrap_or(target_defaults.opus),
+                },
+                ..Routing::default()
+            },
+            attribution: Attribution {
+                referer: var("OPENROUTER_REFERER").unwrap_or(defaults.attribution.referer),
+                title: var("OPENROUTER_TITLE").unwrap_or(defaults.attribution.title),
+            },
+        }
+    }
+}
+
+struct AppState {
+    config: ServerConfig,
+    client: reqwest::Client,
+}
+
+/// The server's routes
+pub fn router(config: ServerConfig) -> Router {
+    let state = Arc::new(AppState {
+        config,
+        client: reqwest::Client::new(),
+    });
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .route("/v1/messages", post(handle_messages))
+        .with_state(state)
+}
+
+/// Serves on `127.0.0.1:{port}` until the process is stopped
+pub async fn serve(port: u16, config: ServerConfig) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    log::info(
+        "ccr listening",
+        &[("address", listener.local_addr()?.to_string().into())],
+    );
+    axum::serve(listener, router(config)).await
+}
+
+async fn handle_messages(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let anthropic_request: AnthropicRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string()),
+    };
+    let Some(api_key) = client_key(&headers).or_else(|| state.config.openrouter_api_key.clone())
+    else {
+        return error_response(401, "authentication_error", "Missing API key");
+    };
+    let openai_request = match anthropic_to_openai(&anthropic_request, &state.config.routing) {
+        Ok(request) => request,
+        Err(e) => return error_response(400, "invalid_request_error", &e.to_string()),
+    };
+
+    let streaming = anthropic_request.stream.unwrap_or(false);
+
+    let url = format!("{}/chat/completions", state.config.openrouter_base_url);
+    let mut upstream = state.client.post(url);
+    for (name, value) in upstream_headers(&api_key, &state.config.attribution) {
+        upstream = upstream.header(name, value);
+    }
+    let response = match upstream.json(&openai_request).send().await {
+        Ok(response) => response,
+        Err(e) if streaming => {
+            return event_stream(error_events("api_error", &format!("Request failed: {e}")))
+        }
+        Err(e) => return error_response(502, "api_error", &format!("Request failed: {e}")),
+    };
+
+    let status = response.status().as_u16();
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&error_text)
+            .ok()
+            .and_then(|error| error["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or(error_text);
+        return error_response(status, "api_error", &message);
+    }
+
+    if streaming {
+        let chunks = response.bytes_stream().map_ok(|chunk| chunk.to_vec());
+        return match stream_openai_to_anthropic(chunks, &anthropic_request.model, None).await {
+            Ok((events, _usage)) => event_stream(events),
+            Err(e) => event_stream(error_events("api_error", &e.to_string())),
+        };
+    }
+
+    let openai_response: Value = match response.json().await {
+        Ok(value) => value,
+        Err(e) => {
+            return error_response(
+                502,
+                "api_error",
+                &format!("Failed to parse OpenAI response: {e}"),
+            )
+        }
+    };
+    match openai_to_anthropic(&openai_response, &anthropic_request.model) {
+        Ok(message) => Json(message).into_response(),
+        Err(e) => error_response(502, "api_error", &e.to_string()),
+    }
+}
+
+/// The client's key from `x-api-key` or a bearer token, as Claude Code sends it
+fn client_key(headers: &HeaderMap) -> Option<String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.trim().is_empty())
+    };
+    header("x-api-key")
+        .or_else(|| header("authorization")?.strip_prefix("Bearer "))
+        .map(str::to_string)
+}
+
+/// Anthropic stream events, or an `error` event for failures streaming clients
+/// would otherwise get as JSON
+fn event_stream(events: String) -> Response {
+    (
+        [
+            ("Content-Type", "text/event-stream"),
+            ("Cache-Control", "no-cache"),
+        ],
+        Body::from(events),
+    )
+        .into_response()
+}
+
+/// Builds an Anthropic-format error response
+fn error_response(status: u16, error_type: &str, message: &str) -> Response {
+    let body = json!({
+        "type": "error",
+        "error": {
+            "type": error_type,
+            "message": message
+        }
+    });
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Starts the server on a free port, returning its base URL
+    async fn start(config: ServerConfig) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(config)).await });
+        format!("http://{address}")
+    }
+
+    fn config_for(upstream: &MockServer) -> ServerConfig {
+        ServerConfig {
+            openrouter_base_url: upstream.uri(),
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = ServerConfig::from_vars(|name| match name {
+            "OPENROUTER_BASE_URL" => Some("http://localhost:9000/v1/".to_string()),
+            "MODEL_SONNET" => Some("deepseek/deepseek-chat".to_string()),
+            "OPENROUTER_API_KEY" => Some(" ".to_string()),
+            "OPENROUTER_TITLE" => Some("Example Router".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.openrouter_base_url, "http://localhost:9000/v1");
+        assert_eq!(config.routing.targets.sonnet, "deepseek/deepseek-chat");
+        assert_eq!(config.routing.targets.haiku, "anthropic/claude-3.5-haiku");
+        assert_eq!(config.openrouter_api_key, None);
+        assert_eq!(config.attribution.title, "Example Router");
+        assert_eq!(config.attribution.referer, "https://ccr.duyet.net");
+    }
+
+    #[tokio::test]
+    async fn test_messages_round_trip() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("authorization", "Bearer sk-or-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{
+                    "message": {"role": "assistant", "content": "Hello!"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 2}
+            })))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let base = start(config_for(&upstream)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{base}/v1/messages"))
+            .header("x-api-key", "sk-or-test")
+            .json(&json!({
+                "model": "claude-sonnet-4",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let message: Value = response.json().await.unwrap();
+        assert_eq!(message["content"][0]["text"], "Hello!");
+        assert_eq!(message["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test]
+    async fn test_messages_without_key_are_rejected() {
+        let upstream = MockServer::start().await;
+        let base = start(config_for(&upstream)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{base}/v1/messages"))
+            .json(&json!({
+                "model": "claude-sonnet-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["type"], "authentication_error");
+    }
+
+    #[tokio::test]
+    async fn test_streaming_failures_are_error_events() {
+        let base = start(ServerConfig {
+            openrouter_base_url: "http://127.0.0.1:1".to_string(),
+            openrouter_api_key: Some("sk-or-server".to_string()),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{base}/v1/messages"))
+            .json(&json!({
+                "model": "claude-sonnet-4",
+                "stream": true,
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let events = response.text().await.unwrap();
+        assert!(events.starts_with("event: error\ndata: "));
+        assert!(events.contains("Request failed"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_errors_keep_their_status() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "error": {"message": "Rate limit exceeded"}
+            })))
+            .mount(&upstream)
+            .await;
+        let base = start(ServerConfig {
+            openrouter_api_key: Some("sk-or-server".to_string()),
+            ..config_for(&upstream)
+        })
+        .await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{base}/v1/messages"))
+            .json(&json!({
+                "model": "claude-sonnet-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["message"], "Rate limit exceeded");
+    }
+}
@@ -0,0 +1,249 @@
+//! Model choices that stick to a conversation
+//!
+//! A client picks the model of one request with `x-ccr-model: <model>`, or of a
+//! conversation by sending `/model <model>` as a message of its own, which CCR
+//! answers itself. With the `SessionModel` Durable Object bound as
+//! `SESSION_MODELS`, either choice is stored for the conversation, and its later
+//! requests go to that model without repeating it. Conversations are told apart
+//! by `x-ccr-session`, or else by the session ID Claude Code puts in
+//! `metadata.user_id`. Choosing `default` forgets the choice, as does
+//! `SESSION_TTL_SECS` without a request in the conversation.
+
+use crate::models::{AnthropicRequest, AnthropicResponse, Usage};
+use crate::sessions;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use worker::*;
+
+/// Durable Object namespace remembering models per conversation; choices last one
+/// request while unbound
+pub const SESSION_MODELS_BINDING: &str = "SESSION_MODELS";
+
+/// Request header choosing the model (`x-ccr-model: <model>`)
+pub const MODEL_HEADER: &str = "x-ccr-model";
+
+/// The model name that forgets a conversation's choice
+pub const DEFAULT_MODEL: &str = "default";
+
+/// Precedes the session ID in Claude Code's `metadata.user_id`
+const USER_ID_SESSION_MARKER: &str = "_session_";
+
+const MODEL_KEY: &str = "model";
+
+/// A change to a conversation's model; an empty update reads it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ModelUpdate {
+    model: Option<String>,
+    clear: bool,
+    ttl_secs: u64,
+}
+
+/// The conversation a request belongs to: its `x-ccr-session`, or else the
+/// session Claude Code names in `metadata.user_id`
+pub fn conversation_id(session_header: Option<&str>, metadata: Option<&Value>) -> Option<String> {
+    let id = match session_header {
+        Some(id) => id,
+        None => {
+            let user_id = metadata?.get("user_id")?.as_str()?;
+            user_id.rsplit_once(USER_ID_SESSION_MARKER)?.1
+        }
+    };
+    sessions::is_valid_id(id).then(|| id.to_string())
+}
+
+/// The model a `/model <model>` directive sent as the newest message chooses
+pub fn directive(request: &AnthropicRequest) -> Option<String> {
+    let message = request.messages.last().filter(|m| m["role"] == "user")?;
+    let text = match &message["content"] {
+        Value::String(text) => text.as_str(),
+        Value::Array(blocks) => match blocks.as_slice() {
+            [block] if block["type"] == "text" => block["text"].as_str()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let model = text.trim().strip_prefix("/model ")?.trim();
+    (!model.is_empty() && !model.contains(char::is_whitespace)).then(|| model.to_string())
+}
+
+/// What CCR answers a `/model` directive with
+pub fn acknowledgement(model: &str, remembered: bool) -> String {
+    match (model == DEFAULT_MODEL, remembered) {
+        (_, false) => format!(
+            "This deployment can't remember a model for the conversation; send {MODEL_HEADER} with each request instead."
+        ),
+        (true, true) => {
+            "Requests in this conversation go to the model they ask for again.".to_string()
+        }
+        (false, true) => format!("Requests in this conversation now go to {model}."),
+    }
+}
+
+/// The answer to a `/model` directive as a message
+pub fn acknowledgement_message(id: &str, model: &str, text: &str) -> AnthropicResponse {
+    AnthropicResponse {
+        id: id.to_string(),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        content: vec![json!({"type": "text", "text": text})],
+        stop_reason: Some("end_turn".to_string()),
+        stop_sequence: None,
+        model: model.to_string(),
+        usage: Usage::default(),
+    }
+}
+
+/// The model chosen for one conversation
+///
+/// Each caller's conversation gets its own instance. The choice is forgotten once
+/// the conversation has been idle for its TTL.
+#[durable_object]
+pub struct SessionModel {
+    state: State,
+}
+
+impl DurableObject for SessionModel {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let update: ModelUpdate = req.json().await?;
+        let storage = self.state.storage();
+        if update.clear {
+            storage.delete(MODEL_KEY).await?;
+            return Response::from_json(&None::<String>);
+        }
+        if let Some(model) = &update.model {
+            storage.put(MODEL_KEY, model).await?;
+        }
+
+        // Storage reads a missing key as an error, meaning no model was chosen
+        let model: Option<String> = storage.get(MODEL_KEY).await.unwrap_or_default();
+        if model.is_some() {
+            storage
+                .set_alarm(std::time::Duration::from_secs(update.ttl_secs))
+                .await?;
+        }
+        Response::from_json(&model)
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        self.state.storage().delete(MODEL_KEY).await?;
+        Response::empty()
+    }
+}
+
+async fn update(stub: &Stub, update: &ModelUpdate) -> Result<Option<String>> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(serde_json::to_string(update)?.into()));
+    let req = Request::new_with_init("https://session-model/model", &init)?;
+    stub.fetch_with_request(req).await?.json().await
+}
+
+/// The model chosen for a conversation, keeping the choice for another `ttl_secs`
+pub async fn recall(stub: &Stub, ttl_secs: u64) -> Result<Option<String>> {
+    update(
+        stub,
+        &ModelUpdate {
+            ttl_secs,
+            ..ModelUpdate::default()
+        },
+    )
+    .await
+}
+
+/// Chooses the model of a conversation; [`DEFAULT_MODEL`] forgets the choice
+pub async fn remember(stub: &Stub, model: &str, ttl_secs: u64) -> Result<()> {
+    let clear = model == DEFAULT_MODEL;
+    update(
+        stub,
+        &ModelUpdate {
+            model: (!clear).then(|| model.to_string()),
+            clear,
+            ttl_secs,
+        },
+    )
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content: Value) -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 1024,
+            "messages": [
+                {"role": "user", "content": "Fix the failing tests"},
+                {"role": "assistant", "content": "Done."},
+                {"role": "user", "content": content}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_conversation_id() {
+        let metadata = json!({
+            "user_id": "user_3f2a_account_9b1c-41d2_session_7e0d5c1a-2b3c-4d5e-8f90-123456789abc"
+        });
+        assert_eq!(
+            conversation_id(None, Some(&metadata)).as_deref(),
+            Some("7e0d5c1a-2b3c-4d5e-8f90-123456789abc")
+        );
+        // An explicit session wins over Claude Code's
+        assert_eq!(
+            conversation_id(Some("chat-42"), Some(&metadata)).as_deref(),
+            Some("chat-42")
+        );
+        assert_eq!(conversation_id(Some("has space"), None), None);
+        assert_eq!(
+            conversation_id(None, Some(&json!({"user_id": "alice"}))),
+            None
+        );
+        assert_eq!(conversation_id(None, None), None);
+    }
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(
+            directive(&request(json!(" /model deepseek/deepseek-chat\n"))).as_deref(),
+            Some("deepseek/deepseek-chat")
+        );
+        assert_eq!(
+            directive(&request(
+                json!([{"type": "text", "text": "/model default"}])
+            ))
+            .as_deref(),
+            Some(DEFAULT_MODEL)
+        );
+        // Only a message of its own is a directive
+        assert_eq!(
+            directive(&request(json!("/model opus and then fix the tests"))),
+            None
+        );
+        assert_eq!(directive(&request(json!("/models"))), None);
+        assert_eq!(directive(&request(json!("/model "))), None);
+        assert_eq!(
+            directive(&request(json!([
+                {"type": "tool_result", "tool_use_id": "t1", "content": "ok"},
+                {"type": "text", "text": "/model opus"}
+            ]))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_acknowledgement() {
+        assert_eq!(
+            acknowledgement("opus", true),
+            "Requests in this conversation now go to opus."
+        );
+        assert!(acknowledgement(DEFAULT_MODEL, true).contains("again"));
+        assert!(acknowledgement("opus", false).contains(MODEL_HEADER));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::auth::hash_key;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use worker::*;
+
+/// Durable Object namespace holding conversation sessions; sessions are off while unbound
+pub const SESSIONS_BINDING: &str = "SESSIONS";
+
+/// Request header naming the caller's session (`x-ccr-session: <id>`)
+pub const SESSION_HEADER: &str = "x-ccr-session";
+
+/// Longest session ID accepted
+pub const MAX_SESSION_ID_LEN: usize = 128;
+
+const HISTORY_KEY: &str = "history";
+
+/// Messages to add to a session's history; an empty update reads the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionUpdate {
+    append: Vec<Value>,
+    max_messages: usize,
+    ttl_secs: u64,
+}
+
+/// Whether a client-chosen session ID is usable
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_SESSION_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+/// Drops the oldest messages so at most `max_messages` remain
+///
+/// The kept history always starts with a user turn that doesn't answer a tool
+/// call, since the calls it answered would have been dropped with it.
+pub fn truncate(messages: &[Value], max_messages: usize) -> Vec<Value> {
+    let mut start = messages.len().saturating_sub(max_messages);
+    while let Some(message) = messages.get(start) {
+        let answers_tool_call = message["content"]
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().any(|block| block["type"] == "tool_result"));
+        if message["role"] == "user" && !answers_tool_call {
+            break;
+        }
+        start += 1;
+    }
+    messages[start..].to_vec()
+}
+
+/// Rebuilds the assistant message from the Anthropic stream events sent to the client
+pub fn message_from_events(events: &str) -> Option<Value> {
+    let mut content: Vec<Value> = Vec::new();
+    let mut partial_json: Vec<String> = Vec::new();
+    for data in events
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+    {
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        let index = event["index"].as_u64().unwrap_or(0) as usize;
+        match event["type"].as_str() {
+            Some("content_block_start") => {
+                content.push(event["content_block"].clone());
+                partial_json.push(String::new());
+            }
+            Some("content_block_delta") => {
+                let (Some(block), Some(json)) =
+                    (content.get_mut(index), partial_json.get_mut(index))
+                else {
+                    continue;
+                };
+                let delta = &event["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => {
+                        let text = block["text"].as_str().unwrap_or_default().to_string();
+                        block["text"] =
+                            format!("{text}{}", delta["text"].as_str().unwrap_or_default()).into();
+                    }
+                    Some("input_json_delta") => {
+                        json.push_str(delta["partial_json"].as_str().unwrap_or_default())
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_stop") => {
+                if let (Some(block), Some(json)) = (content.get_mut(index), partial_json.get(index))
+                {
+                    if block["type"] == "tool_use" && !json.is_empty() {
+                        block["input"] = serde_json::from_str(json).unwrap_or_else(|_| json!({}));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (!content.is_empty()).then(|| json!({"role": "assistant", "content": content}))
+}
+
+/// One conversation's history
+///
+/// Each caller's session gets its own instance, so turns of one conversation are
+/// serialized. The history is deleted once the session has been idle for its TTL.
+#[durable_object]
+pub struct ConversationSession {
+    state: State,
+}
+
+impl DurableObject for ConversationSession {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let update: SessionUpdate = req.json().await?;
+        let storage = self.state.storage();
+        let mut history: Vec<Value> = storage.get(HISTORY_KEY).await.unwrap_or_default();
+
+        if !update.append.is_empty() {
+            history.extend(update.append);
+            history = truncate(&history, update.max_messages);
+            storage.put(HISTORY_KEY, &history).await?;
+            storage
+                .set_alarm(std::time::Duration::from_secs(update.ttl_secs))
+                .await?;
+        }
+
+        Response::from_json(&history)
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        self.state.storage().delete(HISTORY_KEY).await?;
+        Response::empty()
+    }
+}
+
+/// A caller's session, scoped so session IDs can't collide across callers
+pub fn session_stub(namespace: &ObjectNamespace, subject: &str, session_id: &str) -> Result<Stub> {
+    namespace
+        .id_from_name(&hash_key(&format!("{subject}\n{session_id}")))?
+        .get_stub()
+}
+
+async fn update(stub: &Stub, update: &SessionUpdate) -> Result<Vec<Value>> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(serde_json::to_string(update)?.into()));
+    let req = Request::new_with_init("https://conversation-session/history", &init)?;
+    stub.fetch_with_request(req).await?.json().await
+}
+
+/// Reads a session's stored history
+pub async fn history(stub: &Stub) -> Result<Vec<Value>> {
+    update(
+        stub,
+        &SessionUpdate {
+            append: Vec::new(),
+            max_messages: 0,
+            ttl_secs: 0,
+        },
+    )
+    .await
+}
+
+/// Adds a completed turn to a session, keeping at most `max_messages`
+pub async fn append(
+    stub: &Stub,
+    messages: Vec<Value>,
+    max_messages: usize,
+    ttl_secs: u64,
+) -> Result<()> {
+    update(
+        stub,
+        &SessionUpdate {
+            append: messages,
+            max_messages,
+            ttl_secs,
+        },
+    )
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> Value {
+        json!({"role": "user", "content": text})
+    }
+
+    fn assistant(text: &str) -> Value {
+        json!({"role": "assistant", "content": text})
+    }
+
+    #[test]
+    fn test_session_ids() {
+        assert!(is_valid_id("chat-42"));
+        assert!(is_valid_id("team:alice.1"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("has space"));
+        assert!(!is_valid_id(&"x".repeat(MAX_SESSION_ID_LEN + 1)));
+    }
+
+    #[test]
+    fn test_truncate_keeps_newest_messages_from_a_user_turn() {
+        let history = vec![
+            user("1"),
+            assistant("2"),
+            user("3"),
+            assistant("4"),
+            user("5"),
+        ];
+        assert_eq!(truncate(&history, 10), history);
+        assert_eq!(truncate(&history, 3), history[2..]);
+        // Four would start on an assistant turn
+        assert_eq!(truncate(&history, 4), history[2..]);
+    }
+
+    #[test]
+    fn test_truncate_skips_orphaned_tool_results() {
+        let history = vec![
+            user("run it"),
+            json!({"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "bash", "input": {}}]}),
+            json!({"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "ok"}]}),
+            assistant("done"),
+            user("thanks"),
+        ];
+        assert_eq!(truncate(&history, 3), history[4..]);
+    }
+
+    #[test]
+    fn test_message_from_events() {
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1"}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Let me "}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "check."}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "t1", "name": "bash", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"command\":"}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": " \"ls\"}"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_stop"}),
+        ]
+        .iter()
+        .map(|event| format!("event: {}\ndata: {event}\n\n", event["type"].as_str().unwrap()))
+        .collect::<String>();
+
+        assert_eq!(
+            message_from_events(&events).unwrap(),
+            json!({"role": "assistant", "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "t1", "name": "bash", "input": {"command": "ls"}}
+            ]})
+        );
+        assert_eq!(message_from_events(""), None);
+    }
+}
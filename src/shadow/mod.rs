@@ -0,0 +1,191 @@
+use crate::models::OpenAIRequest;
+use crate::utils::{format_date, splitmix64};
+use serde_json::Value;
+use worker::{Bucket, Result};
+
+/// R2 binding that stores shadow comparison records
+pub const SHADOW_BUCKET_BINDING: &str = "SHADOW_BUCKET";
+
+/// Placeholder written in place of redacted credentials
+const REDACTED: &str = "[REDACTED]";
+
+/// Object keys whose values are always redacted, regardless of content
+const SENSITIVE_KEYS: &[&str] = &["api_key", "authorization", "x-api-key"];
+
+/// Decides whether a request falls into the mirrored sample
+///
+/// `seed` should vary per request (e.g. the request timestamp). It is mixed before
+/// bucketing so that consecutive seeds spread evenly across the 0-99 range.
+pub fn should_mirror(sample_percent: u8, seed: u64) -> bool {
+    if sample_percent == 0 {
+        return false;
+    }
+
+    splitmix64(seed) % 100 < u64::from(sample_percent.min(100))
+}
+
+/// Replaces credentials found anywhere in a JSON value
+///
+/// Values under sensitive keys are dropped entirely; inside strings, any word that
+/// looks like an API key (`sk-...`) is replaced while the surrounding text is kept.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            if let Some(redacted) = redact_text(text) {
+                *text = redacted;
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                if SENSITIVE_KEYS.contains(&key.to_lowercase().as_str()) {
+                    *item = Value::String(REDACTED.to_string());
+                } else {
+                    redact(item);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns `text` with API-key-looking words redacted, or `None` if nothing matched
+fn redact_text(text: &str) -> Option<String> {
+    if !text.contains("sk-") {
+        return None;
+    }
+
+    let mut changed = false;
+    let redacted = text
+        .split_inclusive(char::is_whitespace)
+        .map(|chunk| {
+            let word = chunk.trim_end();
+            if word.starts_with("sk-") && word.len() >= 20 {
+                changed = true;
+                format!("{REDACTED}{}", &chunk[word.len()..])
+            } else {
+                chunk.to_string()
+            }
+        })
+        .collect::<String>();
+
+    changed.then_some(redacted)
+}
+
+/// Builds the stored record comparing the primary and shadow responses
+pub fn comparison_record(
+    request: &OpenAIRequest,
+    shadow_model: &str,
+    primary_response: Value,
+    shadow_response: Option<Value>,
+    shadow_latency_ms: u64,
+    timestamp_ms: u64,
+) -> Value {
+    let mut record = serde_json::json!({
+        "id": format!("shadow_{timestamp_ms}"),
+        "timestamp": timestamp_ms,
+        "primary_model": request.model,
+        "shadow_model": shadow_model,
+        "request": request,
+        "primary_response": primary_response,
+        "shadow_response": shadow_response,
+        "shadow_latency_ms": shadow_latency_ms,
+    });
+    redact(&mut record);
+    record
+}
+
+/// Stores a comparison record in R2, partitioned by date
+pub async fn store(bucket: &Bucket, record: &Value) -> Result<()> {
+    let timestamp = record["timestamp"].as_u64().unwrap_or(0);
+    let key = format!(
+        "shadow/{}/{}.json",
+        format_date(timestamp),
+        record["id"].as_str().unwrap_or("unknown")
+    );
+
+    bucket
+        .put(key, serde_json::to_string(record)?)
+        .execute()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_should_mirror_bounds() {
+        assert!(!(0..1000).any(|seed| should_mirror(0, seed)));
+        assert!((0..1000).all(|seed| should_mirror(100, seed)));
+    }
+
+    #[test]
+    fn test_should_mirror_sample_rate() {
+        let mirrored = (0..10_000).filter(|seed| should_mirror(10, *seed)).count();
+        assert!((800..1200).contains(&mirrored), "mirrored {mirrored}");
+    }
+
+    #[test]
+    fn test_redact_keys_and_tokens() {
+        let mut value = json!({
+            "Authorization": "Bearer sk-or-v1-abcdef",
+            "messages": [
+                {"role": "user", "content": "my key is sk-or-v1-0123456789abcdef please\nhelp"}
+            ],
+            "temperature": 0.5
+        });
+
+        redact(&mut value);
+
+        assert_eq!(value["Authorization"], REDACTED);
+        assert_eq!(
+            value["messages"][0]["content"],
+            "my key is [REDACTED] please\nhelp"
+        );
+        assert_eq!(value["temperature"], 0.5);
+    }
+
+    #[test]
+    fn test_redact_leaves_short_prefixes() {
+        let mut value = json!("ask-me anything, sk-short");
+        redact(&mut value);
+        assert_eq!(value, "ask-me anything, sk-short");
+    }
+
+    #[test]
+    fn test_comparison_record() {
+        let request = OpenAIRequest {
+            model: "moonshotai/kimi-k2:free".to_string(),
+            messages: vec![json!({"role": "user", "content": "Hello"})],
+            temperature: None,
+            tools: None,
+            stream: Some(false),
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop: None,
+            seed: None,
+            plugins: None,
+            prompt_cache_key: None,
+            extensions: Default::default(),
+        };
+
+        let record = comparison_record(
+            &request,
+            "google/gemini-2.5-flash",
+            json!({"choices": []}),
+            None,
+            120,
+            1_735_689_600_000,
+        );
+
+        assert_eq!(record["id"], "shadow_1735689600000");
+        assert_eq!(record["primary_model"], "moonshotai/kimi-k2:free");
+        assert_eq!(record["shadow_model"], "google/gemini-2.5-flash");
+        assert_eq!(record["request"]["messages"][0]["content"], "Hello");
+        assert!(record["shadow_response"].is_null());
+    }
+}
@@ -0,0 +1,88 @@
+//! Signed responses, for automation that has to trust where a response came from
+//!
+//! With `RESPONSE_SIGNING_KEY` set, every response of the authenticated routes
+//! carries `x-ccr-signature: t=<unix seconds>,v1=<hex HMAC-SHA256>`, the MAC of
+//! `<t>.<body>` under the key. A verifier recomputes it over the body it read,
+//! compares in constant time and rejects old timestamps, so a response altered
+//! or replayed by a proxy in between is caught. Signed responses are sent
+//! uncompressed, so the body the client reads is the one that was signed.
+
+use crate::auth::constant_time_eq;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use worker::{Response, Result};
+
+/// Response header carrying the signature
+pub const SIGNATURE_HEADER: &str = "x-ccr-signature";
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>`
+fn mac(key: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The `x-ccr-signature` value for `body`, signed at `timestamp` (Unix seconds)
+pub fn signature(key: &str, timestamp: u64, body: &[u8]) -> String {
+    format!("t={timestamp},v1={}", mac(key, timestamp, body))
+}
+
+/// Whether `header` is a valid signature of `body`, made at most `tolerance_secs`
+/// before `now` (Unix seconds)
+pub fn verify(key: &str, header: &str, body: &[u8], now: u64, tolerance_secs: u64) -> bool {
+    let field = |name: &str| {
+        header
+            .split(',')
+            .find_map(|part| part.trim().strip_prefix(name))
+    };
+    let (Some(Ok(timestamp)), Some(signed)) = (field("t=").map(str::parse::<u64>), field("v1="))
+    else {
+        return false;
+    };
+    now.saturating_sub(timestamp) <= tolerance_secs
+        && constant_time_eq(&mac(key, timestamp, body), signed)
+}
+
+/// Adds the signature of `response`'s body, which is read to sign it
+pub async fn sign(mut response: Response, key: &str, now: u64) -> Result<Response> {
+    let status = response.status_code();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?;
+    let signature = signature(key, now, &body);
+    let mut signed = Response::from_bytes(body)?
+        .with_status(status)
+        .with_headers(headers);
+    signed.headers_mut().set(SIGNATURE_HEADER, &signature)?;
+    Ok(signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "whsec_test";
+    const NOW: u64 = 1_750_000_000;
+
+    #[test]
+    fn test_signatures_verify() {
+        let body = br#"{"type":"message","content":[{"type":"text","text":"Hi"}]}"#;
+        let header = signature(KEY, NOW, body);
+        assert!(header.starts_with(&format!("t={NOW},v1=")));
+        assert_eq!(header.len(), format!("t={NOW},v1=").len() + 64);
+        assert!(verify(KEY, &header, body, NOW + 10, 300));
+
+        // Altered bodies, other keys and replays fail
+        assert!(!verify(KEY, &header, br#"{"type":"message"}"#, NOW, 300));
+        assert!(!verify("other", &header, body, NOW, 300));
+        assert!(!verify(KEY, &header, body, NOW + 301, 300));
+        let moved = header.replace(&format!("t={NOW}"), &format!("t={}", NOW + 60));
+        assert!(!verify(KEY, &moved, body, NOW + 60, 300));
+        assert!(!verify(KEY, "v1=abc", body, NOW, 300));
+    }
+}
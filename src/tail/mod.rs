@@ -0,0 +1,218 @@
+use crate::metrics::RequestMetrics;
+use serde::{Deserialize, Serialize};
+use worker::{
+    durable_object, Env, Method, ObjectNamespace, Request, RequestInit, Response, Result, State,
+    WebSocketPair,
+};
+
+/// Durable Object namespace binding behind the `/admin/tail` console
+pub const REQUEST_TAIL_BINDING: &str = "REQUEST_TAIL";
+
+/// Summaries kept for consoles that connect later
+pub const TAIL_BUFFER_SIZE: usize = 100;
+
+/// Storage key of the buffered summaries
+const RECENT_KEY: &str = "recent";
+
+/// Every request goes to one instance, so all consoles see the same stream
+const INSTANCE_NAME: &str = "global";
+
+/// Console page; it connects back to `/admin/tail` with the token from its own URL
+pub const CONSOLE_HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <title>CCR Tail</title>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <script src="https://cdn.tailwindcss.com"></script>
+</head>
+<body class="bg-gray-50 text-gray-900">
+    <div class="max-w-5xl mx-auto py-12 px-4">
+        <h1 class="text-3xl font-bold mb-2">Live requests</h1>
+        <p id="status" class="text-gray-600 mb-8">Connecting…</p>
+        <table class="w-full bg-white border border-gray-200 text-sm">
+            <thead>
+                <tr class="bg-gray-100 text-left"><th class="p-2">Time</th><th class="p-2">Model</th><th class="p-2">Status</th><th class="p-2">Latency (ms)</th><th class="p-2">Input tokens</th><th class="p-2">Output tokens</th></tr>
+            </thead>
+            <tbody id="rows"></tbody>
+        </table>
+    </div>
+    <script>
+        const token = new URLSearchParams(location.search).get("token") || "";
+        const scheme = location.protocol === "https:" ? "wss:" : "ws:";
+        const socket = new WebSocket(`${scheme}//${location.host}/admin/tail?token=${encodeURIComponent(token)}`);
+        const status = document.getElementById("status");
+        const rows = document.getElementById("rows");
+
+        socket.onopen = () => { status.textContent = "Connected"; };
+        socket.onclose = () => { status.textContent = "Disconnected; reload to reconnect"; };
+        socket.onmessage = (event) => {
+            const summary = JSON.parse(event.data);
+            const row = document.createElement("tr");
+            row.className = "border-t" + (summary.status >= 400 ? " text-red-700" : "");
+            const model = summary.model === summary.requested_model
+                ? summary.model
+                : `${summary.requested_model} → ${summary.model}`;
+            for (const value of [
+                new Date(summary.timestamp_ms).toLocaleTimeString(),
+                model,
+                summary.status,
+                Math.round(summary.latency_ms),
+                summary.input_tokens,
+                summary.output_tokens,
+            ]) {
+                const cell = document.createElement("td");
+                cell.className = "p-2 font-mono";
+                cell.textContent = value;
+                row.appendChild(cell);
+            }
+            rows.prepend(row);
+            while (rows.children.length > 500) rows.lastChild.remove();
+        };
+    </script>
+</body>
+</html>
+"#;
+
+/// One proxied request as shown in the tail console; never includes content or keys
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestSummary {
+    pub request_id: String,
+    pub timestamp_ms: u64,
+    pub model: String,
+    pub requested_model: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+impl RequestSummary {
+    pub fn new(request_id: &str, timestamp_ms: u64, metrics: &RequestMetrics) -> Self {
+        RequestSummary {
+            request_id: request_id.to_string(),
+            timestamp_ms,
+            model: metrics.model.clone(),
+            requested_model: metrics.requested_model.clone(),
+            status: metrics.status,
+            latency_ms: metrics.latency_ms,
+            input_tokens: metrics.input_tokens,
+            output_tokens: metrics.output_tokens,
+        }
+    }
+}
+
+/// Appends a summary, dropping the oldest ones beyond `capacity`
+pub fn push_recent(recent: &mut Vec<RequestSummary>, summary: RequestSummary, capacity: usize) {
+    recent.push(summary);
+    if recent.len() > capacity {
+        let excess = recent.len() - capacity;
+        recent.drain(..excess);
+    }
+}
+
+/// Buffers recent request summaries and fans them out to connected consoles
+///
+/// Consoles are accepted through the hibernation API, so idle connections don't
+/// keep the object in memory; the buffer lives in storage for the same reason.
+#[durable_object]
+pub struct RequestTail {
+    state: State,
+}
+
+impl DurableObject for RequestTail {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let storage = self.state.storage();
+        let mut recent: Vec<RequestSummary> = storage.get(RECENT_KEY).await.unwrap_or_default();
+
+        if req.method() == Method::Post {
+            let summary: RequestSummary = req.json().await?;
+            let message = serde_json::to_string(&summary)?;
+            push_recent(&mut recent, summary, TAIL_BUFFER_SIZE);
+            storage.put(RECENT_KEY, &recent).await?;
+
+            // A console that went away is cleaned up by the runtime; ignore send errors
+            for socket in self.state.get_websockets() {
+                let _ = socket.send_with_str(&message);
+            }
+            return Response::empty();
+        }
+
+        let pair = WebSocketPair::new()?;
+        self.state.accept_web_socket(&pair.server);
+        for summary in &recent {
+            pair.server.send_with_str(serde_json::to_string(summary)?)?;
+        }
+        Response::from_websocket(pair.client)
+    }
+}
+
+/// Publishes a request summary to connected consoles
+pub async fn publish(namespace: &ObjectNamespace, summary: &RequestSummary) -> Result<()> {
+    let stub = namespace.id_from_name(INSTANCE_NAME)?.get_stub()?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(serde_json::to_string(summary)?.into()));
+    let req = Request::new_with_init("https://request-tail/publish", &init)?;
+
+    stub.fetch_with_request(req).await?;
+    Ok(())
+}
+
+/// Hands a WebSocket upgrade request to the tail object
+pub async fn connect(namespace: &ObjectNamespace, req: Request) -> Result<Response> {
+    let stub = namespace.id_from_name(INSTANCE_NAME)?.get_stub()?;
+    stub.fetch_with_request(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(request_id: &str) -> RequestSummary {
+        RequestSummary {
+            request_id: request_id.to_string(),
+            timestamp_ms: 0,
+            model: "deepseek/deepseek-chat".to_string(),
+            requested_model: "claude-sonnet-4".to_string(),
+            status: 200,
+            latency_ms: 812.0,
+            input_tokens: 1200,
+            output_tokens: 300,
+        }
+    }
+
+    #[test]
+    fn test_push_recent_keeps_newest() {
+        let mut recent = Vec::new();
+        for id in ["a", "b", "c", "d"] {
+            push_recent(&mut recent, summary(id), 3);
+        }
+        let ids: Vec<&str> = recent.iter().map(|s| s.request_id.as_str()).collect();
+        assert_eq!(ids, ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_summary_from_metrics() {
+        let metrics = RequestMetrics {
+            model: "deepseek/deepseek-chat".to_string(),
+            requested_model: "claude-sonnet-4".to_string(),
+            key_hash: "ab12".to_string(),
+            status: 200,
+            latency_ms: 812.0,
+            input_tokens: 1200,
+            output_tokens: 300,
+            cost_usd: None,
+            prompt_cache_key: None,
+            cached_input_tokens: 0,
+        };
+        let summary = RequestSummary::new("a", 0, &metrics);
+        assert_eq!(summary, self::summary("a"));
+        assert!(!serde_json::to_string(&summary).unwrap().contains("ab12"));
+    }
+}
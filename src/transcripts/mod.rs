@@ -0,0 +1,218 @@
+pub mod sessions;
+
+use crate::auth::hash_key;
+use crate::models::{AnthropicRequest, Usage};
+use crate::shadow;
+use crate::utils::format_date;
+use serde_json::Value;
+use std::str::FromStr;
+use worker::{Bucket, Result};
+
+/// R2 binding that stores request/response transcripts when `LOG_TO_R2` is on
+pub const TRANSCRIPT_BUCKET_BINDING: &str = "TRANSCRIPT_BUCKET";
+
+/// Fields holding conversation content, replaced by their hash in hashed mode
+const CONTENT_KEYS: &[&str] = &[
+    "text",
+    "content",
+    "system",
+    "input",
+    "arguments",
+    "thinking",
+];
+
+/// What `LOG_TO_R2` stores for each request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptMode {
+    /// Nothing is stored (the default)
+    Off,
+    /// Requests and responses with credentials stripped
+    Full,
+    /// As `Full`, but message content is replaced by its SHA-256, so transcripts show
+    /// structure and repeated content without revealing it
+    Hashed,
+}
+
+impl FromStr for TranscriptMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "false" => Ok(TranscriptMode::Off),
+            "full" | "true" => Ok(TranscriptMode::Full),
+            "hashed" => Ok(TranscriptMode::Hashed),
+            _ => Err("expected 'off', 'full' or 'hashed'".to_string()),
+        }
+    }
+}
+
+/// Replaces conversation content with `sha256:<hex>` digests, keeping the structure
+pub fn hash_content(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(hash_content),
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                if !CONTENT_KEYS.contains(&key.as_str()) {
+                    hash_content(item);
+                    continue;
+                }
+                match item {
+                    // Block lists keep their shape so block types stay visible
+                    Value::Array(_) => hash_content(item),
+                    Value::Null => {}
+                    Value::String(text) => *item = format!("sha256:{}", hash_key(text)).into(),
+                    other => *other = format!("sha256:{}", hash_key(&other.to_string())).into(),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Builds the stored transcript for one request
+///
+/// `response` is the Anthropic response sent to the client; streamed responses
+/// aren't buffered, so only their usage is recorded.
+pub fn transcript_record(
+    request_id: &str,
+    timestamp_ms: u64,
+    mode: TranscriptMode,
+    request: &AnthropicRequest,
+    upstream_model: &str,
+    response: Option<Value>,
+    usage: Option<&Usage>,
+) -> Value {
+    let mut record = serde_json::json!({
+        "id": request_id,
+        "timestamp": timestamp_ms,
+        "model": request.model,
+        "upstream_model": upstream_model,
+        "request": request,
+        "response": response,
+        "usage": usage,
+    });
+    shadow::redact(&mut record);
+    if mode == TranscriptMode::Hashed {
+        hash_content(&mut record["request"]);
+        hash_content(&mut record["response"]);
+    }
+    record
+}
+
+/// R2 key of the transcript of request `id`, made on `date` (`YYYY-MM-DD`, UTC)
+pub fn storage_key(date: &str, id: &str) -> String {
+    format!("transcripts/{date}/{id}.json")
+}
+
+/// Stores a transcript in R2 under `transcripts/<YYYY-MM-DD>/<id>.json`
+pub async fn store(bucket: &Bucket, record: &Value) -> Result<()> {
+    let timestamp = record["timestamp"].as_u64().unwrap_or(0);
+    let key = storage_key(
+        &format_date(timestamp),
+        record["id"].as_str().unwrap_or("unknown"),
+    );
+
+    bucket
+        .put(key, serde_json::to_string(record)?)
+        .execute()
+        .await?;
+    Ok(())
+}
+
+/// Reads the transcript of request `id`, made on `date`
+pub async fn load(bucket: &Bucket, date: &str, id: &str) -> Result<Option<Value>> {
+    let Some(object) = bucket.get(storage_key(date, id)).execute().await? else {
+        return Ok(None);
+    };
+    let Some(body) = object.body() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(&body.text().await?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request() -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "system": "You are terse",
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": "my key is sk-or-v1-0123456789abcdef"}]},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "read", "input": {"path": "/etc"}}]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            "Hashed".parse::<TranscriptMode>(),
+            Ok(TranscriptMode::Hashed)
+        );
+        assert_eq!("true".parse::<TranscriptMode>(), Ok(TranscriptMode::Full));
+        assert!("sometimes".parse::<TranscriptMode>().is_err());
+    }
+
+    #[test]
+    fn test_full_transcript_strips_keys() {
+        let record = transcript_record(
+            "ray-1",
+            1_700_000_000_000,
+            TranscriptMode::Full,
+            &request(),
+            "deepseek/deepseek-chat",
+            Some(json!({"content": [{"type": "text", "text": "ok"}]})),
+            None,
+        );
+        let text = record.to_string();
+        assert!(!text.contains("sk-or-v1"));
+        assert!(text.contains("my key is"));
+        assert_eq!(record["response"]["content"][0]["text"], "ok");
+        assert_eq!(record["upstream_model"], "deepseek/deepseek-chat");
+    }
+
+    #[test]
+    fn test_hashed_transcript_hides_content() {
+        let usage = Usage {
+            input_tokens: 12,
+            output_tokens: 3,
+            ..Usage::default()
+        };
+        let record = transcript_record(
+            "ray-1",
+            0,
+            TranscriptMode::Hashed,
+            &request(),
+            "deepseek/deepseek-chat",
+            Some(json!({"content": [{"type": "text", "text": "ok"}]})),
+            Some(&usage),
+        );
+
+        let messages = &record["request"]["messages"];
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[0]["content"][0]["type"], "text");
+        assert!(messages[0]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("sha256:"));
+        assert!(messages[1]["content"][0]["input"]
+            .as_str()
+            .unwrap()
+            .starts_with("sha256:"));
+        assert_eq!(messages[1]["content"][0]["name"], "read");
+        assert!(record["request"]["system"]
+            .as_str()
+            .unwrap()
+            .starts_with("sha256:"));
+        assert_eq!(
+            record["response"]["content"][0]["text"],
+            format!("sha256:{}", hash_key("ok"))
+        );
+        assert_eq!(record["usage"]["input_tokens"], 12);
+        assert_eq!(record["model"], "claude-sonnet-4");
+    }
+}
@@ -0,0 +1,225 @@
+//! Conversation transcripts kept for the people having them
+//!
+//! Claude Code discards a conversation's history once it moves on. A caller who
+//! opts in, with `x-ccr-transcript: true` or `"save_transcripts": true` on their
+//! virtual key, has each conversation saved to `TRANSCRIPT_BUCKET` after every
+//! turn and can fetch it from `GET /sessions/{id}/export` as Markdown or JSON.
+//! Conversations are named by `x-ccr-session`, or else by the session ID Claude
+//! Code puts in `metadata.user_id`, and only the caller who had them can export them.
+//!
+//! Every turn carries the whole conversation so far, so each thread of a session
+//! is stored whole and replaced by its next turn. Threads are told apart by their
+//! first message, since subagents and compacted conversations start their own.
+
+use crate::auth::hash_key;
+use crate::models::AnthropicRequest;
+use crate::shadow;
+use crate::utils::format_date;
+use serde_json::{json, Value};
+use worker::{Bucket, Result};
+
+/// Request header saving the conversation's transcript (`x-ccr-transcript: true`)
+pub const TRANSCRIPT_HEADER: &str = "x-ccr-transcript";
+
+/// Most threads of a session an export holds
+pub const MAX_EXPORT_THREADS: u32 = 50;
+
+/// Thread of the conversations CCR keeps with `x-ccr-session`, whose first
+/// message changes as old turns are dropped
+const KEPT_HISTORY_THREAD: &str = "history";
+
+/// The thread a turn belongs to, named by a digest of its first message unless
+/// CCR keeps the conversation
+pub fn thread_id(kept_by_ccr: bool, messages: &[Value]) -> String {
+    if kept_by_ccr {
+        return KEPT_HISTORY_THREAD.to_string();
+    }
+    let first = messages.first().map(Value::to_string).unwrap_or_default();
+    hash_key(&first)[..16].to_string()
+}
+
+/// R2 prefix of a caller's session; IDs are hashed, so clients never choose keys
+pub fn session_prefix(subject: &str, session_id: &str) -> String {
+    format!(
+        "sessions/{}/",
+        hash_key(&format!("{subject}\n{session_id}"))
+    )
+}
+
+/// A thread as stored after a turn: the conversation sent, with the reply added
+/// and credentials stripped
+pub fn thread_record(
+    session_id: &str,
+    thread: &str,
+    timestamp_ms: u64,
+    request: &AnthropicRequest,
+    upstream_model: &str,
+    reply: Option<Value>,
+) -> Value {
+    let mut messages = request.messages.clone();
+    messages.extend(reply);
+    let mut record = json!({
+        "session": session_id,
+        "thread": thread,
+        "updated_at": timestamp_ms,
+        "model": request.model,
+        "upstream_model": upstream_model,
+        "system": request.system,
+        "messages": messages,
+    });
+    shadow::redact(&mut record);
+    record
+}
+
+/// Stores a thread under its session's prefix, replacing its previous turn
+pub async fn store(bucket: &Bucket, prefix: &str, record: &Value) -> Result<()> {
+    let key = format!(
+        "{prefix}{}.json",
+        record["thread"].as_str().unwrap_or(KEPT_HISTORY_THREAD)
+    );
+    bucket
+        .put(key, serde_json::to_string(record)?)
+        .execute()
+        .await?;
+    Ok(())
+}
+
+/// The stored threads of a session, in the order of their last turns
+pub async fn load(bucket: &Bucket, prefix: &str) -> Result<Vec<Value>> {
+    let listed = bucket
+        .list()
+        .prefix(prefix)
+        .limit(MAX_EXPORT_THREADS)
+        .execute()
+        .await?;
+    let mut threads = Vec::new();
+    for object in listed.objects() {
+        let Some(object) = bucket.get(object.key()).execute().await? else {
+            continue;
+        };
+        let Some(body) = object.body() else {
+            continue;
+        };
+        threads.push(serde_json::from_str::<Value>(&body.text().await?)?);
+    }
+    threads.sort_by_key(|thread| thread["updated_at"].as_u64().unwrap_or(0));
+    Ok(threads)
+}
+
+/// A session's threads as a Markdown document
+pub fn markdown(session_id: &str, threads: &[Value]) -> String {
+    let mut document = format!("# Session {session_id}\n");
+    for (number, thread) in threads.iter().enumerate() {
+        document.push_str(&format!(
+            "\n## Thread {} ({}, {})\n",
+            number + 1,
+            thread["model"].as_str().unwrap_or("unknown model"),
+            format_date(thread["updated_at"].as_u64().unwrap_or(0))
+        ));
+        if !thread["system"].is_null() {
+            document.push_str(&format!("\n### System\n\n{}\n", content(&thread["system"])));
+        }
+        for message in thread["messages"].as_array().into_iter().flatten() {
+            let role = match message["role"].as_str() {
+                Some("assistant") => "Assistant",
+                _ => "User",
+            };
+            document.push_str(&format!(
+                "\n### {role}\n\n{}\n",
+                content(&message["content"])
+            ));
+        }
+    }
+    document
+}
+
+/// Markdown for message content, a string or a list of blocks
+fn content(value: &Value) -> String {
+    let Some(blocks) = value.as_array() else {
+        return value.as_str().unwrap_or_default().to_string();
+    };
+    blocks
+        .iter()
+        .map(|block| match block["type"].as_str() {
+            Some("text") => block["text"].as_str().unwrap_or_default().to_string(),
+            Some("tool_use") => format!(
+                "**Tool call:** `{}`\n\n```json\n{}\n```",
+                block["name"].as_str().unwrap_or_default(),
+                serde_json::to_string_pretty(&block["input"]).unwrap_or_default()
+            ),
+            Some("tool_result") => {
+                format!(
+                    "**Tool result:**\n\n```\n{}\n```",
+                    content(&block["content"])
+                )
+            }
+            Some(other) => format!("*[{other}]*"),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "system": "You are Claude Code.",
+            "messages": [
+                {"role": "user", "content": "List the files"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "t1", "name": "Bash", "input": {"command": "ls"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "Cargo.toml\nsrc"}
+                ]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_thread_ids() {
+        let request = request();
+        let thread = thread_id(false, &request.messages);
+        assert_eq!(thread.len(), 16);
+        // Later turns of the thread start the same way
+        assert_eq!(thread_id(false, &request.messages[..1]), thread);
+        assert_ne!(thread_id(false, &request.messages[1..]), thread);
+        assert_eq!(thread_id(true, &request.messages), KEPT_HISTORY_THREAD);
+        assert_ne!(
+            session_prefix("key:a", "chat-1"),
+            session_prefix("key:b", "chat-1")
+        );
+    }
+
+    #[test]
+    fn test_thread_record_as_markdown() {
+        let reply = json!({"role": "assistant", "content": [{"type": "text", "text": "A manifest and the sources."}]});
+        let record = thread_record(
+            "chat-1",
+            "history",
+            1_750_000_000_000,
+            &request(),
+            "deepseek/deepseek-chat",
+            Some(reply),
+        );
+        assert_eq!(record["messages"].as_array().unwrap().len(), 4);
+        assert_eq!(record["upstream_model"], "deepseek/deepseek-chat");
+
+        let document = markdown("chat-1", &[record]);
+        assert!(
+            document.starts_with("# Session chat-1\n\n## Thread 1 (claude-sonnet-4, 2025-06-15)\n")
+        );
+        assert!(document.contains("### System\n\nYou are Claude Code.\n"));
+        assert!(document.contains("### User\n\nList the files\n"));
+        assert!(
+            document.contains("**Tool call:** `Bash`\n\n```json\n{\n  \"command\": \"ls\"\n}\n```")
+        );
+        assert!(document.contains("**Tool result:**\n\n```\nCargo.toml\nsrc\n```"));
+        assert!(document.ends_with("### Assistant\n\nA manifest and the sources.\n"));
+    }
+}
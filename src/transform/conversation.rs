@@ -0,0 +1,299 @@
+//! Whole conversations converted between the two formats, for logged datasets
+//!
+//! An Anthropic conversation export has the shape of a Messages request and an
+//! OpenAI one the shape of a chat completions request, each holding every turn
+//! so far. Anthropic conversations are translated exactly as the proxy translates
+//! a request, with the same model mapping and content flattening. In OpenAI
+//! conversations, assistant turns are translated as the proxy translates a
+//! response, and `tool` messages become `tool_result` blocks.
+
+use super::request::{anthropic_to_openai, content_text};
+use super::response::assistant_content;
+use super::{Error, Result};
+use crate::models::AnthropicRequest;
+use crate::utils::ModelRouting;
+use serde_json::{json, Map, Value};
+
+/// Format a conversation is converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// An Anthropic Messages request
+    Anthropic,
+    /// An OpenAI chat completions request
+    OpenAI,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "anthropic" => Ok(Format::Anthropic),
+            "openai" => Ok(Format::OpenAI),
+            _ => Err("expected anthropic or openai".to_string()),
+        }
+    }
+}
+
+impl Format {
+    /// The format a conversation export is in, as far as its shape tells
+    ///
+    /// OpenAI conversations are told apart by `system`, `developer` or `tool`
+    /// messages, tool calls, or function tools; anything else is taken to be
+    /// Anthropic, whose plain text turns look the same.
+    pub fn detect(conversation: &Value) -> Format {
+        let openai_message = |message: &Value| {
+            matches!(
+                message["role"].as_str(),
+                Some("system" | "developer" | "tool")
+            ) || message.get("tool_calls").is_some()
+        };
+        let openai = conversation["messages"]
+            .as_array()
+            .is_some_and(|messages| messages.iter().any(openai_message))
+            || conversation["tools"]
+                .as_array()
+                .is_some_and(|tools| tools.iter().any(|tool| tool.get("function").is_some()));
+        if openai {
+            Format::OpenAI
+        } else {
+            Format::Anthropic
+        }
+    }
+
+    /// The other format
+    pub fn other(self) -> Format {
+        match self {
+            Format::Anthropic => Format::OpenAI,
+            Format::OpenAI => Format::Anthropic,
+        }
+    }
+}
+
+/// Converts a conversation export to the format `to`, from the other one
+///
+/// `config` maps model names when converting to OpenAI; OpenAI model names are
+/// kept as they are.
+pub fn convert_conversation(
+    conversation: &Value,
+    to: Format,
+    config: &impl ModelRouting,
+) -> Result<Value> {
+    match to {
+        Format::OpenAI => {
+            let request: AnthropicRequest = serde_json::from_value(conversation.clone())
+                .map_err(|e| Error(format!("Invalid Anthropic conversation: {e}")))?;
+            let request = anthropic_to_openai(&request, config)?;
+            serde_json::to_value(request).map_err(|e| Error(e.to_string()))
+        }
+        Format::Anthropic => openai_to_anthropic_conversation(conversation),
+    }
+}
+
+fn openai_to_anthropic_conversation(conversation: &Value) -> Result<Value> {
+    let messages = conversation["messages"]
+        .as_array()
+        .ok_or_else(|| Error("Invalid OpenAI conversation: missing messages".to_string()))?;
+
+    let mut system = Vec::new();
+    let mut turns: Vec<Value> = Vec::with_capacity(messages.len());
+    for message in messages {
+        match message["role"].as_str() {
+            Some("system" | "developer") => system.push(text(&message["content"])),
+            Some("user") => {
+                turns.push(json!({"role": "user", "content": text(&message["content"])}))
+            }
+            Some("assistant") => {
+                turns.push(json!({"role": "assistant", "content": assistant_content(message)}))
+            }
+            Some("tool") => {
+                let result = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message["tool_call_id"],
+                    "content": text(&message["content"])
+                });
+                // Results of parallel tool calls share one user turn
+                match turns.last_mut() {
+                    Some(last) if is_tool_results(last) => {
+                        if let Some(blocks) = last["content"].as_array_mut() {
+                            blocks.push(result);
+                        }
+                    }
+                    _ => turns.push(json!({"role": "user", "content": [result]})),
+                }
+            }
+            role => {
+                return Err(Error(format!(
+                    "Invalid OpenAI conversation: unknown role {}",
+                    role.unwrap_or("(none)")
+                )))
+            }
+        }
+    }
+
+    let mut anthropic = Map::new();
+    anthropic.insert("model".to_string(), conversation["model"].clone());
+    if !system.is_empty() {
+        anthropic.insert("system".to_string(), system.join("\n\n").into());
+    }
+    anthropic.insert("messages".to_string(), turns.into());
+    if let Some(tools) = conversation["tools"].as_array() {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|tool| {
+                let function = &tool["function"];
+                json!({
+                    "name": function["name"],
+                    "description": function["description"],
+                    "input_schema": function["parameters"]
+                })
+            })
+            .collect();
+        anthropic.insert("tools".to_string(), tools.into());
+    }
+    let parameters = [
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop", "stop_sequences"),
+    ];
+    for (openai, name) in parameters {
+        if let Some(value) = conversation.get(openai).filter(|value| !value.is_null()) {
+            anthropic.insert(name.to_string(), value.clone());
+        }
+    }
+    Ok(Value::Object(anthropic))
+}
+
+/// Text of OpenAI message content, a string or a list of parts
+fn text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => content_text(parts).into_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Whether an Anthropic turn holds only tool results
+fn is_tool_results(turn: &Value) -> bool {
+    turn["role"] == "user"
+        && turn["content"]
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().all(|block| block["type"] == "tool_result"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Routing;
+
+    #[test]
+    fn test_convert_conversation_both_ways() {
+        let anthropic = json!({
+            "model": "sonnet",
+            "system": "You are a coding assistant.",
+            "max_tokens": 1024,
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": "Fix the build"}]},
+                {"role": "assistant", "content": "Done."}
+            ]
+        });
+        let openai = convert_conversation(&anthropic, Format::OpenAI, &Routing::default()).unwrap();
+        assert_eq!(openai["model"], "anthropic/claude-sonnet-4");
+        assert_eq!(
+            openai["messages"],
+            json!([
+                {"role": "system", "content": "You are a coding assistant."},
+                {"role": "user", "content": "Fix the build"},
+                {"role": "assistant", "content": "Done."}
+            ])
+        );
+        assert_eq!(openai["max_tokens"], 1024);
+
+        let openai = json!({
+            "model": "deepseek/deepseek-chat",
+            "stop": ["END"],
+            "tools": [{"type": "function", "function": {
+                "name": "Bash", "description": "Run a command", "parameters": {"type": "object"}
+            }}],
+            "messages": [
+                {"role": "system", "content": "You are a coding assistant."},
+                {"role": "user", "content": "Run the tests"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "Bash", "arguments": "{\"command\":\"cargo test\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "Bash", "arguments": "{\"command\":\"cargo clippy\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "ok"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "no warnings"},
+                {"role": "assistant", "content": "All green."}
+            ]
+        });
+        let anthropic =
+            convert_conversation(&openai, Format::Anthropic, &Routing::default()).unwrap();
+        assert_eq!(anthropic["model"], "deepseek/deepseek-chat");
+        assert_eq!(anthropic["system"], "You are a coding assistant.");
+        assert_eq!(anthropic["stop_sequences"], json!(["END"]));
+        assert_eq!(
+            anthropic["tools"][0]["input_schema"],
+            json!({"type": "object"})
+        );
+        let turns = anthropic["messages"].as_array().unwrap();
+        assert_eq!(turns.len(), 4);
+        assert_eq!(
+            turns[1]["content"][1]["input"],
+            json!({"command": "cargo clippy"})
+        );
+        assert_eq!(
+            turns[2]["content"],
+            json!([
+                {"type": "tool_result", "tool_use_id": "call_1", "content": "ok"},
+                {"type": "tool_result", "tool_use_id": "call_2", "content": "no warnings"}
+            ])
+        );
+        assert_eq!(
+            turns[3]["content"],
+            json!([{"type": "text", "text": "All green."}])
+        );
+
+        // The converted conversation parses as a request the proxy accepts
+        let request: AnthropicRequest = serde_json::from_value(anthropic).unwrap();
+        assert_eq!(request.messages.len(), 4);
+
+        assert!(convert_conversation(&json!({}), Format::Anthropic, &Routing::default()).is_err());
+        assert!(convert_conversation(&json!({}), Format::OpenAI, &Routing::default()).is_err());
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let anthropic = json!({
+            "model": "sonnet",
+            "system": "You are a coding assistant.",
+            "messages": [
+                {"role": "user", "content": "Run the tests"},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "Bash", "input": {}}]}
+            ],
+            "tools": [{"name": "Bash", "input_schema": {"type": "object"}}]
+        });
+        assert_eq!(Format::detect(&anthropic), Format::Anthropic);
+        assert_eq!(
+            Format::detect(&json!({"messages": [{"role": "user", "content": "Hi"}]})),
+            Format::Anthropic
+        );
+        assert_eq!(
+            Format::detect(&json!({"messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hi"}
+            ]})),
+            Format::OpenAI
+        );
+        assert_eq!(
+            Format::detect(&json!({
+                "messages": [{"role": "user", "content": "Hi"}],
+                "tools": [{"type": "function", "function": {"name": "Bash"}}]
+            })),
+            Format::OpenAI
+        );
+        assert_eq!("OpenAI".parse(), Ok(Format::OpenAI));
+        assert!("gemini".parse::<Format>().is_err());
+    }
+}
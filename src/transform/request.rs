@@ -0,0 +1,1258 @@
+use super::Result;
+use crate::auto_model;
+use crate::log;
+use crate::model_route::Route;
+use crate::models::{AnthropicRequest, OpenAIRequest};
+use crate::utils::{model_alias, ModelRouting};
+use std::borrow::Cow;
+
+/// Apply model-specific transformations inspired by claude-code-router
+/// Handles model-specific parameter requirements and incompatibilities, adjusting the request in place
+fn apply_model_specific_transforms(request: &mut OpenAIRequest) {
+    match request.model.as_str() {
+        // MoonshotAI models (like Kimi K2) have specific requirements
+        model_name if model_name.starts_with("moonshotai/") => {
+            // Based on claude-code-router config: moonshotai models work better with specific settings
+            request.temperature = request.temperature.map(|t| (t * 0.6).min(1.0));
+
+            // Set reasonable max_tokens for moonshotai models if not specified
+            request.max_tokens.get_or_insert(16384); // Based on their config
+        }
+
+        // DeepSeek models
+        model_name if model_name.starts_with("deepseek/") || model_name.contains("deepseek") => {
+            // DeepSeek models prefer lower temperature
+            request.temperature = request.temperature.map(|t| (t * 0.8).min(1.0));
+        }
+
+        // Anthropic, OpenAI, Google and other models work well with standard parameters
+        _ => {}
+    }
+}
+
+/// Whether tools are forwarded to the model
+fn supports_tools(model: &str) -> bool {
+    // MoonshotAI models don't support complex tools or cache_control - disable them for now
+    !model.starts_with("moonshotai/")
+}
+
+/// Schema keywords some providers reject; they only annotate or constrain string
+/// values, so dropping them doesn't change which tool inputs are valid in practice
+const UNSUPPORTED_SCHEMA_KEYWORDS: &[&str] = &["$schema", "$id", "$comment", "format"];
+
+/// Keywords whose value is a map of names to subschemas
+const SCHEMA_MAP_KEYWORDS: &[&str] = &["properties", "patternProperties", "$defs", "definitions"];
+
+/// Keywords whose value is a subschema, or a list of them
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "items",
+    "additionalProperties",
+    "not",
+    "anyOf",
+    "oneOf",
+    "allOf",
+    "prefixItems",
+];
+
+/// Removes the unsupported keywords from `schema` and its subschemas, leaving
+/// property names that happen to match a keyword alone
+fn strip_schema_keywords(schema: &mut serde_json::Value) {
+    match schema {
+        serde_json::Value::Object(map) => {
+            for keyword in UNSUPPORTED_SCHEMA_KEYWORDS {
+                map.remove(*keyword);
+            }
+            for keyword in SCHEMA_MAP_KEYWORDS {
+                if let Some(subschemas) = map.get_mut(*keyword).and_then(|v| v.as_object_mut()) {
+                    subschemas.values_mut().for_each(strip_schema_keywords);
+                }
+            }
+            for keyword in SCHEMA_KEYWORDS {
+                if let Some(subschema) = map.get_mut(*keyword) {
+                    strip_schema_keywords(subschema);
+                }
+            }
+        }
+        serde_json::Value::Array(schemas) => schemas.iter_mut().for_each(strip_schema_keywords),
+        _ => {}
+    }
+}
+
+/// Normalizes a tool's `input_schema` into an object schema providers accept:
+/// a missing or empty schema becomes one taking no parameters
+fn sanitize_schema(schema: &mut serde_json::Value) {
+    if !schema.is_object() {
+        *schema = serde_json::json!({});
+    }
+    strip_schema_keywords(schema);
+    let map = schema.as_object_mut().expect("schema is an object");
+    map.remove("cache_control");
+    map.insert("type".to_string(), "object".into());
+    if !map.get("properties").is_some_and(|p| p.is_object()) {
+        map.insert("properties".to_string(), serde_json::json!({}));
+    }
+}
+
+/// Whether `tool` is Anthropic's `web_search` server tool (`web_search_20250305`)
+fn is_web_search_tool(tool: &serde_json::Value) -> bool {
+    tool["type"]
+        .as_str()
+        .is_some_and(|tool_type| tool_type.starts_with("web_search_"))
+}
+
+/// Whether `tool` is Anthropic's code execution server tool (`code_execution_20250522`)
+fn is_code_execution_tool(tool: &serde_json::Value) -> bool {
+    tool["type"]
+        .as_str()
+        .is_some_and(|tool_type| tool_type.starts_with("code_execution_"))
+}
+
+/// OpenRouter's web search plugin, which grounds any model in search results
+pub fn web_search_plugin() -> serde_json::Value {
+    serde_json::json!({"id": "web"})
+}
+
+/// Copies tools for the upstream request, stripping the `cache_control` OpenRouter
+/// rejects and, with `sanitize`, sanitizing their input schemas; Anthropic's server
+/// tools have no input schema to forward and are served some other way (or refused)
+fn clean_tools(tools: &[serde_json::Value], sanitize: bool) -> Vec<serde_json::Value> {
+    let mut tools: Vec<serde_json::Value> = tools
+        .iter()
+        .filter(|tool| !is_web_search_tool(tool) && !is_code_execution_tool(tool))
+        .cloned()
+        .collect();
+    for tool in &mut tools {
+        if let Some(tool_obj) = tool.as_object_mut() {
+            tool_obj.remove("cache_control");
+            if !sanitize {
+                continue;
+            }
+            // Tools already in OpenAI shape keep their parameters, if they declare any
+            if let Some(function) = tool_obj.get_mut("function") {
+                if let Some(parameters) = function.get_mut("parameters") {
+                    sanitize_schema(parameters);
+                }
+            } else {
+                sanitize_schema(
+                    tool_obj
+                        .entry("input_schema")
+                        .or_insert(serde_json::Value::Null),
+                );
+            }
+        }
+    }
+    tools
+}
+
+/// Request parameters upstream providers reject, by model ID prefix
+///
+/// Every entry whose prefix the model ID starts with applies, so `openai/` covers
+/// all OpenAI models and `openai/o` adds what the reasoning models also refuse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedParams {
+    entries: Vec<(String, Vec<String>)>,
+}
+
+impl Default for UnsupportedParams {
+    fn default() -> Self {
+        let entry = |prefix: &str, params: &[&str]| {
+            (
+                prefix.to_string(),
+                params.iter().map(|param| param.to_string()).collect(),
+            )
+        };
+        UnsupportedParams {
+            entries: vec![
+                entry("openai/", &["top_k"]),
+                entry("openai/o", &["temperature", "top_p"]),
+                entry("openai/gpt-5", &["temperature", "top_p"]),
+            ],
+        }
+    }
+}
+
+impl UnsupportedParams {
+    /// The parameters entries can name
+    pub const PARAMS: [&'static str; 5] = ["temperature", "top_p", "top_k", "stop", "max_tokens"];
+
+    /// Replaces the entries for the prefixes in `overrides`, adding new ones; an
+    /// empty list lifts a default entry
+    pub fn with_overrides(
+        mut self,
+        overrides: impl IntoIterator<Item = (String, Vec<String>)>,
+    ) -> std::result::Result<Self, String> {
+        for (prefix, params) in overrides {
+            if let Some(param) = params
+                .iter()
+                .find(|param| !Self::PARAMS.contains(&param.as_str()))
+            {
+                return Err(format!(
+                    "unknown parameter '{param}', expected one of {}",
+                    Self::PARAMS.join(", ")
+                ));
+            }
+            self.entries.retain(|(existing, _)| *existing != prefix);
+            if !params.is_empty() {
+                self.entries.push((prefix, params));
+            }
+        }
+        Ok(self)
+    }
+
+    /// The parameters `model` rejects
+    pub fn for_model<'a>(&'a self, model: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(prefix, _)| model.starts_with(prefix.as_str()))
+            .flat_map(|(_, params)| params.iter().map(String::as_str))
+    }
+}
+
+/// Removes the parameters the request's model rejects
+fn strip_unsupported_params(request: &mut OpenAIRequest, unsupported: &UnsupportedParams) {
+    let mut stripped = Vec::new();
+    for param in unsupported.for_model(&request.model) {
+        let present = match param {
+            "temperature" => request.temperature.take().is_some(),
+            "top_p" => request.top_p.take().is_some(),
+            "top_k" => request.top_k.take().is_some(),
+            "stop" => request.stop.take().is_some(),
+            "max_tokens" => request.max_tokens.take().is_some(),
+            _ => false,
+        };
+        if present {
+            stripped.push(param);
+        }
+    }
+    if !stripped.is_empty() {
+        log::debug(
+            "unsupported parameters stripped",
+            &[
+                ("model", request.model.as_str().into()),
+                ("params", stripped.join(",").into()),
+            ],
+        );
+    }
+}
+
+/// How a provider takes an assistant turn that only calls tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EmptyContent {
+    /// `content: null`, the OpenAI convention
+    Null,
+    /// An empty string, for providers that reject `null` content
+    EmptyString,
+}
+
+/// The empty-content convention of the provider serving `model`
+fn empty_content(model: &str) -> EmptyContent {
+    if model.starts_with("mistralai/") {
+        EmptyContent::EmptyString
+    } else {
+        EmptyContent::Null
+    }
+}
+
+fn is_empty_content(content: Option<&serde_json::Value>) -> bool {
+    match content {
+        None | Some(serde_json::Value::Null) => true,
+        Some(serde_json::Value::String(text)) => text.trim().is_empty(),
+        Some(serde_json::Value::Array(parts)) => parts.is_empty(),
+        Some(_) => false,
+    }
+}
+
+/// Handles messages without content without inventing any
+///
+/// Empty messages, whitespace-only ones included, are dropped, except those the
+/// conversation can't do without: tool-call turns get the provider's empty-content
+/// convention, and tool results and the final turn are sent with an empty string.
+fn clean_empty_content(request: &mut OpenAIRequest) {
+    let convention = empty_content(&request.model);
+    let last = request.messages.len().saturating_sub(1);
+    let before = request.messages.len();
+    let mut index = 0;
+    request.messages.retain_mut(|message| {
+        let is_last = index == last;
+        index += 1;
+        if !is_empty_content(message.get("content")) {
+            return true;
+        }
+        let calls_tools = message["tool_calls"]
+            .as_array()
+            .is_some_and(|calls| !calls.is_empty());
+        let replacement = if calls_tools {
+            match convention {
+                EmptyContent::Null => serde_json::Value::Null,
+                EmptyContent::EmptyString => "".into(),
+            }
+        } else if is_last || message["role"] == "tool" {
+            "".into()
+        } else {
+            return false;
+        };
+        if let Some(message) = message.as_object_mut() {
+            message.insert("content".to_string(), replacement);
+        }
+        true
+    });
+    if request.messages.len() < before {
+        log::debug(
+            "empty messages dropped",
+            &[("count", (before - request.messages.len()).into())],
+        );
+    }
+}
+
+/// Validate and clean the OpenAI request to prevent API errors
+/// Inspired by claude-code-router's approach to handle API incompatibilities
+fn validate_and_clean_request(request: &mut OpenAIRequest, unsupported: &UnsupportedParams) {
+    strip_unsupported_params(request, unsupported);
+
+    clean_empty_content(request);
+
+    // Model-specific validation
+    match request.model.as_str() {
+        model if model.starts_with("moonshotai/") => {
+            // MoonshotAI models might not support certain parameters
+            // Keep basic parameters only if there are issues
+
+            // Ensure max_tokens is reasonable
+            if let Some(max_tokens) = request.max_tokens {
+                if max_tokens > 32768 {
+                    request.max_tokens = Some(16384); // Safe default
+                }
+            }
+
+            // Validate temperature range
+            if let Some(temp) = request.temperature {
+                if !(0.0..=2.0).contains(&temp) {
+                    request.temperature = Some(0.6); // MoonshotAI recommended value
+                }
+            }
+        }
+
+        model if model.starts_with("deepseek/") => {
+            // DeepSeek specific validations
+            if let Some(temp) = request.temperature {
+                if temp > 1.5 {
+                    request.temperature = Some(1.0); // DeepSeek works better with lower temps
+                }
+            }
+        }
+
+        _ => {
+            // General validations for other models
+            if let Some(temp) = request.temperature {
+                if !(0.0..=2.0).contains(&temp) {
+                    request.temperature = Some(1.0); // Safe default
+                }
+            }
+        }
+    }
+}
+
+/// Whether the request can be forwarded without rebuilding its messages
+///
+/// True when there are no tools, every message is a `user` or `assistant` turn
+/// holding only `role` and non-empty string `content`, and the model is already an
+/// OpenRouter ID. Such messages are valid OpenAI messages as they are, so only the
+/// model and system prompt need adjusting.
+fn is_passthrough(req: &AnthropicRequest) -> bool {
+    req.tools.is_none()
+        && req.model.contains('/')
+        && req.messages.iter().all(|message| {
+            let Some(fields) = message.as_object() else {
+                return false;
+            };
+            fields.len() == 2
+                && matches!(
+                    fields.get("role").and_then(|role| role.as_str()),
+                    Some("user" | "assistant")
+                )
+                && fields
+                    .get("content")
+                    .and_then(|content| content.as_str())
+                    .is_some_and(|content| !content.trim().is_empty())
+        })
+}
+
+/// Joins the text of Anthropic content blocks, borrowing it when there is only one
+pub(super) fn content_text(blocks: &[serde_json::Value]) -> Cow<'_, str> {
+    let mut texts = blocks
+        .iter()
+        .filter_map(|block| block.get("text")?.as_str());
+    let Some(first) = texts.next() else {
+        return Cow::Borrowed("");
+    };
+    match texts.next() {
+        None => Cow::Borrowed(first),
+        Some(second) => {
+            let mut joined = String::from(first);
+            joined.push_str(second);
+            joined.extend(texts);
+            Cow::Owned(joined)
+        }
+    }
+}
+
+/// Converts an Anthropic message to an OpenAI message, flattening content blocks to text
+fn convert_message(message: &serde_json::Value) -> serde_json::Value {
+    let mut openai_message = serde_json::Map::with_capacity(2);
+
+    // Copy role
+    if let Some(role) = message.get("role") {
+        openai_message.insert("role".to_string(), role.clone());
+    }
+
+    // Skip cache_control fields that OpenRouter doesn't support
+    // (Claude Code may include these but OpenRouter will reject them)
+
+    // Convert content from Anthropic array format to OpenAI string format
+    if let Some(content) = message.get("content") {
+        if let Some(content_array) = content.as_array() {
+            // Extract text from Anthropic content array; empty text is handled
+            // per provider once the request is assembled
+            let text_content = content_text(content_array);
+            openai_message.insert(
+                "content".to_string(),
+                serde_json::Value::String(text_content.into_owned()),
+            );
+        } else if let Some(content_str) = content.as_str() {
+            openai_message.insert(
+                "content".to_string(),
+                serde_json::Value::String(content_str.to_string()),
+            );
+        }
+    }
+
+    serde_json::Value::Object(openai_message)
+}
+
+/// Whether the request uses Anthropic's code execution server tool or a container
+fn uses_code_execution(req: &AnthropicRequest) -> bool {
+    req.tools.iter().flatten().any(is_code_execution_tool)
+        || req.container.as_ref().is_some_and(|c| !c.is_null())
+}
+
+/// Checks the number of completions a request asks for, in its `field`
+///
+/// Responses are translated from the first choice only, so a request for more
+/// would pay for completions the client never sees, or get one back where it
+/// expects several; it is refused instead. Asking for exactly one is accepted.
+pub fn check_completion_count(
+    field: &str,
+    count: Option<&serde_json::Value>,
+) -> std::result::Result<(), String> {
+    match count {
+        None | Some(serde_json::Value::Null) => Ok(()),
+        Some(count) if count.as_u64() == Some(1) => Ok(()),
+        Some(count) => Err(format!(
+            "{field}: only one completion per request is supported, got {count}"
+        )),
+    }
+}
+
+/// Upstream request fields CCR sets from the Anthropic request, which the
+/// `openrouter` extension can't replace
+///
+/// `models` would send the request to fallbacks no virtual key allowlist or
+/// budget has seen, and `max_completion_tokens` would lift a key's token cap.
+const TRANSLATED_FIELDS: &[&str] = &[
+    "model",
+    "models",
+    "messages",
+    "temperature",
+    "tools",
+    "stream",
+    "max_tokens",
+    "max_completion_tokens",
+    "top_p",
+    "top_k",
+    "stop",
+    "seed",
+    "prompt_cache_key",
+    "n",
+];
+
+/// Rejects fields only the Anthropic API can serve
+///
+/// The Anthropic API connects to `mcp_servers` itself; an OpenAI-format upstream
+/// would run the request without their tools, so it is refused rather than
+/// silently dropped. Code execution is refused the same way unless a
+/// `code_execution_model` is configured to serve it, and so are requests for
+/// more than one completion. The `openrouter` extension may add fields to the
+/// upstream request but not replace the translated ones.
+pub fn check_translatable(
+    req: &AnthropicRequest,
+    config: &impl ModelRouting,
+) -> std::result::Result<(), String> {
+    check_completion_count("n", req.n.as_ref())?;
+    for (name, value) in req.openrouter.iter().flatten() {
+        if TRANSLATED_FIELDS.contains(&name.as_str()) {
+            return Err(format!(
+                "openrouter.{name}: CCR sets this field from the Anthropic request, so it can't be overridden"
+            ));
+        }
+        if name == "plugins" && !value.is_array() {
+            return Err("openrouter.plugins: must be an array".to_string());
+        }
+    }
+    if req
+        .mcp_servers
+        .as_ref()
+        .is_some_and(|servers| !servers.is_empty())
+    {
+        return Err(format!(
+            "mcp_servers: MCP servers are only supported by the Anthropic API, and model '{}' is served through an OpenAI-compatible upstream",
+            req.model
+        ));
+    }
+    if uses_code_execution(req) && config.code_execution_model().is_none() {
+        return Err(format!(
+            "tools: the code execution tool is only supported by the Anthropic API, and model '{}' is served through an OpenAI-compatible upstream",
+            req.model
+        ));
+    }
+    Ok(())
+}
+
+/// Chooses the upstream model for a request, recording each rule applied
+///
+/// Requests using the code execution tool go to the configured code execution
+/// model, `auto` picks a tier from request heuristics, and Claude short names are
+/// then mapped to their configured targets.
+pub fn route_model(req: &AnthropicRequest, config: &impl ModelRouting) -> Route {
+    let mut route = Route::new(req.model.as_str());
+
+    // "auto" lets CCR pick a cheap or strong model from request heuristics
+    let auto_config = config
+        .auto_model()
+        .filter(|_| auto_model::is_auto(&req.model));
+    let code_execution_model = config
+        .code_execution_model()
+        .filter(|_| uses_code_execution(req));
+    if let Some(model) = code_execution_model {
+        // The configured model runs code itself, in place of Anthropic's sandbox
+        log::debug("code execution routed", &[("model", model.into())]);
+        route.push("code_execution", model);
+    } else if let Some(auto_config) = auto_config {
+        let selection = auto_model::select(req, auto_config);
+
+        log::debug(
+            "auto model selected",
+            &[
+                ("model", selection.model.as_str().into()),
+                ("reason", selection.reason.into()),
+            ],
+        );
+
+        route.push(format!("auto.{}", selection.reason), selection.model);
+    }
+
+    if let Some((alias, target)) = model_alias(route.model(), config) {
+        route.push(format!("alias.{alias}"), target);
+    }
+
+    log::debug("model mapped", &[("model", route.model().into())]);
+    route
+}
+
+/// Transforms an Anthropic API request to OpenAI API format
+///
+/// This function handles the conversion of request structure, including:
+/// - Converting system messages to OpenAI format
+/// - Mapping Claude model names to OpenRouter model IDs
+/// - Preserving message structure and optional parameters
+pub fn anthropic_to_openai(
+    req: &AnthropicRequest,
+    config: &impl ModelRouting,
+) -> Result<OpenAIRequest> {
+    anthropic_to_openai_routed(req, &route_model(req, config), config)
+}
+
+/// Transforms an Anthropic API request to OpenAI API format for the model
+/// `route` chose, which callers reporting the route compute first
+pub fn anthropic_to_openai_routed(
+    req: &AnthropicRequest,
+    route: &Route,
+    config: &impl ModelRouting,
+) -> Result<OpenAIRequest> {
+    check_translatable(req, config).map_err(super::Error)?;
+    let mut messages = Vec::with_capacity(req.messages.len() + 1);
+
+    // Add system message if present (OpenAI format uses system role)
+    if let Some(system) = &req.system {
+        messages.push(serde_json::json!({
+            "role": "system",
+            "content": system
+        }));
+    }
+
+    // Requests already in OpenAI shape skip the per-message rebuild
+    if is_passthrough(req) {
+        log::debug("passthrough request", &[]);
+        messages.extend_from_slice(&req.messages);
+    } else {
+        messages.extend(req.messages.iter().map(convert_message));
+    }
+
+    // Only set max_tokens if explicitly provided - let OpenRouter use model defaults
+    let mut openai_request = OpenAIRequest {
+        model: route.model().to_string(),
+        messages,
+        temperature: req.temperature,
+        tools: None,
+        stream: req.stream,
+        max_tokens: req.max_tokens,
+        top_p: req.top_p,
+        top_k: req.top_k,
+        stop: req.stop_sequences.clone(),
+        seed: None,
+        plugins: None,
+        prompt_cache_key: None,
+        extensions: serde_json::Map::new(),
+    };
+
+    // Apply model-specific transformations (similar to claude-code-router approach)
+    let features = config.features();
+    if features.model_transforms {
+        apply_model_specific_transforms(&mut openai_request);
+    }
+
+    // Tools are copied once, and only for models that receive them
+    if supports_tools(&openai_request.model) {
+        openai_request.tools = req
+            .tools
+            .as_deref()
+            .map(|tools| clean_tools(tools, features.tool_schema_transform))
+            .filter(|tools| !tools.is_empty());
+    }
+
+    // Claude Code's web search tool is answered by OpenRouter's web plugin
+    if req.tools.iter().flatten().any(is_web_search_tool) {
+        openai_request.plugins = Some(vec![web_search_plugin()]);
+    }
+
+    // OpenRouter fields the client sent go upstream as they are; plugins join CCR's
+    for (name, value) in req.openrouter.iter().flatten() {
+        match (name.as_str(), value) {
+            ("plugins", serde_json::Value::Array(plugins)) => openai_request
+                .plugins
+                .get_or_insert_with(Vec::new)
+                .extend(plugins.iter().cloned()),
+            _ => {
+                openai_request
+                    .extensions
+                    .insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    // Validate and clean the request to prevent API errors
+    validate_and_clean_request(&mut openai_request, config.unsupported_params());
+
+    // Removed detailed debugging to reduce CPU usage
+
+    Ok(openai_request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auto_model::AutoModelConfig;
+    use crate::utils::Routing;
+    use serde_json::json;
+
+    fn default_config() -> Routing {
+        Routing {
+            auto_model: Some(AutoModelConfig::default()),
+            ..Routing::default()
+        }
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_basic() {
+        let config = default_config();
+        let anthropic_req = AnthropicRequest {
+            model: "claude-3-sonnet-20240229".to_string(),
+            messages: vec![json!({
+                "role": "user",
+                "content": "Hello, world!"
+            })],
+            system: None,
+            temperature: Some(0.7),
+            tools: None,
+            stream: Some(false),
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        };
+
+        let result = anthropic_to_openai(&anthropic_req, &config).unwrap();
+
+        assert_eq!(result.model, "anthropic/claude-sonnet-4");
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(result.temperature, Some(0.7));
+        assert_eq!(result.stream, Some(false));
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_with_system() {
+        let config = default_config();
+        let anthropic_req = AnthropicRequest {
+            model: "claude-3-haiku-20240307".to_string(),
+            messages: vec![json!({
+                "role": "user",
+                "content": "Hello"
+            })],
+            system: Some(json!("You are a helpful assistant")),
+            temperature: None,
+            tools: None,
+            stream: None,
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        };
+
+        let result = anthropic_to_openai(&anthropic_req, &config).unwrap();
+
+        assert_eq!(result.model, "anthropic/claude-3.5-haiku");
+        assert_eq!(result.messages.len(), 2);
+        assert_eq!(result.messages[0]["role"], "system");
+        assert_eq!(result.messages[0]["content"], "You are a helpful assistant");
+        assert_eq!(result.messages[1]["role"], "user");
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_with_tools() {
+        let config = default_config();
+        let tools = vec![json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Get weather information"
+            }
+        })];
+
+        let anthropic_req = AnthropicRequest {
+            model: "claude-3-opus-20240229".to_string(),
+            messages: vec![json!({
+                "role": "user",
+                "content": "What's the weather?"
+            })],
+            system: None,
+            temperature: Some(0.5),
+            tools: Some(tools.clone()),
+            stream: Some(false),
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        };
+
+        let result = anthropic_to_openai(&anthropic_req, &config).unwrap();
+
+        assert_eq!(result.model, "anthropic/claude-opus-4");
+        assert_eq!(result.tools, Some(tools));
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_auto_model() {
+        let config = default_config();
+        let anthropic_req = AnthropicRequest {
+            model: "auto".to_string(),
+            messages: vec![json!({
+                "role": "user",
+                "content": "Hello"
+            })],
+            system: None,
+            temperature: None,
+            tools: None,
+            stream: None,
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
+            cache_control: None,
+        };
+
+        let result = anthropic_to_openai(&anthropic_req, &config).unwrap();
+        assert_eq!(result.model, AutoModelConfig::default().cheap_model);
+    }
+
+    #[test]
+    fn test_route_model_records_each_rule() {
+        let config = default_config();
+        let mut request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-haiku",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap();
+        assert_eq!(
+            route_model(&request, &config).header_value(),
+            "claude-3-5-haiku -> alias.haiku -> anthropic/claude-3.5-haiku"
+        );
+
+        request.model = "auto".to_string();
+        let route = route_model(&request, &config);
+        assert_eq!(route.model(), AutoModelConfig::default().cheap_model);
+        assert!(route
+            .header_value()
+            .starts_with("auto -> auto.simple_request -> "));
+
+        request.model = "deepseek/deepseek-chat".to_string();
+        assert_eq!(
+            route_model(&request, &config).header_value(),
+            "deepseek/deepseek-chat"
+        );
+    }
+
+    #[test]
+    fn test_fixture_request_with_tools() {
+        let request: AnthropicRequest =
+            serde_json::from_str(include_str!("fixtures/anthropic_request_tools.json")).unwrap();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+
+        assert_eq!(result.messages.len(), 2);
+        assert_eq!(result.messages[0]["role"], "system");
+        assert_eq!(
+            result.messages[1]["content"],
+            "What is the weather in Hanoi?"
+        );
+        assert_eq!(result.max_tokens, Some(1024));
+
+        let tools = result.tools.unwrap();
+        assert_eq!(tools[0]["name"], "get_weather");
+        assert!(tools[0].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn test_model_specific_adjustments() {
+        let mut request: AnthropicRequest =
+            serde_json::from_str(include_str!("fixtures/anthropic_request_tools.json")).unwrap();
+        request.model = "moonshotai/kimi-k2".to_string();
+        request.max_tokens = None;
+        request.temperature = Some(1.0);
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert!(result.tools.is_none());
+        assert_eq!(result.max_tokens, Some(16384));
+        assert_eq!(result.temperature, Some(0.6));
+
+        request.model = "deepseek/deepseek-chat".to_string();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(result.tools.unwrap().len(), 1);
+        assert_eq!(result.max_tokens, None);
+        assert_eq!(result.temperature, Some(0.8));
+    }
+
+    #[test]
+    fn test_empty_content_is_dropped_or_left_empty() {
+        let request: OpenAIRequest = serde_json::from_value(json!({
+            "model": "deepseek/deepseek-chat",
+            "messages": [
+                {"role": "user", "content": "Run the tests"},
+                {"role": "assistant", "content": "", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "Bash", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1"},
+                {"role": "assistant", "content": []},
+                {"role": "user", "content": "  "},
+                {"role": "user"}
+            ]
+        }))
+        .unwrap();
+        let mut cleaned = request.clone();
+        clean_empty_content(&mut cleaned);
+        assert_eq!(cleaned.messages.len(), 4);
+        assert_eq!(cleaned.messages[1]["content"], serde_json::Value::Null);
+        assert_eq!(cleaned.messages[2]["content"], "");
+        assert_eq!(cleaned.messages[3], json!({"role": "user", "content": ""}));
+
+        let mut cleaned = request;
+        cleaned.messages[1]["content"] = serde_json::Value::Null;
+        clean_empty_content(&mut cleaned);
+        assert_eq!(cleaned.messages[1]["content"], serde_json::Value::Null);
+        cleaned.model = "mistralai/mistral-large".to_string();
+        clean_empty_content(&mut cleaned);
+        assert_eq!(cleaned.messages[1]["content"], "");
+    }
+
+    #[test]
+    fn test_blank_tool_call_content_follows_the_convention() {
+        let mut request: OpenAIRequest = serde_json::from_value(json!({
+            "model": "openai/gpt-4o",
+            "messages": [
+                {"role": "user", "content": "List the files"},
+                {"role": "assistant", "content": " \n", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "Bash", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "  "}
+            ]
+        }))
+        .unwrap();
+        clean_empty_content(&mut request);
+        assert_eq!(request.messages[1]["content"], serde_json::Value::Null);
+        assert_eq!(request.messages[2]["content"], "");
+
+        request.model = "mistralai/mistral-large".to_string();
+        request.messages[1]["content"] = "".into();
+        clean_empty_content(&mut request);
+        assert_eq!(request.messages[1]["content"], "");
+    }
+
+    #[test]
+    fn test_code_execution_is_rejected_or_routed() {
+        let request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": "Plot the CSV"}],
+            "tools": [
+                {"type": "code_execution_20250522", "name": "code_execution"},
+                {"name": "Read", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+        let error = anthropic_to_openai(&request, &default_config()).unwrap_err();
+        assert!(
+            error.0.starts_with("tools: the code execution tool"),
+            "{error}"
+        );
+
+        let config = Routing {
+            code_execution_model: Some("openai/gpt-4.1".to_string()),
+            ..default_config()
+        };
+        let result = anthropic_to_openai(&request, &config).unwrap();
+        assert_eq!(result.model, "openai/gpt-4.1");
+        let tools = result.tools.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["name"], "Read");
+
+        let mut container_only = request.clone();
+        container_only.tools = None;
+        container_only.container = Some(json!("container_011CPR5CNjB747bTd36fQLFk"));
+        assert!(anthropic_to_openai(&container_only, &default_config()).is_err());
+    }
+
+    #[test]
+    fn test_web_search_tool_becomes_plugin() {
+        let mut request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "deepseek/deepseek-chat",
+            "messages": [{"role": "user", "content": "What changed in Rust 1.82?"}],
+            "tools": [{"type": "web_search_20250305", "name": "web_search", "max_uses": 5}]
+        }))
+        .unwrap();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(result.plugins, Some(vec![json!({"id": "web"})]));
+        assert!(result.tools.is_none());
+
+        request.tools = Some(vec![
+            json!({"name": "Read", "input_schema": {"type": "object"}}),
+        ]);
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert!(result.plugins.is_none());
+        assert_eq!(result.tools.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_openrouter_extension_is_forwarded() {
+        let mut request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "deepseek/deepseek-chat",
+            "messages": [{"role": "user", "content": "Search the docs"}],
+            "tools": [{"type": "web_search_20250305", "name": "web_search"}],
+            "openrouter": {
+                "transforms": ["middle-out"],
+                "route": "fallback",
+                "provider": {"order": ["DeepInfra"]},
+                "plugins": [{"id": "file-parser"}]
+            }
+        }))
+        .unwrap();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(
+            result.plugins,
+            Some(vec![json!({"id": "web"}), json!({"id": "file-parser"})])
+        );
+        let body = serde_json::to_value(&result).unwrap();
+        assert_eq!(body["transforms"], json!(["middle-out"]));
+        assert_eq!(body["route"], "fallback");
+        assert_eq!(body["provider"], json!({"order": ["DeepInfra"]}));
+        assert!(body.get("openrouter").is_none());
+
+        request.openrouter = Some(
+            json!({"model": "openai/gpt-4.1"})
+                .as_object()
+                .cloned()
+                .unwrap(),
+        );
+        let error = anthropic_to_openai(&request, &default_config()).unwrap_err();
+        assert!(error.0.starts_with("openrouter.model:"), "{error}");
+        request.openrouter = Some(
+            json!({"plugins": {"id": "web"}})
+                .as_object()
+                .cloned()
+                .unwrap(),
+        );
+        assert!(anthropic_to_openai(&request, &default_config()).is_err());
+
+        // Fallback models and token limits stay under the key's allowlist and cap
+        for extension in [
+            json!({"models": ["openai/o1-pro"], "route": "fallback"}),
+            json!({"max_completion_tokens": 100_000}),
+        ] {
+            request.openrouter = extension.as_object().cloned();
+            let error = anthropic_to_openai(&request, &default_config()).unwrap_err();
+            assert!(error.0.contains("can't be overridden"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_tool_schemas_are_sanitized() {
+        let tools = clean_tools(
+            &[
+                json!({"name": "list_files"}),
+                json!({"name": "status", "input_schema": {}}),
+                json!({"name": "fetch", "input_schema": {
+                    "$schema": "http://json-schema.org/draft-07/schema#",
+                    "type": "object",
+                    "properties": {
+                        "url": {"type": "string", "format": "uri"},
+                        "format": {"type": "string", "enum": ["json", "text"]},
+                        "headers": {"type": "array", "items": {"type": "string", "format": "byte"}}
+                    },
+                    "required": ["url"]
+                }}),
+            ],
+            true,
+        );
+        let empty = json!({"type": "object", "properties": {}});
+        assert_eq!(tools[0]["input_schema"], empty);
+        assert_eq!(tools[1]["input_schema"], empty);
+        assert_eq!(
+            tools[2]["input_schema"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "url": {"type": "string"},
+                    "format": {"type": "string", "enum": ["json", "text"]},
+                    "headers": {"type": "array", "items": {"type": "string"}}
+                },
+                "required": ["url"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_feature_flags_switch_off_transforms() {
+        let request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "moonshotai/kimi-k2",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "temperature": 1.0,
+            "tools": [{"name": "fetch", "cache_control": {"type": "ephemeral"},
+                       "input_schema": {"type": "object", "properties": {"url": {"format": "uri"}}}}]
+        }))
+        .unwrap();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(result.temperature, Some(0.6));
+        assert_eq!(result.max_tokens, Some(16384));
+
+        let mut config = default_config();
+        config.features.model_transforms = false;
+        config.features.tool_schema_transform = false;
+        let result = anthropic_to_openai(&request, &config).unwrap();
+        assert_eq!(result.temperature, Some(1.0));
+        assert_eq!(result.max_tokens, None);
+
+        let tools = clean_tools(request.tools.as_deref().unwrap(), false);
+        assert_eq!(
+            tools,
+            [json!({"name": "fetch",
+                    "input_schema": {"type": "object", "properties": {"url": {"format": "uri"}}}})]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_params_are_stripped_per_provider() {
+        let mut request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "openai/o3",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "temperature": 0.5,
+            "top_p": 0.9,
+            "top_k": 40,
+            "stop_sequences": ["END"]
+        }))
+        .unwrap();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(
+            (result.temperature, result.top_p, result.top_k),
+            (None, None, None)
+        );
+        assert_eq!(result.stop, Some(vec!["END".to_string()]));
+
+        request.model = "deepseek/deepseek-chat".to_string();
+        let result = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert_eq!(result.top_p, Some(0.9));
+        assert_eq!(result.top_k, Some(40));
+
+        let config = Routing {
+            unsupported_params: UnsupportedParams::default()
+                .with_overrides([("deepseek/".to_string(), vec!["top_k".to_string()])])
+                .unwrap(),
+            ..default_config()
+        };
+        let result = anthropic_to_openai(&request, &config).unwrap();
+        assert_eq!(result.top_p, Some(0.9));
+        assert_eq!(result.top_k, None);
+        assert!(UnsupportedParams::default()
+            .with_overrides([("openai/".to_string(), vec!["seed".to_string()])])
+            .is_err());
+    }
+
+    #[test]
+    fn test_mcp_servers_are_rejected_not_dropped() {
+        let mut request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": "List my issues"}],
+            "mcp_servers": [{"type": "url", "url": "https://mcp.example.com/sse", "name": "tracker"}]
+        }))
+        .unwrap();
+        let error = anthropic_to_openai(&request, &default_config()).unwrap_err();
+        assert!(error.0.starts_with("mcp_servers: "), "{error}");
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["mcp_servers"][0]["name"],
+            "tracker"
+        );
+
+        request.mcp_servers = Some(Vec::new());
+        assert!(anthropic_to_openai(&request, &default_config()).is_ok());
+        request.mcp_servers = None;
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("mcp_servers")
+            .is_none());
+    }
+
+    #[test]
+    fn test_multiple_completions_are_rejected() {
+        let mut request = request(
+            "claude-sonnet-4",
+            json!([{"role": "user", "content": "Hi"}]),
+        );
+        request.n = Some(json!(3));
+        let error = anthropic_to_openai(&request, &default_config()).unwrap_err();
+        assert_eq!(
+            error.0,
+            "n: only one completion per request is supported, got 3"
+        );
+
+        // One completion is what every request gets, and isn't forwarded
+        request.n = Some(json!(1));
+        let openai = anthropic_to_openai(&request, &default_config()).unwrap();
+        assert!(serde_json::to_value(openai).unwrap().get("n").is_none());
+
+        assert!(check_completion_count("n", Some(&json!(0))).is_err());
+        assert!(check_completion_count("n", Some(&json!("2"))).is_err());
+        assert!(check_completion_count("n", Some(&json!(null))).is_ok());
+    }
+
+    #[test]
+    fn test_content_text_joins_blocks() {
+        let blocks = json!([{"type": "text", "text": "a"}, {"type": "image"}, {"type": "text", "text": "b"}]);
+        assert_eq!(content_text(blocks.as_array().unwrap()), "ab");
+        let single = json!([{"type": "text", "text": "only"}]);
+        assert!(matches!(
+            content_text(single.as_array().unwrap()),
+            Cow::Borrowed("only")
+        ));
+        assert_eq!(content_text(&[]), "");
+    }
+
+    fn request(model: &str, messages: serde_json::Value) -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": model,
+            "system": "Be brief.",
+            "messages": messages
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_is_passthrough() {
+        let plain = json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello"}
+        ]);
+        assert!(is_passthrough(&request(
+            "moonshotai/kimi-k2",
+            plain.clone()
+        )));
+        // Claude names still need mapping
+        assert!(!is_passthrough(&request("claude-sonnet-4", plain)));
+        assert!(!is_passthrough(&request(
+            "moonshotai/kimi-k2",
+            json!([{"role": "user", "content": [{"type": "text", "text": "Hi"}]}])
+        )));
+        assert!(!is_passthrough(&request(
+            "moonshotai/kimi-k2",
+            json!([{"role": "user", "content": "Hi", "cache_control": {"type": "ephemeral"}}])
+        )));
+        assert!(!is_passthrough(&request(
+            "moonshotai/kimi-k2",
+            json!([{"role": "user", "content": "  "}])
+        )));
+    }
+
+    #[test]
+    fn test_passthrough_matches_full_conversion() {
+        let config = default_config();
+        let messages = json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello"},
+            {"role": "user", "content": "Summarize this"}
+        ]);
+        let passthrough =
+            anthropic_to_openai(&request("openai/gpt-4o", messages.clone()), &config).unwrap();
+        assert_eq!(
+            passthrough.messages[0],
+            json!({"role": "system", "content": "Be brief."})
+        );
+        assert_eq!(
+            &passthrough.messages[1..],
+            messages.as_array().unwrap().as_slice()
+        );
+
+        // The full conversion would have produced the same messages
+        let converted: Vec<_> = messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(convert_message)
+            .collect();
+        assert_eq!(&passthrough.messages[1..], converted.as_slice());
+    }
+}
@@ -0,0 +1,413 @@
+use super::{Error, Result};
+use crate::clock::{ClockIds, IdGenerator, SystemClock};
+use crate::models::{AnthropicResponse, OpenAICompletion, OpenAIMessage};
+use serde::Deserialize;
+
+/// Transforms an OpenAI API response back to Anthropic API format
+///
+/// This function handles the conversion of response structure, including:
+/// - Converting OpenAI message content to Anthropic format
+/// - Handling both text responses and tool calls
+/// - Mapping OpenAI finish_reason to Anthropic stop_reason
+/// - Generating Anthropic-compatible message IDs
+pub fn openai_to_anthropic(response: &serde_json::Value, model: &str) -> Result<AnthropicResponse> {
+    openai_to_anthropic_with(response, model, &ClockIds::new(SystemClock))
+}
+
+/// Transforms an OpenAI API response back to Anthropic API format, taking the
+/// message ID from `ids`
+pub fn openai_to_anthropic_with(
+    response: &serde_json::Value,
+    model: &str,
+    ids: &impl IdGenerator,
+) -> Result<AnthropicResponse> {
+    if !response["choices"].is_array() {
+        return Err(Error("Response missing choices array".to_string()));
+    }
+    let completion = OpenAICompletion::deserialize(response)
+        .map_err(|e| Error(format!("Invalid OpenAI response: {e}")))?;
+    completion_to_anthropic_with(completion, model, ids)
+}
+
+/// Transforms an OpenAI completion deserialized from the response body
+///
+/// This is the proxy's path: the body is parsed once, into [`OpenAICompletion`],
+/// and its strings are moved into the Anthropic response.
+pub fn completion_to_anthropic(
+    completion: OpenAICompletion,
+    model: &str,
+) -> Result<AnthropicResponse> {
+    completion_to_anthropic_with(completion, model, &ClockIds::new(SystemClock))
+}
+
+/// Transforms an OpenAI completion, taking the message ID from `ids`
+pub fn completion_to_anthropic_with(
+    completion: OpenAICompletion,
+    model: &str,
+    ids: &impl IdGenerator,
+) -> Result<AnthropicResponse> {
+    let message_id = &ids.message_id();
+    let usage = usage_counts(&completion.usage).unwrap_or_default();
+
+    let mut choice = completion
+        .choices
+        .ok_or_else(|| Error("Response missing choices array".to_string()))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error("Response has empty choices array".to_string()))?;
+
+    // Map OpenAI finish_reason to Anthropic stop_reason
+    let stop_reason = match choice.finish_reason.as_str() {
+        Some("tool_calls") => Some("tool_use".to_string()),
+        _ => Some("end_turn".to_string()),
+    };
+
+    let annotations = std::mem::take(&mut choice.message.annotations);
+    let mut content = message_content(choice.message);
+
+    // Pages the web plugin drew on come first, as Anthropic's search results do
+    if let Some(blocks) = web_search_blocks(&web_search_id(message_id), &annotations) {
+        content.splice(0..0, blocks);
+    }
+
+    Ok(AnthropicResponse {
+        id: message_id.to_string(),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        content,
+        stop_reason,
+        stop_sequence: None,
+        model: model.to_string(),
+        usage,
+    })
+}
+
+/// Anthropic content blocks for an OpenAI assistant message's text and tool calls
+pub(super) fn assistant_content(message: &serde_json::Value) -> Vec<serde_json::Value> {
+    OpenAIMessage::deserialize(message)
+        .map(message_content)
+        .unwrap_or_default()
+}
+
+fn message_content(message: OpenAIMessage) -> Vec<serde_json::Value> {
+    // Text comes before the tool calls; Gemini sends an empty string alongside
+    // its tool calls, which isn't kept as a block
+    let tool_calls = message.tool_calls.unwrap_or_default();
+    let mut content = Vec::with_capacity(tool_calls.len() + 1);
+    if let serde_json::Value::String(text) = message.content {
+        if !text.is_empty() || tool_calls.is_empty() {
+            content.push(serde_json::json!({"text": text, "type": "text"}));
+        }
+    }
+
+    // Tool call response - convert to Anthropic format, where the input is an
+    // object rather than the JSON text of one
+    content.extend(tool_calls.into_iter().map(|tc| {
+        let input = match tc.function.arguments {
+            serde_json::Value::String(text) => {
+                serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+            }
+            arguments => arguments,
+        };
+        serde_json::json!({
+            "type": "tool_use",
+            "id": tc.id,
+            "name": tc.function.name,
+            "input": input
+        })
+    }));
+    content
+}
+
+/// ID of the `web_search` call standing in for a message's web plugin results
+pub(crate) fn web_search_id(message_id: &str) -> String {
+    format!("srvtoolu_{}", message_id.trim_start_matches("msg_"))
+}
+
+/// Anthropic web search blocks for the URL citations of OpenRouter's web plugin
+///
+/// OpenRouter reports the pages a response drew on as `url_citation` annotations,
+/// where Anthropic clients expect a `web_search` server tool call and its result.
+/// Each page is listed once; `None` when there are no citations.
+pub(crate) fn web_search_blocks(
+    id: &str,
+    annotations: &serde_json::Value,
+) -> Option<[serde_json::Value; 2]> {
+    let mut seen = std::collections::HashSet::new();
+    let results: Vec<serde_json::Value> = annotations
+        .as_array()?
+        .iter()
+        .filter(|annotation| annotation["type"] == "url_citation")
+        .map(|annotation| &annotation["url_citation"])
+        .filter_map(|citation| {
+            let url = citation["url"].as_str()?;
+            seen.insert(url).then(|| {
+                serde_json::json!({
+                    "type": "web_search_result",
+                    "url": url,
+                    "title": citation["title"].as_str().unwrap_or(url),
+                    "encrypted_content": "",
+                    "page_age": null
+                })
+            })
+        })
+        .collect();
+    if results.is_empty() {
+        return None;
+    }
+    Some([
+        serde_json::json!({"type": "server_tool_use", "id": id, "name": "web_search", "input": {}}),
+        serde_json::json!({"type": "web_search_tool_result", "tool_use_id": id, "content": results}),
+    ])
+}
+
+/// Extracts token usage from an OpenAI response or final streaming chunk
+///
+/// Embeddings responses only report prompt tokens, so missing completion tokens
+/// count as zero, as do cached tokens from upstreams that don't report them.
+/// Cache reads and writes come from the Anthropic-style fields when the upstream
+/// passes them on, and from `prompt_tokens_details` otherwise; either way they
+/// are part of `prompt_tokens`, so they are taken out of `input_tokens`.
+pub fn openai_usage(response: &serde_json::Value) -> Option<crate::models::Usage> {
+    usage_counts(response.get("usage")?)
+}
+
+/// Token usage from an OpenAI `usage` object
+pub fn usage_counts(usage: &serde_json::Value) -> Option<crate::models::Usage> {
+    let count = |field: &str, detail: &str| {
+        usage[field]
+            .as_u64()
+            .or_else(|| usage["prompt_tokens_details"][detail].as_u64())
+            .unwrap_or(0) as u32
+    };
+    let cache_read_input_tokens = count("cache_read_input_tokens", "cached_tokens");
+    let cache_creation_input_tokens = count("cache_creation_input_tokens", "cache_write_tokens");
+    let prompt_tokens = usage["prompt_tokens"].as_u64()? as u32;
+    Some(crate::models::Usage {
+        input_tokens: prompt_tokens
+            .saturating_sub(cache_read_input_tokens)
+            .saturating_sub(cache_creation_input_tokens),
+        output_tokens: usage["completion_tokens"].as_u64().unwrap_or(0) as u32,
+        cache_creation_input_tokens,
+        cache_read_input_tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedIds;
+    use serde_json::json;
+
+    #[test]
+    fn test_openai_to_anthropic_text_response() {
+        let openai_response = json!({
+            "choices": [{
+                "message": {
+                    "content": "Hello! How can I help you today?",
+                    "role": "assistant"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        let result = openai_to_anthropic(&openai_response, "claude-3-sonnet-20240229").unwrap();
+
+        assert_eq!(result.response_type, "message");
+        assert_eq!(result.role, "assistant");
+        assert_eq!(result.model, "claude-3-sonnet-20240229");
+        assert_eq!(result.content.len(), 1);
+        assert_eq!(result.content[0]["type"], "text");
+        assert_eq!(
+            result.content[0]["text"],
+            "Hello! How can I help you today?"
+        );
+        assert_eq!(result.stop_reason, Some("end_turn".to_string()));
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_tool_call() {
+        let openai_response = json!({
+            "choices": [{
+                "message": {
+                    "tool_calls": [{
+                        "id": "call_123",
+                        "function": {
+                            "name": "get_weather",
+                            "arguments": "{\"location\": \"New York\"}"
+                        }
+                    }],
+                    "role": "assistant"
+                },
+                "finish_reason": "tool_calls"
+            }]
+        });
+
+        let result = openai_to_anthropic(&openai_response, "claude-3-sonnet-20240229").unwrap();
+
+        assert_eq!(result.response_type, "message");
+        assert_eq!(result.role, "assistant");
+        assert_eq!(result.content.len(), 1);
+        assert_eq!(result.content[0]["type"], "tool_use");
+        assert_eq!(result.content[0]["id"], "call_123");
+        assert_eq!(result.content[0]["name"], "get_weather");
+        assert_eq!(result.stop_reason, Some("tool_use".to_string()));
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_empty_content() {
+        let openai_response = json!({
+            "choices": [{
+                "message": {
+                    "content": null,
+                    "role": "assistant"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        let result = openai_to_anthropic(&openai_response, "claude-3-sonnet-20240229").unwrap();
+
+        assert_eq!(result.content.len(), 0);
+        assert_eq!(result.stop_reason, Some("end_turn".to_string()));
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_generates_valid_id() {
+        let openai_response = json!({
+            "choices": [{
+                "message": {
+                    "content": "Test message",
+                    "role": "assistant"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        let result = openai_to_anthropic(&openai_response, "claude-3-sonnet-20240229").unwrap();
+
+        assert!(result.id.starts_with("msg_"));
+        assert!(result.id.len() > 4);
+    }
+
+    #[test]
+    fn test_completion_to_anthropic_matches_value_path() {
+        let body = r#"{
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Line one\nwith \"quotes\" and \u00e9",
+                    "tool_calls": [
+                        {"id": "call_1", "type": "function", "function": {"name": "Read", "arguments": "{\"path\":\"src/lib.rs\"}"}},
+                        {"id": "call_2", "type": "function", "function": {"name": "Bash", "arguments": {"command": "ls"}}},
+                        {"id": "call_3", "type": "function", "function": {"name": "Edit", "arguments": "{\"path\":"}}
+                    ]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 120, "completion_tokens": 30, "prompt_tokens_details": {"cached_tokens": 100}}
+        }"#;
+        let ids = FixedIds("msg_test".to_string());
+
+        let completion: OpenAICompletion = serde_json::from_str(body).unwrap();
+        let direct = completion_to_anthropic_with(completion, "claude-sonnet-4", &ids).unwrap();
+        let value: serde_json::Value = serde_json::from_str(body).unwrap();
+        let from_value = openai_to_anthropic_with(&value, "claude-sonnet-4", &ids).unwrap();
+        assert_eq!(
+            serde_json::to_value(&direct).unwrap(),
+            serde_json::to_value(&from_value).unwrap()
+        );
+
+        assert_eq!(direct.content[0]["text"], "Line one\nwith \"quotes\" and é");
+        assert_eq!(direct.content[1]["input"], json!({"path": "src/lib.rs"}));
+        assert_eq!(direct.content[2]["input"], json!({"command": "ls"}));
+        // Truncated arguments are passed on as the text they are
+        assert_eq!(direct.content[3]["input"], "{\"path\":");
+        assert_eq!(direct.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(direct.usage.cache_read_input_tokens, 100);
+        assert_eq!(direct.usage.input_tokens, 20);
+
+        let missing: OpenAICompletion = serde_json::from_str("{}").unwrap();
+        assert!(completion_to_anthropic_with(missing, "claude-sonnet-4", &ids).is_err());
+    }
+
+    #[test]
+    fn test_openai_usage() {
+        let usage = openai_usage(&json!({
+            "usage": {"prompt_tokens": 12, "completion_tokens": 34, "total_tokens": 46}
+        }))
+        .unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 34);
+        assert_eq!(usage.cache_read_input_tokens, 0);
+
+        let cached = openai_usage(&json!({
+            "usage": {"prompt_tokens": 1200, "completion_tokens": 5,
+                      "prompt_tokens_details": {"cached_tokens": 1024, "cache_write_tokens": 100}}
+        }))
+        .unwrap();
+        assert_eq!(cached.cache_read_input_tokens, 1024);
+        assert_eq!(cached.cache_creation_input_tokens, 100);
+        assert_eq!(cached.input_tokens, 76);
+        assert_eq!(cached.prompt_tokens(), 1200);
+
+        let anthropic_style = openai_usage(&json!({
+            "usage": {"prompt_tokens": 1200, "completion_tokens": 5,
+                      "cache_read_input_tokens": 1000, "cache_creation_input_tokens": 150}
+        }))
+        .unwrap();
+        assert_eq!(anthropic_style.input_tokens, 50);
+        assert_eq!(anthropic_style.cache_read_input_tokens, 1000);
+        assert_eq!(anthropic_style.cache_creation_input_tokens, 150);
+
+        let embeddings = openai_usage(&json!({
+            "usage": {"prompt_tokens": 8, "total_tokens": 8}
+        }))
+        .unwrap();
+        assert_eq!(embeddings.input_tokens, 8);
+        assert_eq!(embeddings.output_tokens, 0);
+
+        assert!(openai_usage(&json!({"choices": []})).is_none());
+        assert!(openai_usage(&json!({"usage": null})).is_none());
+    }
+
+    #[test]
+    fn test_fixture_response_with_web_citations() {
+        let openai_response: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/openai_response_web_citations.json"))
+                .unwrap();
+        let result = openai_to_anthropic(&openai_response, "claude-sonnet-4-20250514").unwrap();
+
+        let types: Vec<&str> = result
+            .content
+            .iter()
+            .filter_map(|block| block["type"].as_str())
+            .collect();
+        assert_eq!(types, ["server_tool_use", "web_search_tool_result", "text"]);
+        assert_eq!(result.content[0]["name"], "web_search");
+        assert_eq!(result.content[1]["tool_use_id"], result.content[0]["id"]);
+        let results = result.content[1]["content"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["title"], "Announcing Rust 1.82.0");
+        assert_eq!(result.stop_reason.as_deref(), Some("end_turn"));
+    }
+
+    #[test]
+    fn test_fixture_response_with_tool_call() {
+        let openai_response: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/openai_response_tool_call.json")).unwrap();
+        let result = openai_to_anthropic(&openai_response, "claude-sonnet-4-20250514").unwrap();
+
+        assert_eq!(result.model, "claude-sonnet-4-20250514");
+        assert_eq!(result.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(result.content.len(), 1);
+        assert_eq!(result.content[0]["type"], "tool_use");
+        assert_eq!(result.content[0]["id"], "call_weather_1");
+        assert_eq!(result.content[0]["name"], "get_weather");
+        assert_eq!(result.usage.input_tokens, 87);
+
+        let usage = openai_usage(&openai_response).unwrap();
+        assert_eq!(usage.input_tokens, 87);
+        assert_eq!(usage.output_tokens, 21);
+    }
+}
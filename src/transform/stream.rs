@@ -0,0 +1,849 @@
+//! OpenAI server-sent events translated into Anthropic stream events
+//!
+//! Each stream reports a [`StreamProfile`] of the work done in its parse,
+//! transform and serialize phases. The Workers runtime doesn't advance clocks
+//! while code runs, so the phases are measured in work, which CPU time grows
+//! with, rather than in milliseconds. Long streams and slow requests would run
+//! into the runtime's CPU and time limits, so the stream switches to a coarser
+//! [`Coalescing`] as it goes: the events say the same, in fewer and larger deltas.
+//! Streams for OpenAI clients are relayed untranslated and never get here; an
+//! Anthropic client needs translated events, so one delta per block is the
+//! coarsest a stream gets.
+
+use super::response::{openai_usage, web_search_blocks, web_search_id};
+use super::{Error, Result};
+use crate::clock::{ClockIds, IdGenerator, SystemClock};
+use crate::deadline::Budget;
+use std::collections::HashMap;
+
+/// How finely text and tool argument deltas are relayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Coalescing {
+    /// One Anthropic delta per upstream event
+    #[default]
+    Event,
+    /// Deltas to the same block within one upstream read are merged
+    Read,
+    /// Deltas are held until their block closes, one delta per block
+    Block,
+}
+
+impl Coalescing {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Coalescing::Event => "event",
+            Coalescing::Read => "read",
+            Coalescing::Block => "block",
+        }
+    }
+
+    /// The coalescing a stream switches to after `parsed_events` upstream events
+    ///
+    /// Past the budget's soft limit deltas are held for the whole block; after
+    /// `coalesce_after_events` events (0 for never) they are merged per read.
+    fn for_load(parsed_events: u32, budget: Option<&Budget>, coalesce_after_events: u32) -> Self {
+        if budget.is_some_and(Budget::past_soft_limit) {
+            Coalescing::Block
+        } else if coalesce_after_events > 0 && parsed_events >= coalesce_after_events {
+            Coalescing::Read
+        } else {
+            Coalescing::Event
+        }
+    }
+}
+
+/// Work done translating one stream, by phase
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamProfile {
+    /// Parse: upstream reads, their bytes, and the events parsed from them
+    pub reads: u32,
+    pub upstream_bytes: u64,
+    pub parsed_events: u32,
+    /// Data lines that weren't JSON, skipped
+    pub malformed_events: u32,
+    /// Transform: text and argument deltas, and how many were merged into an earlier one
+    pub deltas: u32,
+    pub coalesced_deltas: u32,
+    /// Serialize: Anthropic events written and their bytes
+    pub events: u32,
+    pub event_bytes: u64,
+    /// Coalescing the stream ended with
+    pub coalescing: Coalescing,
+    /// Upstream events parsed when the stream first switched to coarser coalescing
+    pub degraded_after: Option<u32>,
+}
+
+/// Streaming state to track content blocks and tool calls
+#[derive(Debug, Clone)]
+struct StreamingState {
+    content_block_index: u32,
+    has_started_text_block: bool,
+    /// Whether a closed block already holds the current index
+    index_used: bool,
+    is_tool_use: bool,
+    current_tool_call_id: Option<String>,
+    tool_call_json_map: HashMap<String, String>,
+    usage: Option<crate::models::Usage>,
+    /// Whether reading stopped before the upstream finished
+    truncated: bool,
+    /// Delta held back to merge with the next ones: block index, delta type and
+    /// the text or partial JSON so far
+    pending: Option<(u32, &'static str, String)>,
+    profile: StreamProfile,
+}
+
+impl StreamingState {
+    fn new() -> Self {
+        Self {
+            content_block_index: 0,
+            has_started_text_block: false,
+            index_used: false,
+            is_tool_use: false,
+            current_tool_call_id: None,
+            tool_call_json_map: HashMap::new(),
+            usage: None,
+            truncated: false,
+            pending: None,
+            profile: StreamProfile::default(),
+        }
+    }
+
+    /// Relays a text or argument delta to the open block, or holds it back to
+    /// merge with the next ones
+    fn delta(
+        &mut self,
+        events: &mut Vec<String>,
+        delta_type: &'static str,
+        text: &str,
+    ) -> Result<()> {
+        self.profile.deltas += 1;
+        match &mut self.pending {
+            Some((index, held_type, held))
+                if *index == self.content_block_index && *held_type == delta_type =>
+            {
+                held.push_str(text);
+                self.profile.coalesced_deltas += 1;
+            }
+            _ => {
+                self.flush(events)?;
+                self.pending = Some((self.content_block_index, delta_type, text.to_string()));
+            }
+        }
+        if self.profile.coalescing == Coalescing::Event {
+            self.flush(events)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the delta held back, before its block closes or a read ends
+    fn flush(&mut self, events: &mut Vec<String>) -> Result<()> {
+        let Some((index, delta_type, text)) = self.pending.take() else {
+            return Ok(());
+        };
+        let field = if delta_type == "text_delta" {
+            "text"
+        } else {
+            "partial_json"
+        };
+        let content_block_delta = crate::models::ContentBlockDelta {
+            event_type: "content_block_delta".to_string(),
+            index,
+            delta: crate::models::Delta {
+                delta_type: delta_type.to_string(),
+                data: serde_json::json!({ field: text }),
+            },
+        };
+        events.push(format_sse_event(
+            "content_block_delta",
+            &content_block_delta,
+        )?);
+        Ok(())
+    }
+
+    /// Switches to coarser coalescing when the load calls for it
+    fn degrade(&mut self, budget: Option<&Budget>, coalesce_after_events: u32) {
+        let profile = &mut self.profile;
+        let coalescing = Coalescing::for_load(profile.parsed_events, budget, coalesce_after_events);
+        if coalescing > profile.coalescing {
+            profile.coalescing = coalescing;
+            profile.degraded_after.get_or_insert(profile.parsed_events);
+        }
+    }
+}
+
+/// Picks the usage out of an OpenAI event stream that is relayed unchanged
+///
+/// Chunks may split events anywhere, so incomplete lines are held until the rest
+/// arrives.
+#[derive(Debug, Default)]
+pub struct SseUsageScanner {
+    pending: String,
+    usage: Option<crate::models::Usage>,
+}
+
+impl SseUsageScanner {
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.pending.push_str(&String::from_utf8_lossy(chunk));
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            if !data.contains("\"usage\"") {
+                continue;
+            }
+            if let Ok(event) = serde_json::from_str::<serde_json::Value>(data.trim()) {
+                if let Some(usage) = openai_usage(&event) {
+                    self.usage = Some(usage);
+                }
+            }
+        }
+    }
+
+    /// Usage from the last event that reported it
+    pub fn usage(&self) -> Option<crate::models::Usage> {
+        self.usage.clone()
+    }
+}
+
+/// Transforms OpenAI streaming response to Anthropic streaming format
+///
+/// This function converts Server-Sent Events from OpenAI API to Anthropic's
+/// streaming event format, handling both text content and tool calls.
+/// Returns the upstream token usage alongside the events when the stream reported it.
+/// The stream ends at the first chunk that fails to arrive, or, with a `budget`,
+/// when the request is about to run out of time; the message then stops with
+/// `max_tokens`, as a response cut short.
+pub async fn stream_openai_to_anthropic<E>(
+    openai_body: impl futures::Stream<Item = std::result::Result<Vec<u8>, E>>,
+    model: &str,
+    budget: Option<&Budget>,
+) -> Result<(String, Option<crate::models::Usage>)> {
+    stream_openai_to_anthropic_with(openai_body, model, budget, &ClockIds::new(SystemClock)).await
+}
+
+/// Transforms an OpenAI stream as [`stream_openai_to_anthropic`] does, also
+/// coalescing deltas after `coalesce_after_events` upstream events (0 for never),
+/// and reports the work done
+pub async fn stream_openai_to_anthropic_profiled<E>(
+    openai_body: impl futures::Stream<Item = std::result::Result<Vec<u8>, E>>,
+    model: &str,
+    budget: Option<&Budget>,
+    coalesce_after_events: u32,
+) -> Result<(String, Option<crate::models::Usage>, StreamProfile)> {
+    let message_id = ClockIds::new(SystemClock).message_id();
+    format_streaming_response(
+        openai_body,
+        &message_id,
+        model,
+        budget,
+        coalesce_after_events,
+    )
+    .await
+}
+
+/// Transforms OpenAI streaming response to Anthropic streaming format, taking the
+/// message ID from `ids`
+pub async fn stream_openai_to_anthropic_with<E>(
+    openai_body: impl futures::Stream<Item = std::result::Result<Vec<u8>, E>>,
+    model: &str,
+    budget: Option<&Budget>,
+    ids: &impl IdGenerator,
+) -> Result<(String, Option<crate::models::Usage>)> {
+    let (events, usage, _) =
+        format_streaming_response(openai_body, &ids.message_id(), model, budget, 0).await?;
+    Ok((events, usage))
+}
+
+/// A stream holding only an Anthropic `error` event
+///
+/// Streaming clients expect events rather than a JSON body, so failures before any
+/// event was produced are reported this way, as the Anthropic API reports errors
+/// that occur once a stream has started.
+pub fn error_events(error_type: &str, message: &str) -> String {
+    let data = serde_json::json!({
+        "type": "error",
+        "error": {"type": error_type, "message": message}
+    });
+    format!("event: error\ndata: {data}\n\n")
+}
+
+/// The stream of a message CCR answers itself, holding one text block
+pub fn text_message_events(id: &str, model: &str, text: &str, stop_reason: &str) -> String {
+    let usage = serde_json::json!({"input_tokens": 0, "output_tokens": 0});
+    let events = [
+        (
+            "message_start",
+            serde_json::json!({"type": "message_start", "message": {
+                "id": id, "type": "message", "role": "assistant", "content": [], "model": model,
+                "stop_reason": null, "stop_sequence": null, "usage": usage,
+            }}),
+        ),
+        (
+            "content_block_start",
+            serde_json::json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+        ),
+        (
+            "content_block_delta",
+            serde_json::json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text}}),
+        ),
+        (
+            "content_block_stop",
+            serde_json::json!({"type": "content_block_stop", "index": 0}),
+        ),
+        (
+            "message_delta",
+            serde_json::json!({"type": "message_delta", "delta": {"stop_reason": stop_reason, "stop_sequence": null}, "usage": usage}),
+        ),
+        ("message_stop", serde_json::json!({"type": "message_stop"})),
+    ];
+    events
+        .iter()
+        .map(|(name, data)| format!("event: {name}\ndata: {data}\n\n"))
+        .collect()
+}
+
+/// Wraps Anthropic stream events in a response with the headers for SSE
+#[cfg(feature = "cloudflare")]
+pub fn event_stream_response(events: String) -> worker::Result<worker::Response> {
+    let mut response = worker::Response::ok(events)?;
+    response
+        .headers_mut()
+        .set("Content-Type", "text/event-stream")?;
+    response.headers_mut().set("Cache-Control", "no-cache")?;
+    response.headers_mut().set("Connection", "keep-alive")?;
+    Ok(response)
+}
+
+/// Formats streaming response from OpenAI to Anthropic format
+async fn format_streaming_response<E>(
+    openai_body: impl futures::Stream<Item = std::result::Result<Vec<u8>, E>>,
+    message_id: &str,
+    model: &str,
+    budget: Option<&Budget>,
+    coalesce_after_events: u32,
+) -> Result<(String, Option<crate::models::Usage>, StreamProfile)> {
+    let mut stream = std::pin::pin!(openai_body);
+    let mut buffer = String::new();
+    let mut state = StreamingState::new();
+    let mut output_lines = Vec::new();
+
+    // Send message_start event
+    let message_start = crate::models::MessageStart {
+        event_type: "message_start".to_string(),
+        message: crate::models::MessageInfo {
+            id: message_id.to_string(),
+            message_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![],
+            model: model.to_string(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: crate::models::Usage {
+                input_tokens: 1,
+                output_tokens: 1,
+                ..Default::default()
+            },
+        },
+    };
+
+    output_lines.push(format_sse_event("message_start", &message_start)?);
+
+    // Process streaming chunks
+    use futures::StreamExt;
+    while let Some(chunk_result) = stream.next().await {
+        match chunk_result {
+            Ok(chunk) => {
+                state.degrade(budget, coalesce_after_events);
+                state.profile.reads += 1;
+                state.profile.upstream_bytes += chunk.len() as u64;
+                let chunk_str = String::from_utf8_lossy(&chunk);
+                buffer.push_str(&chunk_str);
+
+                // Process complete lines
+                let lines: Vec<&str> = buffer.split('\n').collect();
+                let new_buffer = lines.last().unwrap_or(&"").to_string();
+
+                for line in &lines[..lines.len() - 1] {
+                    if line.trim().starts_with("data: ") {
+                        let data = line.trim().strip_prefix("data: ").unwrap_or("");
+                        if data == "[DONE]" {
+                            break;
+                        }
+
+                        let Ok(parsed) = serde_json::from_str::<serde_json::Value>(data) else {
+                            state.profile.malformed_events += 1;
+                            continue;
+                        };
+                        state.profile.parsed_events += 1;
+                        // Usage arrives on the final chunk
+                        if let Some(usage) = openai_usage(&parsed) {
+                            state.usage = Some(usage);
+                        }
+                        if let Some(choices) = parsed["choices"].as_array() {
+                            if let Some(choice) = choices.first() {
+                                if let Some(delta) = choice.get("delta") {
+                                    if let Ok(events) =
+                                        process_stream_delta(delta, message_id, &mut state)
+                                    {
+                                        output_lines.extend(events);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // Update buffer with incomplete line
+                buffer = new_buffer;
+                if state.profile.coalescing == Coalescing::Read {
+                    state.flush(&mut output_lines)?;
+                }
+            }
+            Err(_) => break,
+        }
+        if budget.is_some_and(Budget::should_close_stream) {
+            state.truncated = true;
+            break;
+        }
+    }
+
+    // Close last content block
+    state.flush(&mut output_lines)?;
+    if state.is_tool_use || state.has_started_text_block {
+        let content_block_stop = crate::models::ContentBlockStop {
+            event_type: "content_block_stop".to_string(),
+            index: state.content_block_index,
+        };
+        output_lines.push(format_sse_event("content_block_stop", &content_block_stop)?);
+    }
+
+    // Send message_delta and message_stop
+    let message_delta = crate::models::MessageDelta {
+        event_type: "message_delta".to_string(),
+        delta: crate::models::MessageDeltaData {
+            stop_reason: Some(if state.truncated {
+                "max_tokens".to_string()
+            } else if state.is_tool_use {
+                "tool_use".to_string()
+            } else {
+                "end_turn".to_string()
+            }),
+            stop_sequence: None,
+        },
+        usage: state.usage.clone().unwrap_or(crate::models::Usage {
+            input_tokens: 100,
+            output_tokens: 150,
+            ..Default::default()
+        }),
+    };
+    output_lines.push(format_sse_event("message_delta", &message_delta)?);
+
+    let message_stop = crate::models::MessageStop {
+        event_type: "message_stop".to_string(),
+    };
+    output_lines.push(format_sse_event("message_stop", &message_stop)?);
+
+    state.profile.events = output_lines.len() as u32;
+    state.profile.event_bytes = output_lines.iter().map(|line| line.len() as u64).sum();
+
+    // Join all lines and return as String
+    let response_text = output_lines.join("");
+    Ok((response_text, state.usage, state.profile))
+}
+
+/// Formats Server-Sent Event
+fn format_sse_event<T: serde::Serialize>(event_type: &str, data: &T) -> Result<String> {
+    let json_data =
+        serde_json::to_string(data).map_err(|e| Error(format!("JSON serialization error: {e}")))?;
+
+    Ok(format!("event: {event_type}\ndata: {json_data}\n\n"))
+}
+
+/// Processes streaming delta from OpenAI and generates Anthropic events
+fn process_stream_delta(
+    delta: &serde_json::Value,
+    message_id: &str,
+    state: &mut StreamingState,
+) -> Result<Vec<String>> {
+    let mut events = Vec::new();
+
+    // Web plugin citations become a web_search call and its result, after
+    // whatever block is open
+    if let Some(blocks) = web_search_blocks(&web_search_id(message_id), &delta["annotations"]) {
+        let block_open = state.is_tool_use || state.has_started_text_block;
+        if block_open {
+            state.flush(&mut events)?;
+            let content_block_stop = crate::models::ContentBlockStop {
+                event_type: "content_block_stop".to_string(),
+                index: state.content_block_index,
+            };
+            events.push(format_sse_event("content_block_stop", &content_block_stop)?);
+        }
+        if block_open || state.index_used {
+            state.content_block_index += 1;
+        }
+        for (offset, block) in blocks.into_iter().enumerate() {
+            let index = state.content_block_index + offset as u32;
+            let content_block_start = crate::models::ContentBlockStart {
+                event_type: "content_block_start".to_string(),
+                index,
+                content_block: crate::models::ContentBlock {
+                    block_type: block["type"].as_str().unwrap_or_default().to_string(),
+                    data: block,
+                },
+            };
+            events.push(format_sse_event(
+                "content_block_start",
+                &content_block_start,
+            )?);
+            let content_block_stop = crate::models::ContentBlockStop {
+                event_type: "content_block_stop".to_string(),
+                index,
+            };
+            events.push(format_sse_event("content_block_stop", &content_block_stop)?);
+        }
+        state.content_block_index += 1;
+        state.index_used = true;
+        state.is_tool_use = false;
+        state.has_started_text_block = false;
+        state.current_tool_call_id = None;
+    }
+
+    // Handle tool calls
+    if let Some(tool_calls) = delta["tool_calls"].as_array() {
+        for tool_call in tool_calls {
+            if let Some(tool_call_id) = tool_call["id"].as_str() {
+                if Some(tool_call_id.to_string()) != state.current_tool_call_id {
+                    // Close previous content block if needed
+                    if state.is_tool_use || state.has_started_text_block {
+                        state.flush(&mut events)?;
+                        let content_block_stop = crate::models::ContentBlockStop {
+                            event_type: "content_block_stop".to_string(),
+                            index: state.content_block_index,
+                        };
+                        events.push(format_sse_event("content_block_stop", &content_block_stop)?);
+                    }
+
+                    // Start new tool use block
+                    state.is_tool_use = true;
+                    state.has_started_text_block = false;
+                    state.index_used = false;
+                    state.current_tool_call_id = Some(tool_call_id.to_string());
+                    state.content_block_index += 1;
+                    state
+                        .tool_call_json_map
+                        .insert(tool_call_id.to_string(), String::new());
+
+                    let tool_block = serde_json::json!({
+                        "type": "tool_use",
+                        "id": tool_call_id,
+                        "name": tool_call["function"]["name"].as_str().unwrap_or(""),
+                        "input": {}
+                    });
+
+                    let content_block_start = crate::models::ContentBlockStart {
+                        event_type: "content_block_start".to_string(),
+                        index: state.content_block_index,
+                        content_block: crate::models::ContentBlock {
+                            block_type: "tool_use".to_string(),
+                            data: tool_block,
+                        },
+                    };
+                    events.push(format_sse_event(
+                        "content_block_start",
+                        &content_block_start,
+                    )?);
+                }
+            }
+
+            // Handle tool call arguments
+            if let Some(arguments) = tool_call["function"]["arguments"].as_str() {
+                if let Some(current_id) = &state.current_tool_call_id {
+                    state
+                        .tool_call_json_map
+                        .entry(current_id.clone())
+                        .or_default()
+                        .push_str(arguments);
+                    state.delta(&mut events, "input_json_delta", arguments)?;
+                }
+            }
+        }
+    }
+    // Handle text content; an empty delta doesn't open a block on its own
+    else if let Some(content) = delta["content"]
+        .as_str()
+        .filter(|content| !content.is_empty() || state.has_started_text_block)
+    {
+        if state.is_tool_use {
+            state.flush(&mut events)?;
+            let content_block_stop = crate::models::ContentBlockStop {
+                event_type: "content_block_stop".to_string(),
+                index: state.content_block_index,
+            };
+            events.push(format_sse_event("content_block_stop", &content_block_stop)?);
+            state.is_tool_use = false;
+            state.current_tool_call_id = None;
+            state.content_block_index += 1;
+        } else if state.index_used && !state.has_started_text_block {
+            state.content_block_index += 1;
+        }
+
+        if !state.has_started_text_block {
+            let text_block = serde_json::json!({
+                "type": "text",
+                "text": ""
+            });
+
+            let content_block_start = crate::models::ContentBlockStart {
+                event_type: "content_block_start".to_string(),
+                index: state.content_block_index,
+                content_block: crate::models::ContentBlock {
+                    block_type: "text".to_string(),
+                    data: text_block,
+                },
+            };
+            events.push(format_sse_event(
+                "content_block_start",
+                &content_block_start,
+            )?);
+            state.has_started_text_block = true;
+            state.index_used = false;
+        }
+
+        state.delta(&mut events, "text_delta", content)?;
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_events() {
+        let events = error_events("api_error", "Request failed: connection reset");
+        let data = events
+            .strip_prefix("event: error\ndata: ")
+            .and_then(|rest| rest.strip_suffix("\n\n"))
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(error["type"], "error");
+        assert_eq!(error["error"]["type"], "api_error");
+        assert_eq!(
+            error["error"]["message"],
+            "Request failed: connection reset"
+        );
+    }
+
+    #[test]
+    fn test_sse_usage_scanner_handles_split_events() {
+        let mut scanner = SseUsageScanner::default();
+        scanner.push(b"data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\n");
+        assert!(scanner.usage().is_none());
+
+        scanner.push(b"data: {\"choices\":[],\"usage\":{\"prompt_");
+        scanner.push(b"tokens\":12,\"completion_tokens\":3}}\n\ndata: [DONE]\n\n");
+        let usage = scanner.usage().unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 3);
+    }
+
+    #[tokio::test]
+    async fn test_stream_closes_when_out_of_time() {
+        fn late() -> u64 {
+            29_500
+        }
+        let budget = Budget::new(0, 25_000, late);
+        let (head, tail) = include_str!("fixtures/openai_stream_tool_call.sse").split_at(150);
+        let chunks = [head, tail].map(|chunk| Ok::<_, Error>(chunk.as_bytes().to_vec()));
+        let (events, usage) = stream_openai_to_anthropic(
+            futures::stream::iter(chunks),
+            "claude-sonnet-4-20250514",
+            Some(&budget),
+        )
+        .await
+        .unwrap();
+
+        assert!(events.contains("Let me check."));
+        assert!(!events.contains("get_weather"));
+        assert!(events.contains(r#""stop_reason":"max_tokens""#));
+        assert!(events.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+        assert!(usage.is_none());
+    }
+
+    /// The fixture tool call stream, fed a few bytes at a time
+    fn small_reads() -> Vec<std::result::Result<Vec<u8>, Error>> {
+        include_str!("fixtures/openai_stream_tool_call.sse")
+            .as_bytes()
+            .chunks(16)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect()
+    }
+
+    /// Text and partial JSON of each block's deltas, and the number of deltas
+    fn delta_contents(events: &str) -> (Vec<String>, usize) {
+        let mut contents: Vec<String> = Vec::new();
+        let mut deltas = 0;
+        for data in events
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+        {
+            let event: serde_json::Value = serde_json::from_str(data).unwrap();
+            if event["type"] != "content_block_delta" {
+                continue;
+            }
+            deltas += 1;
+            let index = event["index"].as_u64().unwrap() as usize;
+            contents.resize(contents.len().max(index + 1), String::new());
+            let delta = &event["delta"];
+            let text = delta["text"].as_str().or(delta["partial_json"].as_str());
+            contents[index].push_str(text.unwrap());
+        }
+        (contents, deltas)
+    }
+
+    #[tokio::test]
+    async fn test_stream_coalesces_under_load() {
+        let model = "claude-sonnet-4-20250514";
+        let (full, _, profile) = stream_openai_to_anthropic_profiled(
+            futures::stream::iter(small_reads()),
+            model,
+            None,
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(profile.coalescing, Coalescing::Event);
+        assert_eq!(profile.degraded_after, None);
+        assert_eq!(profile.parsed_events, 5);
+        assert_eq!(profile.deltas, 4);
+        assert_eq!(profile.events, 11);
+        assert_eq!(profile.event_bytes, full.len() as u64);
+        let (expected, full_deltas) = delta_contents(&full);
+        assert_eq!(expected[1], r#"{"city":"Hanoi"}"#);
+
+        // After the first event, deltas arriving together are merged
+        let (events, usage, profile) = stream_openai_to_anthropic_profiled(
+            futures::stream::iter(small_reads()),
+            model,
+            None,
+            1,
+        )
+        .await
+        .unwrap();
+        assert_eq!(profile.coalescing, Coalescing::Read);
+        assert_eq!(profile.degraded_after, Some(1));
+        assert_eq!(usage.unwrap().output_tokens, 21);
+        assert_eq!(delta_contents(&events).0, expected);
+
+        // Past the soft limit each block gets a single delta
+        fn slow() -> u64 {
+            26_000
+        }
+        let budget = Budget::new(0, 25_000, slow);
+        let (events, _, profile) = stream_openai_to_anthropic_profiled(
+            futures::stream::iter(small_reads()),
+            model,
+            Some(&budget),
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(profile.coalescing, Coalescing::Block);
+        assert_eq!(profile.degraded_after, Some(0));
+        assert_eq!(profile.coalesced_deltas, 2);
+        let (contents, deltas) = delta_contents(&events);
+        assert_eq!(contents, expected);
+        assert_eq!((full_deltas, deltas), (4, 2));
+        assert!(events.contains(r#""stop_reason":"tool_use""#));
+    }
+
+    #[tokio::test]
+    async fn test_fixture_stream_with_web_citations() {
+        let chunks = [Ok::<_, Error>(
+            include_str!("fixtures/openai_stream_web_citations.sse")
+                .as_bytes()
+                .to_vec(),
+        )];
+        let (events, _) = stream_openai_to_anthropic(
+            futures::stream::iter(chunks),
+            "claude-sonnet-4-20250514",
+            None,
+        )
+        .await
+        .unwrap();
+
+        let blocks: Vec<(String, serde_json::Value)> = events
+            .split("\n\n")
+            .filter_map(|event| {
+                let (name, data) = event.strip_prefix("event: ")?.split_once("\ndata: ")?;
+                Some((name.to_string(), serde_json::from_str(data).ok()?))
+            })
+            .filter(|(name, _)| name.starts_with("content_block_"))
+            .collect();
+        let starts: Vec<(u64, &str)> = blocks
+            .iter()
+            .filter(|(name, _)| name == "content_block_start")
+            .map(|(_, data)| {
+                (
+                    data["index"].as_u64().unwrap(),
+                    data["content_block"]["type"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                (0, "text"),
+                (1, "server_tool_use"),
+                (2, "web_search_tool_result")
+            ]
+        );
+        let stops = blocks
+            .iter()
+            .filter(|(name, _)| name == "content_block_stop")
+            .count();
+        assert_eq!(stops, 3);
+        assert!(events.contains("Announcing Rust 1.82.0"));
+    }
+
+    #[tokio::test]
+    async fn test_fixture_stream_with_tool_call() {
+        // Split mid-event, the way the network delivers it
+        let (head, tail) = include_str!("fixtures/openai_stream_tool_call.sse").split_at(150);
+        let chunks = [head, tail].map(|chunk| Ok::<_, Error>(chunk.as_bytes().to_vec()));
+        let (events, usage) = stream_openai_to_anthropic(
+            futures::stream::iter(chunks),
+            "claude-sonnet-4-20250514",
+            None,
+        )
+        .await
+        .unwrap();
+
+        let names: Vec<&str> = events
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert!(events.contains(r#""name":"get_weather""#));
+        assert!(events.contains(r#""stop_reason":"tool_use""#));
+        assert_eq!(usage.unwrap().output_tokens, 21);
+    }
+}
@@ -0,0 +1,149 @@
+//! Replays recorded provider responses through the response and stream transforms
+//!
+//! `fixtures/providers/<provider>/` holds sanitized OpenRouter responses, one
+//! `<case>.json` per non-streaming response and one `<case>.sse` per event
+//! stream, each next to the `<case>.<json|sse>.expected.json` translation it
+//! must produce.
+//! Message IDs come from [`FixedIds`], so the translations compare exactly. A new provider
+//! quirk comes with a recording here; run the tests with `UPDATE_FIXTURES=1` to
+//! write its expected translation, then review it.
+
+use super::{
+    completion_to_anthropic_with, openai_to_anthropic_with, stream_openai_to_anthropic_with, Error,
+    Result,
+};
+use crate::clock::FixedIds;
+use crate::models::OpenAICompletion;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Message ID given to every replayed translation
+pub const MESSAGE_ID: &str = "msg_fixture";
+
+/// Model name the translations are reported under
+pub const MODEL: &str = "claude-sonnet-4-20250514";
+
+/// Bytes per chunk a recorded stream is fed in, so events split across reads
+const CHUNK_BYTES: usize = 64;
+
+fn fixed_ids() -> FixedIds {
+    FixedIds(MESSAGE_ID.to_string())
+}
+
+/// Directory holding the recordings of each provider
+pub fn providers_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/transform/fixtures/providers")
+}
+
+/// The Anthropic message translated from a recorded non-streaming response
+///
+/// The body is translated both as the proxy does, deserialized straight into
+/// [`OpenAICompletion`], and from a parsed `Value`; the two must agree.
+pub fn replay_message(recorded: &str) -> Result<Value> {
+    let invalid = |e: serde_json::Error| Error(format!("Invalid recording: {e}"));
+    let completion: OpenAICompletion = serde_json::from_str(recorded).map_err(invalid)?;
+    let message = completion_to_anthropic_with(completion, MODEL, &fixed_ids())?;
+    let message = serde_json::to_value(message).map_err(|e| Error(e.to_string()))?;
+
+    let response: Value = serde_json::from_str(recorded).map_err(invalid)?;
+    let from_value = openai_to_anthropic_with(&response, MODEL, &fixed_ids())?;
+    if serde_json::to_value(from_value).ok().as_ref() != Some(&message) {
+        return Err(Error(
+            "Translations from the body and from a Value differ".to_string(),
+        ));
+    }
+    Ok(message)
+}
+
+/// The Anthropic events translated from a recorded event stream, as
+/// `{"event", "data"}` objects
+pub async fn replay_stream(recorded: &str) -> Result<Value> {
+    let chunks: Vec<std::result::Result<Vec<u8>, Error>> = recorded
+        .as_bytes()
+        .chunks(CHUNK_BYTES)
+        .map(|chunk| Ok(chunk.to_vec()))
+        .collect();
+    let (events, _) =
+        stream_openai_to_anthropic_with(futures::stream::iter(chunks), MODEL, None, &fixed_ids())
+            .await?;
+    Ok(parse_events(&events))
+}
+
+/// Splits Anthropic server-sent events into `{"event", "data"}` objects
+pub fn parse_events(events: &str) -> Value {
+    events
+        .split("\n\n")
+        .filter(|event| !event.is_empty())
+        .map(|event| {
+            let field = |name: &str| {
+                event
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .unwrap_or_default()
+            };
+            json!({
+                "event": field("event: "),
+                "data": serde_json::from_str::<Value>(field("data: ")).unwrap_or(Value::Null),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recordings under the providers directory, with their expected translations
+    fn recordings() -> Vec<(PathBuf, PathBuf)> {
+        let mut recordings = Vec::new();
+        for provider in std::fs::read_dir(providers_dir()).unwrap() {
+            for entry in std::fs::read_dir(provider.unwrap().path()).unwrap() {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                let Some(case) = name
+                    .strip_suffix(".json")
+                    .filter(|case| !case.ends_with(".expected"))
+                    .or_else(|| name.strip_suffix(".sse"))
+                else {
+                    continue;
+                };
+                let kind = path.extension().unwrap().to_string_lossy().into_owned();
+                let expected = path.with_file_name(format!("{case}.{kind}.expected.json"));
+                recordings.push((path, expected));
+            }
+        }
+        recordings.sort();
+        recordings
+    }
+
+    #[tokio::test]
+    async fn test_provider_recordings() {
+        let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+        let recordings = recordings();
+        assert!(!recordings.is_empty());
+
+        for (path, expected_path) in recordings {
+            let recorded = std::fs::read_to_string(&path).unwrap();
+            let actual = if path.extension().is_some_and(|kind| kind == "sse") {
+                replay_stream(&recorded).await
+            } else {
+                replay_message(&recorded)
+            }
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+
+            if update {
+                let pretty = serde_json::to_string_pretty(&actual).unwrap();
+                std::fs::write(&expected_path, pretty + "\n").unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+                panic!(
+                    "{} has no expected translation; run with UPDATE_FIXTURES=1",
+                    path.display()
+                )
+            });
+            let expected: Value = serde_json::from_str(&expected).unwrap();
+            assert_eq!(actual, expected, "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::http;
+use crate::utils::{upstream_headers, Attribution};
+use futures::future::{select, Either, LocalBoxFuture};
+use futures::stream::{self, LocalBoxStream};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use worker::{Delay, Result};
+
+/// Sends chat completion requests to the OpenAI-compatible upstream
+///
+/// The proxy path is generic over this trait so it can be exercised against
+/// `MockClient` in native tests rather than a live Worker. Returned futures own
+/// everything they need, so a request can be raced (hedging) or finished in the
+/// background (shadow traffic) after the caller's borrows end.
+pub trait UpstreamClient {
+    /// POSTs `body` as JSON and reads the whole response
+    fn send_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>>;
+
+    /// POSTs `body` as JSON, resolving once headers arrive with the body left streaming
+    fn send_streaming<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>>;
+
+    /// Waits before a retry
+    fn sleep(&self, ms: u64) -> LocalBoxFuture<'static, ()>;
+}
+
+enum Body {
+    Buffered(String),
+    Streaming(LocalBoxStream<'static, Result<Vec<u8>>>),
+}
+
+/// An upstream response, either read in full or still streaming
+pub struct UpstreamResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+impl UpstreamResponse {
+    pub fn buffered(status: u16, body: impl Into<String>) -> Self {
+        UpstreamResponse {
+            status,
+            headers: Vec::new(),
+            body: Body::Buffered(body.into()),
+        }
+    }
+
+    pub fn streaming(status: u16, body: LocalBoxStream<'static, Result<Vec<u8>>>) -> Self {
+        UpstreamResponse {
+            status,
+            headers: Vec::new(),
+            body: Body::Streaming(body),
+        }
+    }
+
+    pub fn with_headers(self, headers: Vec<(String, String)>) -> Self {
+        UpstreamResponse { headers, ..self }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The whole body as text, draining it first if it is streaming
+    pub async fn text(self) -> Result<String> {
+        match self.body {
+            Body::Buffered(text) => Ok(text),
+            Body::Streaming(chunks) => {
+                let bytes: Vec<u8> = chunks.try_concat().await?;
+                Ok(String::from_utf8_lossy(&bytes).into_owned())
+            }
+        }
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        Ok(serde_json::from_str(&self.text().await?)?)
+    }
+
+    /// The body as a stream of chunks; a buffered body arrives as a single chunk
+    pub fn into_stream(self) -> LocalBoxStream<'static, Result<Vec<u8>>> {
+        match self.body {
+            Body::Buffered(text) => {
+                stream::once(async move { Ok(text.into_bytes()) }).boxed_local()
+            }
+            Body::Streaming(chunks) => chunks,
+        }
+    }
+}
+
+/// `UpstreamClient` backed by the Workers `fetch` API
+#[derive(Debug, Clone, Default)]
+pub struct FetchClient {
+    client: http::Client,
+    /// Longest wait for a response in milliseconds; 0 waits indefinitely
+    timeout_ms: u64,
+    /// Client headers passed through by the header policy
+    headers: Vec<(String, String)>,
+    attribution: Attribution,
+}
+
+impl FetchClient {
+    pub fn new() -> Self {
+        FetchClient::default()
+    }
+
+    /// Attributes requests to `attribution` rather than CCR's own site
+    pub fn with_attribution(self, attribution: Attribution) -> Self {
+        FetchClient {
+            attribution,
+            ..self
+        }
+    }
+
+    /// Sends `headers` with every request, after the ones CCR sets
+    pub fn with_headers(self, headers: Vec<(String, String)>) -> Self {
+        FetchClient { headers, ..self }
+    }
+
+    /// Gives up on requests after `timeout_ms`, aborting the fetch
+    ///
+    /// Buffered requests must be read in full within the timeout; streaming ones
+    /// only need their headers, since the body may take minutes to generate.
+    pub fn with_timeout(self, timeout_ms: u64) -> Self {
+        FetchClient { timeout_ms, ..self }
+    }
+
+    fn post<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> http::RequestBuilder {
+        let mut request = self.client.post(url);
+        for (name, value) in upstream_headers(api_key, &self.attribution) {
+            request = request.header(name, value);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        // fetch decodes compressed bodies before they are read
+        request.header("Accept-Encoding", "gzip, br").json(body)
+    }
+}
+
+impl UpstreamClient for FetchClient {
+    fn send_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>> {
+        let request = self.post(url, api_key, body);
+        let exchange = async move {
+            let response = request.send().await?;
+            let status = response.status();
+            let headers = response.headers();
+            Ok(UpstreamResponse::buffered(status, response.text().await?).with_headers(headers))
+        };
+        with_timeout(exchange, self.timeout_ms).boxed_local()
+    }
+
+    fn send_streaming<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>> {
+        let request = self.post(url, api_key, body);
+        let timeout_ms = self.timeout_ms;
+        async move {
+            let response = with_timeout(request.send(), timeout_ms).await?;
+            let status = response.status();
+            let headers = response.headers();
+            Ok(
+                UpstreamResponse::streaming(status, response.bytes_stream()?.boxed_local())
+                    .with_headers(headers),
+            )
+        }
+        .boxed_local()
+    }
+
+    fn sleep(&self, ms: u64) -> LocalBoxFuture<'static, ()> {
+        Delay::from(Duration::from_millis(ms)).boxed_local()
+    }
+}
+
+/// Fails `future` once `timeout_ms` passes (0 waits indefinitely)
+///
+/// Dropping an `http` request before its response arrives aborts the fetch, so
+/// the upstream connection is released rather than left running.
+async fn with_timeout<T>(future: impl Future<Output = Result<T>>, timeout_ms: u64) -> Result<T> {
+    if timeout_ms == 0 {
+        return future.await;
+    }
+    let delay = Delay::from(Duration::from_millis(timeout_ms));
+    futures::pin_mut!(future, delay);
+    match select(future, delay).await {
+        Either::Left((result, _)) => result,
+        Either::Right(((), _)) => Err(worker::Error::RustError(format!(
+            "Upstream request timed out after {timeout_ms} ms"
+        ))),
+    }
+}
+
+/// A request received by `MockClient`
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    pub url: String,
+    pub api_key: String,
+    pub body: serde_json::Value,
+    pub streaming: bool,
+}
+
+#[cfg(test)]
+type MockReply = (u16, Vec<String>, Vec<(String, String)>);
+
+/// Replays canned responses in order and records the requests it was sent
+///
+/// Sleeps return at once and are recorded in `slept`.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockClient {
+    replies: std::cell::RefCell<std::collections::VecDeque<MockReply>>,
+    pub sent: std::cell::RefCell<Vec<SentRequest>>,
+    pub slept: std::cell::RefCell<Vec<u64>>,
+}
+
+#[cfg(test)]
+impl MockClient {
+    /// Queues a response; a streaming request receives `chunks` one at a time
+    pub fn reply(self, status: u16, chunks: &[&str]) -> Self {
+        self.reply_with_headers(status, chunks, &[])
+    }
+
+    /// Queues a response carrying `headers`
+    pub fn reply_with_headers(
+        self,
+        status: u16,
+        chunks: &[&str],
+        headers: &[(&str, &str)],
+    ) -> Self {
+        self.replies.borrow_mut().push_back((
+            status,
+            chunks.iter().map(|chunk| chunk.to_string()).collect(),
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        ));
+        self
+    }
+
+    fn next<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+        streaming: bool,
+    ) -> Result<MockReply> {
+        self.sent.borrow_mut().push(SentRequest {
+            url: url.to_string(),
+            api_key: api_key.to_string(),
+            body: serde_json::to_value(body)?,
+            streaming,
+        });
+        self.replies
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| worker::Error::RustError("connection refused".to_string()))
+    }
+}
+
+#[cfg(test)]
+impl UpstreamClient for MockClient {
+    fn send_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>> {
+        let reply = self.next(url, api_key, body, false);
+        async move {
+            let (status, chunks, headers) = reply?;
+            Ok(UpstreamResponse::buffered(status, chunks.concat()).with_headers(headers))
+        }
+        .boxed_local()
+    }
+
+    fn send_streaming<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        api_key: &str,
+        body: &T,
+    ) -> LocalBoxFuture<'static, Result<UpstreamResponse>> {
+        let reply = self.next(url, api_key, body, true);
+        async move {
+            let (status, chunks, headers) = reply?;
+            let chunks = chunks.into_iter().map(|chunk| Ok(chunk.into_bytes()));
+            Ok(
+                UpstreamResponse::streaming(status, stream::iter(chunks).boxed_local())
+                    .with_headers(headers),
+            )
+        }
+        .boxed_local()
+    }
+
+    fn sleep(&self, ms: u64) -> LocalBoxFuture<'static, ()> {
+        self.slept.borrow_mut().push(ms);
+        async {}.boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_streaming_body_reads_as_text() {
+        let client = MockClient::default().reply(200, &["data: {\"a\"", ":1}\n\n"]);
+        let response = client
+            .send_streaming("https://upstream/chat", "key", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(response.is_success());
+        assert_eq!(response.text().await.unwrap(), "data: {\"a\":1}\n\n");
+        assert!(client.sent.borrow()[0].streaming);
+    }
+
+    #[tokio::test]
+    async fn test_mock_fails_when_no_reply_is_queued() {
+        let client = MockClient::default();
+        let result = client
+            .send_json("https://upstream/chat", "key", &serde_json::json!({}))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(client.sent.borrow().len(), 1);
+    }
+}
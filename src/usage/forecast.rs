@@ -0,0 +1,286 @@
+//! Spend trends per key and model, and the digest posted by the cron trigger
+//!
+//! Forecasts read the same daily rows as the usage report. Today is still in
+//! progress, so the daily average is taken over the complete days before it and
+//! projected over the next week and month. A key or model spending more today
+//! than [`SPIKE_FACTOR`] times its daily average, and at least [`SPIKE_MIN_USD`],
+//! is flagged: that is how a runaway agent loop usually shows up.
+
+use super::UsageRow;
+use crate::utils::format_date;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Days of history a forecast is based on when `?days=` isn't given
+pub const DEFAULT_DAYS: u32 = 14;
+
+/// Today's spend over the daily average that flags a key or model
+pub const SPIKE_FACTOR: f64 = 3.0;
+
+/// Spend today below which nothing is flagged, however quiet the history
+pub const SPIKE_MIN_USD: f64 = 1.0;
+
+/// Models and keys listed in the digest besides the flagged ones
+const DIGEST_TOP: usize = 3;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// Spend of one key or model (or of everything, named `total`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Trend {
+    pub name: String,
+    /// Spend so far today (UTC)
+    pub today_usd: f64,
+    pub yesterday_usd: f64,
+    /// Spend over the seven complete days before today
+    pub last_7_days_usd: f64,
+    /// Average over the complete days in the window
+    pub daily_average_usd: f64,
+    pub projected_7_days_usd: f64,
+    pub projected_30_days_usd: f64,
+    /// Whether today's spend is far above the daily average
+    pub spiking: bool,
+}
+
+impl Trend {
+    fn new(name: &str) -> Self {
+        Trend {
+            name: name.to_string(),
+            ..Trend::default()
+        }
+    }
+
+    fn finish(&mut self, complete_days: u32) {
+        self.daily_average_usd /= f64::from(complete_days.max(1));
+        self.projected_7_days_usd = self.daily_average_usd * 7.0;
+        self.projected_30_days_usd = self.daily_average_usd * 30.0;
+        self.spiking = self.today_usd >= SPIKE_MIN_USD
+            && self.today_usd > self.daily_average_usd * SPIKE_FACTOR;
+    }
+
+    /// How many times the daily average today's spend is, when there's an average
+    pub fn today_ratio(&self) -> Option<f64> {
+        (self.daily_average_usd > 0.0).then(|| self.today_usd / self.daily_average_usd)
+    }
+}
+
+/// Spend trends over the last `days` days, overall and per key and model
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Forecast {
+    /// The UTC day the forecast was made on, `YYYY-MM-DD`
+    pub today: String,
+    pub days: u32,
+    pub total: Trend,
+    /// Flagged first, then by projected spend
+    pub by_key: Vec<Trend>,
+    pub by_model: Vec<Trend>,
+}
+
+impl Forecast {
+    /// Builds the forecast from usage rows, as of the time `now_ms`
+    ///
+    /// The first of the `days` days is cut off by the query window, so only the
+    /// `days - 1` days between it and today count towards the average.
+    pub fn new(rows: &[UsageRow], days: u32, now_ms: u64) -> Self {
+        let day = |ago: u32| format_date(now_ms.saturating_sub(u64::from(ago) * DAY_MS));
+        let today = day(0);
+        let yesterday = day(1);
+        let week_start = day(7);
+        let complete_days = days.saturating_sub(1);
+        let history_start = day(complete_days);
+
+        let mut total = Trend::new("total");
+        let mut by_key: BTreeMap<&str, Trend> = BTreeMap::new();
+        let mut by_model: BTreeMap<&str, Trend> = BTreeMap::new();
+        for row in rows {
+            let day = row.day.as_str();
+            let key = by_key
+                .entry(&row.key_hash)
+                .or_insert_with(|| Trend::new(&row.key_hash));
+            let model = by_model
+                .entry(&row.model)
+                .or_insert_with(|| Trend::new(&row.model));
+            for trend in [&mut total, key, model] {
+                if day == today {
+                    trend.today_usd += row.cost_usd;
+                    continue;
+                }
+                if day == yesterday {
+                    trend.yesterday_usd += row.cost_usd;
+                }
+                if day >= week_start.as_str() && day < today.as_str() {
+                    trend.last_7_days_usd += row.cost_usd;
+                }
+                // The sum is divided into the average by `finish`
+                if day >= history_start.as_str() && day < today.as_str() {
+                    trend.daily_average_usd += row.cost_usd;
+                }
+            }
+        }
+
+        total.finish(complete_days);
+        let rank = |trends: BTreeMap<&str, Trend>| {
+            let mut trends: Vec<Trend> = trends
+                .into_values()
+                .map(|mut trend| {
+                    trend.finish(complete_days);
+                    trend
+                })
+                .collect();
+            trends.sort_by(|a, b| {
+                b.spiking
+                    .cmp(&a.spiking)
+                    .then(b.projected_30_days_usd.total_cmp(&a.projected_30_days_usd))
+            });
+            trends
+        };
+
+        Forecast {
+            today,
+            days,
+            total,
+            by_key: rank(by_key),
+            by_model: rank(by_model),
+        }
+    }
+
+    /// Keys and models whose spend today is far above their average
+    pub fn spikes(&self) -> impl Iterator<Item = (&'static str, &Trend)> {
+        let keys = self.by_key.iter().map(|trend| ("key", trend));
+        let models = self.by_model.iter().map(|trend| ("model", trend));
+        keys.chain(models).filter(|(_, trend)| trend.spiking)
+    }
+
+    /// Plain-text summary for a chat webhook
+    pub fn digest(&self) -> String {
+        let total = &self.total;
+        let mut lines = vec![format!(
+            "📊 CCR spend on {}: ${:.2} so far, ${:.2} yesterday, ${:.2} over the last 7 days. \
+             At ${:.2} a day, the next 30 days come to ${:.2}.",
+            self.today,
+            total.today_usd,
+            total.yesterday_usd,
+            total.last_7_days_usd,
+            total.daily_average_usd,
+            total.projected_30_days_usd
+        )];
+
+        for (kind, trend) in self.spikes() {
+            let ratio = trend
+                .today_ratio()
+                .map_or("no earlier spend".to_string(), |ratio| {
+                    format!("{ratio:.1}x the daily average")
+                });
+            lines.push(format!(
+                "⚠️ {kind} {}: ${:.2} today, {ratio}",
+                trend.name, trend.today_usd
+            ));
+        }
+
+        for (title, trends) in [("Top models", &self.by_model), ("Top keys", &self.by_key)] {
+            let top: Vec<String> = trends
+                .iter()
+                .filter(|trend| !trend.spiking && trend.last_7_days_usd + trend.today_usd > 0.0)
+                .take(DIGEST_TOP)
+                .map(|trend| format!("{} ${:.2}/7d", trend.name, trend.last_7_days_usd))
+                .collect();
+            if !top.is_empty() {
+                lines.push(format!("{title}: {}", top.join(", ")));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-06-10 12:00 UTC
+    const NOW_MS: u64 = 1_749_556_800_000;
+
+    fn row(day: &str, model: &str, key_hash: &str, cost_usd: f64) -> UsageRow {
+        UsageRow {
+            day: day.to_string(),
+            model: model.to_string(),
+            key_hash: key_hash.to_string(),
+            requests: 1.0,
+            input_tokens: 0.0,
+            output_tokens: 0.0,
+            cost_usd,
+            cached_input_tokens: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_forecast_trends() {
+        let mut rows = Vec::new();
+        // Key aa spends $2 a day on sonnet, key bb $0.50 on deepseek
+        for day in 1..=9 {
+            let day = format!("2025-06-{day:02}");
+            rows.push(row(&day, "anthropic/claude-sonnet-4", "aa", 2.0));
+            rows.push(row(&day, "deepseek/deepseek-chat", "bb", 0.5));
+        }
+        // Today key bb loops
+        rows.push(row("2025-06-10", "anthropic/claude-sonnet-4", "aa", 1.0));
+        rows.push(row("2025-06-10", "deepseek/deepseek-chat", "bb", 6.0));
+
+        let forecast = Forecast::new(&rows, 10, NOW_MS);
+        assert_eq!(forecast.today, "2025-06-10");
+
+        let total = &forecast.total;
+        assert_eq!(total.today_usd, 7.0);
+        assert_eq!(total.yesterday_usd, 2.5);
+        assert_eq!(total.last_7_days_usd, 17.5);
+        assert_eq!(total.daily_average_usd, 2.5);
+        assert_eq!(total.projected_30_days_usd, 75.0);
+        assert!(!total.spiking);
+
+        // The spiking key is listed first even though it projects less
+        assert_eq!(forecast.by_key[0].name, "bb");
+        assert!(forecast.by_key[0].spiking);
+        assert_eq!(forecast.by_key[0].today_ratio(), Some(12.0));
+        assert_eq!(forecast.by_key[1].projected_7_days_usd, 14.0);
+        let spikes: Vec<(&str, &str)> = forecast
+            .spikes()
+            .map(|(kind, trend)| (kind, trend.name.as_str()))
+            .collect();
+        assert_eq!(spikes, [("key", "bb"), ("model", "deepseek/deepseek-chat")]);
+
+        // The first day of the window is partial and left out of the average
+        let short = Forecast::new(&rows, 3, NOW_MS);
+        assert_eq!(short.total.daily_average_usd, 2.5);
+        assert_eq!(short.total.last_7_days_usd, 17.5);
+    }
+
+    #[test]
+    fn test_small_spend_is_not_flagged() {
+        let rows = [row("2025-06-10", "deepseek/deepseek-chat", "aa", 0.2)];
+        let forecast = Forecast::new(&rows, 14, NOW_MS);
+        assert_eq!(forecast.by_key[0].today_ratio(), None);
+        assert_eq!(forecast.spikes().count(), 0);
+    }
+
+    #[test]
+    fn test_digest() {
+        let rows = [
+            row("2025-06-09", "anthropic/claude-sonnet-4", "aa", 2.0),
+            row("2025-06-10", "anthropic/claude-sonnet-4", "aa", 30.0),
+            row("2025-06-09", "deepseek/deepseek-chat", "bb", 0.25),
+        ];
+        let digest = Forecast::new(&rows, 2, NOW_MS).digest();
+        let lines: Vec<&str> = digest.lines().collect();
+        assert_eq!(
+            lines[0],
+            "📊 CCR spend on 2025-06-10: $30.00 so far, $2.25 yesterday, $2.25 over the last 7 days. \
+             At $2.25 a day, the next 30 days come to $67.50."
+        );
+        assert_eq!(lines[1], "⚠️ key aa: $30.00 today, 15.0x the daily average");
+        assert_eq!(
+            lines[2],
+            "⚠️ model anthropic/claude-sonnet-4: $30.00 today, 15.0x the daily average"
+        );
+        assert_eq!(lines[3], "Top models: deepseek/deepseek-chat $0.25/7d");
+        assert_eq!(lines[4], "Top keys: bb $0.25/7d");
+    }
+}
@@ -0,0 +1,356 @@
+pub mod forecast;
+
+use crate::http;
+use crate::pricing::{ModelPricing, PriceTable};
+use crate::utils::escape_html;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use worker::Result;
+
+/// Days covered by a report when `?days=` isn't given
+pub const DEFAULT_DAYS: u32 = 7;
+
+/// Analytics Engine keeps data for three months
+pub const MAX_DAYS: u32 = 90;
+
+/// Access to the Analytics Engine SQL API, which is the only way to read the dataset
+///
+/// Enabled by `CF_ACCOUNT_ID` and `CF_ANALYTICS_TOKEN` (an API token with
+/// Account Analytics read permission).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalyticsSqlConfig {
+    pub account_id: String,
+    pub api_token: String,
+    /// Dataset name from the `analytics_engine_datasets` binding
+    pub dataset: String,
+}
+
+/// Returns true if `name` can be used as a dataset name in SQL without quoting
+pub fn is_valid_dataset(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Usage for one day, model and key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRow {
+    /// `YYYY-MM-DD`
+    #[serde(deserialize_with = "day")]
+    pub day: String,
+    pub model: String,
+    pub key_hash: String,
+    #[serde(deserialize_with = "number")]
+    pub requests: f64,
+    #[serde(deserialize_with = "number")]
+    pub input_tokens: f64,
+    #[serde(deserialize_with = "number")]
+    pub output_tokens: f64,
+    #[serde(deserialize_with = "number")]
+    pub cost_usd: f64,
+    /// Input tokens read from the upstream's prompt cache
+    #[serde(default, deserialize_with = "number")]
+    pub cached_input_tokens: f64,
+}
+
+/// The SQL API returns 64-bit sums as strings
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Float(f64),
+        Text(String),
+    }
+    match Number::deserialize(deserializer)? {
+        Number::Float(value) => Ok(value),
+        Number::Text(text) => text.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+/// Days come back as `YYYY-MM-DD hh:mm:ss`
+fn day<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    Ok(text.get(..10).unwrap_or(&text).to_string())
+}
+
+/// Totals for one value of a dimension (a day, a model or a key)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub name: String,
+    pub requests: f64,
+    pub input_tokens: f64,
+    pub output_tokens: f64,
+    pub cost_usd: f64,
+    pub cached_input_tokens: f64,
+}
+
+impl Summary {
+    fn add(&mut self, row: &UsageRow) {
+        self.requests += row.requests;
+        self.input_tokens += row.input_tokens;
+        self.output_tokens += row.output_tokens;
+        self.cost_usd += row.cost_usd;
+        self.cached_input_tokens += row.cached_input_tokens;
+    }
+
+    /// Share of input tokens read from the upstream's prompt cache
+    pub fn cache_hit_rate(&self) -> f64 {
+        if self.input_tokens > 0.0 {
+            self.cached_input_tokens / self.input_tokens
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Usage over the last `days` days, broken down three ways
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub days: u32,
+    pub total: Summary,
+    pub by_day: Vec<Summary>,
+    pub by_model: Vec<Summary>,
+    pub by_key: Vec<Summary>,
+    pub rows: Vec<UsageRow>,
+    /// Current price per token of each model in the report, where listed
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, ModelPricing>,
+}
+
+fn summarize(rows: &[UsageRow], name: impl Fn(&UsageRow) -> &str) -> Vec<Summary> {
+    let mut summaries: BTreeMap<&str, Summary> = BTreeMap::new();
+    for row in rows {
+        let key = name(row);
+        summaries
+            .entry(key)
+            .or_insert_with(|| Summary {
+                name: key.to_string(),
+                ..Summary::default()
+            })
+            .add(row);
+    }
+    summaries.into_values().collect()
+}
+
+impl UsageReport {
+    pub fn new(days: u32, rows: Vec<UsageRow>) -> Self {
+        let mut total = Summary {
+            name: "total".to_string(),
+            ..Summary::default()
+        };
+        rows.iter().for_each(|row| total.add(row));
+
+        let mut by_day = summarize(&rows, |row| &row.day);
+        by_day.reverse();
+        let mut by_model = summarize(&rows, |row| &row.model);
+        by_model.sort_by(|a, b| b.cost_usd.total_cmp(&a.cost_usd));
+        let mut by_key = summarize(&rows, |row| &row.key_hash);
+        by_key.sort_by(|a, b| b.cost_usd.total_cmp(&a.cost_usd));
+
+        UsageReport {
+            days,
+            total,
+            by_day,
+            by_model,
+            by_key,
+            rows,
+            prices: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the current prices of the report's models
+    pub fn with_prices(mut self, prices: &PriceTable) -> Self {
+        self.prices = self
+            .by_model
+            .iter()
+            .filter_map(|model| Some((model.name.clone(), *prices.get(&model.name)?)))
+            .collect();
+        self
+    }
+
+    /// Renders the report as a standalone HTML page
+    pub fn to_html(&self) -> String {
+        let mut html = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <title>CCR Usage</title>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <script src="https://cdn.tailwindcss.com"></script>
+</head>
+<body class="bg-gray-50 text-gray-900">
+    <div class="max-w-5xl mx-auto py-12 px-4">
+        <h1 class="text-3xl font-bold mb-2">Usage</h1>
+        <p class="text-gray-600 mb-8">Last {} days: {} requests, {} input and {} output tokens, ${:.2}</p>
+"#,
+            self.days,
+            self.total.requests,
+            self.total.input_tokens,
+            self.total.output_tokens,
+            self.total.cost_usd
+        );
+        for (title, summaries) in [
+            ("By day", &self.by_day),
+            ("By model", &self.by_model),
+            ("By key", &self.by_key),
+        ] {
+            html.push_str(&summary_table(title, summaries));
+        }
+        html.push_str("    </div>\n</body>\n</html>\n");
+        html
+    }
+}
+
+fn summary_table(title: &str, summaries: &[Summary]) -> String {
+    let mut table = format!(
+        r#"        <h2 class="text-xl font-semibold mb-2">{title}</h2>
+        <table class="w-full mb-8 bg-white border border-gray-200 text-sm">
+            <tr class="bg-gray-100 text-left"><th class="p-2"></th><th class="p-2">Requests</th><th class="p-2">Input tokens</th><th class="p-2">Output tokens</th><th class="p-2">Cost (USD)</th><th class="p-2">Cache hits</th></tr>
+"#
+    );
+    for summary in summaries {
+        table.push_str(&format!(
+            "            <tr class=\"border-t\"><td class=\"p-2 font-mono\">{}</td><td class=\"p-2\">{}</td><td class=\"p-2\">{}</td><td class=\"p-2\">{}</td><td class=\"p-2\">{:.4}</td><td class=\"p-2\">{:.0}%</td></tr>\n",
+            escape_html(&summary.name),
+            summary.requests,
+            summary.input_tokens,
+            summary.output_tokens,
+            summary.cost_usd,
+            summary.cache_hit_rate() * 100.0
+        ));
+    }
+    table.push_str("        </table>\n");
+    table
+}
+
+/// SQL aggregating the metrics written by [`crate::metrics`] per day, model and key
+pub fn query(dataset: &str, days: u32) -> String {
+    format!(
+        "SELECT toStartOfDay(timestamp) AS day, blob1 AS model, blob2 AS key_hash, \
+         SUM(_sample_interval) AS requests, \
+         SUM(_sample_interval * double3) AS input_tokens, \
+         SUM(_sample_interval * double4) AS output_tokens, \
+         SUM(_sample_interval * double5) AS cost_usd, \
+         SUM(_sample_interval * double6) AS cached_input_tokens \
+         FROM {dataset} WHERE timestamp > NOW() - INTERVAL '{days}' DAY \
+         GROUP BY day, model, key_hash ORDER BY day DESC FORMAT JSON"
+    )
+}
+
+#[derive(Deserialize)]
+struct SqlResponse {
+    data: Vec<UsageRow>,
+}
+
+/// Runs the usage query against the Analytics Engine SQL API
+pub async fn fetch(
+    client: &http::Client,
+    config: &AnalyticsSqlConfig,
+    days: u32,
+) -> Result<UsageReport> {
+    let url = format!(
+        "https://api.cloudflare.com/client/v4/accounts/{}/analytics_engine/sql",
+        config.account_id
+    );
+    let response = client
+        .post(&url)
+        .bearer_auth(&config.api_token)
+        .body(query(&config.dataset, days))
+        .send()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Usage query failed: {e}")))?;
+
+    if !response.is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(worker::Error::RustError(format!(
+            "Usage query failed with HTTP {status}: {text}"
+        )));
+    }
+
+    let sql: SqlResponse = response
+        .json()
+        .await
+        .map_err(|e| worker::Error::RustError(format!("Invalid usage query response: {e}")))?;
+    Ok(UsageReport::new(days, sql.data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<UsageRow> {
+        serde_json::from_value(serde_json::json!([
+            {"day": "2025-06-02 00:00:00", "model": "deepseek/deepseek-chat", "key_hash": "aa",
+             "requests": "3", "input_tokens": "3000", "output_tokens": 600, "cost_usd": 0.002},
+            {"day": "2025-06-02 00:00:00", "model": "anthropic/claude-sonnet-4", "key_hash": "bb",
+             "requests": "1", "input_tokens": "1000", "output_tokens": 200, "cost_usd": 0.006,
+             "cached_input_tokens": "750"},
+            {"day": "2025-06-01 00:00:00", "model": "deepseek/deepseek-chat", "key_hash": "bb",
+             "requests": "2", "input_tokens": "2000", "output_tokens": 400, "cost_usd": 0.001}
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_deserialize_rows() {
+        let rows = rows();
+        assert_eq!(rows[0].day, "2025-06-02");
+        assert_eq!(rows[0].requests, 3.0);
+        assert_eq!(rows[0].output_tokens, 600.0);
+    }
+
+    #[test]
+    fn test_report_aggregates() {
+        let report = UsageReport::new(7, rows());
+        assert_eq!(report.total.requests, 6.0);
+        assert_eq!(report.total.input_tokens, 6000.0);
+
+        let days: Vec<&str> = report.by_day.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(days, ["2025-06-02", "2025-06-01"]);
+
+        // Most expensive first
+        assert_eq!(report.by_model[0].name, "anthropic/claude-sonnet-4");
+        assert_eq!(report.by_model[1].requests, 5.0);
+
+        let key_bb = report.by_key.iter().find(|s| s.name == "bb").unwrap();
+        assert_eq!(key_bb.requests, 3.0);
+        assert_eq!(key_bb.cache_hit_rate(), 0.25);
+
+        let sonnet = ModelPricing {
+            prompt: 0.000003,
+            completion: 0.000015,
+        };
+        let priced = report.with_prices(&PriceTable::from([
+            ("anthropic/claude-sonnet-4".to_string(), sonnet),
+            ("openai/gpt-4.1".to_string(), ModelPricing::default()),
+        ]));
+        assert_eq!(
+            priced.prices,
+            BTreeMap::from([("anthropic/claude-sonnet-4".to_string(), sonnet)])
+        );
+    }
+
+    #[test]
+    fn test_html_escapes_names() {
+        let mut rows = rows();
+        rows[0].model = "<script>".to_string();
+        let html = UsageReport::new(7, rows).to_html();
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("Last 7 days: 6 requests"));
+    }
+
+    #[test]
+    fn test_query() {
+        let sql = query("ccr_usage", 30);
+        assert!(sql.contains("FROM ccr_usage"));
+        assert!(sql.contains("INTERVAL '30' DAY"));
+    }
+
+    #[test]
+    fn test_is_valid_dataset() {
+        assert!(is_valid_dataset("ccr_usage"));
+        assert!(!is_valid_dataset("ccr_usage; DROP"));
+        assert!(!is_valid_dataset(""));
+    }
+}
@@ -1,27 +1,115 @@
-use crate::config::Config;
+use crate::auto_model::AutoModelConfig;
+use crate::features::FeatureFlags;
+use crate::transform::request::UnsupportedParams;
+
+/// OpenRouter models that the Claude short names (`haiku`, `sonnet`, `opus`) route to
+#[derive(Debug, Clone)]
+pub struct ModelTargets {
+    pub haiku: String,
+    pub sonnet: String,
+    pub opus: String,
+}
+
+impl Default for ModelTargets {
+    fn default() -> Self {
+        ModelTargets {
+            haiku: "anthropic/claude-3.5-haiku".to_string(),
+            sonnet: "anthropic/claude-sonnet-4".to_string(),
+            opus: "anthropic/claude-opus-4".to_string(),
+        }
+    }
+}
+
+/// The settings model name mapping depends on
+///
+/// Implemented by the Worker's `Config`; [`Routing`] provides the same settings
+/// to code that uses the transform outside the Worker.
+pub trait ModelRouting {
+    /// Targets for the Claude short names
+    fn model_targets(&self) -> &ModelTargets;
+
+    /// Settings for the `auto` model, or `None` when it is disabled
+    fn auto_model(&self) -> Option<&AutoModelConfig>;
+
+    /// Parameters stripped from requests to the providers that reject them
+    fn unsupported_params(&self) -> &UnsupportedParams;
+
+    /// Model serving requests that use Anthropic's code execution tool, which are
+    /// rejected when there is none
+    fn code_execution_model(&self) -> Option<&str>;
+
+    /// Which of the switchable transforms are applied
+    fn features(&self) -> &FeatureFlags;
+}
+
+/// Model routing settings held directly rather than read from the Worker environment
+#[derive(Debug, Clone, Default)]
+pub struct Routing {
+    pub targets: ModelTargets,
+    pub auto_model: Option<AutoModelConfig>,
+    pub unsupported_params: UnsupportedParams,
+    pub code_execution_model: Option<String>,
+    pub features: FeatureFlags,
+}
+
+impl ModelRouting for Routing {
+    fn model_targets(&self) -> &ModelTargets {
+        &self.targets
+    }
+
+    fn auto_model(&self) -> Option<&AutoModelConfig> {
+        self.auto_model.as_ref()
+    }
+
+    fn unsupported_params(&self) -> &UnsupportedParams {
+        &self.unsupported_params
+    }
+
+    fn code_execution_model(&self) -> Option<&str> {
+        self.code_execution_model.as_deref()
+    }
+
+    fn features(&self) -> &FeatureFlags {
+        &self.features
+    }
+}
 
 /// Maps Claude model names to OpenRouter model identifiers
 ///
 /// This function handles the model name passed from Claude Code. It:
 /// - Passes through OpenRouter model IDs (containing '/') unchanged
-/// - Maps common Claude short names to full OpenRouter model IDs
+/// - Maps common Claude short names to the configured OpenRouter model IDs
 /// - Returns unknown models as-is
 ///
 /// # Arguments
 /// * `anthropic_model` - The model name from the Anthropic API request
-/// * `_config` - Configuration (unused but kept for API compatibility)
+/// * `config` - Routing settings providing the short name targets
 ///
 /// # Returns
 /// The OpenRouter-compatible model identifier
-pub fn map_model(anthropic_model: &str, _config: &Config) -> String {
-    // Removed debug logging to reduce CPU usage
+pub fn map_model(anthropic_model: &str, config: &impl ModelRouting) -> String {
+    match model_alias(anthropic_model, config) {
+        Some((_, target)) => target.to_string(),
+        // Return unknown models unchanged - Claude Code will set ANTHROPIC_MODEL
+        None => anthropic_model.to_string(),
+    }
+}
 
-    // If model already contains '/', it's an OpenRouter model ID - return as-is
+/// The Claude short name (`haiku`, `sonnet` or `opus`) a model name stands for,
+/// with the OpenRouter model it is mapped to
+///
+/// OpenRouter model IDs (containing '/') and unknown names match no alias.
+pub fn model_alias<'a>(
+    anthropic_model: &str,
+    config: &'a impl ModelRouting,
+) -> Option<(&'static str, &'a str)> {
+    // If model already contains '/', it's an OpenRouter model ID, not an alias
     if anthropic_model.contains('/') {
-        return anthropic_model.to_string();
+        return None;
     }
 
     let model_lower = anthropic_model.to_lowercase();
+    let targets = config.model_targets();
 
     // Map common Claude short names to full OpenRouter model IDs
     // Only match exact names or standard Claude model patterns
@@ -29,31 +117,171 @@ pub fn map_model(anthropic_model: &str, _config: &Config) -> String {
     if model_lower == "haiku"
         || model_lower.starts_with("claude-3") && model_lower.contains("haiku")
     {
-        "anthropic/claude-3.5-haiku".to_string()
+        Some(("haiku", &targets.haiku))
     } else if model_lower == "sonnet"
         || model_lower.starts_with("claude-3") && model_lower.contains("sonnet")
         || model_lower.starts_with("claude-sonnet-4")
     {
-        "anthropic/claude-sonnet-4".to_string()
+        Some(("sonnet", &targets.sonnet))
     } else if model_lower == "opus"
         || model_lower.starts_with("claude-3") && model_lower.contains("opus")
     {
-        "anthropic/claude-opus-4".to_string()
+        Some(("opus", &targets.opus))
     } else {
-        // Return unknown models unchanged - Claude Code will set ANTHROPIC_MODEL
-        anthropic_model.to_string()
+        None
     }
 }
 
+/// Formats a Unix timestamp in milliseconds as a `YYYY-MM-DD` UTC date
+///
+/// Used for date-partitioned storage keys, since the Workers runtime has no
+/// timezone database and pulling in `chrono` would bloat the WASM bundle.
+pub fn format_date(millis: u64) -> String {
+    let days = (millis / 86_400_000) as i64;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Formats a Unix timestamp in milliseconds as an RFC 3339 UTC timestamp
+pub fn format_timestamp(millis: u64) -> String {
+    let seconds = millis / 1000 % 86_400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(millis),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Decodes `%XX` escapes in a URL path segment, leaving malformed escapes as they are
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The app OpenRouter attributes requests to in its rankings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribution {
+    /// Site URL sent as `HTTP-Referer`
+    pub referer: String,
+    /// App name sent as `X-Title`
+    pub title: String,
+}
+
+impl Default for Attribution {
+    fn default() -> Self {
+        Attribution {
+            referer: "https://ccr.duyet.net".to_string(),
+            title: "CCR - Claude Code Router".to_string(),
+        }
+    }
+}
+
+/// Headers sent with every upstream request
+pub fn upstream_headers(api_key: &str, attribution: &Attribution) -> [(&'static str, String); 4] {
+    [
+        ("Content-Type", "application/json".to_string()),
+        ("Authorization", format!("Bearer {api_key}")),
+        ("HTTP-Referer", attribution.referer.clone()),
+        ("X-Title", attribution.title.clone()),
+    ]
+}
+
+/// Escapes text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// SplitMix64 finalizer: spreads consecutive seeds (e.g. timestamps) evenly across `u64`
+pub fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn default_config() -> Config {
-        Config {
-            openrouter_base_url: "https://openrouter.ai/api/v1".to_string(),
-            default_max_tokens: 4096,
-        }
+    #[test]
+    fn test_headers_carry_bearer_key() {
+        let attribution = Attribution {
+            referer: "https://router.example.com".to_string(),
+            title: "Example Router".to_string(),
+        };
+        let headers = upstream_headers("sk-or-test", &attribution);
+        assert!(headers.contains(&("Authorization", "Bearer sk-or-test".to_string())));
+        assert!(headers.contains(&("Content-Type", "application/json".to_string())));
+        assert!(headers.contains(&("HTTP-Referer", "https://router.example.com".to_string())));
+        assert!(headers.contains(&("X-Title", "Example Router".to_string())));
+    }
+
+    fn default_config() -> Routing {
+        Routing::default()
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400_000), "2000-02-29");
+        assert_eq!(format_date(1_735_689_599_999), "2024-12-31");
+        assert_eq!(format_date(1_735_689_600_000), "2025-01-01");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("moonshotai%2Fkimi-k2%3Afree"),
+            "moonshotai/kimi-k2:free"
+        );
+        assert_eq!(
+            percent_decode("anthropic/claude-sonnet-4"),
+            "anthropic/claude-sonnet-4"
+        );
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1_735_689_599_999), "2024-12-31T23:59:59Z");
+        assert_eq!(format_timestamp(1_700_000_000_000), "2023-11-14T22:13:20Z");
     }
 
     #[test]
@@ -162,6 +390,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_map_model_configured_targets() {
+        let mut config = default_config();
+        config.targets.sonnet = "deepseek/deepseek-chat".to_string();
+
+        assert_eq!(map_model("sonnet", &config), "deepseek/deepseek-chat");
+        assert_eq!(
+            map_model("claude-sonnet-4-20250514", &config),
+            "deepseek/deepseek-chat"
+        );
+        assert_eq!(map_model("haiku", &config), "anthropic/claude-3.5-haiku");
+    }
+
     #[test]
     fn test_map_model_passthrough() {
         let config = default_config();
@@ -0,0 +1,385 @@
+//! Images sent to models that can't view them
+//!
+//! A conversation routed across models of mixed capability can carry screenshots
+//! or diagrams into a turn handled by a text-only model. When the catalog says the
+//! routed model takes no image input, `IMAGE_FALLBACK` decides what the model sees
+//! instead: a placeholder (`strip`) or a caption written by a cheap vision model
+//! (`describe`). Captions are cached per image, so the images of earlier turns,
+//! which clients resend with every request, are described only once.
+
+use crate::auth::hash_key;
+use crate::catalog;
+use crate::http;
+use crate::models::{AnthropicRequest, Usage};
+use crate::transform::openai_usage;
+use crate::upstream::UpstreamClient;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use worker::kv::KvStore;
+use worker::Result;
+
+/// Response header saying how images were handled (`stripped` or `described`)
+pub const IMAGES_HEADER: &str = "x-ccr-images";
+
+/// Caption model used when `IMAGE_CAPTION_MODEL` isn't set
+pub const DEFAULT_CAPTION_MODEL: &str = "google/gemini-2.5-flash-lite";
+
+/// Text standing in for an image that wasn't described
+const PLACEHOLDER: &str = "[Image omitted: this model cannot view images]";
+
+const CAPTION_PROMPT: &str = "Describe this image for someone who cannot see it. Transcribe any \
+    text, code or error messages exactly and describe layout, diagrams and charts. Reply with \
+    the description only.";
+
+/// Longest caption requested from the caption model
+const CAPTION_MAX_TOKENS: u32 = 512;
+
+/// Captions kept per isolate before the cache is cleared
+const CAPTION_CACHE_SIZE: usize = 256;
+
+/// Which catalog models accept images, fetched once per isolate
+static IMAGE_SUPPORT: OnceLock<HashMap<String, bool>> = OnceLock::new();
+
+thread_local! {
+    /// Captions by hash of the image source
+    static CAPTIONS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// What a model that can't view images is sent in their place
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFallback {
+    /// Images are left to the translation, which drops them
+    #[default]
+    Off,
+    /// Each image becomes a placeholder saying it was omitted
+    Strip,
+    /// Each image becomes a caption from the caption model
+    Describe,
+}
+
+impl ImageFallback {
+    /// Value of the `x-ccr-images` header once images were replaced
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImageFallback::Off => "off",
+            ImageFallback::Strip => "stripped",
+            ImageFallback::Describe => "described",
+        }
+    }
+}
+
+impl FromStr for ImageFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(ImageFallback::Off),
+            "strip" => Ok(ImageFallback::Strip),
+            "describe" => Ok(ImageFallback::Describe),
+            _ => Err("expected off, strip or describe".to_string()),
+        }
+    }
+}
+
+/// Whether the catalog lists `model` as taking image input; `None` when it isn't listed
+pub async fn supports_images(
+    client: &http::Client,
+    base_url: &str,
+    catalog_kv: Option<&KvStore>,
+    model: &str,
+) -> Result<Option<bool>> {
+    let support = match IMAGE_SUPPORT.get() {
+        Some(support) => support,
+        None => {
+            let catalog = catalog::cached(client, base_url, catalog_kv).await?;
+            IMAGE_SUPPORT.get_or_init(|| catalog::image_support(&catalog))
+        }
+    };
+    let base = model.split(':').next().unwrap_or(model);
+    Ok(support.get(model).or_else(|| support.get(base)).copied())
+}
+
+/// Calls `f` on every image block, including those nested in tool results
+fn visit_images(content: &mut Value, f: &mut impl FnMut(&mut Value)) {
+    let Some(blocks) = content.as_array_mut() else {
+        return;
+    };
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("image") => f(block),
+            Some("tool_result") => visit_images(&mut block["content"], f),
+            _ => {}
+        }
+    }
+}
+
+fn collect_images<'a>(content: &'a Value, found: &mut Vec<&'a Value>) {
+    for block in content.as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("image") => found.push(block),
+            Some("tool_result") => collect_images(&block["content"], found),
+            _ => {}
+        }
+    }
+}
+
+/// The image blocks of `request`, in conversation order
+pub fn images(request: &AnthropicRequest) -> Vec<&Value> {
+    let mut found = Vec::new();
+    for message in &request.messages {
+        collect_images(&message["content"], &mut found);
+    }
+    found
+}
+
+/// Replaces each image block with a text block from `text`, returning how many were replaced
+pub fn replace_images(
+    request: &mut AnthropicRequest,
+    mut text: impl FnMut(&Value) -> String,
+) -> usize {
+    let mut replaced = 0;
+    for message in &mut request.messages {
+        visit_images(&mut message["content"], &mut |block| {
+            *block = json!({"type": "text", "text": text(block)});
+            replaced += 1;
+        });
+    }
+    replaced
+}
+
+/// Cache key of an image: the hash of its source
+fn image_key(image: &Value) -> String {
+    hash_key(&image["source"].to_string())
+}
+
+/// The data or URL of an image, as an OpenAI `image_url`
+fn image_url(image: &Value) -> Option<String> {
+    let source = &image["source"];
+    match source["type"].as_str()? {
+        "base64" => Some(format!(
+            "data:{};base64,{}",
+            source["media_type"].as_str()?,
+            source["data"].as_str()?
+        )),
+        "url" => source["url"].as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// A caption for `image`, from the cache or the caption model, with the caption
+/// model's usage when it was called
+pub async fn caption(
+    upstream: &impl UpstreamClient,
+    url: &str,
+    api_key: &str,
+    model: &str,
+    image: &Value,
+) -> Result<(String, Option<Usage>)> {
+    let key = image_key(image);
+    if let Some(caption) = CAPTIONS.with(|captions| captions.borrow().get(&key).cloned()) {
+        return Ok((caption, None));
+    }
+
+    let image_url = image_url(image).ok_or_else(|| {
+        worker::Error::RustError("Image has no base64 data or URL to describe".to_string())
+    })?;
+    let request = json!({
+        "model": model,
+        "max_tokens": CAPTION_MAX_TOKENS,
+        "messages": [{
+            "role": "user",
+            "content": [
+                {"type": "text", "text": CAPTION_PROMPT},
+                {"type": "image_url", "image_url": {"url": image_url}}
+            ]
+        }]
+    });
+    let response = upstream.send_json(url, api_key, &request).await?;
+    if !response.is_success() {
+        return Err(worker::Error::RustError(format!(
+            "Caption model returned {}",
+            response.status()
+        )));
+    }
+    let body: Value = response.json().await?;
+    let usage = openai_usage(&body);
+    let caption = body["choices"][0]["message"]["content"]
+        .as_str()
+        .map(str::trim)
+        .filter(|caption| !caption.is_empty())
+        .ok_or_else(|| worker::Error::RustError("Caption model returned no text".to_string()))?
+        .to_string();
+
+    CAPTIONS.with(|captions| {
+        let mut captions = captions.borrow_mut();
+        if captions.len() >= CAPTION_CACHE_SIZE {
+            captions.clear();
+        }
+        captions.insert(key, caption.clone());
+    });
+    Ok((caption, usage))
+}
+
+/// Text replacing an image: its caption, or the placeholder when there is none
+pub fn replacement_text(caption: Option<&str>) -> String {
+    match caption {
+        Some(caption) => format!("[Image description: {caption}]"),
+        None => PLACEHOLDER.to_string(),
+    }
+}
+
+/// Replaces the images of `request` per `fallback`, returning how many were
+/// replaced and what the caption model used, if it was called
+///
+/// Images the caption model fails on get the placeholder, so a caption outage
+/// degrades to `strip` rather than failing the request.
+pub async fn apply(
+    fallback: ImageFallback,
+    request: &mut AnthropicRequest,
+    upstream: &impl UpstreamClient,
+    url: &str,
+    api_key: &str,
+    caption_model: &str,
+) -> (usize, Option<Usage>) {
+    let mut usage: Option<Usage> = None;
+    let captions: HashMap<String, String> = match fallback {
+        ImageFallback::Off => return (0, None),
+        ImageFallback::Strip => HashMap::new(),
+        ImageFallback::Describe => {
+            let mut captions = HashMap::new();
+            for image in images(request) {
+                let key = image_key(image);
+                if captions.contains_key(&key) {
+                    continue;
+                }
+                if let Ok((text, spent)) =
+                    caption(upstream, url, api_key, caption_model, image).await
+                {
+                    captions.insert(key, text);
+                    if let Some(spent) = spent {
+                        let total = usage.get_or_insert_with(Usage::default);
+                        total.input_tokens += spent.input_tokens;
+                        total.output_tokens += spent.output_tokens;
+                        total.cache_creation_input_tokens += spent.cache_creation_input_tokens;
+                        total.cache_read_input_tokens += spent.cache_read_input_tokens;
+                    }
+                }
+            }
+            captions
+        }
+    };
+    let replaced = replace_images(request, |image| {
+        replacement_text(captions.get(&image_key(image)).map(String::as_str))
+    });
+    (replaced, usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::MockClient;
+
+    fn request() -> AnthropicRequest {
+        serde_json::from_value(json!({
+            "model": "deepseek/deepseek-chat",
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What does this error mean?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}}
+                ]},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "screenshot", "input": {}}]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": [
+                    {"type": "image", "source": {"type": "url", "url": "https://example.com/shot.png"}}
+                ]}]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_fallback() {
+        assert_eq!("Describe".parse(), Ok(ImageFallback::Describe));
+        assert_eq!("strip".parse(), Ok(ImageFallback::Strip));
+        assert!("caption".parse::<ImageFallback>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_strip_replaces_nested_images() {
+        let mut request = request();
+        let upstream = MockClient::default();
+        let (replaced, usage) =
+            apply(ImageFallback::Strip, &mut request, &upstream, "", "", "").await;
+
+        assert_eq!(replaced, 2);
+        assert!(usage.is_none());
+        assert!(upstream.sent.borrow().is_empty());
+        assert_eq!(request.messages[0]["content"][1]["text"], PLACEHOLDER);
+        assert_eq!(
+            request.messages[2]["content"][0]["content"][0]["text"],
+            PLACEHOLDER
+        );
+        assert!(images(&request).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_describe_captions_images_once() {
+        let reply = |text: &str| {
+            json!({
+                "choices": [{"message": {"role": "assistant", "content": text}}],
+                "usage": {"prompt_tokens": 1200, "completion_tokens": 40}
+            })
+            .to_string()
+        };
+        let upstream = MockClient::default()
+            .reply(
+                200,
+                &[&reply("A stack trace ending in a NullPointerException")],
+            )
+            .reply(500, &["{}"]);
+        let mut request = request();
+        let (replaced, usage) = apply(
+            ImageFallback::Describe,
+            &mut request,
+            &upstream,
+            "https://openrouter.ai/api/v1/chat/completions",
+            "sk-or-test",
+            DEFAULT_CAPTION_MODEL,
+        )
+        .await;
+
+        assert_eq!(replaced, 2);
+        // Only the caption that was written is charged
+        let usage = usage.unwrap();
+        assert_eq!((usage.prompt_tokens(), usage.output_tokens), (1200, 40));
+        let sent = upstream.sent.take();
+        assert_eq!(sent[0].body["model"], DEFAULT_CAPTION_MODEL);
+        assert_eq!(
+            sent[0].body["messages"][0]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,AAAA"
+        );
+        assert_eq!(
+            request.messages[0]["content"][1]["text"],
+            "[Image description: A stack trace ending in a NullPointerException]"
+        );
+        // The failed caption falls back to the placeholder
+        assert_eq!(
+            request.messages[2]["content"][0]["content"][0]["text"],
+            PLACEHOLDER
+        );
+
+        // The next turn resends the first image, which is now cached
+        let mut next = self::request();
+        next.messages.truncate(1);
+        let upstream = MockClient::default();
+        let (_, usage) = apply(ImageFallback::Describe, &mut next, &upstream, "", "", "").await;
+        assert!(upstream.sent.borrow().is_empty());
+        assert!(usage.is_none());
+        assert_eq!(
+            next.messages[0]["content"][1]["text"],
+            "[Image description: A stack trace ending in a NullPointerException]"
+        );
+    }
+}
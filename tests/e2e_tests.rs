@@ -10,6 +10,7 @@ mod e2e_tests {
         ccr::config::Config {
             openrouter_base_url: "https://openrouter.ai/api/v1".to_string(),
             default_max_tokens: 4096,
+            ..Default::default()
         }
     }
 
@@ -61,6 +62,14 @@ mod e2e_tests {
             tools: None,
             stream: Some(false),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -168,6 +177,14 @@ mod e2e_tests {
             })]),
             stream: Some(false),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -233,6 +250,14 @@ mod e2e_tests {
             tools: None,
             stream: Some(false),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -273,6 +298,14 @@ mod e2e_tests {
             tools: None,
             stream: Some(true),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -323,7 +356,15 @@ mod e2e_tests {
                 tools: None,
                 stream: Some(false),
                 max_tokens: None,
-            cache_control: None,
+                top_p: None,
+                top_k: None,
+                stop_sequences: None,
+                metadata: None,
+                mcp_servers: None,
+                container: None,
+                n: None,
+                openrouter: None,
+                cache_control: None,
             };
 
             let config = default_config();
@@ -370,6 +411,14 @@ mod e2e_tests {
             tools: None,
             stream: Some(false),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -420,10 +469,16 @@ mod e2e_tests {
             .mount(&mock_server)
             .await;
 
-        // Create config pointing to mock server
+        // Create config pointing to mock server, passing sampling settings through
+        // unchanged (Kimi's temperature adjustment is covered by the transform tests)
         let config = ccr::config::Config {
             openrouter_base_url: mock_server.uri(),
             default_max_tokens: 4096,
+            features: ccr::features::FeatureFlags {
+                model_transforms: false,
+                ..Default::default()
+            },
+            ..Default::default()
         };
 
         // Simulate Claude Code request with x-api-key header
@@ -438,6 +493,14 @@ mod e2e_tests {
             tools: None,
             stream: Some(false),
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            metadata: None,
+            mcp_servers: None,
+            container: None,
+            n: None,
+            openrouter: None,
             cache_control: None,
         };
 
@@ -571,7 +634,15 @@ mod e2e_tests {
                     tools: None,
                     stream: Some(false),
                     max_tokens: None,
-            cache_control: None,
+                    top_p: None,
+                    top_k: None,
+                    stop_sequences: None,
+                    metadata: None,
+                    mcp_servers: None,
+                    container: None,
+                    n: None,
+                    openrouter: None,
+                    cache_control: None,
                 };
 
                 let config = default_config();